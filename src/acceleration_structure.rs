@@ -0,0 +1,415 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use nalgebra::Matrix4;
+use vulkano::acceleration_structure::{
+    AccelerationStructure, AccelerationStructureBuildGeometryInfo,
+    AccelerationStructureBuildRangeInfo, AccelerationStructureBuildType,
+    AccelerationStructureCreateInfo, AccelerationStructureGeometries,
+    AccelerationStructureGeometryInstancesData, AccelerationStructureGeometryInstancesDataType,
+    AccelerationStructureGeometryTrianglesData, AccelerationStructureInstance,
+    AccelerationStructureType, BuildAccelerationStructureFlags, BuildAccelerationStructureMode,
+    CopyAccelerationStructureInfo, CopyAccelerationStructureMode, GeometryFlags,
+};
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, IndexBuffer, Subbuffer};
+use vulkano::format::Format;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType};
+use vulkano::{DeviceSize, Packed24_8};
+
+use crate::vulkan_device::{Vertex, VulkanDevice};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BottomLevelId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceId(usize);
+
+struct BottomLevel {
+    acceleration_structure: Arc<AccelerationStructure>,
+    vertex_buffer: Subbuffer<[Vertex]>,
+    index_buffer: Subbuffer<[u32]>,
+}
+
+impl BottomLevel {
+    fn geometries(&self) -> AccelerationStructureGeometries {
+        triangle_geometries(&self.vertex_buffer, &self.index_buffer)
+    }
+
+    fn primitive_count(&self) -> u32 {
+        self.index_buffer.len() as u32 / 3
+    }
+}
+
+#[derive(Default)]
+pub struct AccelerationStructureManager {
+    bottom_levels: Vec<BottomLevel>,
+    instances: Vec<AccelerationStructureInstance>,
+    top_level: Option<Arc<AccelerationStructure>>,
+    top_level_instance_count: u32,
+    scratch_pool: Vec<Subbuffer<[u8]>>,
+}
+
+impl AccelerationStructureManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The buffers can be slices of ones shared with other bottom levels, as long as they come from
+    // `upload_geometry`.
+    pub fn add_bottom_level(
+        &mut self,
+        vulkan_device: &VulkanDevice,
+        vertex_buffer: Subbuffer<[Vertex]>,
+        index_buffer: Subbuffer<[u32]>,
+    ) -> Result<BottomLevelId> {
+        let built = self.build(
+            vulkan_device,
+            AccelerationStructureType::BottomLevel,
+            triangle_geometries(&vertex_buffer, &index_buffer),
+            index_buffer.len() as u32 / 3,
+            None,
+        )?;
+
+        self.bottom_levels.push(BottomLevel {
+            acceleration_structure: compact(
+                vulkan_device,
+                built,
+                AccelerationStructureType::BottomLevel,
+            )?,
+            vertex_buffer,
+            index_buffer,
+        });
+        Ok(BottomLevelId(self.bottom_levels.len() - 1))
+    }
+
+    pub fn refit_bottom_level(
+        &mut self,
+        vulkan_device: &VulkanDevice,
+        id: BottomLevelId,
+        vertices: &[Vertex],
+    ) -> Result<()> {
+        let bottom_level = &self.bottom_levels[id.0];
+        if vertices.len() as DeviceSize != bottom_level.vertex_buffer.len() {
+            bail!("Refitting cannot change the vertex count of a bottom level acceleration structure");
+        }
+        bottom_level.vertex_buffer.write()?.copy_from_slice(vertices);
+
+        let geometries = bottom_level.geometries();
+        let primitive_count = bottom_level.primitive_count();
+        let source = Arc::clone(&bottom_level.acceleration_structure);
+        self.build(
+            vulkan_device,
+            AccelerationStructureType::BottomLevel,
+            geometries,
+            primitive_count,
+            Some(source),
+        )?;
+
+        Ok(())
+    }
+
+    // Shaders read `custom_index` back from their hits, to tell instances apart.
+    pub fn add_instance(
+        &mut self,
+        bottom_level: BottomLevelId,
+        custom_index: u32,
+        transform: &Matrix4<f32>,
+    ) -> InstanceId {
+        let acceleration_structure = &self.bottom_levels[bottom_level.0].acceleration_structure;
+        self.instances.push(AccelerationStructureInstance {
+            transform: instance_transform(transform),
+            instance_custom_index_and_mask: Packed24_8::new(custom_index, 0xFF),
+            acceleration_structure_reference: acceleration_structure.device_address().get(),
+            ..Default::default()
+        });
+        InstanceId(self.instances.len() - 1)
+    }
+
+    pub fn set_instance_transform(&mut self, id: InstanceId, transform: &Matrix4<f32>) {
+        self.instances[id.0].transform = instance_transform(transform);
+    }
+
+    pub fn clear_instances(&mut self) {
+        self.instances.clear();
+    }
+
+    pub fn build_top_level(&mut self, vulkan_device: &VulkanDevice) -> Result<()> {
+        let instance_count = self.instances.len() as u32;
+        let instance_buffer = Buffer::from_iter(
+            vulkan_device.memory_allocator().clone(),
+            input_buffer_info(),
+            input_allocation_info(),
+            self.instances.iter().copied(),
+        )?;
+
+        // Updating in place requires the instance count to stay the same.
+        let source = self
+            .top_level
+            .as_ref()
+            .filter(|_| instance_count == self.top_level_instance_count)
+            .cloned();
+
+        let top_level = self.build(
+            vulkan_device,
+            AccelerationStructureType::TopLevel,
+            AccelerationStructureGeometries::Instances(
+                AccelerationStructureGeometryInstancesData::new(
+                    AccelerationStructureGeometryInstancesDataType::Values(Some(instance_buffer)),
+                ),
+            ),
+            instance_count,
+            source,
+        )?;
+
+        self.top_level = Some(top_level);
+        self.top_level_instance_count = instance_count;
+        Ok(())
+    }
+
+    pub fn release_scratch_buffers(&mut self) {
+        self.scratch_pool.clear();
+    }
+
+    pub fn top_level(&self) -> Option<&Arc<AccelerationStructure>> {
+        self.top_level.as_ref()
+    }
+
+    fn build(
+        &mut self,
+        vulkan_device: &VulkanDevice,
+        ty: AccelerationStructureType,
+        geometries: AccelerationStructureGeometries,
+        primitive_count: u32,
+        source: Option<Arc<AccelerationStructure>>,
+    ) -> Result<Arc<AccelerationStructure>> {
+        let mut build_info = AccelerationStructureBuildGeometryInfo {
+            flags: BuildAccelerationStructureFlags::PREFER_FAST_TRACE
+                | BuildAccelerationStructureFlags::ALLOW_UPDATE
+                | BuildAccelerationStructureFlags::ALLOW_COMPACTION,
+            mode: match &source {
+                Some(source) => BuildAccelerationStructureMode::Update(Arc::clone(source)),
+                None => BuildAccelerationStructureMode::Build,
+            },
+            ..AccelerationStructureBuildGeometryInfo::new(geometries)
+        };
+
+        let build_sizes = vulkan_device.queue().device().acceleration_structure_build_sizes(
+            AccelerationStructureBuildType::Device,
+            &build_info,
+            &[primitive_count],
+        )?;
+
+        let (acceleration_structure, scratch_size) = match source {
+            Some(source) => (source, build_sizes.update_scratch_size),
+            None => (
+                create_acceleration_structure(
+                    vulkan_device,
+                    ty,
+                    build_sizes.acceleration_structure_size,
+                )?,
+                build_sizes.build_scratch_size,
+            ),
+        };
+
+        build_info.dst_acceleration_structure = Some(Arc::clone(&acceleration_structure));
+        build_info.scratch_data = Some(self.scratch_buffer(vulkan_device, scratch_size)?);
+
+        vulkan_device.submit_and_wait(|builder| {
+            unsafe {
+                builder.build_acceleration_structure(
+                    build_info,
+                    [AccelerationStructureBuildRangeInfo {
+                        primitive_count,
+                        ..Default::default()
+                    }]
+                    .into_iter()
+                    .collect(),
+                )?;
+            }
+            Ok(())
+        })?;
+
+        Ok(acceleration_structure)
+    }
+
+    // Builds are waited on before returning, so a pooled buffer is never in flight twice.
+    fn scratch_buffer(
+        &mut self,
+        vulkan_device: &VulkanDevice,
+        size: DeviceSize,
+    ) -> Result<Subbuffer<[u8]>> {
+        if let Some(buffer) = self
+            .scratch_pool
+            .iter()
+            .filter(|buffer| buffer.size() >= size)
+            .min_by_key(|buffer| buffer.size())
+        {
+            return Ok(buffer.clone());
+        }
+
+        let alignment = vulkan_device
+            .queue()
+            .device()
+            .physical_device()
+            .properties()
+            .min_acceleration_structure_scratch_offset_alignment
+            .unwrap_or(1) as DeviceSize;
+
+        let buffer = Buffer::new_slice::<u8>(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::SHADER_DEVICE_ADDRESS | BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            size + alignment,
+        )?;
+        let address = buffer.device_address()?.get();
+        let offset = address.next_multiple_of(alignment) - address;
+        let buffer = buffer.slice(offset..offset + size);
+
+        self.scratch_pool.push(buffer.clone());
+        Ok(buffer)
+    }
+}
+
+// Vertex and index buffers that bottom levels can be built from.
+pub type GeometryBuffers = (Subbuffer<[Vertex]>, Subbuffer<[u32]>);
+
+pub fn upload_geometry(
+    vulkan_device: &VulkanDevice,
+    vertices: &[Vertex],
+    indices: &[u32],
+) -> Result<GeometryBuffers> {
+    let vertex_buffer = Buffer::from_iter(
+        vulkan_device.memory_allocator().clone(),
+        input_buffer_info(),
+        input_allocation_info(),
+        vertices.iter().copied(),
+    )?;
+    let index_buffer = Buffer::from_iter(
+        vulkan_device.memory_allocator().clone(),
+        input_buffer_info(),
+        input_allocation_info(),
+        indices.iter().copied(),
+    )?;
+    Ok((vertex_buffer, index_buffer))
+}
+
+fn compact(
+    vulkan_device: &VulkanDevice,
+    acceleration_structure: Arc<AccelerationStructure>,
+    ty: AccelerationStructureType,
+) -> Result<Arc<AccelerationStructure>> {
+    let query_pool = QueryPool::new(
+        Arc::clone(vulkan_device.queue().device()),
+        QueryPoolCreateInfo {
+            query_count: 1,
+            ..QueryPoolCreateInfo::query_type(QueryType::AccelerationStructureCompactedSize)
+        },
+    )?;
+
+    vulkan_device.submit_and_wait(|builder| {
+        unsafe {
+            builder
+                .reset_query_pool(Arc::clone(&query_pool), 0..1)?
+                .write_acceleration_structures_properties(
+                    [Arc::clone(&acceleration_structure)].into_iter().collect(),
+                    Arc::clone(&query_pool),
+                    0,
+                )?;
+        }
+        Ok(())
+    })?;
+
+    let mut compacted_size = [0u64];
+    query_pool.get_results(0..1, &mut compacted_size, QueryResultFlags::WAIT)?;
+
+    let compacted = create_acceleration_structure(vulkan_device, ty, compacted_size[0])?;
+
+    vulkan_device.submit_and_wait(|builder| {
+        unsafe {
+            builder.copy_acceleration_structure(CopyAccelerationStructureInfo {
+                mode: CopyAccelerationStructureMode::Compact,
+                ..CopyAccelerationStructureInfo::new(
+                    Arc::clone(&acceleration_structure),
+                    Arc::clone(&compacted),
+                )
+            })?;
+        }
+        Ok(())
+    })?;
+
+    Ok(compacted)
+}
+
+fn create_acceleration_structure(
+    vulkan_device: &VulkanDevice,
+    ty: AccelerationStructureType,
+    size: DeviceSize,
+) -> Result<Arc<AccelerationStructure>> {
+    let storage_buffer = Buffer::new_slice::<u8>(
+        vulkan_device.memory_allocator().clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::ACCELERATION_STRUCTURE_STORAGE | BufferUsage::SHADER_DEVICE_ADDRESS,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        },
+        size,
+    )?;
+
+    Ok(unsafe {
+        AccelerationStructure::new(
+            Arc::clone(vulkan_device.queue().device()),
+            AccelerationStructureCreateInfo {
+                ty,
+                ..AccelerationStructureCreateInfo::new(storage_buffer)
+            },
+        )?
+    })
+}
+
+fn triangle_geometries(
+    vertex_buffer: &Subbuffer<[Vertex]>,
+    index_buffer: &Subbuffer<[u32]>,
+) -> AccelerationStructureGeometries {
+    AccelerationStructureGeometries::Triangles(vec![AccelerationStructureGeometryTrianglesData {
+        flags: GeometryFlags::OPAQUE,
+        vertex_data: Some(vertex_buffer.clone().into_bytes()),
+        vertex_stride: std::mem::size_of::<Vertex>() as u32,
+        max_vertex: vertex_buffer.len() as u32 - 1,
+        index_data: Some(IndexBuffer::U32(index_buffer.clone())),
+        ..AccelerationStructureGeometryTrianglesData::new(Format::R32G32B32_SFLOAT)
+    }])
+}
+
+fn input_buffer_info() -> BufferCreateInfo {
+    BufferCreateInfo {
+        usage: BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY
+            | BufferUsage::SHADER_DEVICE_ADDRESS
+            | BufferUsage::STORAGE_BUFFER,
+        ..Default::default()
+    }
+}
+
+fn input_allocation_info() -> AllocationCreateInfo {
+    AllocationCreateInfo {
+        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+        ..Default::default()
+    }
+}
+
+fn instance_transform(transform: &Matrix4<f32>) -> [[f32; 4]; 3] {
+    let row = |index| {
+        let row = transform.row(index);
+        [row[0], row[1], row[2], row[3]]
+    };
+    [row(0), row(1), row(2)]
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use vulkano::buffer::allocator::SubbufferAllocator;
+use vulkano::buffer::{BufferContents, Subbuffer};
+use vulkano::memory::allocator::suballocator::SuballocationType;
+use vulkano::memory::allocator::{MemoryAllocatorError, StandardMemoryAllocator, Suballocator};
+use vulkano::DeviceSize;
+
+// Arena occupancy isn't exposed by vulkano, so the largest suballocation served is recorded
+// instead; anything above the arena size forces a dedicated arena. The allocator isn't `Sync`, and
+// the passes owning one are shared with the render thread.
+pub struct TrackedSubbufferAllocator {
+    allocator: Mutex<SubbufferAllocator>,
+    peak_allocation: AtomicU64,
+}
+
+impl TrackedSubbufferAllocator {
+    pub fn new(allocator: SubbufferAllocator) -> Self {
+        Self {
+            allocator: Mutex::new(allocator),
+            peak_allocation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn allocate_slice<T: BufferContents>(
+        &self,
+        len: DeviceSize,
+    ) -> Result<Subbuffer<[T]>, MemoryAllocatorError> {
+        let subbuffer = self.allocator.lock().unwrap().allocate_slice(len)?;
+        self.peak_allocation
+            .fetch_max(subbuffer.size(), Ordering::Relaxed);
+        Ok(subbuffer)
+    }
+
+    pub fn statistics(&self, name: &'static str) -> ArenaStatistics {
+        ArenaStatistics {
+            name,
+            arena_size: self.allocator.lock().unwrap().arena_size(),
+            peak_allocation: self.peak_allocation.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ArenaStatistics {
+    pub name: &'static str,
+    pub arena_size: DeviceSize,
+    pub peak_allocation: DeviceSize,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryTypeStatistics {
+    pub memory_type_index: u32,
+    pub block_count: usize,
+    pub block_size: DeviceSize,
+    pub allocation_count: usize,
+    pub free_size: DeviceSize,
+    pub largest_free_range: DeviceSize,
+}
+
+impl MemoryTypeStatistics {
+    // 0 when all free memory is one contiguous range, approaching 1 as it splinters.
+    pub fn fragmentation(&self) -> f32 {
+        if self.free_size == 0 {
+            return 0.0;
+        }
+        1.0 - self.largest_free_range as f32 / self.free_size as f32
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AllocatorStatistics {
+    pub memory_types: Vec<MemoryTypeStatistics>,
+    pub arenas: Vec<ArenaStatistics>,
+}
+
+impl AllocatorStatistics {
+    pub fn collect(
+        memory_allocator: &StandardMemoryAllocator,
+        arenas: Vec<ArenaStatistics>,
+    ) -> Self {
+        let memory_types = memory_allocator
+            .pools()
+            .iter()
+            .enumerate()
+            .filter_map(|(memory_type_index, pool)| {
+                let mut statistics = MemoryTypeStatistics {
+                    memory_type_index: memory_type_index as u32,
+                    ..Default::default()
+                };
+                for block in pool.blocks() {
+                    statistics.block_count += 1;
+                    statistics.block_size += block.device_memory().allocation_size();
+                    statistics.allocation_count += block.allocation_count();
+                    statistics.free_size += block.suballocator().free_size();
+                    statistics.largest_free_range = block
+                        .suballocator()
+                        .suballocations()
+                        .filter(|node| node.allocation_type == SuballocationType::Free)
+                        .map(|node| node.size)
+                        .fold(statistics.largest_free_range, DeviceSize::max);
+                }
+                (statistics.block_count > 0).then_some(statistics)
+            })
+            .collect();
+
+        Self {
+            memory_types,
+            arenas,
+        }
+    }
+}
+
+impl fmt::Display for AllocatorStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for memory_type in &self.memory_types {
+            writeln!(
+                f,
+                "memory type {}: {} blocks, {} bytes, {} allocations, {} bytes free, {:.0}% fragmented",
+                memory_type.memory_type_index,
+                memory_type.block_count,
+                memory_type.block_size,
+                memory_type.allocation_count,
+                memory_type.free_size,
+                memory_type.fragmentation() * 100.0
+            )?;
+        }
+        for arena in &self.arenas {
+            writeln!(
+                f,
+                "{} arena: {} bytes, peak suballocation {} bytes",
+                arena.name, arena.arena_size, arena.peak_allocation
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,100 +1,721 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
+use nalgebra::Matrix4;
+use tracing::{info, warn};
 use vulkano::image::{ImageUsage, SampleCount};
-use winit::event::{Event, WindowEvent};
-use winit::event_loop::{EventLoop, EventLoopWindowTarget};
-use winit::window::{Window, WindowBuilder, WindowId};
+use vulkano::swapchain::Surface;
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
+use winit::event_loop::{EventLoop, EventLoopProxy, EventLoopWindowTarget};
+use winit::window::{Window, WindowId};
 
-use crate::vulkan_device::VulkanDevice;
-use crate::vulkan_instance::VulkanInstance;
+use crate::background::Background;
+use crate::benchmark::{Benchmark, BenchmarkSettings};
+use crate::capture::{CaptureSettings, FrameCapture};
+use crate::color_lut::ColorLut;
+use crate::color_management::{OutputColorSpace, WorkingColorSpace};
+use crate::environment::EnvironmentMap;
+use crate::foliage::{Foliage, ScatterSettings};
+use crate::frame_pacing::{monitor_refresh_interval, FramePacer};
+use crate::gizmo::{Gizmo, GizmoMode};
+use crate::lightmap::Lightmap;
+use crate::metrics::MetricsRecorder;
+use crate::minimap::{Minimap, MinimapSettings};
+use crate::particles::EmitterSettings;
+#[cfg(feature = "physics")]
+use crate::physics::{PhysicsSettings, PhysicsWorld};
+use crate::picture_in_picture::PictureInPicture;
+use crate::reflection_probe::ReflectionProbeSettings;
+use crate::render_thread::{FrameReport, RenderThread, RendererHost, Renderers};
+use crate::scene_camera::find_scene_camera;
+use crate::scene_file::{SceneFile, DEFAULT_SCENE_FILE_PATH};
+use crate::scene_graph::find_scene;
+use crate::scripting::{ScriptCommand, ScriptHost};
+use crate::sh_probes::{ShProbeBuffers, ShProbeGrid, ShProbeGridSettings};
+use crate::shadow_map::ShadowQuality;
+use crate::simulation::{SimulationSettings, SimulationSetup, SimulationThread};
+use crate::sky::SkySettings;
+use crate::split_screen::SplitScreenLayout;
+use crate::swapchain_format::SwapchainFormat;
+use crate::tonemap::TonemapEffect;
+use crate::transparency::TransparencyMode;
+use crate::vulkan_device::{AntiAliasing, DebugView, VulkanDevice};
+use crate::vulkan_instance::{DeviceSelection, VulkanInstance};
 use crate::vulkan_renderer::VulkanRenderer;
+use crate::window_mode::{OverlayMode, WindowMode};
+use crate::window_title::{load_icon, WindowTitle, DEFAULT_TITLE_TEMPLATE, ICON_PATH};
+
+// Device-owned resources can't be shared across GPUs, so each device loads its own copy.
+struct GpuContext {
+    vulkan_device: Arc<VulkanDevice>,
+    color_lut: Option<Arc<ColorLut>>,
+    environment: Option<Arc<EnvironmentMap>>,
+    foliage: Option<Arc<Foliage>>,
+    lightmap: Option<Arc<Lightmap>>,
+    sh_probes: Option<Arc<ShProbeBuffers>>,
+}
+
+impl GpuContext {
+    fn new(
+        vulkan_instance: Arc<VulkanInstance>,
+        anti_aliasing: AntiAliasing,
+        color_lut_path: Option<&str>,
+        environment_source: Option<&str>,
+        foliage_count: Option<u32>,
+        lightmap_path: Option<&str>,
+        sh_probe_grid: Option<&ShProbeGrid>,
+    ) -> Result<Self> {
+        let vulkan_device = Arc::new(VulkanDevice::new(vulkan_instance, anti_aliasing)?);
+
+        let color_lut = color_lut_path
+            .map(|path| ColorLut::load(&vulkan_device, path).map(Arc::new))
+            .transpose()?;
+
+        let environment = match environment_source {
+            Some("sky") => Some(EnvironmentMap::procedural_sky(
+                &vulkan_device,
+                &SkySettings::default(),
+                256,
+            )?),
+            Some(path) => Some(EnvironmentMap::load_equirectangular(
+                &vulkan_device,
+                path,
+                1024,
+            )?),
+            None => EnvironmentMap::find_in("assets")
+                .map(|path| EnvironmentMap::load_equirectangular(&vulkan_device, path, 1024))
+                .transpose()?,
+        }
+        .map(Arc::new);
+
+        let foliage = foliage_count
+            .map(|count| -> Result<_> {
+                Ok(Arc::new(Foliage::scatter(
+                    &vulkan_device,
+                    &[
+                        [-20.0, -1.0, -20.0],
+                        [20.0, -1.0, -20.0],
+                        [20.0, -1.0, 20.0],
+                        [-20.0, -1.0, 20.0],
+                    ],
+                    &[0, 2, 1, 0, 3, 2],
+                    &ScatterSettings {
+                        count,
+                        ..Default::default()
+                    },
+                )?))
+            })
+            .transpose()?;
+
+        let lightmap = lightmap_path
+            .map(|path| Lightmap::load(&vulkan_device, path).map(Arc::new))
+            .transpose()?;
+
+        let sh_probes = sh_probe_grid
+            .map(|grid| grid.upload(&vulkan_device).map(Arc::new))
+            .transpose()?;
+
+        Ok(Self {
+            vulkan_device,
+            color_lut,
+            environment,
+            foliage,
+            lightmap,
+            sh_probes,
+        })
+    }
+}
 
 pub struct VisualSystem {
+    // Declared first so a render thread stops before the windows are dropped.
+    renderers: RendererHost,
     primary_window_id: WindowId,
     windows: HashMap<WindowId, Arc<Window>>,
-    vulkan_instance: Arc<VulkanInstance>,
-    vulkan_device: Arc<VulkanDevice>,
-    vulkan_renderers: HashMap<WindowId, Arc<RefCell<VulkanRenderer>>>,
+    gpus: Vec<GpuContext>,
+    window_gpus: HashMap<WindowId, usize>,
+    particle_emitters: Vec<(u32, EmitterSettings)>,
+    mesh_shading: bool,
+    vertex_pulling: bool,
+    ray_tracing: bool,
+    path_tracing: bool,
+    wireframe: bool,
+    grid: bool,
+    gizmo: bool,
+    frusta: bool,
+    light_visualization: bool,
+    debug_view: DebugView,
+    shadow_quality: ShadowQuality,
+    split_screen: Option<SplitScreenLayout>,
+    split_screen_debug_views: Vec<DebugView>,
+    backgrounds: Vec<Background>,
+    minimap: Option<MinimapSettings>,
+    picture_in_picture_camera: Option<String>,
+    reflection_probe_size: Option<u32>,
+    // A glTF scene's name or index, or the document's default scene.
+    scene: Option<String>,
+    scene_file_path: String,
+    scene_file: Option<SceneFile>,
+    stereo: bool,
+    parallel_recording: bool,
+    reuse_scene_commands: bool,
+    transparency_mode: TransparencyMode,
+    swapchain_format: SwapchainFormat,
+    working_color_space: WorkingColorSpace,
+    output_color_space: OutputColorSpace,
+    color_audit: bool,
+    background_alpha: Option<f32>,
+    overlay: Option<OverlayMode>,
+    window_titles: HashMap<WindowId, WindowTitle>,
+    // Windows whose renderer lost its surface and has a new one on the way.
+    replaced_surfaces: HashSet<WindowId>,
+    frame_pacer: FramePacer,
+    capture_settings: Option<CaptureSettings>,
+    benchmark_settings: Option<BenchmarkSettings>,
+    metrics: Option<MetricsRecorder>,
+    script: Option<ScriptHost>,
+    #[cfg(feature = "physics")]
+    physics: Option<PhysicsWorld>,
+    simulation: Option<SimulationThread>,
+    start_time: Instant,
+    previous_update_time: Instant,
+    update_count: u64,
+    modifiers: ModifiersState,
 }
 
 impl VisualSystem {
-    pub fn new<T>(window_target: &EventLoopWindowTarget<T>) -> Result<Self> {
+    pub fn new<T>(
+        window_target: &EventLoopWindowTarget<T>,
+        benchmark_settings: Option<BenchmarkSettings>,
+        device_selection: &DeviceSelection,
+        scene: Option<String>,
+        event_loop_proxy: EventLoopProxy<()>,
+    ) -> Result<Self> {
+        let window_mode: WindowMode = std::env::var("VULKANOX_WINDOW_MODE")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or_default();
+        // The opacity of the cleared background; any value makes the windows alpha-composited.
+        let background_alpha = std::env::var("VULKANOX_TRANSPARENT")
+            .ok()
+            .map(|value| value.parse::<f32>())
+            .transpose()?;
+        let overlay: Option<OverlayMode> = std::env::var("VULKANOX_OVERLAY")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?;
+        let title_template =
+            std::env::var("VULKANOX_TITLE").unwrap_or_else(|_| DEFAULT_TITLE_TEMPLATE.to_owned());
+        let icon =
+            load_icon(std::env::var("VULKANOX_ICON").unwrap_or_else(|_| ICON_PATH.to_owned()))?;
+
         let primary_window = Arc::new(
-            WindowBuilder::new()
+            window_mode
+                .window_builder()
+                .with_transparent(background_alpha.is_some())
+                .with_window_icon(icon.clone())
                 .with_visible(false)
                 .build(window_target)?,
         );
         let primary_window_id = primary_window.id();
 
-        let vulkan_instance = Arc::new(VulkanInstance::new(&primary_window)?);
-        let vulkan_device = Arc::new(VulkanDevice::new(Arc::clone(&vulkan_instance), SampleCount::Sample8)?);
+        let anti_aliasing = std::env::var("VULKANOX_ANTI_ALIASING")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or(AntiAliasing::Msaa(SampleCount::Sample8));
+        let color_lut_path = std::env::var("VULKANOX_COLOR_LUT").ok();
+        let environment_source = std::env::var("VULKANOX_ENVIRONMENT").ok();
+        let foliage_count = std::env::var("VULKANOX_FOLIAGE")
+            .ok()
+            .map(|count| count.parse())
+            .transpose()?;
+        // A KTX2 file written by `--bake-lightmap`.
+        let lightmap_path = std::env::var("VULKANOX_LIGHTMAP").ok();
+        // Probes per axis of a grid baked over the scene at startup, shared by every GPU.
+        let sh_probe_grid = std::env::var("VULKANOX_SH_PROBES")
+            .ok()
+            .map(|resolution| -> Result<_> {
+                ShProbeGrid::bake(&ShProbeGridSettings {
+                    resolution: [resolution.parse()?; 3],
+                    ..Default::default()
+                })
+            })
+            .transpose()?;
+
+        let gpus = VulkanInstance::select(&primary_window, device_selection)?
+            .into_iter()
+            .map(|vulkan_instance| {
+                GpuContext::new(
+                    Arc::new(vulkan_instance),
+                    anti_aliasing,
+                    color_lut_path.as_deref(),
+                    environment_source.as_deref(),
+                    foliage_count,
+                    lightmap_path.as_deref(),
+                    sh_probe_grid.as_ref(),
+                )
+            })
+            .try_collect::<Vec<_>>()?;
+
+        let window_count = std::env::var("VULKANOX_WINDOWS")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or(1);
 
         let mut windows = HashMap::from([(primary_window_id, primary_window)]);
+        // The primary window stays on the selected device; extra windows are dealt out round-robin.
+        let mut window_gpus = HashMap::from([(primary_window_id, 0)]);
 
-        for i in 0..0 {
-            let window = Arc::new(
-                WindowBuilder::new()
-                    .with_visible(false)
-                    .build(window_target)?,
-            );
+        for i in 1..window_count {
+            let mut window_builder = window_mode
+                .window_builder()
+                .with_transparent(background_alpha.is_some())
+                .with_window_icon(icon.clone())
+                .with_visible(false);
+            if let Some(overlay) = overlay {
+                window_builder = overlay.configure(window_builder);
+            }
+            let window = Arc::new(window_builder.build(window_target)?);
+            if let Some(overlay) = overlay {
+                overlay.apply(&window);
+            }
+            window_gpus.insert(window.id(), i % gpus.len());
             windows.insert(window.id(), window);
         }
 
-        let mut vulkan_renderers = HashMap::with_capacity(windows.len());
-
-        for (window_index, (window_id, window)) in windows.iter().enumerate() {
-            let c = (window_index as f32) / (windows.len() as f32);
-            vulkan_renderers.insert(
-                *window_id,
-                Arc::new(RefCell::new(VulkanRenderer::new(
-                    Arc::clone(&vulkan_device),
-                    Arc::clone(&window),
-                    [c, c, c, c],
-                    true,
-                    ImageUsage::COLOR_ATTACHMENT,
-                    window_index,
-                    windows.len(),
-                )?)),
-            );
-        }
+        let particle_emitters = std::env::var("VULKANOX_PARTICLES")
+            .ok()
+            .map(|capacity| capacity.parse())
+            .transpose()?
+            .map(|capacity| (capacity, EmitterSettings::default()))
+            .into_iter()
+            .collect::<Vec<_>>();
 
-        windows.iter().for_each(|(_, window)| {
-            window.set_visible(true);
-        });
+        let mesh_shading = env_flag("VULKANOX_MESH_SHADING")?;
+        let vertex_pulling = env_flag("VULKANOX_VERTEX_PULLING")?;
+        // Replaces the rasterized passes with the ray query preview where it's supported.
+        let ray_tracing = env_flag("VULKANOX_RAY_TRACING")?;
+        let path_tracing = env_flag("VULKANOX_PATH_TRACING")?;
+        let wireframe = env_flag("VULKANOX_WIREFRAME")?;
+        let grid = env_flag("VULKANOX_GRID")?;
+        let gizmo = env_flag("VULKANOX_GIZMO")?;
+        let frusta = env_flag("VULKANOX_FRUSTA")?;
+        let light_visualization = env_flag("VULKANOX_LIGHT_VISUALIZATION")?;
 
-        Ok(Self {
+        let debug_view = std::env::var("VULKANOX_DEBUG_VIEW")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or_default();
+        let shadow_quality = std::env::var("VULKANOX_SHADOW_QUALITY")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let split_screen = std::env::var("VULKANOX_SPLIT_SCREEN")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?;
+        // A comma-separated debug view per split-screen cell, e.g. `lit,normals,depth`.
+        let split_screen_debug_views = std::env::var("VULKANOX_SPLIT_SCREEN_DEBUG_VIEWS")
+            .ok()
+            .map(|value| value.split(',').map(str::parse).try_collect::<Vec<_>>())
+            .transpose()?
+            .unwrap_or_default();
+        // A comma-separated background per window, e.g. `gradient,plasma`, shown when there's no
+        // skybox. The last one carries over to the remaining windows.
+        let backgrounds = std::env::var("VULKANOX_BACKGROUNDS")
+            .ok()
+            .map(|value| value.split(',').map(str::parse).try_collect::<Vec<_>>())
+            .transpose()?
+            .unwrap_or_default();
+
+        // The minimap's size in pixels.
+        let minimap = std::env::var("VULKANOX_MINIMAP")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .map(|size| MinimapSettings {
+                size,
+                ..Default::default()
+            });
+
+        // A glTF camera's name or index to show in the picture-in-picture inset.
+        let picture_in_picture_camera = std::env::var("VULKANOX_PIP_CAMERA").ok();
+
+        // The face size of a reflection probe covering the scene's bounds.
+        let reflection_probe_size = std::env::var("VULKANOX_REFLECTION_PROBE")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?;
+
+        let stereo = env_flag("VULKANOX_STEREO")?;
+        let parallel_recording = env_flag("VULKANOX_PARALLEL_RECORDING")?;
+
+        // For static viewers: the scene's draws are recorded once and replayed every frame.
+        let reuse_scene_commands = env_flag("VULKANOX_REUSE_SCENE_COMMANDS")?;
+
+        let transparency_mode = std::env::var("VULKANOX_TRANSPARENCY")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let swapchain_format = std::env::var("VULKANOX_SWAPCHAIN_FORMAT")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or_default();
+        let working_color_space = std::env::var("VULKANOX_WORKING_COLOR_SPACE")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or_default();
+        // Windows whose surface can't present it stay in sRGB.
+        let output_color_space = std::env::var("VULKANOX_OUTPUT_COLOR_SPACE")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or_default();
+        // Stripes sprites whose textures look decoded with the wrong transfer function.
+        let color_audit = env_flag("VULKANOX_COLOR_AUDIT")?;
+
+        let frame_pacing = std::env::var("VULKANOX_FRAME_PACING")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or_default();
+        // Renders every window back to back on a thread of its own instead of on redraw requests.
+        let render_thread = env_flag("VULKANOX_RENDER_THREAD")?;
+
+        let capture_settings = std::env::var("VULKANOX_CAPTURE")
+            .ok()
+            .map(|output| -> Result<_> {
+                Ok(CaptureSettings {
+                    output: output.parse()?,
+                    frame_rate: std::env::var("VULKANOX_CAPTURE_FPS")
+                        .ok()
+                        .map(|value| value.parse())
+                        .transpose()?
+                        .unwrap_or(60),
+                })
+            })
+            .transpose()?;
+        ensure!(
+            output_color_space == OutputColorSpace::Srgb || capture_settings.is_none(),
+            "Captures are written as sRGB, so they need the sRGB output color space"
+        );
+
+        let metrics = std::env::var("VULKANOX_METRICS")
+            .ok()
+            .map(MetricsRecorder::new);
+
+        let script_path = std::env::var("VULKANOX_SCRIPT")
+            .ok()
+            .map(Into::into)
+            .or_else(|| ScriptHost::find_in("assets"));
+        // Steps the script and physics at a fixed rate on a thread of their own, with frames
+        // blending between the steps.
+        let simulation_thread = env_flag("VULKANOX_SIMULATION_THREAD")?;
+        ensure!(
+            !simulation_thread || capture_settings.is_none(),
+            "Capturing steps the simulation once per frame, which a simulation thread can't"
+        );
+        let script = if simulation_thread {
+            None
+        } else {
+            script_path.clone().map(ScriptHost::load).transpose()?
+        };
+
+        // Restored on startup when it exists; F12 saves the focused window's scene to it.
+        let scene_file_path = std::env::var("VULKANOX_SCENE_FILE")
+            .unwrap_or_else(|_| DEFAULT_SCENE_FILE_PATH.to_owned());
+        let scene_file = Path::new(&scene_file_path)
+            .exists()
+            .then(|| SceneFile::load(&scene_file_path))
+            .transpose()?;
+
+        let window_titles = windows
+            .keys()
+            .enumerate()
+            .map(|(window_index, window_id)| {
+                (*window_id, WindowTitle::new(&title_template, window_index))
+            })
+            .collect();
+
+        let mut visual_system = Self {
+            renderers: RendererHost::Local(HashMap::new()),
             primary_window_id,
             windows,
-            vulkan_instance,
-            vulkan_device,
-            vulkan_renderers,
+            gpus,
+            window_gpus,
+            particle_emitters,
+            mesh_shading,
+            vertex_pulling,
+            ray_tracing,
+            path_tracing,
+            wireframe,
+            grid,
+            gizmo,
+            frusta,
+            light_visualization,
+            debug_view,
+            shadow_quality,
+            split_screen,
+            split_screen_debug_views,
+            backgrounds,
+            minimap,
+            picture_in_picture_camera,
+            reflection_probe_size,
+            scene,
+            scene_file_path,
+            scene_file,
+            stereo,
+            parallel_recording,
+            reuse_scene_commands,
+            transparency_mode,
+            swapchain_format,
+            working_color_space,
+            output_color_space,
+            color_audit,
+            background_alpha,
+            overlay,
+            window_titles,
+            replaced_surfaces: HashSet::new(),
+            frame_pacer: FramePacer::new(frame_pacing),
+            capture_settings,
+            benchmark_settings,
+            metrics,
+            script,
+            #[cfg(feature = "physics")]
+            physics: None,
+            simulation: None,
+            start_time: Instant::now(),
+            previous_update_time: Instant::now(),
+            update_count: 0,
+            modifiers: ModifiersState::empty(),
+        };
+
+        let vulkan_renderers = visual_system.create_renderers()?;
+        visual_system.windows.values().for_each(|window| {
+            window.set_visible(true);
+        });
+
+        // One world drives every window, starting from the primary window's scene.
+        #[cfg(feature = "physics")]
+        let mut physics = env_flag("VULKANOX_PHYSICS")?.then(|| {
+            PhysicsWorld::new(
+                vulkan_renderers[&primary_window_id].scene_graph(),
+                PhysicsSettings::default(),
+            )
+        });
+
+        visual_system.simulation = simulation_thread
+            .then(|| {
+                SimulationThread::spawn(
+                    SimulationSettings::default(),
+                    SimulationSetup {
+                        script_path,
+                        #[cfg(feature = "physics")]
+                        physics: physics.take(),
+                    },
+                )
+            })
+            .transpose()?;
+        #[cfg(feature = "physics")]
+        {
+            visual_system.physics = physics;
+        }
+
+        visual_system.renderers = if render_thread {
+            RendererHost::Threaded(RenderThread::spawn(
+                vulkan_renderers,
+                FramePacer::new(frame_pacing),
+                primary_window_id,
+                event_loop_proxy,
+            )?)
+        } else {
+            RendererHost::Local(vulkan_renderers)
+        };
+
+        let commands = match &mut visual_system.script {
+            Some(script) => {
+                script.start()?;
+                script.drain_commands()
+            }
+            None => Vec::new(),
+        };
+        visual_system.apply_script_commands(commands)?;
+
+        Ok(visual_system)
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        let vulkan_renderers = self.create_renderers()?;
+        self.renderers.run(move |renderers| {
+            renderers.extend(vulkan_renderers);
+            Ok(())
         })
     }
 
-    pub fn resume<T>(&mut self, window_target: &EventLoopWindowTarget<T>) -> Result<()> {
-        for (window_index, (window_id, window)) in self.windows.iter().enumerate() {
-            let c = (window_index as f32) / (self.windows.len() as f32);
-            self.vulkan_renderers.insert(
-                *window_id,
-                Arc::new(RefCell::new(VulkanRenderer::new(
-                    Arc::clone(&self.vulkan_device),
-                    Arc::clone(&window),
-                    [c, c, c, c],
-                    true,
-                    ImageUsage::COLOR_ATTACHMENT,
-                    window_index,
-                    self.windows.len(),
-                )?)),
+    fn create_renderers(&self) -> Result<Renderers> {
+        self.windows
+            .iter()
+            .enumerate()
+            .map(|(window_index, (window_id, window))| {
+                Ok((
+                    *window_id,
+                    self.create_renderer(window_index, *window_id, window)?,
+                ))
+            })
+            .collect()
+    }
+
+    // Every renderer is set up the same way on startup and when resuming.
+    fn create_renderer(
+        &self,
+        window_index: usize,
+        window_id: WindowId,
+        window: &Arc<Window>,
+    ) -> Result<VulkanRenderer> {
+        let gpu = &self.gpus[self.window_gpus[&window_id]];
+        let is_overlay = self.overlay.is_some() && window_id != self.primary_window_id;
+        let mut vulkan_renderer = VulkanRenderer::new(
+            Arc::clone(&gpu.vulkan_device),
+            Arc::clone(window),
+            self.benchmark_settings.is_none(),
+            self.swapchain_format,
+            self.output_color_space,
+            // Overlays clear to fully transparent unless a background opacity is configured.
+            if is_overlay {
+                Some(self.background_alpha.unwrap_or(0.0))
+            } else {
+                self.background_alpha
+            },
+            // Blitted into when every post-processing effect is off.
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
+        )?;
+        let tonemap_settings = &mut vulkan_renderer
+            .post_process_stack_mut()
+            .effect_mut::<TonemapEffect>()
+            .unwrap()
+            .settings;
+        tonemap_settings.color_lut = gpu.color_lut.clone();
+        tonemap_settings.working_color_space = self.working_color_space;
+        vulkan_renderer.set_environment(gpu.environment.clone())?;
+        vulkan_renderer.set_foliage(gpu.foliage.clone());
+        vulkan_renderer.set_lightmap(gpu.lightmap.clone())?;
+        vulkan_renderer.set_sh_probes(gpu.sh_probes.clone())?;
+        for (capacity, settings) in &self.particle_emitters {
+            vulkan_renderer.add_particle_system(*capacity, *settings)?;
+        }
+        vulkan_renderer.set_mesh_shading(self.mesh_shading);
+        vulkan_renderer.set_vertex_pulling(self.vertex_pulling);
+        if let Some(scene) = &self.scene {
+            vulkan_renderer.select_scene(find_scene(gpu.vulkan_device.scenes(), scene)?)?;
+        }
+        if let Some(scene_file) = &self.scene_file {
+            vulkan_renderer.apply_scene_file(scene_file)?;
+        }
+        vulkan_renderer.set_ray_tracing(self.ray_tracing);
+        vulkan_renderer.set_path_tracing(self.path_tracing)?;
+        vulkan_renderer.set_stereo(self.stereo)?;
+        vulkan_renderer.parallel_recording_settings_mut().enabled = self.parallel_recording;
+        vulkan_renderer.parallel_recording_settings_mut().reuse = self.reuse_scene_commands;
+        vulkan_renderer.set_wireframe(self.wireframe);
+        vulkan_renderer.set_color_audit(self.color_audit);
+        vulkan_renderer.set_background(
+            self.backgrounds
+                .get(window_index)
+                .or(self.backgrounds.last())
+                .copied()
+                .unwrap_or_default(),
+        );
+        vulkan_renderer.grid_settings_mut().enabled = self.grid;
+        vulkan_renderer.set_gizmo(self.gizmo.then(|| Gizmo::new(Matrix4::identity())));
+        vulkan_renderer.frustum_visualization_settings_mut().enabled = self.frusta;
+        vulkan_renderer.light_visualization_settings_mut().enabled = self.light_visualization;
+        vulkan_renderer.set_debug_view(self.debug_view);
+        vulkan_renderer.shadow_map_settings_mut().quality = self.shadow_quality;
+        vulkan_renderer.set_split_viewports(self.split_screen.map_or_else(Vec::new, |layout| {
+            layout.viewports(
+                gpu.vulkan_device.camera_position(),
+                gpu.vulkan_device.camera_target(),
+                &self.split_screen_debug_views,
+            )
+        }));
+        vulkan_renderer.set_minimap(
+            self.minimap
+                .map(|settings| Minimap::new(&gpu.vulkan_device, settings))
+                .transpose()?,
+        );
+        vulkan_renderer.set_picture_in_picture(
+            self.picture_in_picture_camera
+                .as_deref()
+                .map(|name| {
+                    PictureInPicture::new(
+                        &gpu.vulkan_device,
+                        find_scene_camera(gpu.vulkan_device.scene_cameras(), name)?,
+                        Default::default(),
+                    )
+                })
+                .transpose()?,
+        );
+        if let Some((face_size, bounds)) = self
+            .reflection_probe_size
+            .zip(vulkan_renderer.scene_graph().world_bounds())
+        {
+            vulkan_renderer.add_reflection_probe(ReflectionProbeSettings {
+                face_size,
+                ..ReflectionProbeSettings::new(bounds)
+            })?;
+        }
+        vulkan_renderer.set_transparency_mode(self.transparency_mode);
+        if window_id == self.primary_window_id {
+            vulkan_renderer.set_frame_capture(
+                self.capture_settings
+                    .clone()
+                    .map(FrameCapture::new)
+                    .transpose()?,
+            );
+            vulkan_renderer.set_benchmark(
+                self.benchmark_settings
+                    .clone()
+                    .map(|settings| Benchmark::new(&gpu.vulkan_device, settings))
+                    .transpose()?,
             );
         }
-        Ok(())
+        Ok(vulkan_renderer)
     }
 
-    pub fn suspend(&mut self) {
-        self.vulkan_renderers.clear();
+    // Android destroys the native window right after suspending, so every swapchain has to be idle
+    // and dropped before returning. Dropping a renderer releases its swapchain before its surface.
+    pub fn suspend(&mut self) -> Result<()> {
+        let primary_window_id = self.primary_window_id;
+        let scene_file = self.renderers.query(move |renderers| {
+            for vulkan_renderer in renderers.values_mut() {
+                vulkan_renderer.wait_for_frames()?;
+            }
+            // Renderers are recreated on resume, so keep what was changed in the scene meanwhile.
+            let scene_file = renderers
+                .get(&primary_window_id)
+                .map(VulkanRenderer::scene_file);
+            renderers.clear();
+            Ok(scene_file)
+        })?;
+        if scene_file.is_some() {
+            self.scene_file = scene_file;
+        }
+        // The process may be killed while in the background without a chance to close cleanly.
+        self.gpus[0].vulkan_device.save_pipeline_cache()?;
+        Ok(())
     }
 
     pub fn process_window_event(
@@ -103,21 +724,628 @@ impl VisualSystem {
         window_id: WindowId,
     ) -> Result<bool> {
         match event {
-            WindowEvent::CloseRequested if self.primary_window_id == window_id => return Ok(true),
-            WindowEvent::Resized(_) => self.vulkan_renderers[&window_id].borrow_mut().recreate()?,
+            WindowEvent::CloseRequested if self.primary_window_id == window_id => {
+                // Every device would write the same cache file, so only the primary one keeps its cache.
+                self.gpus[0].vulkan_device.save_pipeline_cache()?;
+                if let Some(metrics) = &self.metrics {
+                    metrics.write()?;
+                }
+                return Ok(true);
+            }
+            // Renderers take the new size before their next frame. Drawing it right away keeps up
+            // with an interactive resize, which can hold off `AboutToWait` until it's over.
+            WindowEvent::Resized(size) => {
+                let window = &self.windows[&window_id];
+                // A resize can also mean the window moved to a display with another refresh rate.
+                let monitor_refresh_interval = monitor_refresh_interval(window);
+                self.renderers.run_on(window_id, move |vulkan_renderer| {
+                    vulkan_renderer.set_window_size(size.into());
+                    vulkan_renderer.set_monitor_refresh_interval(monitor_refresh_interval);
+                    Ok(())
+                })?;
+                window.request_redraw();
+            }
+            WindowEvent::Moved(_) => {
+                let monitor_refresh_interval = monitor_refresh_interval(&self.windows[&window_id]);
+                self.renderers.run_on(window_id, move |vulkan_renderer| {
+                    vulkan_renderer.set_monitor_refresh_interval(monitor_refresh_interval);
+                    Ok(())
+                })?;
+            }
             WindowEvent::RedrawRequested => {
-                self.vulkan_renderers[&window_id].borrow_mut().render()?
+                // A render thread doesn't wait for redraw requests.
+                if let RendererHost::Local(renderers) = &mut self.renderers {
+                    let vulkan_renderer = renderers.get_mut(&window_id).unwrap();
+                    vulkan_renderer.render()?;
+                    let frame_report = FrameReport::new(window_id, vulkan_renderer);
+                    self.on_frame(&frame_report)?;
+                }
             }
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
             WindowEvent::CursorMoved { position, .. } => {
-                self.vulkan_renderers[&window_id]
-                    .borrow_mut()
-                    .on_mouse_moved(position);
+                self.renderers.run_on(window_id, move |vulkan_renderer| {
+                    vulkan_renderer.on_mouse_moved(position);
+                    Ok(())
+                })?;
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.renderers.run_on(window_id, move |vulkan_renderer| {
+                    vulkan_renderer.on_mouse_input(state, button);
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F1),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.wireframe = !self.wireframe;
+                let wireframe = self.wireframe;
+                self.renderers.run_on_all(move |vulkan_renderer| {
+                    vulkan_renderer.set_wireframe(wireframe);
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F2),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let grid_settings = vulkan_renderer.grid_settings_mut();
+                    grid_settings.enabled = !grid_settings.enabled;
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F3),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    if let Some(gizmo) = vulkan_renderer.gizmo_mut() {
+                        gizmo.set_mode(match gizmo.mode() {
+                            GizmoMode::Translate => GizmoMode::Rotate,
+                            GizmoMode::Rotate => GizmoMode::Scale,
+                            GizmoMode::Scale => GizmoMode::Translate,
+                        });
+                    }
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F4),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let settings = vulkan_renderer.normal_visualization_settings_mut();
+                    settings.enabled = !settings.enabled;
+                    settings.tangents = settings.enabled;
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F5),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let debug_view = vulkan_renderer.debug_view().next();
+                    vulkan_renderer.set_debug_view(debug_view);
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F6),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    vulkan_renderer.save_gif()?;
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F7),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let memory_report = self.renderers.query_on(window_id, |vulkan_renderer| {
+                    Ok(vulkan_renderer.memory_report())
+                })?;
+                info!("GPU memory:\n{memory_report}");
+                memory_report.warn_if_near_budget();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F8),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                info!(
+                    "Allocator statistics:\n{}",
+                    self.gpus[self.window_gpus[&window_id]]
+                        .vulkan_device
+                        .allocator_statistics()
+                );
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F9),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let window = &self.windows[&window_id];
+                WindowMode::of(window).next().apply(window);
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F10),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    vulkan_renderer.swap_picture_in_picture();
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F11),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                // Every device loads the same document, so they all have the same scenes.
+                let scenes = self.gpus[self.window_gpus[&window_id]]
+                    .vulkan_device
+                    .scenes();
+                let selected_scene = self.renderers.query_on(window_id, |vulkan_renderer| {
+                    Ok(vulkan_renderer.selected_scene())
+                })?;
+                let index = (selected_scene + 1) % scenes.len().max(1);
+                if index != selected_scene {
+                    info!("Showing scene {}", scenes[index].name);
+                    self.renderers
+                        .run_on_all(move |vulkan_renderer| vulkan_renderer.select_scene(index))?;
+                    self.scene = Some(index.to_string());
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F12),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let scene_file = self.renderers.query_on(window_id, |vulkan_renderer| {
+                    Ok(vulkan_renderer.scene_file())
+                })?;
+                match scene_file.save(&self.scene_file_path) {
+                    Ok(()) => info!("Saved the scene to {}", self.scene_file_path),
+                    Err(error) => warn!("{error:#}"),
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let contact_shadow_settings = vulkan_renderer.contact_shadow_settings_mut();
+                    contact_shadow_settings.enabled = !contact_shadow_settings.enabled;
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let frustum_visualization_settings =
+                        vulkan_renderer.frustum_visualization_settings_mut();
+                    frustum_visualization_settings.enabled =
+                        !frustum_visualization_settings.enabled;
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyI),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let pixel_inspector = vulkan_renderer.pixel_inspector_mut();
+                    pixel_inspector.set_enabled(!pixel_inspector.enabled());
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyL),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let light_visualization_settings =
+                        vulkan_renderer.light_visualization_settings_mut();
+                    light_visualization_settings.enabled = !light_visualization_settings.enabled;
+                    Ok(())
+                })?;
+            }
+            // Switches between soft and plain filtered shadow map edges.
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let shadow_map_settings = vulkan_renderer.shadow_map_settings_mut();
+                    shadow_map_settings.soft = !shadow_map_settings.soft;
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyR),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    vulkan_renderer.capture_reflection_probes();
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyV),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let vsync = !vulkan_renderer.vsync();
+                    info!("Vsync {}", if vsync { "on" } else { "off" });
+                    vulkan_renderer.set_vsync(vsync)
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyA),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.color_audit = !self.color_audit;
+                let color_audit = self.color_audit;
+                self.renderers.run_on_all(move |vulkan_renderer| {
+                    vulkan_renderer.set_color_audit(color_audit);
+                    Ok(())
+                })?;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyB),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderers.run_on(window_id, |vulkan_renderer| {
+                    let background = vulkan_renderer.background().next();
+                    info!("Background {background:?}");
+                    vulkan_renderer.set_background(background);
+                    Ok(())
+                })?;
+            }
+            // A number key bookmarks the camera, Shift with it jumps back. Saving the scene file
+            // keeps the bookmarks.
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key_code),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(slot) = camera_bookmark_slot(key_code) {
+                    let recall = self.modifiers.shift_key();
+                    self.renderers.run_on(window_id, move |vulkan_renderer| {
+                        if recall {
+                            if !vulkan_renderer.recall_camera_bookmark(slot) {
+                                info!("No camera bookmarked in slot {}", slot + 1);
+                            }
+                        } else {
+                            vulkan_renderer.save_camera_bookmark(slot);
+                            info!("Bookmarked the camera in slot {}", slot + 1);
+                        }
+                        Ok(())
+                    })?;
+                }
             }
             _ => {}
         };
         Ok(false)
     }
 
+    fn on_frame(&mut self, frame_report: &FrameReport) -> Result<()> {
+        let window_id = frame_report.window_id;
+        if !frame_report.surface_lost {
+            self.replaced_surfaces.remove(&window_id);
+        } else if self.replaced_surfaces.insert(window_id) {
+            let instance = self.gpus[self.window_gpus[&window_id]]
+                .vulkan_device
+                .queue()
+                .device()
+                .instance();
+            let surface =
+                Surface::from_window(Arc::clone(instance), Arc::clone(&self.windows[&window_id]))?;
+            self.renderers.run_on(window_id, move |vulkan_renderer| {
+                vulkan_renderer.replace_surface(surface)
+            })?;
+        }
+        self.window_titles.get_mut(&window_id).unwrap().update(
+            &self.windows[&window_id],
+            self.gpus[self.window_gpus[&window_id]]
+                .vulkan_device
+                .device_name(),
+            &frame_report.frame_metrics,
+            frame_report.pixel_sample.as_ref(),
+        );
+        if let Some(metrics) = self
+            .metrics
+            .as_mut()
+            .filter(|_| window_id == self.primary_window_id)
+        {
+            metrics.push(frame_report.frame_metrics);
+        }
+
+        Ok(())
+    }
+
+    pub fn has_render_thread(&self) -> bool {
+        self.renderers.is_threaded()
+    }
+
+    // Takes the frames the render thread has finished since the last wakeup and simulates the next
+    // one. Returns true once the benchmark is done.
+    pub fn process_frame_reports(&mut self) -> Result<bool> {
+        let RendererHost::Threaded(render_thread) = &mut self.renderers else {
+            return Ok(false);
+        };
+        let frame_reports = render_thread.take_reports()?;
+        if frame_reports.is_empty() {
+            return Ok(false);
+        }
+
+        for frame_report in &frame_reports {
+            self.on_frame(frame_report)?;
+        }
+        self.update()?;
+        if frame_reports.iter().any(|frame_report| {
+            frame_report.window_id == self.primary_window_id && frame_report.benchmark_finished
+        }) {
+            return self.finish_benchmark();
+        }
+        Ok(false)
+    }
+
+    pub fn update(&mut self) -> Result<()> {
+        // A render thread paces its own frames.
+        if let RendererHost::Local(renderers) = &mut self.renderers {
+            for vulkan_renderer in renderers.values_mut() {
+                vulkan_renderer.wait_for_previous_present()?;
+            }
+            // Windows on other displays follow the primary window's cadence.
+            if let Some(vulkan_renderer) = renderers.get(&self.primary_window_id) {
+                self.frame_pacer
+                    .set_refresh_interval(vulkan_renderer.refresh_interval());
+            }
+            self.frame_pacer.pace();
+        }
+
+        let now = Instant::now();
+        let (time, delta_time) = match &self.capture_settings {
+            Some(capture_settings) => {
+                let delta_time = 1.0 / capture_settings.frame_rate as f32;
+                (self.update_count as f32 * delta_time, delta_time)
+            }
+            None => (
+                (now - self.start_time).as_secs_f32(),
+                (now - self.previous_update_time).as_secs_f32(),
+            ),
+        };
+        self.previous_update_time = now;
+        self.update_count += 1;
+
+        if let Some(script) = &mut self.script {
+            script.update(time, delta_time)?;
+        }
+
+        #[cfg(feature = "physics")]
+        if let Some(physics) = &mut self.physics {
+            if physics.update(delta_time) > 0 {
+                let snapshot = physics.snapshot();
+                self.renderers.run_on_all(move |vulkan_renderer| {
+                    let dragged_node = vulkan_renderer.dragged_node();
+                    snapshot.write_back(vulkan_renderer.scene_graph_mut(), dragged_node);
+                    Ok(())
+                })?;
+            }
+        }
+
+        let mut commands = self
+            .script
+            .as_ref()
+            .map_or_else(Vec::new, ScriptHost::drain_commands);
+        if let Some(simulation) = &mut self.simulation {
+            let (snapshot, simulation_commands) = simulation.latest()?;
+            #[cfg(feature = "physics")]
+            if let Some(physics) = snapshot.physics {
+                self.renderers.run_on_all(move |vulkan_renderer| {
+                    let dragged_node = vulkan_renderer.dragged_node();
+                    physics.write_back(vulkan_renderer.scene_graph_mut(), dragged_node);
+                    Ok(())
+                })?;
+            }
+            commands.extend(simulation_commands);
+            commands.extend(snapshot.debug_draw);
+        }
+        self.apply_script_commands(commands)
+    }
+
+    fn apply_script_commands(&mut self, commands: Vec<ScriptCommand>) -> Result<()> {
+        if commands.is_empty() {
+            return Ok(());
+        }
+        for command in &commands {
+            if let ScriptCommand::SelectScene(index) = command {
+                self.scene = Some(index.to_string());
+            }
+        }
+
+        self.renderers.run_on_all(move |vulkan_renderer| {
+            for &command in &commands {
+                match command {
+                    ScriptCommand::SetEnvironmentIntensity(intensity) => {
+                        vulkan_renderer.set_environment_intensity(intensity)
+                    }
+                    ScriptCommand::SetSun(sun) => vulkan_renderer.set_sun(Some(sun))?,
+                    ScriptCommand::SetWireframe(enabled) => vulkan_renderer.set_wireframe(enabled),
+                    ScriptCommand::SetGrid(enabled) => {
+                        vulkan_renderer.grid_settings_mut().enabled = enabled
+                    }
+                    ScriptCommand::SetDebugView(debug_view) => {
+                        vulkan_renderer.set_debug_view(debug_view)
+                    }
+                    ScriptCommand::DrawLine(start, end, color) => {
+                        vulkan_renderer.debug_draw_mut().line(&start, &end, color)
+                    }
+                    ScriptCommand::DrawSphere(center, radius, color) => {
+                        vulkan_renderer.debug_draw_mut().sphere(&center, radius, color)
+                    }
+                    ScriptCommand::SelectScene(index) => vulkan_renderer.select_scene(index)?,
+                }
+            }
+            Ok(())
+        })
+    }
+
+    pub fn finish_benchmark(&mut self) -> Result<bool> {
+        let primary_window_id = self.primary_window_id;
+        let finished = self.renderers.query(move |renderers| {
+            match renderers
+                .get(&primary_window_id)
+                .and_then(VulkanRenderer::benchmark)
+            {
+                Some(benchmark) if benchmark.is_finished() => {
+                    benchmark.write_report()?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        })?;
+        if finished {
+            if let Some(metrics) = &self.metrics {
+                metrics.write()?;
+            }
+        }
+        Ok(finished)
+    }
+
     pub fn request_redraw(&self) {
         self.windows
             .iter()
@@ -125,9 +1353,45 @@ impl VisualSystem {
     }
 }
 
+// Parses a `true`/`false` environment variable, defaulting to `false` when it's unset.
+pub fn env_flag(name: &str) -> Result<bool> {
+    Ok(std::env::var(name)
+        .ok()
+        .map(|value| value.parse())
+        .transpose()?
+        .unwrap_or(false))
+}
+
+fn camera_bookmark_slot(key_code: KeyCode) -> Option<usize> {
+    [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ]
+    .iter()
+    .position(|&digit| digit == key_code)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LifecycleState {
+    NotStarted,
+    Running,
+    Suspended,
+}
+
 pub struct App {
-    is_started: bool,
+    state: LifecycleState,
     visual_system: Option<VisualSystem>,
+    benchmark_settings: Option<BenchmarkSettings>,
+    device_selection: DeviceSelection,
+    scene: Option<String>,
+    event_loop_proxy: EventLoopProxy<()>,
 }
 
 impl App {
@@ -136,27 +1400,71 @@ impl App {
         event: Event<()>,
         window_target: &EventLoopWindowTarget<()>,
     ) -> Result<()> {
-        match event {
-            Event::WindowEvent { event, window_id } => {
-                if self
+        match (self.state, event) {
+            (LifecycleState::Running, Event::WindowEvent { event, window_id }) => {
+                let exit = self
                     .visual_system
                     .as_mut()
                     .unwrap()
-                    .process_window_event(event, window_id)?
-                {
+                    .process_window_event(event, window_id)?;
+                if exit {
                     window_target.exit()
                 }
             }
-            Event::Resumed => {
-                if self.is_started {
-                    self.resume(window_target)?
-                } else {
-                    self.is_started = true;
-                    self.start(window_target)?
+            // Without renderers only closing makes sense; redraws and resizes wait for the resume.
+            (
+                LifecycleState::Suspended,
+                Event::WindowEvent {
+                    event: event @ WindowEvent::CloseRequested,
+                    window_id,
+                },
+            ) => {
+                let exit = self
+                    .visual_system
+                    .as_mut()
+                    .unwrap()
+                    .process_window_event(event, window_id)?;
+                if exit {
+                    window_target.exit()
                 }
             }
-            Event::Suspended => self.suspend(),
-            Event::AboutToWait => self.visual_system.as_mut().unwrap().request_redraw(),
+            (LifecycleState::NotStarted, Event::Resumed) => {
+                self.start(window_target)?;
+                self.state = LifecycleState::Running;
+            }
+            (LifecycleState::Suspended, Event::Resumed) => {
+                self.resume()?;
+                self.state = LifecycleState::Running;
+            }
+            (LifecycleState::Running, Event::Suspended) => {
+                self.suspend()?;
+                self.state = LifecycleState::Suspended;
+            }
+            // The render thread wakes the event loop after each round of frames.
+            (LifecycleState::Running, Event::UserEvent(())) => {
+                let exit = self
+                    .visual_system
+                    .as_mut()
+                    .unwrap()
+                    .process_frame_reports()?;
+                if exit {
+                    window_target.exit()
+                }
+            }
+            (LifecycleState::Running, Event::AboutToWait) => {
+                let visual_system = self.visual_system.as_mut().unwrap();
+                if visual_system.has_render_thread() {
+                    return Ok(());
+                }
+                visual_system.update()?;
+                if visual_system.finish_benchmark()? {
+                    window_target.exit()
+                }
+                visual_system.request_redraw();
+            }
+            (state, event @ (Event::Resumed | Event::Suspended)) => {
+                warn!("Ignoring {event:?} while {state:?}");
+            }
             _ => {}
         }
         Ok(())
@@ -164,24 +1472,39 @@ impl App {
 }
 
 impl App {
-    pub fn new<T>(event_loop: &EventLoop<T>) -> Result<Self> {
+    pub fn new(
+        event_loop: &EventLoop<()>,
+        benchmark_settings: Option<BenchmarkSettings>,
+        device_selection: DeviceSelection,
+        scene: Option<String>,
+    ) -> Result<Self> {
         Ok(Self {
-            is_started: false,
+            state: LifecycleState::NotStarted,
             visual_system: None,
+            benchmark_settings,
+            device_selection,
+            scene,
+            event_loop_proxy: event_loop.create_proxy(),
         })
     }
 
     pub fn start<T>(&mut self, window_target: &EventLoopWindowTarget<T>) -> Result<()> {
-        self.visual_system = Some(VisualSystem::new(window_target)?);
+        self.visual_system = Some(VisualSystem::new(
+            window_target,
+            self.benchmark_settings.clone(),
+            &self.device_selection,
+            self.scene.clone(),
+            self.event_loop_proxy.clone(),
+        )?);
         Ok(())
     }
 
-    pub fn resume<T>(&mut self, window_target: &EventLoopWindowTarget<T>) -> Result<()> {
-        self.visual_system.as_mut().unwrap().resume(window_target)?;
+    pub fn resume(&mut self) -> Result<()> {
+        self.visual_system.as_mut().unwrap().resume()?;
         Ok(())
     }
 
-    pub fn suspend(&mut self) {
-        self.visual_system.as_mut().unwrap().suspend();
+    pub fn suspend(&mut self) -> Result<()> {
+        self.visual_system.as_mut().unwrap().suspend()
     }
 }
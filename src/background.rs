@@ -0,0 +1,277 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::device::Device;
+use vulkano::image::view::ImageView;
+use vulkano::image::SampleCount;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+use vulkano::shader::EntryPoint;
+
+use crate::fullscreen;
+use crate::vulkan_device::HDR_FORMAT;
+
+// Every background shader takes the same push constants as the gradient, with `mousePosition`
+// in 0..1 across the window.
+mod gradient_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(push_constant) uniform BackgroundParameters {
+                    float time;
+                    vec2 mousePosition;
+                    float aspectRatio;
+                } parameters;
+
+                void main() {
+                    // The horizon follows the cursor up and down.
+                    float height = clamp(1.0 - uv.y + (0.5 - parameters.mousePosition.y) * 0.5, 0.0, 1.0);
+                    vec3 color = mix(vec3(0.02, 0.02, 0.03), vec3(0.15, 0.25, 0.45), smoothstep(0.0, 1.0, height));
+                    outColor = vec4(color, 1.0);
+                }
+            ",
+    }
+}
+
+mod plasma_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(push_constant) uniform BackgroundParameters {
+                    float time;
+                    vec2 mousePosition;
+                    float aspectRatio;
+                } parameters;
+
+                void main() {
+                    vec2 scale = vec2(parameters.aspectRatio, 1.0) * 4.0;
+                    vec2 position = (uv - 0.5) * scale;
+                    vec2 mouse = (parameters.mousePosition - 0.5) * scale;
+                    float time = parameters.time;
+                    float value = sin(position.x + time)
+                        + sin(position.y * 1.3 - time * 0.7)
+                        + sin(length(position - mouse) * 2.0 - time * 1.5)
+                        + sin(length(position) * 1.5 + time * 0.5);
+                    vec3 color = 0.5 + 0.5 * cos(value * 1.2 + vec3(0.0, 2.1, 4.2));
+                    // Squared to roughly linearize, and dimmed so the scene stands out.
+                    outColor = vec4(color * color * 0.25, 1.0);
+                }
+            ",
+    }
+}
+
+mod ripples_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(push_constant) uniform BackgroundParameters {
+                    float time;
+                    vec2 mousePosition;
+                    float aspectRatio;
+                } parameters;
+
+                void main() {
+                    float distance = length((uv - parameters.mousePosition) * vec2(parameters.aspectRatio, 1.0));
+                    float wave = 0.5 + 0.5 * sin(distance * 40.0 - parameters.time * 4.0);
+                    vec3 color = mix(vec3(0.01, 0.015, 0.03), vec3(0.05, 0.12, 0.2), wave * exp(-distance * 3.0));
+                    outColor = vec4(color, 1.0);
+                }
+            ",
+    }
+}
+
+// What fills the parts of a window the scene doesn't cover when there's no skybox.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Background {
+    // The flat clear color.
+    #[default]
+    Clear,
+    Gradient,
+    Plasma,
+    // Rings spreading out from the cursor.
+    Ripples,
+}
+
+impl FromStr for Background {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "clear" | "none" => Background::Clear,
+            "gradient" => Background::Gradient,
+            "plasma" => Background::Plasma,
+            "ripples" => Background::Ripples,
+            _ => bail!("Unknown background: {value}"),
+        })
+    }
+}
+
+impl Background {
+    const ALL: [Background; 4] = [
+        Background::Clear,
+        Background::Gradient,
+        Background::Plasma,
+        Background::Ripples,
+    ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&background| background == self);
+        Self::ALL[(index.unwrap() + 1) % Self::ALL.len()]
+    }
+}
+
+// Draws a background shader into the scene's color target in a pass of its own, which the scene
+// then loads instead of clearing. Adding a background takes a fragment shader taking the
+// gradient's push constants, and an entry in `new`.
+pub struct BackgroundPass {
+    pipelines: Vec<(Background, Arc<GraphicsPipeline>)>,
+}
+
+impl BackgroundPass {
+    pub fn new(device: &Arc<Device>, samples: SampleCount) -> Result<Self> {
+        let shaders = [
+            (Background::Gradient, gradient_fs::load(Arc::clone(device))?),
+            (Background::Plasma, plasma_fs::load(Arc::clone(device))?),
+            (Background::Ripples, ripples_fs::load(Arc::clone(device))?),
+        ];
+        let pipelines = shaders
+            .into_iter()
+            .map(|(background, module)| {
+                let fragment_shader = module.entry_point("main").unwrap();
+                Ok((
+                    background,
+                    create_pipeline(device, fragment_shader, samples)?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { pipelines })
+    }
+
+    // Covers the viewport, so split-screen cells can each have their own.
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        background: Background,
+        target: &Arc<ImageView>,
+        viewport: &Viewport,
+        time: f32,
+        mouse_position: [f32; 2],
+    ) -> Result<()> {
+        let pipeline = self
+            .pipelines
+            .iter()
+            .find(|(pipeline_background, _)| *pipeline_background == background)
+            .map(|(_, pipeline)| pipeline)
+            .with_context(|| format!("No background shader for {background:?}"))?;
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::DontCare,
+                    store_op: AttachmentStoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(target))
+                })],
+                render_area_offset: viewport.offset.map(|offset| offset as u32),
+                render_area_extent: viewport.extent.map(|extent| extent as u32),
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport.clone()].into_iter().collect())?
+            .bind_pipeline_graphics(Arc::clone(pipeline))?
+            .push_constants(
+                Arc::clone(pipeline.layout()),
+                0,
+                gradient_fs::BackgroundParameters {
+                    time: time.into(),
+                    mousePosition: mouse_position,
+                    aspectRatio: viewport.extent[0] / viewport.extent[1],
+                },
+            )?;
+        unsafe { builder.draw(3, 1, 0, 0) }?;
+        builder.end_rendering()?;
+
+        Ok(())
+    }
+}
+
+fn create_pipeline(
+    device: &Arc<Device>,
+    fragment_shader: EntryPoint,
+    samples: SampleCount,
+) -> Result<Arc<GraphicsPipeline>> {
+    let vertex_shader = fullscreen::vs::load(Arc::clone(device))?
+        .entry_point("main")
+        .unwrap();
+
+    let stages = [
+        PipelineShaderStageCreateInfo::new(vertex_shader),
+        PipelineShaderStageCreateInfo::new(fragment_shader),
+    ];
+
+    let layout = PipelineLayout::new(
+        Arc::clone(device),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(Arc::clone(device))
+            .unwrap(),
+    )?;
+
+    let subpass = PipelineRenderingCreateInfo {
+        color_attachment_formats: vec![Some(HDR_FORMAT)],
+        ..Default::default()
+    };
+
+    Ok(GraphicsPipeline::new(
+        Arc::clone(device),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            vertex_input_state: Some(VertexInputState::default()),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            viewport_state: Some(ViewportState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState {
+                rasterization_samples: samples,
+                ..Default::default()
+            }),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                1,
+                ColorBlendAttachmentState::default(),
+            )),
+            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )?)
+}
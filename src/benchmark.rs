@@ -0,0 +1,480 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{anyhow, Context, Result};
+use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, Vector3};
+use tracing::info;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType};
+use vulkano::sync::PipelineStage;
+
+use crate::scene_graph::{SceneGraph, TraversalSettings};
+use crate::vulkan_device::VulkanDevice;
+
+// Each frame writes a begin/end timestamp pair; reading a slot back only after this many
+// frames keeps the wait on its results from stalling the frames still in flight.
+const QUERY_SLOTS: u32 = 4;
+
+#[derive(Clone, Debug)]
+pub struct BenchmarkSettings {
+    pub frames: u32,
+    pub warmup_frames: u32,
+    pub report_path: PathBuf,
+}
+
+impl Default for BenchmarkSettings {
+    fn default() -> Self {
+        Self {
+            frames: 1000,
+            warmup_frames: 60,
+            report_path: "benchmark.json".into(),
+        }
+    }
+}
+
+impl BenchmarkSettings {
+    // Accepts `--benchmark [frames]` and `--benchmark-report <path>`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>> {
+        let mut settings = None;
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--benchmark" => {
+                    let settings = settings.get_or_insert_with(Self::default);
+                    if let Some(frames) = args.next_if(|arg| !arg.starts_with("--")) {
+                        settings.frames = frames.parse()?;
+                    }
+                }
+                "--benchmark-report" => {
+                    settings.get_or_insert_with(Self::default).report_path = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--benchmark-report needs a path"))?
+                        .into();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TraversalBenchmarkSettings {
+    pub scene_path: PathBuf,
+    pub iterations: u32,
+    pub report_path: PathBuf,
+}
+
+impl TraversalBenchmarkSettings {
+    // Accepts `--traversal-benchmark <scene> [iterations]` and `--benchmark-report <path>`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>> {
+        let mut settings = None;
+        let mut report_path = None;
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--traversal-benchmark" => {
+                    let scene_path = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--traversal-benchmark needs a glTF scene"))?;
+                    let iterations = args
+                        .next_if(|arg| !arg.starts_with("--"))
+                        .map(|iterations| iterations.parse())
+                        .transpose()?
+                        .unwrap_or(100);
+                    settings = Some((scene_path.into(), iterations));
+                }
+                "--benchmark-report" => {
+                    report_path = args.next().map(PathBuf::from);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(settings.map(|(scene_path, iterations)| Self {
+            scene_path,
+            iterations,
+            report_path: report_path.unwrap_or_else(|| "traversal_benchmark.json".into()),
+        }))
+    }
+}
+
+// Times world-transform propagation and frustum culling on one thread and on rayon's pool, with
+// the camera orbiting the scene so the visible set changes between iterations.
+pub fn run_traversal_benchmark(settings: &TraversalBenchmarkSettings) -> Result<()> {
+    let document = gltf::Gltf::open(&settings.scene_path)
+        .with_context(|| format!("Failed to open {}", settings.scene_path.display()))?
+        .document;
+    let mut scene_graph = SceneGraph::from_gltf(&document);
+    let bounds = scene_graph
+        .world_bounds()
+        .context("The scene has no meshes to cull")?;
+    let center = bounds.center();
+    let radius = (bounds.max - center).norm().max(f32::EPSILON);
+    let camera_path = CameraPath::orbit(&(center + Vector3::new(radius, radius, radius)), &center);
+    let projection = Perspective3::new(
+        800.0 / 600.0,
+        70.0f32.to_radians(),
+        radius * 0.01,
+        radius * 8.0,
+    );
+    info!(
+        "Traversing {} nodes, {} iterations per mode",
+        scene_graph.node_count(),
+        settings.iterations
+    );
+
+    let mut modes = Vec::new();
+    for (name, parallel) in [("sequential", false), ("parallel", true)] {
+        let traversal_settings = TraversalSettings {
+            parallel,
+            min_parallel_nodes: 0,
+        };
+        let mut update_times = Vec::with_capacity(settings.iterations as usize);
+        let mut cull_times = Vec::with_capacity(settings.iterations as usize);
+
+        for iteration in 0..settings.iterations {
+            let eye = camera_path.eye(iteration as f32 / settings.iterations as f32);
+            let view_projection = projection.as_matrix()
+                * Isometry3::look_at_rh(&eye, camera_path.target(), &Vector3::y()).to_homogeneous();
+
+            let start = Instant::now();
+            scene_graph.update_world_transforms(&traversal_settings);
+            let updated = Instant::now();
+            std::hint::black_box(scene_graph.cull(&view_projection, &traversal_settings));
+            let culled = Instant::now();
+
+            update_times.push((updated - start).as_secs_f32() * 1000.0);
+            cull_times.push((culled - updated).as_secs_f32() * 1000.0);
+        }
+
+        let mut fields = Vec::new();
+        for (field, samples) in [("update_ms", &update_times), ("cull_ms", &cull_times)] {
+            let Some(statistics) = FrameTimeStatistics::from_samples(samples) else {
+                continue;
+            };
+            info!(
+                "{name} {field}: min {:.3} avg {:.3} p95 {:.3} max {:.3}",
+                statistics.min, statistics.average, statistics.p95, statistics.max
+            );
+            fields.push(format!(r#""{field}": {}"#, statistics.to_json()));
+        }
+        modes.push(format!(r#"  "{name}": {{ {} }}"#, fields.join(", ")));
+    }
+
+    let report = format!(
+        "{{\n  \"nodes\": {},\n  \"iterations\": {},\n{}\n}}\n",
+        scene_graph.node_count(),
+        settings.iterations,
+        modes.join(",\n")
+    );
+    std::fs::write(&settings.report_path, report)?;
+    info!("Wrote {}", settings.report_path.display());
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTimeStatistics {
+    pub min: f32,
+    pub average: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+impl FrameTimeStatistics {
+    pub fn from_samples(samples: &[f32]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+
+        Some(Self {
+            min: sorted[0],
+            average: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+
+    fn to_json(self) -> String {
+        format!(
+            r#"{{ "min": {}, "average": {}, "p95": {}, "p99": {}, "max": {} }}"#,
+            self.min, self.average, self.p95, self.p99, self.max
+        )
+    }
+}
+
+pub struct CameraPath {
+    target: Point3<f32>,
+    radius: f32,
+    height: f32,
+    start_angle: f32,
+}
+
+impl CameraPath {
+    pub fn orbit(eye: &Point3<f32>, target: &Point3<f32>) -> Self {
+        let offset = eye - target;
+        Self {
+            target: *target,
+            radius: offset.xz().norm(),
+            height: offset.y,
+            start_angle: offset.z.atan2(offset.x),
+        }
+    }
+
+    // `t` runs from 0 to 1 over one full revolution.
+    pub fn eye(&self, t: f32) -> Point3<f32> {
+        let angle = self.start_angle + t * std::f32::consts::TAU;
+        self.target
+            + Vector3::new(
+                angle.cos() * self.radius,
+                self.height,
+                angle.sin() * self.radius,
+            )
+    }
+
+    pub fn target(&self) -> &Point3<f32> {
+        &self.target
+    }
+}
+
+pub struct Benchmark {
+    settings: BenchmarkSettings,
+    camera_path: CameraPath,
+    query_pool: Option<Arc<QueryPool>>,
+    timestamp_period: f32,
+    frame_index: u32,
+    frame_start: Option<Instant>,
+    previous_view_projection: Option<Matrix4<f32>>,
+    frame_times: Vec<f32>,
+    cpu_times: Vec<f32>,
+    gpu_times: Vec<f32>,
+}
+
+impl Benchmark {
+    pub fn new(vulkan_device: &VulkanDevice, settings: BenchmarkSettings) -> Result<Self> {
+        let device = vulkan_device.queue().device();
+        let supports_timestamps = device.physical_device().queue_family_properties()
+            [vulkan_device.queue().queue_family_index() as usize]
+            .timestamp_valid_bits
+            .is_some();
+
+        let query_pool = supports_timestamps
+            .then(|| {
+                QueryPool::new(
+                    Arc::clone(device),
+                    QueryPoolCreateInfo {
+                        query_count: QUERY_SLOTS * 2,
+                        ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                    },
+                )
+            })
+            .transpose()?;
+
+        let frames = settings.frames as usize;
+        Ok(Self {
+            camera_path: CameraPath::orbit(
+                vulkan_device.camera_position(),
+                vulkan_device.scene_center(),
+            ),
+            query_pool,
+            timestamp_period: device.physical_device().properties().timestamp_period,
+            frame_index: 0,
+            frame_start: None,
+            previous_view_projection: None,
+            frame_times: Vec::with_capacity(frames),
+            cpu_times: Vec::with_capacity(frames),
+            gpu_times: Vec::with_capacity(frames),
+            settings,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame_index >= self.settings.warmup_frames + self.settings.frames
+    }
+
+    fn is_measuring(&self) -> bool {
+        self.frame_index >= self.settings.warmup_frames
+    }
+
+    pub fn begin_frame(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        projection: &Perspective3<f32>,
+        pre_rotation: &Matrix4<f32>,
+    ) -> Result<Matrix4<f32>> {
+        let now = Instant::now();
+        if let Some(frame_start) = self.frame_start.filter(|_| self.is_measuring()) {
+            self.frame_times
+                .push((now - frame_start).as_secs_f32() * 1000.0);
+        }
+        self.frame_start = Some(now);
+
+        if let Some(query_pool) = &self.query_pool {
+            let slot = self.frame_index % QUERY_SLOTS;
+            let queries = slot * 2..slot * 2 + 2;
+
+            // The slot was last written QUERY_SLOTS frames ago, by a frame that was already measured.
+            if self.frame_index >= self.settings.warmup_frames + QUERY_SLOTS {
+                let mut timestamps = [0u64; 2];
+                query_pool.get_results(queries.clone(), &mut timestamps, QueryResultFlags::WAIT)?;
+                self.gpu_times.push(
+                    timestamps[1].wrapping_sub(timestamps[0]) as f32 * self.timestamp_period
+                        / 1_000_000.0,
+                );
+            }
+
+            unsafe {
+                builder
+                    .reset_query_pool(Arc::clone(query_pool), queries.clone())?
+                    .write_timestamp(
+                        Arc::clone(query_pool),
+                        queries.start,
+                        PipelineStage::TopOfPipe,
+                    )?;
+            }
+        }
+
+        let total_frames = self.settings.warmup_frames + self.settings.frames;
+        let eye = self
+            .camera_path
+            .eye(self.frame_index as f32 / total_frames as f32);
+        // Without history the first frame reads as not having moved.
+        let previous_view_projection = self.previous_view_projection.unwrap_or_else(|| {
+            let view = Isometry3::look_at_rh(&eye, self.camera_path.target(), &Vector3::y());
+            pre_rotation * projection.as_matrix() * view.to_homogeneous()
+        });
+        let view_projection = vulkan_device.record_camera(
+            builder,
+            &eye,
+            self.camera_path.target(),
+            projection,
+            &previous_view_projection,
+            pre_rotation,
+        )?;
+        self.previous_view_projection = Some(view_projection);
+
+        Ok(view_projection)
+    }
+
+    pub fn end_frame(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<()> {
+        if let Some(query_pool) = &self.query_pool {
+            let slot = self.frame_index % QUERY_SLOTS;
+            unsafe {
+                builder.write_timestamp(
+                    Arc::clone(query_pool),
+                    slot * 2 + 1,
+                    PipelineStage::BottomOfPipe,
+                )?;
+            }
+        }
+
+        if let Some(frame_start) = self.frame_start.filter(|_| self.is_measuring()) {
+            self.cpu_times
+                .push((Instant::now() - frame_start).as_secs_f32() * 1000.0);
+        }
+        self.frame_index += 1;
+
+        Ok(())
+    }
+
+    pub fn write_report(&self) -> Result<()> {
+        let statistics = [
+            ("frame_time_ms", &self.frame_times),
+            ("cpu_time_ms", &self.cpu_times),
+            ("gpu_time_ms", &self.gpu_times),
+        ]
+        .map(|(name, samples)| (name, FrameTimeStatistics::from_samples(samples)));
+
+        for (name, statistics) in &statistics {
+            if let Some(statistics) = statistics {
+                info!(
+                    "{name}: min {:.3} avg {:.3} p95 {:.3} p99 {:.3} max {:.3}",
+                    statistics.min,
+                    statistics.average,
+                    statistics.p95,
+                    statistics.p99,
+                    statistics.max
+                );
+            }
+        }
+
+        let fields = statistics
+            .iter()
+            .map(|(name, statistics)| {
+                let value = statistics.map_or_else(|| "null".to_owned(), |s| s.to_json());
+                format!(r#"  "{name}": {value}"#)
+            })
+            .collect::<Vec<_>>();
+        let report = format!(
+            "{{\n  \"frames\": {},\n{}\n}}\n",
+            self.settings.frames,
+            fields.join(",\n")
+        );
+        std::fs::write(&self.settings.report_path, report)?;
+        info!("Wrote {}", self.settings.report_path.display());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn has_no_statistics_without_samples() {
+        assert!(FrameTimeStatistics::from_samples(&[]).is_none());
+    }
+
+    #[test]
+    fn reads_percentiles_from_the_sorted_samples() {
+        // 1 to 100 ms, shuffled, so percentile p lands on roughly p * 100 ms.
+        let samples = (0..100)
+            .map(|index| ((index * 37) % 100 + 1) as f32)
+            .collect::<Vec<_>>();
+        let statistics = FrameTimeStatistics::from_samples(&samples).unwrap();
+        assert_eq!(statistics.min, 1.0);
+        assert_eq!(statistics.average, 50.5);
+        assert_eq!(statistics.p95, 95.0);
+        assert_eq!(statistics.p99, 99.0);
+        assert_eq!(statistics.max, 100.0);
+    }
+
+    #[test]
+    fn rounds_percentiles_to_the_nearest_sample() {
+        let statistics = FrameTimeStatistics::from_samples(&[4.0, 1.0, 3.0, 2.0]).unwrap();
+        // The 95th and 99th percentiles of four samples both round up to the last one.
+        assert_eq!(statistics.p95, 4.0);
+        assert_eq!(statistics.p99, 4.0);
+        assert_eq!(statistics.average, 2.5);
+    }
+
+    #[test]
+    fn uses_a_single_sample_for_every_statistic() {
+        let statistics = FrameTimeStatistics::from_samples(&[16.0]).unwrap();
+        for value in [
+            statistics.min,
+            statistics.average,
+            statistics.p95,
+            statistics.p99,
+            statistics.max,
+        ] {
+            assert_eq!(value, 16.0);
+        }
+    }
+}
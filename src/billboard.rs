@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra::Point3;
+use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
+use vulkano::buffer::{BufferContents, BufferUsage};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{
+    AttachmentBlend, ColorBlendAttachmentState, ColorBlendState,
+};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineShaderStageCreateInfo,
+};
+use vulkano::DeviceSize;
+
+use crate::allocator_stats::{ArenaStatistics, TrackedSubbufferAllocator};
+use crate::sprite::SpriteTexture;
+use crate::vulkan_device::{VulkanDevice, DEPTH_FORMAT, HDR_FORMAT};
+
+mod billboard_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec2 size;
+                layout(location = 2) in vec4 color;
+                layout(location = 3) in uint mode;
+
+                layout(location = 0) out vec2 uv;
+                layout(location = 1) out vec4 billboardColor;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                void main() {
+                    vec2 corner = vec2(gl_VertexIndex & 1, (gl_VertexIndex >> 1) & 1);
+
+                    vec3 right = vec3(uniforms.view[0][0], uniforms.view[1][0], uniforms.view[2][0]);
+                    vec3 up = vec3(uniforms.view[0][1], uniforms.view[1][1], uniforms.view[2][1]);
+                    if (mode == 1) {
+                        right = normalize(vec3(right.x, 0.0, right.z));
+                        up = vec3(0.0, 1.0, 0.0);
+                    }
+
+                    vec2 offset = (corner * 2.0 - 1.0) * size * 0.5;
+                    vec3 worldPosition = position + right * offset.x + up * offset.y;
+                    gl_Position = uniforms.view_projection * vec4(worldPosition, 1.0);
+                    uv = vec2(corner.x, 1.0 - corner.y);
+                    billboardColor = color;
+                }
+            ",
+    }
+}
+
+mod billboard_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+                layout(location = 1) in vec4 billboardColor;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 1) uniform sampler2D billboardTexture;
+
+                void main() {
+                    outColor = texture(billboardTexture, uv) * billboardColor;
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BillboardMode {
+    #[default]
+    Spherical,
+    Cylindrical,
+}
+
+impl BillboardMode {
+    pub fn shader_value(self) -> u32 {
+        match self {
+            BillboardMode::Spherical => 0,
+            BillboardMode::Cylindrical => 1,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Billboard {
+    pub texture: Arc<SpriteTexture>,
+    pub position: Point3<f32>,
+    pub size: [f32; 2],
+    pub color: [f32; 4],
+    pub mode: BillboardMode,
+}
+
+impl Billboard {
+    pub fn new(texture: Arc<SpriteTexture>, position: Point3<f32>, size: [f32; 2]) -> Self {
+        Self {
+            texture,
+            position,
+            size,
+            color: [1.0; 4],
+            mode: BillboardMode::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BillboardId(u32);
+
+#[derive(Default)]
+pub struct Billboards {
+    billboards: HashMap<BillboardId, Billboard>,
+    next_id: u32,
+}
+
+impl Billboards {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attach(&mut self, billboard: Billboard) -> BillboardId {
+        let id = BillboardId(self.next_id);
+        self.next_id += 1;
+        self.billboards.insert(id, billboard);
+        id
+    }
+
+    pub fn detach(&mut self, id: BillboardId) -> Option<Billboard> {
+        self.billboards.remove(&id)
+    }
+
+    pub fn get_mut(&mut self, id: BillboardId) -> Option<&mut Billboard> {
+        self.billboards.get_mut(&id)
+    }
+
+    pub fn set_position(&mut self, id: BillboardId, position: Point3<f32>) {
+        if let Some(billboard) = self.billboards.get_mut(&id) {
+            billboard.position = position;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.billboards.is_empty()
+    }
+}
+
+#[derive(BufferContents, VertexInputVertex, Clone, Copy)]
+#[repr(C)]
+struct BillboardInstance {
+    #[format(R32G32B32_SFLOAT)]
+    position: [f32; 3],
+    #[format(R32G32_SFLOAT)]
+    size: [f32; 2],
+    #[format(R32G32B32A32_SFLOAT)]
+    color: [f32; 4],
+    #[format(R32_UINT)]
+    mode: u32,
+}
+
+pub struct BillboardPass {
+    pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    instance_allocator: TrackedSubbufferAllocator,
+}
+
+impl BillboardPass {
+    pub fn new(
+        device: &Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        pipeline_cache: &Arc<PipelineCache>,
+        samples: SampleCount,
+    ) -> Result<Self> {
+        let vertex_shader = billboard_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = BillboardInstance::per_instance()
+            .definition(&vertex_shader)
+            .unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(
+                billboard_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(Arc::clone(device))
+                .unwrap(),
+        )?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState {
+                    topology: PrimitiveTopology::TriangleStrip,
+                    ..Default::default()
+                }),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: CompareOp::LessOrEqual,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: samples,
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    },
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        let instance_allocator = TrackedSubbufferAllocator::new(SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::VERTEX_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        ));
+
+        Ok(Self {
+            pipeline,
+            sampler,
+            instance_allocator,
+        })
+    }
+
+    pub fn arena_statistics(&self) -> ArenaStatistics {
+        self.instance_allocator.statistics("billboard instances")
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        billboards: &Billboards,
+    ) -> Result<()> {
+        if billboards.is_empty() {
+            return Ok(());
+        }
+
+        let mut billboards = billboards.billboards.values().collect::<Vec<_>>();
+        billboards.sort_by_key(|billboard| Arc::as_ptr(&billboard.texture));
+
+        let instances = self
+            .instance_allocator
+            .allocate_slice::<BillboardInstance>(billboards.len() as DeviceSize)?;
+        {
+            let mut instances = instances.write()?;
+            for (instance, billboard) in instances.iter_mut().zip(&billboards) {
+                *instance = BillboardInstance {
+                    position: billboard.position.coords.into(),
+                    size: billboard.size,
+                    color: billboard.color,
+                    mode: billboard.mode.shader_value(),
+                };
+            }
+        }
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_vertex_buffers(0, instances)?;
+
+        let mut first = 0;
+        while first < billboards.len() {
+            let texture = &billboards[first].texture;
+            let count = billboards[first..]
+                .iter()
+                .take_while(|billboard| Arc::ptr_eq(&billboard.texture, texture))
+                .count();
+
+            let set = DescriptorSet::new(
+                vulkan_device.descriptor_set_allocator().clone(),
+                Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+                [
+                    WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        1,
+                        Arc::clone(texture.view()),
+                        Arc::clone(&self.sampler),
+                    ),
+                ],
+                [],
+            )?;
+
+            builder.bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                set,
+            )?;
+            unsafe { builder.draw(4, count as u32, 0, first as u32) }?;
+
+            first += count;
+        }
+
+        Ok(())
+    }
+}
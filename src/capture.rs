@@ -0,0 +1,171 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::format::Format;
+use vulkano::image::Image;
+use vulkano::DeviceSize;
+
+use crate::readback::Readback;
+use crate::vulkan_device::VulkanDevice;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CaptureOutput {
+    Png(PathBuf),
+    // Raw RGBA frames are written to the command's stdin, e.g.
+    // `|ffmpeg -f rawvideo -pix_fmt rgba -s 1280x720 -r 60 -i - demo.mp4`.
+    Pipe(String),
+}
+
+impl FromStr for CaptureOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.strip_prefix('|') {
+            Some(command) if command.trim().is_empty() => {
+                Err(anyhow!("Capture pipe needs an encoder command"))
+            }
+            Some(command) => Ok(CaptureOutput::Pipe(command.trim().to_owned())),
+            None => Ok(CaptureOutput::Png(value.into())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CaptureSettings {
+    pub output: CaptureOutput,
+    pub frame_rate: u32,
+}
+
+enum CaptureSink {
+    Png(PathBuf),
+    Pipe(Child, Option<ChildStdin>),
+}
+
+pub struct FrameCapture {
+    settings: CaptureSettings,
+    sink: CaptureSink,
+    // A single staging buffer, since every captured frame is waited on.
+    readback: Option<Readback<u8>>,
+    extent: [u32; 2],
+    frame_index: u64,
+}
+
+impl FrameCapture {
+    pub fn new(settings: CaptureSettings) -> Result<Self> {
+        let sink = match &settings.output {
+            CaptureOutput::Png(directory) => {
+                std::fs::create_dir_all(directory)?;
+                CaptureSink::Png(directory.clone())
+            }
+            CaptureOutput::Pipe(command) => {
+                let mut child = shell(command).stdin(Stdio::piped()).spawn()?;
+                let stdin = child.stdin.take();
+                CaptureSink::Pipe(child, stdin)
+            }
+        };
+
+        Ok(Self {
+            settings,
+            sink,
+            readback: None,
+            extent: [0, 0],
+            frame_index: 0,
+        })
+    }
+
+    pub fn time(&self) -> f32 {
+        self.frame_index as f32 / self.settings.frame_rate as f32
+    }
+
+    pub fn delta_time(&self) -> f32 {
+        1.0 / self.settings.frame_rate as f32
+    }
+
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        image: &Arc<Image>,
+    ) -> Result<()> {
+        let extent = [image.extent()[0], image.extent()[1]];
+        let len = extent[0] as DeviceSize * extent[1] as DeviceSize * 4;
+        match &mut self.readback {
+            Some(readback) if self.extent != extent => readback.resize(vulkan_device, len)?,
+            Some(_) => {}
+            None => self.readback = Some(Readback::new(vulkan_device, len, 1)?),
+        }
+        self.extent = extent;
+
+        self.readback
+            .as_mut()
+            .unwrap()
+            .record_image(builder, image)?;
+
+        Ok(())
+    }
+
+    // Must only be called once the submission that recorded the copy has finished.
+    pub fn write_frame(&mut self, format: Format) -> Result<()> {
+        let Some(mut pixels) = self.readback.as_mut().and_then(Readback::poll) else {
+            return Ok(());
+        };
+        match format {
+            Format::B8G8R8A8_SRGB | Format::B8G8R8A8_UNORM => pixels
+                .chunks_exact_mut(4)
+                .for_each(|pixel| pixel.swap(0, 2)),
+            // The sinks take 8-bit RGBA, so packed 10-bit channels keep their top 8 bits.
+            Format::A2B10G10R10_UNORM_PACK32 => pixels.chunks_exact_mut(4).for_each(|pixel| {
+                let packed = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                pixel.copy_from_slice(&[
+                    (packed >> 2) as u8,
+                    (packed >> 12) as u8,
+                    (packed >> 22) as u8,
+                    (packed >> 30) as u8 * 85,
+                ]);
+            }),
+            _ => {}
+        }
+
+        match &mut self.sink {
+            CaptureSink::Png(directory) => {
+                image::RgbaImage::from_raw(self.extent[0], self.extent[1], pixels)
+                    .unwrap()
+                    .save(directory.join(format!("frame_{:06}.png", self.frame_index)))?;
+            }
+            CaptureSink::Pipe(_, stdin) => stdin
+                .as_mut()
+                .ok_or_else(|| anyhow!("Capture encoder has no stdin"))?
+                .write_all(&pixels)?,
+        }
+        self.frame_index += 1;
+
+        Ok(())
+    }
+}
+
+impl Drop for FrameCapture {
+    // Closing stdin lets the encoder finalize its output before we wait on it.
+    fn drop(&mut self) {
+        if let CaptureSink::Pipe(encoder, stdin) = &mut self.sink {
+            drop(stdin.take());
+            let _ = encoder.wait();
+        }
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
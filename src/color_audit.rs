@@ -0,0 +1,190 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::sprite::TextureEncoding;
+
+// Words in a file name that say what a texture holds.
+const DATA_TEXTURE_WORDS: [&str; 14] = [
+    "normal",
+    "normals",
+    "nrm",
+    "rough",
+    "roughness",
+    "metallic",
+    "metalness",
+    "occlusion",
+    "ao",
+    "orm",
+    "mask",
+    "height",
+    "displacement",
+    "disp",
+];
+const COLOR_TEXTURE_WORDS: [&str; 7] = [
+    "albedo",
+    "basecolor",
+    "color",
+    "colour",
+    "diffuse",
+    "emissive",
+    "emission",
+];
+
+// A texture interpreted with the wrong transfer function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorAuditIssue {
+    // Decoding tangent-space normals as sRGB bends them towards the surface.
+    NormalMapAsSrgb,
+    // Its name says it holds data, but it's stored as sRGB.
+    DataAsSrgb,
+    // Its name says it holds color, but it's stored as UNORM, which washes it out.
+    ColorAsLinear,
+}
+
+impl fmt::Display for ColorAuditIssue {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            ColorAuditIssue::NormalMapAsSrgb => "looks like a normal map but is decoded as sRGB",
+            ColorAuditIssue::DataAsSrgb => "is named like data but is decoded as sRGB",
+            ColorAuditIssue::ColorAsLinear => "is named like color but isn't decoded as sRGB",
+        })
+    }
+}
+
+// What a texture's file name says about its contents, from words like `normal` or `albedo`
+// separated by anything but letters and digits. Data wins when a name has both.
+pub fn suggested_encoding(path: &Path) -> Option<TextureEncoding> {
+    let stem = path.file_stem()?.to_str()?.to_ascii_lowercase();
+    let words = stem
+        .split(|character: char| !character.is_ascii_alphanumeric())
+        .collect::<Vec<_>>();
+    if words.iter().any(|word| DATA_TEXTURE_WORDS.contains(word)) {
+        Some(TextureEncoding::Linear)
+    } else if words.iter().any(|word| COLOR_TEXTURE_WORDS.contains(word)) {
+        Some(TextureEncoding::Srgb)
+    } else {
+        None
+    }
+}
+
+// Checks 8-bit RGBA texels against how they're about to be decoded.
+pub fn audit_texture(
+    path: Option<&Path>,
+    texels: &[u8],
+    encoding: TextureEncoding,
+) -> Option<ColorAuditIssue> {
+    match (path.and_then(suggested_encoding), encoding) {
+        (Some(TextureEncoding::Linear), TextureEncoding::Srgb) => {
+            return Some(ColorAuditIssue::DataAsSrgb)
+        }
+        (Some(TextureEncoding::Srgb), TextureEncoding::Linear) => {
+            return Some(ColorAuditIssue::ColorAsLinear)
+        }
+        _ => {}
+    }
+
+    (encoding == TextureEncoding::Srgb && looks_like_normal_map(texels))
+        .then_some(ColorAuditIssue::NormalMapAsSrgb)
+}
+
+// Tangent-space normals point out of the surface, so their blue stays above the middle while red
+// and green average around it.
+fn looks_like_normal_map(texels: &[u8]) -> bool {
+    let count = texels.len() / 4;
+    if count == 0 {
+        return false;
+    }
+
+    let mut sums = [0u64; 3];
+    let mut facing_out = 0;
+    for texel in texels.chunks_exact(4) {
+        for (sum, &channel) in sums.iter_mut().zip(texel) {
+            *sum += channel as u64;
+        }
+        facing_out += (texel[2] >= 128) as usize;
+    }
+    let [red, green, blue] = sums.map(|sum| sum as f32 / (count as f32 * 255.0));
+    (0.4..0.6).contains(&red)
+        && (0.4..0.6).contains(&green)
+        && blue > 0.75
+        && facing_out as f32 >= count as f32 * 0.95
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texels(texel: [u8; 4], count: usize) -> Vec<u8> {
+        texel.repeat(count)
+    }
+
+    #[test]
+    fn suggests_an_encoding_from_words_in_the_name() {
+        for (path, encoding) in [
+            ("textures/brick_normal.png", Some(TextureEncoding::Linear)),
+            ("Brick-Roughness.PNG", Some(TextureEncoding::Linear)),
+            ("brick.ao.png", Some(TextureEncoding::Linear)),
+            ("brick_albedo.png", Some(TextureEncoding::Srgb)),
+            ("BaseColor.jpg", Some(TextureEncoding::Srgb)),
+            ("brick.png", None),
+            // Words have to match whole, not as part of a longer one.
+            ("abnormal.png", None),
+            ("colorful.png", None),
+        ] {
+            assert_eq!(suggested_encoding(Path::new(path)), encoding, "{path}");
+        }
+    }
+
+    #[test]
+    fn prefers_data_when_a_name_has_both() {
+        assert_eq!(
+            suggested_encoding(Path::new("color_mask.png")),
+            Some(TextureEncoding::Linear)
+        );
+    }
+
+    #[test]
+    fn recognizes_flat_and_slightly_bumpy_normal_maps() {
+        assert!(looks_like_normal_map(&texels([128, 128, 255, 255], 16)));
+        let mut bumpy = texels([128, 128, 255, 255], 16);
+        bumpy.extend(texels([100, 150, 230, 255], 16));
+        assert!(looks_like_normal_map(&bumpy));
+    }
+
+    #[test]
+    fn doesnt_mistake_colors_for_normal_maps() {
+        assert!(!looks_like_normal_map(&[]));
+        assert!(!looks_like_normal_map(&texels([128, 128, 128, 255], 16)));
+        assert!(!looks_like_normal_map(&texels([40, 90, 220, 255], 16)));
+        // Mostly blue, but with too many texels facing into the surface.
+        let mut sky = texels([128, 128, 255, 255], 18);
+        sky.extend(texels([128, 128, 100, 255], 2));
+        assert!(!looks_like_normal_map(&sky));
+    }
+
+    #[test]
+    fn flags_names_before_contents() {
+        let flat = texels([128, 128, 255, 255], 4);
+        assert_eq!(
+            audit_texture(
+                Some(Path::new("brick_normal.png")),
+                &flat,
+                TextureEncoding::Srgb
+            ),
+            Some(ColorAuditIssue::DataAsSrgb)
+        );
+        assert_eq!(
+            audit_texture(
+                Some(Path::new("brick_albedo.png")),
+                &flat,
+                TextureEncoding::Linear
+            ),
+            Some(ColorAuditIssue::ColorAsLinear)
+        );
+        assert_eq!(
+            audit_texture(None, &flat, TextureEncoding::Srgb),
+            Some(ColorAuditIssue::NormalMapAsSrgb)
+        );
+        assert_eq!(audit_texture(None, &flat, TextureEncoding::Linear), None);
+    }
+}
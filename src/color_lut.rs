@@ -0,0 +1,341 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CopyBufferToImageInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter};
+
+use crate::vulkan_device::VulkanDevice;
+
+pub const COLOR_LUT_FORMAT: Format = Format::A2B10G10R10_UNORM_PACK32;
+
+pub struct ColorLut {
+    view: Arc<ImageView>,
+    size: u32,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+}
+
+// A LUT as read from disk. Inputs in `domain_min..domain_max` map onto the texels' full range.
+#[derive(Debug)]
+struct LutData {
+    size: u32,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    texels: Vec<[f32; 3]>,
+}
+
+impl ColorLut {
+    pub fn identity(
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Self> {
+        let size = 2;
+        let texels = (0..size * size * size)
+            .map(|i| {
+                [
+                    (i % size) as f32,
+                    ((i / size) % size) as f32,
+                    (i / (size * size)) as f32,
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        Self::upload(
+            memory_allocator,
+            builder,
+            &LutData {
+                size,
+                domain_min: [0.0; 3],
+                domain_max: [1.0; 3],
+                texels,
+            },
+        )
+    }
+
+    pub fn load(vulkan_device: &VulkanDevice, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let data = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("cube") => parse_cube(&std::fs::read_to_string(path)?)?,
+            Some("png") => parse_strip(&image::open(path)?.to_rgb32f())?,
+            _ => bail!("Unsupported color LUT format: {}", path.display()),
+        };
+
+        let mut lut = None;
+        vulkan_device.submit_and_wait(|builder| {
+            lut = Some(Self::upload(
+                vulkan_device.memory_allocator().clone(),
+                builder,
+                &data,
+            )?);
+            Ok(())
+        })?;
+
+        lut.with_context(|| format!("Failed to upload color LUT {}", path.display()))
+    }
+
+    fn upload(
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        data: &LutData,
+    ) -> Result<Self> {
+        let staging_buffer = Buffer::from_iter(
+            Arc::clone(&memory_allocator),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            data.texels.iter().map(|&texel| pack_a2b10g10r10(texel)),
+        )?;
+
+        let image = Image::new(
+            memory_allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim3d,
+                format: COLOR_LUT_FORMAT,
+                extent: [data.size; 3],
+                usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?;
+
+        builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+            staging_buffer,
+            Arc::clone(&image),
+        ))?;
+
+        Ok(Self {
+            view: ImageView::new_default(image)?,
+            size: data.size,
+            domain_min: data.domain_min,
+            domain_max: data.domain_max,
+        })
+    }
+
+    pub fn view(&self) -> &Arc<ImageView> {
+        &self.view
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn domain_min(&self) -> [f32; 3] {
+        self.domain_min
+    }
+
+    pub fn domain_max(&self) -> [f32; 3] {
+        self.domain_max
+    }
+}
+
+fn pack_a2b10g10r10(texel: [f32; 3]) -> u32 {
+    let [r, g, b] = texel.map(|channel| (channel.clamp(0.0, 1.0) * 1023.0).round() as u32);
+    (3 << 30) | (b << 20) | (g << 10) | r
+}
+
+// The domain only says which inputs the texels cover, so it's applied to the lookup rather than
+// the texels.
+fn parse_cube(source: &str) -> Result<LutData> {
+    let mut size = None;
+    let mut domain_min = [0.0; 3];
+    let mut domain_max = [1.0; 3];
+    let mut texels = Vec::new();
+
+    for line in source.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with("TITLE") {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next().unwrap();
+        match keyword {
+            "LUT_3D_SIZE" => {
+                size = Some(tokens.next().context("Missing LUT_3D_SIZE")?.parse()?);
+            }
+            "LUT_1D_SIZE" => bail!("1D .cube LUTs are not supported"),
+            "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                let values = tokens.map(str::parse).try_collect::<Vec<f32>>()?;
+                let domain: [f32; 3] = values
+                    .try_into()
+                    .ok()
+                    .with_context(|| format!("Malformed {keyword}"))?;
+                if keyword == "DOMAIN_MIN" {
+                    domain_min = domain;
+                } else {
+                    domain_max = domain;
+                }
+            }
+            _ => {
+                let values = line
+                    .split_whitespace()
+                    .map(str::parse)
+                    .try_collect::<Vec<f32>>()?;
+                let texel: [f32; 3] = values
+                    .try_into()
+                    .ok()
+                    .with_context(|| format!("Malformed LUT entry: {line}"))?;
+                texels.push(texel);
+            }
+        }
+    }
+
+    let size: u32 = size.context("Missing LUT_3D_SIZE")?;
+    if domain_min
+        .iter()
+        .zip(&domain_max)
+        .any(|(min, max)| min >= max)
+    {
+        bail!("DOMAIN_MIN {domain_min:?} must be below DOMAIN_MAX {domain_max:?}");
+    }
+    if texels.len() != (size * size * size) as usize {
+        bail!(
+            "Expected {} LUT entries, found {}",
+            size * size * size,
+            texels.len()
+        );
+    }
+
+    Ok(LutData {
+        size,
+        domain_min,
+        domain_max,
+        texels,
+    })
+}
+
+fn parse_strip(strip: &image::Rgb32FImage) -> Result<LutData> {
+    let size = strip.height();
+    if strip.width() != size * size {
+        bail!(
+            "LUT strip must be {}x{} for a {size}^3 LUT, found {}x{}",
+            size * size,
+            size,
+            strip.width(),
+            strip.height()
+        );
+    }
+
+    let mut texels = Vec::with_capacity((size * size * size) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                texels.push(strip.get_pixel(b * size + r, g).0);
+            }
+        }
+    }
+
+    Ok(LutData {
+        size,
+        domain_min: [0.0; 3],
+        domain_max: [1.0; 3],
+        texels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY_CUBE: &str = "\
+TITLE \"Identity\"
+# Red changes fastest.
+LUT_3D_SIZE 2
+
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    #[test]
+    fn parses_cube_size_and_texels() {
+        let lut = parse_cube(IDENTITY_CUBE).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.texels.len(), 8);
+        assert_eq!(lut.texels[1], [1.0, 0.0, 0.0]);
+        assert_eq!(lut.texels[6], [0.0, 1.0, 1.0]);
+        assert_eq!(lut.domain_min, [0.0; 3]);
+        assert_eq!(lut.domain_max, [1.0; 3]);
+    }
+
+    #[test]
+    fn keeps_texels_outside_the_default_domain() {
+        let source = IDENTITY_CUBE.replace(
+            "LUT_3D_SIZE 2",
+            "LUT_3D_SIZE 2\nDOMAIN_MIN -1 0 0\nDOMAIN_MAX 1 2 4",
+        );
+        let lut = parse_cube(&source).unwrap();
+        assert_eq!(lut.domain_min, [-1.0, 0.0, 0.0]);
+        assert_eq!(lut.domain_max, [1.0, 2.0, 4.0]);
+        assert_eq!(lut.texels, parse_cube(IDENTITY_CUBE).unwrap().texels);
+    }
+
+    #[test]
+    fn rejects_empty_domains() {
+        let source = IDENTITY_CUBE.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 2\nDOMAIN_MIN 0 1 0");
+        assert!(parse_cube(&source).is_err());
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let source = IDENTITY_CUBE.replace("0 1 0\n", "\n# A comment between rows.\n  0 1 0\n");
+        assert_eq!(
+            parse_cube(&source).unwrap().texels,
+            parse_cube(IDENTITY_CUBE).unwrap().texels
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_rows() {
+        for row in ["0 1", "0 1 0 1", "0 one 0"] {
+            let source = IDENTITY_CUBE.replace("0 1 0\n", &format!("{row}\n"));
+            assert!(parse_cube(&source).is_err(), "{row}");
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_entry_counts() {
+        assert!(parse_cube(&IDENTITY_CUBE.replace("1 1 1\n", "")).is_err());
+        assert!(parse_cube(&IDENTITY_CUBE.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 3")).is_err());
+        assert!(parse_cube(&IDENTITY_CUBE.replace("LUT_3D_SIZE 2", "")).is_err());
+    }
+
+    #[test]
+    fn rejects_1d_cubes() {
+        assert!(parse_cube(&IDENTITY_CUBE.replace("LUT_3D_SIZE", "LUT_1D_SIZE")).is_err());
+    }
+
+    #[test]
+    fn reads_strips_slice_by_slice() {
+        // Blue picks the slice, red the column within it and green the row.
+        let strip = image::Rgb32FImage::from_fn(4, 2, |x, y| {
+            image::Rgb([(x % 2) as f32, y as f32, (x / 2) as f32])
+        });
+        let lut = parse_strip(&strip).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.texels, parse_cube(IDENTITY_CUBE).unwrap().texels);
+    }
+
+    #[test]
+    fn rejects_strips_of_the_wrong_shape() {
+        assert!(parse_strip(&image::Rgb32FImage::new(4, 4)).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use palette::Srgba;
+use tracing::warn;
+use vulkano::format::Format;
+use vulkano::swapchain::ColorSpace;
+
+use crate::swapchain_format::SwapchainFormat;
+
+// Colors are authored in sRGB and the scene is lit in linear Rec.709, which is what sRGB textures
+// and authored colors decode to. The final pass grades and tonemaps in the working space, then
+// maps the result into the gamut the display is presented in.
+
+// Authored colors decoded into the space the scene is lit in.
+pub fn authored_color(color: Srgba) -> [f32; 4] {
+    color.into_linear().into()
+}
+
+// The order matches the indices the tonemap shader selects its matrices by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WorkingColorSpace {
+    #[default]
+    LinearRec709,
+    // ACES' wider AP1 primaries, where the tonemap curve shifts saturated hues less.
+    AcesCg,
+}
+
+impl FromStr for WorkingColorSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "rec709" | "linear-rec709" | "srgb" => WorkingColorSpace::LinearRec709,
+            "acescg" | "ap1" => WorkingColorSpace::AcesCg,
+            _ => bail!("Unknown working color space: {value}"),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputColorSpace {
+    #[default]
+    Srgb,
+    // Wider primaries with the sRGB transfer function, as on most recent laptops and phones.
+    DisplayP3,
+    // Presented linearly, so it needs a floating-point swapchain to avoid banding.
+    Rec2020,
+}
+
+impl FromStr for OutputColorSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "srgb" | "rec709" => OutputColorSpace::Srgb,
+            "p3" | "display-p3" => OutputColorSpace::DisplayP3,
+            "rec2020" | "bt2020" => OutputColorSpace::Rec2020,
+            _ => bail!("Unknown output color space: {value}"),
+        })
+    }
+}
+
+impl OutputColorSpace {
+    pub fn color_space(self) -> ColorSpace {
+        match self {
+            OutputColorSpace::Srgb => ColorSpace::SrgbNonLinear,
+            OutputColorSpace::DisplayP3 => ColorSpace::DisplayP3NonLinear,
+            OutputColorSpace::Rec2020 => ColorSpace::Bt2020Linear,
+        }
+    }
+
+    fn formats(self, swapchain_format: SwapchainFormat) -> &'static [Format] {
+        match self {
+            OutputColorSpace::Rec2020 => &[Format::R16G16B16A16_SFLOAT],
+            _ => swapchain_format.candidates(),
+        }
+    }
+
+    // The swapchain's format and the space it's presented in, which falls back to sRGB when the
+    // surface can't present this one.
+    pub fn select(
+        self,
+        swapchain_format: SwapchainFormat,
+        surface_formats: &[(Format, ColorSpace)],
+    ) -> Result<(Format, OutputColorSpace)> {
+        if self != OutputColorSpace::Srgb {
+            let format = self
+                .formats(swapchain_format)
+                .iter()
+                .copied()
+                .find(|format| surface_formats.contains(&(*format, self.color_space())));
+            if let Some(format) = format {
+                return Ok((format, self));
+            }
+            warn!("The surface can't present {self:?}, falling back to sRGB");
+        }
+        Ok((
+            swapchain_format.select(surface_formats)?,
+            OutputColorSpace::Srgb,
+        ))
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::DescriptorSet;
+use vulkano::device::Device;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::compute::ComputePipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo,
+};
+use vulkano::shader::EntryPoint;
+
+pub fn create_compute_pipeline(
+    device: &Arc<Device>,
+    cache: Option<Arc<PipelineCache>>,
+    shader: EntryPoint,
+) -> Result<Arc<ComputePipeline>> {
+    let stage = PipelineShaderStageCreateInfo::new(shader);
+
+    let layout = PipelineLayout::new(
+        Arc::clone(device),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+            .into_pipeline_layout_create_info(Arc::clone(device))
+            .unwrap(),
+    )?;
+
+    Ok(ComputePipeline::new(
+        Arc::clone(device),
+        cache,
+        ComputePipelineCreateInfo::stage_layout(stage, layout),
+    )?)
+}
+
+pub fn bind_compute<'a>(
+    builder: &'a mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    pipeline: &Arc<ComputePipeline>,
+    set: Arc<DescriptorSet>,
+) -> Result<&'a mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>> {
+    builder
+        .bind_pipeline_compute(Arc::clone(pipeline))?
+        .bind_descriptor_sets(
+            PipelineBindPoint::Compute,
+            Arc::clone(pipeline.layout()),
+            0,
+            set,
+        )?;
+
+    Ok(builder)
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::{ComputePipeline, Pipeline};
+
+use crate::compute;
+use crate::light::DirectionalLight;
+use crate::vulkan_device::VulkanDevice;
+
+mod contact_shadow_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0, rgba8) uniform image2D visibility;
+                layout(set = 0, binding = 1) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+                layout(set = 0, binding = 2) uniform sampler2D depthTexture;
+
+                layout(push_constant) uniform ContactShadowParameters {
+                    vec3 sunDirection;
+                    float rayLength;
+                    float thickness;
+                    uint stepCount;
+                } parameters;
+
+                float linearDepth(float depth) {
+                    return uniforms.projection[3][2] / (depth + uniforms.projection[2][2]);
+                }
+
+                float interleavedGradientNoise(vec2 pixel) {
+                    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+                }
+
+                void main() {
+                    ivec2 size = imageSize(visibility);
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    if (any(greaterThanEqual(pixel, size))) {
+                        return;
+                    }
+
+                    vec4 current = imageLoad(visibility, pixel);
+                    float depth = texelFetch(depthTexture, pixel, 0).r;
+                    if (depth >= 1.0 || current.g == 0.0) {
+                        return;
+                    }
+
+                    vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+                    vec4 viewPosition = uniforms.inverse_projection * vec4(uv * 2.0 - 1.0, depth, 1.0);
+                    vec3 position = viewPosition.xyz / viewPosition.w;
+                    vec3 direction = normalize(mat3(uniforms.view) * parameters.sunDirection);
+
+                    // Jittered steps trade banding for noise.
+                    float stepLength = parameters.rayLength / float(max(parameters.stepCount, 1u));
+                    float offset = interleavedGradientNoise(vec2(pixel));
+                    float shadow = 1.0;
+                    for (uint i = 0u; i < parameters.stepCount; i++) {
+                        vec3 samplePosition = position + direction * stepLength * (float(i) + offset);
+                        vec4 clip = uniforms.projection * vec4(samplePosition, 1.0);
+                        vec3 ndc = clip.xyz / clip.w;
+                        vec2 sampleUv = ndc.xy * 0.5 + 0.5;
+                        if (clip.w <= 0.0 || any(lessThan(sampleUv, vec2(0.0))) || any(greaterThan(sampleUv, vec2(1.0)))) {
+                            break;
+                        }
+
+                        float rayDepth = linearDepth(ndc.z);
+                        float sceneDepth = linearDepth(textureLod(depthTexture, sampleUv, 0.0).r);
+                        float delta = rayDepth - sceneDepth;
+                        // Only thin occluders right in front of the ray count, so silhouettes further
+                        // away don't cast shadows over everything behind them.
+                        if (delta > rayDepth * 0.001 && delta < parameters.thickness) {
+                            // Fades out towards the end of the ray, where longer-range shadows take over.
+                            shadow = float(i) / float(parameters.stepCount);
+                            break;
+                        }
+                    }
+
+                    imageStore(visibility, pixel, vec4(current.r, current.g * shadow, current.ba));
+                }
+            ",
+    }
+}
+
+// Short screen-space rays towards the sun, which catch the contacts between objects and the ground
+// that shadows from further away miss or let light leak through.
+#[derive(Clone, Copy, Debug)]
+pub struct ContactShadowSettings {
+    pub enabled: bool,
+    // How far the rays march in view space.
+    pub length: f32,
+    // How deep a surface in the depth buffer is assumed to be.
+    pub thickness: f32,
+    pub step_count: u32,
+}
+
+impl Default for ContactShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            length: 0.25,
+            thickness: 0.05,
+            step_count: 16,
+        }
+    }
+}
+
+pub struct ContactShadowPass {
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl ContactShadowPass {
+    pub fn new(device: &Arc<Device>, pipeline_cache: &Arc<PipelineCache>) -> Result<Self> {
+        let pipeline = compute::create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            contact_shadow_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self { pipeline, sampler })
+    }
+
+    // Darkens the sun visibility in the visibility image's green channel in place, so it has to
+    // run after whatever filled it in.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        visibility: &Arc<ImageView>,
+        depth: &Arc<ImageView>,
+        settings: &ContactShadowSettings,
+        sun: &DirectionalLight,
+    ) -> Result<()> {
+        if !settings.enabled {
+            return Ok(());
+        }
+
+        let [width, height, _] = visibility.image().extent();
+        vulkan_device
+            .bind_compute(
+                builder,
+                &self.pipeline,
+                [
+                    WriteDescriptorSet::image_view(0, Arc::clone(visibility)),
+                    WriteDescriptorSet::buffer(1, vulkan_device.uniform_buffer().clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        2,
+                        Arc::clone(depth),
+                        Arc::clone(&self.sampler),
+                    ),
+                ],
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                contact_shadow_cs::ContactShadowParameters {
+                    sunDirection: sun.direction.normalize().into(),
+                    rayLength: settings.length,
+                    thickness: settings.thickness,
+                    stepCount: settings.step_count,
+                },
+            )?;
+        unsafe { builder.dispatch([width.div_ceil(8), height.div_ceil(8), 1]) }?;
+
+        Ok(())
+    }
+}
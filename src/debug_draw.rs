@@ -0,0 +1,433 @@
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Point3, Vector3};
+use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
+use vulkano::buffer::{BufferContents, BufferUsage};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineShaderStageCreateInfo,
+};
+use vulkano::DeviceSize;
+
+use crate::allocator_stats::{ArenaStatistics, TrackedSubbufferAllocator};
+use crate::vulkan_device::{VulkanDevice, DEPTH_FORMAT, HDR_FORMAT};
+
+const SPHERE_SEGMENTS: usize = 32;
+
+// Frusta of the cameras other than the one being looked through, drawn as wireframes.
+#[derive(Clone, Copy, Debug)]
+pub struct FrustumVisualizationSettings {
+    pub enabled: bool,
+    // Far planes are cut off this far in front of their camera, as glTF cameras without one reach
+    // a thousand units.
+    pub max_distance: f32,
+    pub scene_camera_color: [f32; 4],
+    pub render_texture_color: [f32; 4],
+    pub main_camera_color: [f32; 4],
+}
+
+impl Default for FrustumVisualizationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_distance: 20.0,
+            scene_camera_color: [1.0, 0.8, 0.1, 1.0],
+            render_texture_color: [0.1, 0.8, 1.0, 1.0],
+            main_camera_color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+}
+
+mod debug_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec4 color;
+
+                layout(location = 0) out vec4 lineColor;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                void main() {
+                    gl_Position = uniforms.view_projection * vec4(position, 1.0);
+                    lineColor = color;
+                }
+            ",
+    }
+}
+
+mod debug_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec4 lineColor;
+
+                layout(location = 0) out vec4 outColor;
+
+                void main() {
+                    outColor = lineColor;
+                }
+            ",
+    }
+}
+
+#[derive(BufferContents, VertexInputVertex, Clone, Copy)]
+#[repr(C)]
+struct DebugVertex {
+    #[format(R32G32B32_SFLOAT)]
+    position: [f32; 3],
+    #[format(R32G32B32A32_SFLOAT)]
+    color: [f32; 4],
+}
+
+#[derive(Default)]
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn line(&mut self, start: &Point3<f32>, end: &Point3<f32>, color: [f32; 4]) {
+        self.vertices.extend([
+            DebugVertex {
+                position: start.coords.into(),
+                color,
+            },
+            DebugVertex {
+                position: end.coords.into(),
+                color,
+            },
+        ]);
+    }
+
+    pub fn wire_box(&mut self, minimum: &Point3<f32>, maximum: &Point3<f32>, color: [f32; 4]) {
+        let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|index| {
+            Point3::new(
+                if index & 1 == 0 { minimum.x } else { maximum.x },
+                if index & 2 == 0 { minimum.y } else { maximum.y },
+                if index & 4 == 0 { minimum.z } else { maximum.z },
+            )
+        });
+        self.box_edges(&corners, color);
+    }
+
+    pub fn sphere(&mut self, center: &Point3<f32>, radius: f32, color: [f32; 4]) {
+        for (axis_u, axis_v) in [
+            (Vector3::x(), Vector3::y()),
+            (Vector3::y(), Vector3::z()),
+            (Vector3::z(), Vector3::x()),
+        ] {
+            let point = |segment: usize| {
+                let angle = segment as f32 / SPHERE_SEGMENTS as f32 * TAU;
+                center + (axis_u * angle.cos() + axis_v * angle.sin()) * radius
+            };
+            for segment in 0..SPHERE_SEGMENTS {
+                self.line(&point(segment), &point(segment + 1), color);
+            }
+        }
+    }
+
+    // `angle` is between the axis and the sides, in radians.
+    pub fn cone(
+        &mut self,
+        apex: &Point3<f32>,
+        direction: &Vector3<f32>,
+        length: f32,
+        angle: f32,
+        color: [f32; 4],
+    ) {
+        let axis = direction.normalize();
+        let tangent = if axis.x.abs() < 0.9 {
+            axis.cross(&Vector3::x()).normalize()
+        } else {
+            axis.cross(&Vector3::y()).normalize()
+        };
+        let bitangent = axis.cross(&tangent);
+        let base = apex + axis * length;
+        let radius = length * angle.tan();
+        let point = |segment: usize| {
+            let angle = segment as f32 / SPHERE_SEGMENTS as f32 * TAU;
+            base + (tangent * angle.cos() + bitangent * angle.sin()) * radius
+        };
+        for segment in 0..SPHERE_SEGMENTS {
+            self.line(&point(segment), &point(segment + 1), color);
+        }
+        for segment in (0..SPHERE_SEGMENTS).step_by(SPHERE_SEGMENTS / 4) {
+            self.line(apex, &point(segment), color);
+        }
+    }
+
+    pub fn axes(&mut self, transform: &Matrix4<f32>, size: f32) {
+        let origin = transform.transform_point(&Point3::origin());
+        for (axis, color) in [
+            (Vector3::x(), [1.0, 0.0, 0.0, 1.0]),
+            (Vector3::y(), [0.0, 1.0, 0.0, 1.0]),
+            (Vector3::z(), [0.0, 0.0, 1.0, 1.0]),
+        ] {
+            let end = transform.transform_point(&Point3::from(axis * size));
+            self.line(&origin, &end, color);
+        }
+    }
+
+    pub fn frustum(&mut self, view_projection: &Matrix4<f32>, color: [f32; 4]) {
+        let Some(inverse) = view_projection.try_inverse() else {
+            return;
+        };
+        let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|index| {
+            inverse.transform_point(&Point3::new(
+                if index & 1 == 0 { -1.0 } else { 1.0 },
+                if index & 2 == 0 { -1.0 } else { 1.0 },
+                if index & 4 == 0 { -1.0 } else { 1.0 },
+            ))
+        });
+        self.box_edges(&corners, color);
+    }
+
+    // A camera's frustum cut off at `max_distance` along its view direction. Each side edge is a
+    // straight line in view space, so the cut corners lie on it for either kind of projection.
+    pub fn camera_frustum(
+        &mut self,
+        view: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+        max_distance: f32,
+        color: [f32; 4],
+    ) {
+        let (Some(inverse_view), Some(inverse_projection)) =
+            (view.try_inverse(), projection.try_inverse())
+        else {
+            return;
+        };
+        let mut corners = [Point3::origin(); 8];
+        for index in 0..4 {
+            let unproject = |z| {
+                inverse_projection.transform_point(&Point3::new(
+                    if index & 1 == 0 { -1.0 } else { 1.0 },
+                    if index & 2 == 0 { -1.0 } else { 1.0 },
+                    z,
+                ))
+            };
+            let (near, far) = (unproject(-1.0), unproject(1.0));
+            // View space looks down -Z.
+            let cut = ((max_distance + near.z) / (near.z - far.z)).clamp(0.0, 1.0);
+            corners[index] = inverse_view.transform_point(&near);
+            corners[index + 4] = inverse_view.transform_point(&(near + (far - near) * cut));
+        }
+        self.box_edges(&corners, color);
+    }
+
+    fn box_edges(&mut self, corners: &[Point3<f32>; 8], color: [f32; 4]) {
+        for (start, end) in [
+            (0, 1),
+            (2, 3),
+            (4, 5),
+            (6, 7),
+            (0, 2),
+            (1, 3),
+            (4, 6),
+            (5, 7),
+            (0, 4),
+            (1, 5),
+            (2, 6),
+            (3, 7),
+        ] {
+            self.line(&corners[start], &corners[end], color);
+        }
+    }
+}
+
+pub struct DebugDrawPass {
+    pipeline: Arc<GraphicsPipeline>,
+    overlay_pipeline: Arc<GraphicsPipeline>,
+    vertex_allocator: TrackedSubbufferAllocator,
+}
+
+impl DebugDrawPass {
+    pub fn new(
+        device: &Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        pipeline_cache: &Arc<PipelineCache>,
+        samples: SampleCount,
+    ) -> Result<Self> {
+        let vertex_shader = debug_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = DebugVertex::per_vertex()
+            .definition(&vertex_shader)
+            .unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(
+                debug_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(Arc::clone(device))
+                .unwrap(),
+        )?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let create_pipeline = |depth: Option<DepthState>| {
+            GraphicsPipeline::new(
+                Arc::clone(device),
+                Some(Arc::clone(pipeline_cache)),
+                GraphicsPipelineCreateInfo {
+                    stages: stages.iter().cloned().collect(),
+                    vertex_input_state: Some(vertex_input_state.clone()),
+                    input_assembly_state: Some(InputAssemblyState {
+                        topology: PrimitiveTopology::LineList,
+                        ..Default::default()
+                    }),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    depth_stencil_state: Some(DepthStencilState {
+                        depth,
+                        ..Default::default()
+                    }),
+                    multisample_state: Some(MultisampleState {
+                        rasterization_samples: samples,
+                        ..Default::default()
+                    }),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        1,
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(subpass.clone().into()),
+                    ..GraphicsPipelineCreateInfo::layout(Arc::clone(&layout))
+                },
+            )
+        };
+
+        let pipeline = create_pipeline(Some(DepthState {
+            write_enable: false,
+            compare_op: CompareOp::LessOrEqual,
+        }))?;
+        let overlay_pipeline = create_pipeline(None)?;
+
+        let vertex_allocator = TrackedSubbufferAllocator::new(SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::VERTEX_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        ));
+
+        Ok(Self {
+            pipeline,
+            overlay_pipeline,
+            vertex_allocator,
+        })
+    }
+
+    pub fn arena_statistics(&self) -> ArenaStatistics {
+        self.vertex_allocator.statistics("debug draw vertices")
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        debug_draw: &DebugDraw,
+    ) -> Result<()> {
+        self.record(builder, vulkan_device, &self.pipeline, debug_draw)
+    }
+
+    pub fn draw_overlay(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        debug_draw: &DebugDraw,
+    ) -> Result<()> {
+        self.record(builder, vulkan_device, &self.overlay_pipeline, debug_draw)
+    }
+
+    fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        pipeline: &Arc<GraphicsPipeline>,
+        debug_draw: &DebugDraw,
+    ) -> Result<()> {
+        if debug_draw.is_empty() {
+            return Ok(());
+        }
+
+        let vertex_buffer = self
+            .vertex_allocator
+            .allocate_slice::<DebugVertex>(debug_draw.vertices.len() as DeviceSize)?;
+        vertex_buffer.write()?.copy_from_slice(&debug_draw.vertices);
+
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone())],
+            [],
+        )?;
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(pipeline))?
+            .bind_vertex_buffers(0, vertex_buffer)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(pipeline.layout()),
+                0,
+                set,
+            )?;
+        unsafe { builder.draw(debug_draw.vertices.len() as u32, 1, 0, 0) }?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Result};
+use half::f16;
+use image::RgbaImage;
+use vulkano::buffer::{BufferContents, BufferUsage, Subbuffer};
+use vulkano::format::Format;
+use vulkano::image::{Image, ImageAspects, ImageUsage, SampleCount};
+use vulkano::sync::future::FenceSignalFuture;
+use vulkano::sync::GpuFuture;
+use vulkano::DeviceSize;
+
+// A copy to host memory with its own submission, which tools can check on or wait for whenever
+// suits them instead of tying it to the renderer's frames like `Readback`.
+pub struct Download<T: BufferContents> {
+    future: FenceSignalFuture<Box<dyn GpuFuture>>,
+    buffer: Subbuffer<[T]>,
+}
+
+impl<T: BufferContents + Copy> Download<T> {
+    pub(crate) fn new(
+        future: FenceSignalFuture<Box<dyn GpuFuture>>,
+        buffer: Subbuffer<[T]>,
+    ) -> Self {
+        Self { future, buffer }
+    }
+
+    pub fn is_ready(&self) -> Result<bool> {
+        Ok(self.future.is_signaled()?)
+    }
+
+    pub fn wait(self) -> Result<Vec<T>> {
+        self.future.wait(None)?;
+        Ok(self.buffer.read()?.to_vec())
+    }
+}
+
+pub struct ImageDownload {
+    download: Download<u8>,
+    format: Format,
+    extent: [u32; 3],
+    array_layers: u32,
+}
+
+impl ImageDownload {
+    pub(crate) fn new(download: Download<u8>, image: &Arc<Image>) -> Self {
+        Self {
+            download,
+            format: image.format(),
+            extent: image.extent(),
+            array_layers: image.array_layers(),
+        }
+    }
+
+    pub fn is_ready(&self) -> Result<bool> {
+        self.download.is_ready()
+    }
+
+    pub fn wait(self) -> Result<DownloadedImage> {
+        Ok(DownloadedImage {
+            format: self.format,
+            extent: self.extent,
+            array_layers: self.array_layers,
+            data: self.download.wait()?,
+        })
+    }
+}
+
+// The first mip level of an image, with its texels tightly packed row by row and layer by layer
+// in the image's own format.
+#[derive(Clone, Debug)]
+pub struct DownloadedImage {
+    format: Format,
+    extent: [u32; 3],
+    array_layers: u32,
+    data: Vec<u8>,
+}
+
+impl DownloadedImage {
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> [u32; 3] {
+        self.extent
+    }
+
+    pub fn array_layers(&self) -> u32 {
+        self.array_layers
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // The values a shader would sample, so sRGB formats come back linear and depth lands in red.
+    pub fn texels(&self) -> Result<Vec<[f32; 4]>> {
+        let texel_size = self.format.block_size() as usize;
+        self.data
+            .chunks_exact(texel_size)
+            .map(|texel| decode_texel(self.format, texel))
+            .collect()
+    }
+
+    // The first layer as 8-bit RGBA for saving. sRGB formats keep their encoding and everything else
+    // is clamped and quantized as it is, like storing it in an `R8G8B8A8_UNORM` image would.
+    pub fn to_rgba8(&self) -> Result<RgbaImage> {
+        let [width, height, _] = self.extent;
+        let srgb = is_srgb(self.format);
+        let pixels = self
+            .texels()?
+            .into_iter()
+            .take(width as usize * height as usize)
+            .flat_map(|texel| {
+                let [red, green, blue, alpha] = texel;
+                let encode = |channel: f32| {
+                    if srgb {
+                        linear_to_srgb(channel)
+                    } else {
+                        channel
+                    }
+                };
+                [encode(red), encode(green), encode(blue), alpha]
+                    .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect();
+        Ok(RgbaImage::from_raw(width, height, pixels).unwrap())
+    }
+}
+
+// What the copy into host memory needs from an image, checked up front for a clearer error than
+// the copy's validation gives.
+pub(crate) fn image_download_size(image: &Image) -> Result<DeviceSize> {
+    ensure!(
+        image.usage().intersects(ImageUsage::TRANSFER_SRC),
+        "Downloading an image needs it to be created with TRANSFER_SRC usage"
+    );
+    ensure!(
+        image.samples() == SampleCount::Sample1,
+        "Multisampled images have to be resolved before downloading them"
+    );
+    ensure!(
+        image.format().compression().is_none(),
+        "Downloading compressed {:?} images isn't supported",
+        image.format()
+    );
+    ensure!(
+        !image
+            .format()
+            .aspects()
+            .contains(ImageAspects::DEPTH | ImageAspects::STENCIL),
+        "Downloading combined depth/stencil images isn't supported"
+    );
+
+    let [width, height, depth] = image.extent();
+    Ok(image.format().block_size()
+        * width as DeviceSize
+        * height as DeviceSize
+        * depth as DeviceSize
+        * image.array_layers() as DeviceSize)
+}
+
+pub(crate) fn ensure_buffer_downloadable<T: BufferContents + ?Sized>(
+    buffer: &Subbuffer<T>,
+) -> Result<()> {
+    ensure!(
+        buffer
+            .buffer()
+            .usage()
+            .intersects(BufferUsage::TRANSFER_SRC),
+        "Downloading a buffer needs it to be created with TRANSFER_SRC usage"
+    );
+    Ok(())
+}
+
+pub(crate) fn decode_texel(format: Format, texel: &[u8]) -> Result<[f32; 4]> {
+    let unorm8 = |channel: u8| channel as f32 / 255.0;
+    let half = |offset: usize| f16::from_le_bytes([texel[offset], texel[offset + 1]]).to_f32();
+    let float = |offset: usize| {
+        f32::from_le_bytes([
+            texel[offset],
+            texel[offset + 1],
+            texel[offset + 2],
+            texel[offset + 3],
+        ])
+    };
+    let uint = |offset: usize| float(offset).to_bits() as f32;
+
+    Ok(match format {
+        Format::R8_UNORM => [unorm8(texel[0]), 0.0, 0.0, 1.0],
+        Format::R8G8B8A8_UNORM => texel_array(texel).map(unorm8),
+        Format::B8G8R8A8_UNORM => [texel[2], texel[1], texel[0], texel[3]].map(unorm8),
+        Format::R8G8B8A8_SRGB => {
+            let [red, green, blue, alpha] = texel_array(texel).map(unorm8);
+            let [red, green, blue] = [red, green, blue].map(srgb_to_linear);
+            [red, green, blue, alpha]
+        }
+        Format::B8G8R8A8_SRGB => {
+            let [blue, green, red, alpha] = texel_array(texel).map(unorm8);
+            let [red, green, blue] = [red, green, blue].map(srgb_to_linear);
+            [red, green, blue, alpha]
+        }
+        Format::A2B10G10R10_UNORM_PACK32 => {
+            let packed = u32::from_le_bytes(texel_array(texel));
+            [
+                (packed & 0x3ff) as f32 / 1023.0,
+                ((packed >> 10) & 0x3ff) as f32 / 1023.0,
+                ((packed >> 20) & 0x3ff) as f32 / 1023.0,
+                (packed >> 30) as f32 / 3.0,
+            ]
+        }
+        Format::R16_SFLOAT => [half(0), 0.0, 0.0, 1.0],
+        Format::R16G16_SFLOAT => [half(0), half(2), 0.0, 1.0],
+        Format::R16G16B16A16_SFLOAT => [half(0), half(2), half(4), half(6)],
+        Format::R32_SFLOAT | Format::D32_SFLOAT => [float(0), 0.0, 0.0, 1.0],
+        Format::R32G32B32A32_SFLOAT => [float(0), float(4), float(8), float(12)],
+        Format::R32G32_UINT => [uint(0), uint(4), 0.0, 1.0],
+        format => bail!("Converting {format:?} texels isn't supported"),
+    })
+}
+
+fn texel_array(texel: &[u8]) -> [u8; 4] {
+    [texel[0], texel[1], texel[2], texel[3]]
+}
+
+fn is_srgb(format: Format) -> bool {
+    matches!(format, Format::R8G8B8A8_SRGB | Format::B8G8R8A8_SRGB)
+}
+
+fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(channel: f32) -> f32 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    }
+}
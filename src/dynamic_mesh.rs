@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CopyBufferInfo, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::DescriptorSet;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::DeviceSize;
+
+use crate::vulkan_device::{vs, Vertex, VulkanDevice};
+
+// Enough for double buffering; the ring grows when more frames are in flight than that.
+const INITIAL_MAPPED_BUFFERS: usize = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DynamicMeshMode {
+    // Vertices are written straight into host-visible buffers the GPU reads from, one per frame in
+    // flight. Best when every vertex changes every frame.
+    #[default]
+    Mapped,
+    // Vertices are staged and copied into a device-local buffer, which is faster to draw from on
+    // discrete GPUs but costs a copy per update.
+    Staged,
+}
+
+// A mesh whose vertices change from frame to frame, such as CPU skinning or streamed geometry. The
+// indices are fixed and drawn with the scene's pipeline, so they follow the scene's topology.
+pub struct DynamicMesh {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    mode: DynamicMeshMode,
+    capacity: DeviceSize,
+    // Mapped: a ring of host-visible buffers. Staged: the single device-local buffer.
+    vertex_buffers: Vec<Subbuffer<[Vertex]>>,
+    current: usize,
+    staging_allocator: SubbufferAllocator,
+    pending_upload: Option<Subbuffer<[Vertex]>>,
+    index_buffer: Subbuffer<[u16]>,
+    vertex_count: u32,
+}
+
+impl DynamicMesh {
+    pub fn new(
+        vulkan_device: &VulkanDevice,
+        capacity: DeviceSize,
+        indices: &[u16],
+        mode: DynamicMeshMode,
+    ) -> Result<Self> {
+        ensure!(!indices.is_empty(), "A dynamic mesh needs indices");
+        let memory_allocator = vulkan_device.memory_allocator();
+        let buffer_count = match mode {
+            DynamicMeshMode::Mapped => INITIAL_MAPPED_BUFFERS,
+            DynamicMeshMode::Staged => 1,
+        };
+        let vertex_buffers = (0..buffer_count)
+            .map(|_| create_vertex_buffer(memory_allocator, mode, capacity))
+            .try_collect::<Vec<_>>()?;
+
+        let index_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::INDEX_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            indices.iter().copied(),
+        )?;
+
+        let staging_allocator = SubbufferAllocator::new(
+            memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::TRANSFER_SRC,
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        Ok(Self {
+            memory_allocator: Arc::clone(memory_allocator),
+            mode,
+            capacity,
+            vertex_buffers,
+            current: 0,
+            staging_allocator,
+            pending_upload: None,
+            index_buffer,
+            vertex_count: 0,
+        })
+    }
+
+    // Takes effect for the next frame recorded, and never waits on the GPU.
+    pub fn update(&mut self, vertices: &[Vertex]) -> Result<()> {
+        ensure!(
+            vertices.len() as DeviceSize <= self.capacity,
+            "{} vertices don't fit a dynamic mesh of {}",
+            vertices.len(),
+            self.capacity
+        );
+
+        match self.mode {
+            DynamicMeshMode::Mapped => {
+                let next = (self.current + 1) % self.vertex_buffers.len();
+                // Vulkano refuses host access to buffers pending on the GPU, which means every
+                // buffer in the ring is in flight; add another one instead of stalling.
+                if self.vertex_buffers[next].write().is_err() {
+                    self.vertex_buffers.insert(
+                        next,
+                        create_vertex_buffer(&self.memory_allocator, self.mode, self.capacity)?,
+                    );
+                }
+                self.vertex_buffers[next].write()?[..vertices.len()].copy_from_slice(vertices);
+                self.current = next;
+            }
+            DynamicMeshMode::Staged => {
+                let staging_buffer = self
+                    .staging_allocator
+                    .allocate_slice::<Vertex>(vertices.len() as DeviceSize)?;
+                staging_buffer.write()?.copy_from_slice(vertices);
+                self.pending_upload = Some(staging_buffer);
+            }
+        }
+
+        self.vertex_count = vertices.len() as u32;
+        Ok(())
+    }
+
+    // Copies the latest staged update; has to be recorded outside of rendering, before the draw.
+    // The copy's barrier is inserted by vulkano.
+    pub fn record_upload(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<()> {
+        if let Some(staging_buffer) = self.pending_upload.take() {
+            let size = staging_buffer.len();
+            builder.copy_buffer(CopyBufferInfo::buffers(
+                staging_buffer,
+                self.vertex_buffers[0].clone().slice(..size),
+            ))?;
+        }
+        Ok(())
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: &Arc<GraphicsPipeline>,
+        descriptor_sets: Vec<Arc<DescriptorSet>>,
+        push_constants: vs::PushConstantData,
+    ) -> Result<()> {
+        if self.vertex_count == 0 {
+            return Ok(());
+        }
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(pipeline))?
+            .bind_vertex_buffers(0, self.vertex_buffers[self.current].clone())?
+            .bind_index_buffer(self.index_buffer.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(pipeline.layout()),
+                0,
+                descriptor_sets,
+            )?
+            .push_constants(Arc::clone(pipeline.layout()), 0, push_constants)?;
+        unsafe { builder.draw_indexed(self.index_count(), 1, 0, 0, 0) }?;
+        Ok(())
+    }
+
+    pub fn mode(&self) -> DynamicMeshMode {
+        self.mode
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_buffer.len() as u32
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        self.vertex_buffers
+            .iter()
+            .map(|vertex_buffer| vertex_buffer.size())
+            .sum::<DeviceSize>()
+            + self.index_buffer.size()
+    }
+}
+
+fn create_vertex_buffer(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    mode: DynamicMeshMode,
+    capacity: DeviceSize,
+) -> Result<Subbuffer<[Vertex]>> {
+    let (usage, memory_type_filter) = match mode {
+        DynamicMeshMode::Mapped => (
+            BufferUsage::VERTEX_BUFFER,
+            MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+        ),
+        DynamicMeshMode::Staged => (
+            BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_DST,
+            MemoryTypeFilter::PREFER_DEVICE,
+        ),
+    };
+    Ok(Buffer::new_slice(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter,
+            ..Default::default()
+        },
+        capacity,
+    )?)
+}
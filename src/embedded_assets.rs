@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tracing::warn;
+
+use crate::sprite::{SpriteTexture, TextureEncoding};
+use crate::vulkan_device::VulkanDevice;
+
+pub const SCENE_PATH: &str = "assets/cube.gltf";
+
+// Copies of the default assets built into the binary, so it still runs when started away from the
+// repository. The glTF embeds its buffers as data URIs, so it imports without any other file.
+#[cfg(feature = "embedded-assets")]
+const CUBE_GLTF: &[u8] = include_bytes!("../assets/cube.gltf");
+#[cfg(feature = "embedded-assets")]
+const CHECKER_PNG: &[u8] = include_bytes!("../assets/checker.png");
+
+pub type GltfImport = (
+    gltf::Document,
+    Vec<gltf::buffer::Data>,
+    Vec<gltf::image::Data>,
+);
+
+// A scene that can't be read from disk falls back to the embedded cube. Files that exist but fail
+// to parse are still an error.
+pub fn import_scene(path: impl AsRef<Path>) -> Result<GltfImport> {
+    let path = path.as_ref();
+    match gltf::import(path) {
+        Ok(import) => Ok(import),
+        #[cfg(feature = "embedded-assets")]
+        Err(gltf::Error::Io(error)) => {
+            warn!(
+                "Failed to read {}: {error}, using the embedded cube",
+                path.display()
+            );
+            Ok(gltf::import_slice(CUBE_GLTF)?)
+        }
+        Err(error) => Err(error).with_context(|| format!("Failed to load {}", path.display())),
+    }
+}
+
+// An 8x8 grey checkerboard, or plain white without the embedded assets.
+pub fn checker_texture(vulkan_device: &VulkanDevice) -> Result<SpriteTexture> {
+    #[cfg(feature = "embedded-assets")]
+    {
+        let image = image::load_from_memory(CHECKER_PNG)?.to_rgba8();
+        SpriteTexture::from_rgba(
+            vulkan_device,
+            image.dimensions().into(),
+            image.as_raw(),
+            TextureEncoding::Srgb,
+        )
+    }
+    #[cfg(not(feature = "embedded-assets"))]
+    SpriteTexture::white(vulkan_device)
+}
+
+// Textures that fail to load are replaced by the checkerboard so the mistake stays visible.
+pub fn load_texture_or_checker(
+    vulkan_device: &VulkanDevice,
+    path: impl AsRef<Path>,
+) -> Result<SpriteTexture> {
+    let path = path.as_ref();
+    SpriteTexture::load(vulkan_device, path, TextureEncoding::for_path(path)).or_else(|error| {
+        warn!(
+            "Failed to load {}: {error}, using a checker texture",
+            path.display()
+        );
+        checker_texture(vulkan_device)
+    })
+}
@@ -0,0 +1,322 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, BlitImageInfo, ImageBlit, PrimaryAutoCommandBuffer,
+};
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::image::sampler::Filter;
+use vulkano::image::view::{ImageView, ImageViewCreateInfo, ImageViewType};
+use vulkano::image::{
+    Image, ImageAspects, ImageCreateFlags, ImageCreateInfo, ImageSubresourceLayers,
+    ImageSubresourceRange, ImageUsage,
+};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter};
+use vulkano::pipeline::Pipeline;
+
+use crate::ibl::ImageBasedLighting;
+use crate::light::DirectionalLight;
+use crate::sky::SkySettings;
+use crate::vulkan_device::{VulkanDevice, HDR_FORMAT};
+
+mod equirectangular_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0) readonly buffer Equirectangular {
+                    vec4 texels[];
+                } equirectangular;
+
+                layout(set = 0, binding = 1, rgba16f) uniform writeonly image2DArray cubemap;
+
+                layout(push_constant) uniform ConversionParameters {
+                    uint width;
+                    uint height;
+                    uint faceSize;
+                } parameters;
+
+                const float PI = 3.14159265359;
+
+                vec3 cubeDirection(uvec3 id) {
+                    vec2 uv = (vec2(id.xy) + 0.5) / float(parameters.faceSize) * 2.0 - 1.0;
+                    switch (id.z) {
+                        case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+                        case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+                        case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+                        case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+                        case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+                        default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+                    }
+                }
+
+                vec3 fetch(ivec2 position) {
+                    int width = int(parameters.width);
+                    int height = int(parameters.height);
+                    position.x = (position.x % width + width) % width;
+                    position.y = clamp(position.y, 0, height - 1);
+                    return equirectangular.texels[position.y * width + position.x].rgb;
+                }
+
+                void main() {
+                    if (any(greaterThanEqual(gl_GlobalInvocationID.xy, uvec2(parameters.faceSize)))) {
+                        return;
+                    }
+
+                    vec3 direction = cubeDirection(gl_GlobalInvocationID);
+                    vec2 uv = vec2(
+                        atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+                        acos(clamp(direction.y, -1.0, 1.0)) / PI
+                    );
+
+                    vec2 position = uv * vec2(parameters.width, parameters.height) - 0.5;
+                    ivec2 base = ivec2(floor(position));
+                    vec2 weight = fract(position);
+
+                    vec3 color = mix(
+                        mix(fetch(base), fetch(base + ivec2(1, 0)), weight.x),
+                        mix(fetch(base + ivec2(0, 1)), fetch(base + ivec2(1, 1)), weight.x),
+                        weight.y
+                    );
+
+                    imageStore(cubemap, ivec3(gl_GlobalInvocationID), vec4(color, 1.0));
+                }
+            ",
+    }
+}
+
+pub struct EnvironmentMap {
+    cubemap: Arc<Image>,
+    cube_view: Arc<ImageView>,
+    lighting: ImageBasedLighting,
+    sun: Option<DirectionalLight>,
+}
+
+impl EnvironmentMap {
+    pub fn load_equirectangular(
+        vulkan_device: &VulkanDevice,
+        path: impl AsRef<Path>,
+        face_size: u32,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let device = vulkan_device.queue().device();
+        let equirectangular = image::open(path)?.to_rgba32f();
+
+        let equirectangular_buffer = Buffer::from_iter(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            equirectangular.pixels().map(|pixel| pixel.0),
+        )?;
+
+        let pipeline = vulkan_device.create_compute_pipeline(
+            equirectangular_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+
+        Self::generate(vulkan_device, face_size, None, |builder, target| {
+            vulkan_device
+                .bind_compute(
+                    builder,
+                    &pipeline,
+                    [
+                        WriteDescriptorSet::buffer(0, equirectangular_buffer),
+                        WriteDescriptorSet::image_view(1, target),
+                    ],
+                )?
+                .push_constants(
+                    Arc::clone(pipeline.layout()),
+                    0,
+                    equirectangular_cs::ConversionParameters {
+                        width: equirectangular.width(),
+                        height: equirectangular.height(),
+                        faceSize: face_size,
+                    },
+                )?;
+            unsafe { builder.dispatch([face_size.div_ceil(8), face_size.div_ceil(8), 6]) }?;
+
+            Ok(())
+        })
+        .with_context(|| format!("Failed to load environment map {}", path.display()))
+    }
+
+    pub fn procedural_sky(
+        vulkan_device: &VulkanDevice,
+        settings: &SkySettings,
+        face_size: u32,
+    ) -> Result<Self> {
+        Self::generate(
+            vulkan_device,
+            face_size,
+            Some(settings.sun_light()),
+            |builder, target| settings.record(builder, vulkan_device, target),
+        )
+    }
+
+    fn generate(
+        vulkan_device: &VulkanDevice,
+        face_size: u32,
+        sun: Option<DirectionalLight>,
+        record: impl FnOnce(
+            &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+            Arc<ImageView>,
+        ) -> Result<()>,
+    ) -> Result<Self> {
+        let mip_levels = u32::BITS - face_size.leading_zeros();
+        let cubemap = create_cubemap(
+            vulkan_device.memory_allocator().clone(),
+            face_size,
+            mip_levels,
+            ImageUsage::STORAGE
+                | ImageUsage::SAMPLED
+                | ImageUsage::TRANSFER_SRC
+                | ImageUsage::TRANSFER_DST,
+        )?;
+        let cube_view = create_cube_view(&cubemap)?;
+        let target = create_face_array_view(&cubemap, 0)?;
+
+        let mut lighting = None;
+        vulkan_device.submit_and_wait(|builder| {
+            record(builder, target)?;
+
+            record_cubemap_mips(builder, &cubemap)?;
+
+            lighting = Some(ImageBasedLighting::new(
+                vulkan_device.memory_allocator().clone(),
+                vulkan_device.descriptor_set_allocator(),
+                vulkan_device.pipeline_cache(),
+                builder,
+                &cube_view,
+            )?);
+            Ok(())
+        })?;
+
+        Ok(Self {
+            cubemap,
+            cube_view,
+            lighting: lighting.context("Failed to precompute image-based lighting")?,
+            sun,
+        })
+    }
+
+    pub fn find_in(directory: impl AsRef<Path>) -> Option<PathBuf> {
+        std::fs::read_dir(directory)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .find(|path| {
+                matches!(
+                    path.extension().and_then(|extension| extension.to_str()),
+                    Some("hdr" | "exr")
+                )
+            })
+    }
+
+    pub fn cubemap(&self) -> &Arc<Image> {
+        &self.cubemap
+    }
+
+    pub fn cube_view(&self) -> &Arc<ImageView> {
+        &self.cube_view
+    }
+
+    pub fn lighting(&self) -> &ImageBasedLighting {
+        &self.lighting
+    }
+
+    pub fn sun(&self) -> Option<&DirectionalLight> {
+        self.sun.as_ref()
+    }
+}
+
+pub fn create_cubemap(
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    face_size: u32,
+    mip_levels: u32,
+    usage: ImageUsage,
+) -> Result<Arc<Image>> {
+    Ok(Image::new(
+        memory_allocator,
+        ImageCreateInfo {
+            flags: ImageCreateFlags::CUBE_COMPATIBLE,
+            format: HDR_FORMAT,
+            extent: [face_size, face_size, 1],
+            array_layers: 6,
+            mip_levels,
+            usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )?)
+}
+
+// Downsamples mip level 0 of every face into the rest of the chain.
+pub fn record_cubemap_mips(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    cubemap: &Arc<Image>,
+) -> Result<()> {
+    let face_size = cubemap.extent()[0];
+    let mip_levels = cubemap.mip_levels();
+    for mip_level in 1..mip_levels {
+        let source_size = (face_size >> (mip_level - 1)).max(1);
+        let destination_size = (face_size >> mip_level).max(1);
+        builder.blit_image(BlitImageInfo {
+            regions: [ImageBlit {
+                src_subresource: ImageSubresourceLayers {
+                    aspects: ImageAspects::COLOR,
+                    mip_level: mip_level - 1,
+                    array_layers: 0..6,
+                },
+                src_offsets: [[0, 0, 0], [source_size, source_size, 1]],
+                dst_subresource: ImageSubresourceLayers {
+                    aspects: ImageAspects::COLOR,
+                    mip_level,
+                    array_layers: 0..6,
+                },
+                dst_offsets: [[0, 0, 0], [destination_size, destination_size, 1]],
+                ..Default::default()
+            }]
+            .into(),
+            filter: Filter::Linear,
+            ..BlitImageInfo::images(Arc::clone(cubemap), Arc::clone(cubemap))
+        })?;
+    }
+    Ok(())
+}
+
+pub fn create_cube_view(cubemap: &Arc<Image>) -> Result<Arc<ImageView>> {
+    Ok(ImageView::new(
+        Arc::clone(cubemap),
+        ImageViewCreateInfo {
+            view_type: ImageViewType::Cube,
+            ..ImageViewCreateInfo::from_image(cubemap)
+        },
+    )?)
+}
+
+pub fn create_face_array_view(cubemap: &Arc<Image>, mip_level: u32) -> Result<Arc<ImageView>> {
+    Ok(ImageView::new(
+        Arc::clone(cubemap),
+        ImageViewCreateInfo {
+            view_type: ImageViewType::Dim2dArray,
+            subresource_range: ImageSubresourceRange {
+                aspects: ImageAspects::COLOR,
+                mip_levels: mip_level..mip_level + 1,
+                array_layers: 0..6,
+            },
+            ..ImageViewCreateInfo::from_image(cubemap)
+        },
+    )?)
+}
@@ -0,0 +1,639 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use nalgebra::Vector3;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CopyBufferInfo, DrawIndirectCommand, PrimaryAutoCommandBuffer,
+};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineShaderStageCreateInfo,
+};
+
+use crate::compute::create_compute_pipeline;
+use crate::light::DirectionalLight;
+use crate::vulkan_device::{
+    alpha_masked_multisample_state, specialize, VulkanDevice, DEPTH_FORMAT, HDR_FORMAT,
+};
+
+const BLADE_VERTEX_COUNT: u32 = 15;
+const BLADE_HEIGHT: f32 = 1.0;
+
+mod cull_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+                struct Instance {
+                    vec3 position;
+                    float yaw;
+                    float scale;
+                    uint cell;
+                };
+
+                struct Cell {
+                    vec4 minimum;
+                    vec4 maximum;
+                };
+
+                layout(set = 0, binding = 0) readonly buffer Instances {
+                    Instance instances[];
+                };
+
+                layout(set = 0, binding = 1) readonly buffer Cells {
+                    Cell cells[];
+                };
+
+                layout(set = 0, binding = 2) writeonly buffer VisibleInstances {
+                    Instance visibleInstances[];
+                };
+
+                layout(set = 0, binding = 3) buffer IndirectCommand {
+                    uint vertexCount;
+                    uint instanceCount;
+                    uint firstVertex;
+                    uint firstInstance;
+                } command;
+
+                layout(set = 0, binding = 4) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(push_constant) uniform CullParameters {
+                    uint instanceCount;
+                    float maxDistance;
+                } parameters;
+
+                bool isCellVisible(Cell cell) {
+                    mat4 rows = transpose(uniforms.view_projection);
+                    vec4 planes[6] = vec4[](
+                        rows[3] + rows[0],
+                        rows[3] - rows[0],
+                        rows[3] + rows[1],
+                        rows[3] - rows[1],
+                        rows[3] + rows[2],
+                        rows[3] - rows[2]
+                    );
+
+                    for (uint i = 0u; i < 6u; i++) {
+                        vec3 farthest = mix(cell.minimum.xyz, cell.maximum.xyz, step(0.0, planes[i].xyz));
+                        if (dot(planes[i].xyz, farthest) + planes[i].w < 0.0) {
+                            return false;
+                        }
+                    }
+                    return true;
+                }
+
+                void main() {
+                    uint index = gl_GlobalInvocationID.x;
+                    if (index >= parameters.instanceCount) {
+                        return;
+                    }
+
+                    Instance instance = instances[index];
+                    if (!isCellVisible(cells[instance.cell])) {
+                        return;
+                    }
+
+                    vec3 viewPosition = (uniforms.view * vec4(instance.position, 1.0)).xyz;
+                    if (length(viewPosition) > parameters.maxDistance) {
+                        return;
+                    }
+
+                    visibleInstances[atomicAdd(command.instanceCount, 1u)] = instance;
+                }
+            ",
+    }
+}
+
+mod blade_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                struct Instance {
+                    vec3 position;
+                    float yaw;
+                    float scale;
+                    uint cell;
+                };
+
+                layout(location = 0) out vec3 worldNormal;
+                layout(location = 1) out float bladeHeight;
+                layout(location = 2) out float bladeSide;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(set = 0, binding = 1) readonly buffer VisibleInstances {
+                    Instance visibleInstances[];
+                };
+
+                layout(push_constant) uniform BladeParameters {
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                    float time;
+                    float windStrength;
+                } parameters;
+
+                const vec2 BLADE[15] = vec2[](
+                    vec2(-0.05, 0.0), vec2(0.05, 0.0), vec2(-0.04, 0.4),
+                    vec2(-0.04, 0.4), vec2(0.05, 0.0), vec2(0.04, 0.4),
+                    vec2(-0.04, 0.4), vec2(0.04, 0.4), vec2(-0.025, 0.75),
+                    vec2(-0.025, 0.75), vec2(0.04, 0.4), vec2(0.025, 0.75),
+                    vec2(-0.025, 0.75), vec2(0.025, 0.75), vec2(0.0, 1.0)
+                );
+
+                void main() {
+                    Instance instance = visibleInstances[gl_InstanceIndex];
+                    vec2 vertex = BLADE[gl_VertexIndex];
+
+                    vec3 side = vec3(cos(instance.yaw), 0.0, sin(instance.yaw));
+                    vec3 facing = vec3(-side.z, 0.0, side.x);
+
+                    float sway = sin(parameters.time * 1.7 + instance.position.x * 0.6 + instance.position.z * 0.4);
+                    vec3 bend = facing * sway * parameters.windStrength * vertex.y * vertex.y;
+
+                    vec3 position = instance.position
+                        + (side * vertex.x + vec3(0.0, vertex.y, 0.0) + bend) * instance.scale;
+
+                    worldNormal = normalize(facing + vec3(0.0, 0.3, 0.0));
+                    bladeHeight = vertex.y;
+                    bladeSide = sign(vertex.x);
+                    gl_Position = uniforms.view_projection * vec4(position, 1.0);
+                }
+            ",
+    }
+}
+
+mod blade_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 worldNormal;
+                layout(location = 1) in float bladeHeight;
+                layout(location = 2) in float bladeSide;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(constant_id = 0) const bool ALPHA_TO_COVERAGE = false;
+
+                layout(push_constant) uniform BladeParameters {
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                    float time;
+                    float windStrength;
+                } parameters;
+
+                void main() {
+                    vec3 albedo = mix(vec3(0.05, 0.18, 0.03), vec3(0.35, 0.6, 0.15), bladeHeight);
+                    vec3 normal = gl_FrontFacing ? worldNormal : -worldNormal;
+                    float diffuse = max(dot(normal, parameters.sunDirection.xyz), 0.0);
+
+                    float edge = 1.0 - abs(bladeSide);
+                    float alpha = clamp(edge / max(fwidth(edge), 1e-4), 0.0, 1.0);
+                    if (!ALPHA_TO_COVERAGE) {
+                        if (alpha < 0.5) {
+                            discard;
+                        }
+                        alpha = 1.0;
+                    }
+
+                    outColor = vec4(albedo * (0.3 + diffuse * parameters.sunColor.rgb), alpha);
+                }
+            ",
+    }
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct FoliageInstance {
+    position: [f32; 3],
+    yaw: f32,
+    scale: f32,
+    cell: u32,
+    padding: [u32; 2],
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct FoliageCell {
+    minimum: [f32; 4],
+    maximum: [f32; 4],
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScatterSettings {
+    pub count: u32,
+    pub cell_size: f32,
+    pub scale_range: [f32; 2],
+    pub min_up: f32,
+    pub seed: u64,
+}
+
+impl Default for ScatterSettings {
+    fn default() -> Self {
+        Self {
+            count: 100_000,
+            cell_size: 4.0,
+            scale_range: [0.3, 0.6],
+            min_up: 0.6,
+            seed: 0x853c49e6748fea9b,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FoliageSettings {
+    pub max_distance: f32,
+    pub wind_strength: f32,
+}
+
+impl Default for FoliageSettings {
+    fn default() -> Self {
+        Self {
+            max_distance: 60.0,
+            wind_strength: 0.15,
+        }
+    }
+}
+
+pub struct Foliage {
+    instance_count: u32,
+    instance_buffer: Subbuffer<[FoliageInstance]>,
+    cell_buffer: Subbuffer<[FoliageCell]>,
+    visible_buffer: Subbuffer<[FoliageInstance]>,
+    indirect_buffer: Subbuffer<[DrawIndirectCommand]>,
+    indirect_reset_buffer: Subbuffer<[DrawIndirectCommand]>,
+}
+
+impl Foliage {
+    pub fn scatter(
+        vulkan_device: &VulkanDevice,
+        positions: &[[f32; 3]],
+        indices: &[u32],
+        settings: &ScatterSettings,
+    ) -> Result<Self> {
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                [triangle[0], triangle[1], triangle[2]]
+                    .map(|index| Vector3::from(positions[index as usize]))
+            })
+            .collect::<Vec<_>>();
+
+        let mut total_area = 0.0;
+        let cumulative_areas = triangles
+            .iter()
+            .map(|[a, b, c]| {
+                let cross = (b - a).cross(&(c - a));
+                let area = cross.norm() * 0.5;
+                if area > 0.0 && cross.y / (area * 2.0) >= settings.min_up {
+                    total_area += area;
+                }
+                total_area
+            })
+            .collect::<Vec<_>>();
+
+        if total_area <= 0.0 {
+            bail!("Foliage surface has no upward-facing triangles");
+        }
+
+        let mut random = Random::new(settings.seed);
+        let mut cells = HashMap::new();
+        let mut cell_bounds = Vec::<FoliageCell>::new();
+
+        let instances = (0..settings.count)
+            .map(|_| {
+                let target = random.next_f32() * total_area;
+                let triangle_index = cumulative_areas
+                    .partition_point(|&area| area < target)
+                    .min(triangles.len() - 1);
+                let [a, b, c] = triangles[triangle_index];
+
+                let u = random.next_f32().sqrt();
+                let v = random.next_f32();
+                let position = a * (1.0 - u) + b * (u * (1.0 - v)) + c * (u * v);
+                let scale = settings.scale_range[0]
+                    + random.next_f32() * (settings.scale_range[1] - settings.scale_range[0]);
+
+                let key = (
+                    (position.x / settings.cell_size).floor() as i32,
+                    (position.z / settings.cell_size).floor() as i32,
+                );
+                let cell = *cells.entry(key).or_insert_with(|| {
+                    cell_bounds.push(FoliageCell {
+                        minimum: [f32::MAX; 4],
+                        maximum: [f32::MIN; 4],
+                    });
+                    cell_bounds.len() as u32 - 1
+                });
+
+                let bounds = &mut cell_bounds[cell as usize];
+                let reach = Vector3::new(scale * 0.5, scale * BLADE_HEIGHT, scale * 0.5);
+                for axis in 0..3 {
+                    bounds.minimum[axis] = bounds.minimum[axis].min(position[axis] - reach[axis]);
+                    bounds.maximum[axis] = bounds.maximum[axis].max(position[axis] + reach[axis]);
+                }
+
+                FoliageInstance {
+                    position: position.into(),
+                    yaw: random.next_f32() * std::f32::consts::TAU,
+                    scale,
+                    cell,
+                    padding: [0; 2],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let memory_allocator = vulkan_device.memory_allocator();
+        let upload_allocation = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+
+        let instance_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            upload_allocation.clone(),
+            instances,
+        )?;
+
+        let cell_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            upload_allocation.clone(),
+            cell_bounds,
+        )?;
+
+        let visible_buffer = Buffer::new_slice(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            settings.count as u64,
+        )?;
+
+        let indirect_buffer = Buffer::new_slice(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER
+                    | BufferUsage::INDIRECT_BUFFER
+                    | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            1,
+        )?;
+
+        let indirect_reset_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            upload_allocation,
+            [DrawIndirectCommand {
+                vertex_count: BLADE_VERTEX_COUNT,
+                instance_count: 0,
+                first_vertex: 0,
+                first_instance: 0,
+            }],
+        )?;
+
+        Ok(Self {
+            instance_count: settings.count,
+            instance_buffer,
+            cell_buffer,
+            visible_buffer,
+            indirect_buffer,
+            indirect_reset_buffer,
+        })
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+}
+
+pub struct FoliagePass {
+    cull_pipeline: Arc<ComputePipeline>,
+    pipeline: Arc<GraphicsPipeline>,
+}
+
+impl FoliagePass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        samples: SampleCount,
+    ) -> Result<Self> {
+        let cull_pipeline = create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            cull_cs::load(Arc::clone(device))?.entry_point("main").unwrap(),
+        )?;
+
+        let vertex_shader = blade_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+        let fragment_shader = blade_fs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let multisample_state = alpha_masked_multisample_state(samples);
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(specialize(
+                &fragment_shader,
+                [(0, multisample_state.alpha_to_coverage_enable.into())],
+            )?),
+        ];
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(Arc::clone(device))
+                .unwrap(),
+        )?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: true,
+                        compare_op: CompareOp::LessOrEqual,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(multisample_state),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        Ok(Self {
+            cull_pipeline,
+            pipeline,
+        })
+    }
+
+    pub fn cull(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        foliage: &Foliage,
+        settings: &FoliageSettings,
+    ) -> Result<()> {
+        builder.copy_buffer(CopyBufferInfo::buffers(
+            foliage.indirect_reset_buffer.clone(),
+            foliage.indirect_buffer.clone(),
+        ))?;
+
+        vulkan_device
+            .bind_compute(
+                builder,
+                &self.cull_pipeline,
+                [
+                    WriteDescriptorSet::buffer(0, foliage.instance_buffer.clone()),
+                    WriteDescriptorSet::buffer(1, foliage.cell_buffer.clone()),
+                    WriteDescriptorSet::buffer(2, foliage.visible_buffer.clone()),
+                    WriteDescriptorSet::buffer(3, foliage.indirect_buffer.clone()),
+                    WriteDescriptorSet::buffer(4, vulkan_device.uniform_buffer().clone()),
+                ],
+            )?
+            .push_constants(
+                Arc::clone(self.cull_pipeline.layout()),
+                0,
+                cull_cs::CullParameters {
+                    instanceCount: foliage.instance_count,
+                    maxDistance: settings.max_distance,
+                },
+            )?;
+        unsafe { builder.dispatch([foliage.instance_count.div_ceil(64), 1, 1]) }?;
+
+        Ok(())
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        foliage: &Foliage,
+        settings: &FoliageSettings,
+        sun: &DirectionalLight,
+        time: f32,
+    ) -> Result<()> {
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+            [
+                WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone()),
+                WriteDescriptorSet::buffer(1, foliage.visible_buffer.clone()),
+            ],
+            [],
+        )?;
+
+        let sun_direction = sun.direction.normalize();
+        let [sun_r, sun_g, sun_b] = sun.color;
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                blade_vs::BladeParameters {
+                    sunDirection: [sun_direction.x, sun_direction.y, sun_direction.z, 0.0],
+                    sunColor: [sun_r, sun_g, sun_b, 1.0],
+                    time,
+                    windStrength: settings.wind_strength,
+                },
+            )?;
+        unsafe { builder.draw_indirect(foliage.indirect_buffer.clone()) }?;
+
+        Ok(())
+    }
+}
+
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
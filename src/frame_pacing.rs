@@ -0,0 +1,160 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use tracing::info;
+use vulkano::device::DeviceOwned;
+use vulkano::swapchain::Swapchain;
+use vulkano::VulkanObject;
+use winit::window::Window;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FramePacing {
+    #[default]
+    Off,
+    // The smallest multiple of the refresh interval that recent frames fit into.
+    Auto,
+    // Every n-th refresh.
+    Interval(u32),
+}
+
+impl FromStr for FramePacing {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "off" => FramePacing::Off,
+            "auto" => FramePacing::Auto,
+            interval => match interval.parse() {
+                Ok(0) | Err(_) => bail!("Unknown frame pacing: {value}"),
+                Ok(interval) => FramePacing::Interval(interval),
+            },
+        })
+    }
+}
+
+// vulkano doesn't wrap VK_GOOGLE_display_timing, so the refresh cycle is queried by hand.
+fn refresh_cycle_duration(swapchain: &Swapchain) -> Option<Duration> {
+    let device = swapchain.device();
+    if !device.enabled_extensions().google_display_timing {
+        return None;
+    }
+
+    let mut properties = ash::vk::RefreshCycleDurationGOOGLE::default();
+    let result = unsafe {
+        (device
+            .fns()
+            .google_display_timing
+            .get_refresh_cycle_duration_google)(
+            device.handle(),
+            swapchain.handle(),
+            &mut properties,
+        )
+    };
+    (result == ash::vk::Result::SUCCESS && properties.refresh_duration > 0)
+        .then(|| Duration::from_nanos(properties.refresh_duration))
+}
+
+// The display timing extension reports what the presentation engine actually uses; the
+// monitor's nominal mode is the fallback everywhere else.
+pub fn refresh_interval(
+    swapchain: &Swapchain,
+    monitor_refresh_interval: Option<Duration>,
+) -> Option<Duration> {
+    refresh_cycle_duration(swapchain).or(monitor_refresh_interval)
+}
+
+// The nominal refresh interval of the monitor the window is on. Only the event loop's thread may
+// ask, as some platforms block other threads querying the window on it.
+pub fn monitor_refresh_interval(window: &Window) -> Option<Duration> {
+    window
+        .current_monitor()?
+        .refresh_rate_millihertz()
+        .filter(|&millihertz| millihertz > 0)
+        .map(|millihertz| Duration::from_secs_f64(1000.0 / millihertz as f64))
+}
+
+// Fifo on a high refresh display shows a frame that just misses vsync for twice as long as its
+// neighbours. Holding frames to a fixed multiple of the refresh interval trades peak frame rate
+// for an even cadence.
+pub struct FramePacer {
+    pacing: FramePacing,
+    refresh_interval: Option<Duration>,
+    average_frame_time: Duration,
+    frame_start: Option<Instant>,
+    deadline: Option<Instant>,
+}
+
+impl FramePacer {
+    pub fn new(pacing: FramePacing) -> Self {
+        Self {
+            pacing,
+            refresh_interval: None,
+            average_frame_time: Duration::ZERO,
+            frame_start: None,
+            deadline: None,
+        }
+    }
+
+    pub fn set_refresh_interval(&mut self, refresh_interval: Option<Duration>) {
+        if self.refresh_interval != refresh_interval {
+            if let Some(refresh_interval) = refresh_interval {
+                info!(
+                    "Display refresh rate: {:.2} Hz",
+                    1.0 / refresh_interval.as_secs_f64()
+                );
+            }
+            self.refresh_interval = refresh_interval;
+            self.deadline = None;
+        }
+    }
+
+    fn target_interval(&self) -> Option<Duration> {
+        let refresh_interval = self.refresh_interval?;
+        let multiple = match self.pacing {
+            FramePacing::Off => return None,
+            // Frames throttled by Fifo measure whole intervals, so the tolerance keeps them from
+            // being pushed to the next multiple.
+            FramePacing::Auto => {
+                (self.average_frame_time.as_secs_f64() / refresh_interval.as_secs_f64() - 0.1)
+                    .ceil()
+                    .max(1.0) as u32
+            }
+            FramePacing::Interval(interval) => interval,
+        };
+        Some(refresh_interval * multiple)
+    }
+
+    // Sleeps until the next frame is due. Called once per frame, before simulating it.
+    pub fn pace(&mut self) {
+        if self.pacing == FramePacing::Off {
+            return;
+        }
+
+        let now = Instant::now();
+        if let Some(frame_start) = self.frame_start {
+            let frame_time = now - frame_start;
+            self.average_frame_time = (self.average_frame_time * 15 + frame_time) / 16;
+        }
+
+        let Some(target_interval) = self.target_interval() else {
+            self.frame_start = Some(now);
+            return;
+        };
+        if let Some(deadline) = self.deadline {
+            if deadline > now {
+                std::thread::sleep(deadline - now);
+            }
+        }
+
+        let frame_start = Instant::now();
+        self.frame_start = Some(frame_start);
+        // Deadlines advance by whole intervals to hold the cadence, and only resync after a miss.
+        self.deadline = Some(match self.deadline {
+            Some(deadline) if deadline + target_interval > frame_start => {
+                deadline + target_interval
+            }
+            _ => frame_start + target_interval,
+        });
+    }
+}
@@ -0,0 +1,288 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::{Pipeline, PipelineBindPoint};
+
+use crate::fullscreen::{begin_fullscreen_pass, create_fullscreen_pipeline};
+use crate::post_process::{PostProcessContext, PostProcessEffect};
+use crate::swapchain_format::OutputPipelines;
+use crate::vulkan_device::VulkanDevice;
+
+mod fxaa_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform sampler2D sceneTexture;
+
+                layout(push_constant) uniform FxaaParameters {
+                    float edgeThreshold;
+                    float edgeThresholdMin;
+                    float subpixelQuality;
+                } parameters;
+
+                const float STEPS[12] = float[](1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+                float luma(vec3 color) {
+                    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+                }
+
+                float lumaAt(vec2 coord) {
+                    return luma(textureLod(sceneTexture, coord, 0.0).rgb);
+                }
+
+                void main() {
+                    vec2 texel = 1.0 / vec2(textureSize(sceneTexture, 0));
+
+                    vec4 colorCenter = textureLod(sceneTexture, uv, 0.0);
+                    float lumaM = luma(colorCenter.rgb);
+                    float lumaN = luma(textureLodOffset(sceneTexture, uv, 0.0, ivec2(0, -1)).rgb);
+                    float lumaS = luma(textureLodOffset(sceneTexture, uv, 0.0, ivec2(0, 1)).rgb);
+                    float lumaE = luma(textureLodOffset(sceneTexture, uv, 0.0, ivec2(1, 0)).rgb);
+                    float lumaW = luma(textureLodOffset(sceneTexture, uv, 0.0, ivec2(-1, 0)).rgb);
+
+                    float lumaMin = min(lumaM, min(min(lumaN, lumaS), min(lumaE, lumaW)));
+                    float lumaMax = max(lumaM, max(max(lumaN, lumaS), max(lumaE, lumaW)));
+                    float range = lumaMax - lumaMin;
+
+                    if (range < max(parameters.edgeThresholdMin, lumaMax * parameters.edgeThreshold)) {
+                        outColor = colorCenter;
+                        return;
+                    }
+
+                    float lumaNW = luma(textureLodOffset(sceneTexture, uv, 0.0, ivec2(-1, -1)).rgb);
+                    float lumaNE = luma(textureLodOffset(sceneTexture, uv, 0.0, ivec2(1, -1)).rgb);
+                    float lumaSW = luma(textureLodOffset(sceneTexture, uv, 0.0, ivec2(-1, 1)).rgb);
+                    float lumaSE = luma(textureLodOffset(sceneTexture, uv, 0.0, ivec2(1, 1)).rgb);
+
+                    float edgeHorizontal =
+                        abs(0.25 * lumaNW - 0.5 * lumaW + 0.25 * lumaSW) +
+                        abs(0.5 * lumaN - lumaM + 0.5 * lumaS) +
+                        abs(0.25 * lumaNE - 0.5 * lumaE + 0.25 * lumaSE);
+                    float edgeVertical =
+                        abs(0.25 * lumaNW - 0.5 * lumaN + 0.25 * lumaNE) +
+                        abs(0.5 * lumaW - lumaM + 0.5 * lumaE) +
+                        abs(0.25 * lumaSW - 0.5 * lumaS + 0.25 * lumaSE);
+                    bool isHorizontal = edgeHorizontal >= edgeVertical;
+
+                    float luma1 = isHorizontal ? lumaN : lumaW;
+                    float luma2 = isHorizontal ? lumaS : lumaE;
+                    float gradient1 = luma1 - lumaM;
+                    float gradient2 = luma2 - lumaM;
+                    bool is1Steepest = abs(gradient1) >= abs(gradient2);
+                    float gradientScaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+                    float stepLength = isHorizontal ? texel.y : texel.x;
+                    float lumaLocalAverage;
+                    if (is1Steepest) {
+                        stepLength = -stepLength;
+                        lumaLocalAverage = 0.5 * (luma1 + lumaM);
+                    } else {
+                        lumaLocalAverage = 0.5 * (luma2 + lumaM);
+                    }
+
+                    vec2 currentUv = uv;
+                    if (isHorizontal) {
+                        currentUv.y += stepLength * 0.5;
+                    } else {
+                        currentUv.x += stepLength * 0.5;
+                    }
+
+                    vec2 offset = isHorizontal ? vec2(texel.x, 0.0) : vec2(0.0, texel.y);
+                    vec2 uv1 = currentUv - offset;
+                    vec2 uv2 = currentUv + offset;
+
+                    float lumaEnd1 = 0.0;
+                    float lumaEnd2 = 0.0;
+                    bool reached1 = false;
+                    bool reached2 = false;
+                    for (int i = 0; i < 12; i++) {
+                        if (!reached1) {
+                            lumaEnd1 = lumaAt(uv1) - lumaLocalAverage;
+                        }
+                        if (!reached2) {
+                            lumaEnd2 = lumaAt(uv2) - lumaLocalAverage;
+                        }
+                        reached1 = abs(lumaEnd1) >= gradientScaled;
+                        reached2 = abs(lumaEnd2) >= gradientScaled;
+                        if (reached1 && reached2) {
+                            break;
+                        }
+                        if (!reached1) {
+                            uv1 -= offset * STEPS[i];
+                        }
+                        if (!reached2) {
+                            uv2 += offset * STEPS[i];
+                        }
+                    }
+
+                    float distance1 = isHorizontal ? (uv.x - uv1.x) : (uv.y - uv1.y);
+                    float distance2 = isHorizontal ? (uv2.x - uv.x) : (uv2.y - uv.y);
+                    bool isDirection1 = distance1 < distance2;
+                    float distanceFinal = min(distance1, distance2);
+                    float edgeLength = distance1 + distance2;
+
+                    bool isLumaCenterSmaller = lumaM < lumaLocalAverage;
+                    bool correctVariation = ((isDirection1 ? lumaEnd1 : lumaEnd2) < 0.0) != isLumaCenterSmaller;
+                    float pixelOffset = correctVariation ? 0.5 - distanceFinal / edgeLength : 0.0;
+
+                    float lumaAverage = (1.0 / 12.0) * (2.0 * (lumaN + lumaS + lumaE + lumaW) + lumaNW + lumaNE + lumaSW + lumaSE);
+                    float subpixelOffset = clamp(abs(lumaAverage - lumaM) / range, 0.0, 1.0);
+                    subpixelOffset = (-2.0 * subpixelOffset + 3.0) * subpixelOffset * subpixelOffset;
+                    pixelOffset = max(pixelOffset, subpixelOffset * subpixelOffset * parameters.subpixelQuality);
+
+                    vec2 finalUv = uv;
+                    if (isHorizontal) {
+                        finalUv.y += pixelOffset * stepLength;
+                    } else {
+                        finalUv.x += pixelOffset * stepLength;
+                    }
+
+                    outColor = textureLod(sceneTexture, finalUv, 0.0);
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FxaaSettings {
+    pub edge_threshold: f32,
+    pub edge_threshold_min: f32,
+    pub subpixel_quality: f32,
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel_quality: 0.75,
+        }
+    }
+}
+
+pub struct FxaaPass {
+    pipelines: OutputPipelines,
+    sampler: Arc<Sampler>,
+}
+
+impl FxaaPass {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let fragment_shader = fxaa_fs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+        let pipelines = OutputPipelines::new(|output_format| {
+            create_fullscreen_pipeline(device, fragment_shader.clone(), output_format)
+        })?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self { pipelines, sampler })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+        settings: &FxaaSettings,
+    ) -> Result<()> {
+        let pipeline = self.pipelines.get(output.format())?;
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::image_view_sampler(
+                0,
+                Arc::clone(input),
+                Arc::clone(&self.sampler),
+            )],
+            [],
+        )?;
+
+        begin_fullscreen_pass(builder, pipeline, output)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(pipeline.layout()),
+                0,
+                fxaa_fs::FxaaParameters {
+                    edgeThreshold: settings.edge_threshold,
+                    edgeThresholdMin: settings.edge_threshold_min,
+                    subpixelQuality: settings.subpixel_quality,
+                },
+            )?;
+        unsafe { builder.draw(3, 1, 0, 0) }?;
+        builder.end_rendering()?;
+
+        Ok(())
+    }
+}
+
+pub struct FxaaEffect {
+    pub settings: FxaaSettings,
+    output_format: Format,
+}
+
+impl FxaaEffect {
+    pub fn new(output_format: Format) -> Self {
+        Self {
+            settings: FxaaSettings::default(),
+            output_format,
+        }
+    }
+}
+
+impl PostProcessEffect for FxaaEffect {
+    fn name(&self) -> &'static str {
+        "fxaa"
+    }
+
+    fn output_format(&self) -> Format {
+        self.output_format
+    }
+
+    fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        context: &PostProcessContext,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+    ) -> Result<()> {
+        context.vulkan_device.fxaa_pass().record(
+            builder,
+            context.vulkan_device,
+            input,
+            output,
+            &self.settings,
+        )
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
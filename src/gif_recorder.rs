@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+use tracing::{error, info};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, BlitImageInfo, PrimaryAutoCommandBuffer};
+use vulkano::format::Format;
+use vulkano::image::sampler::Filter;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageUsage, SampleCount};
+use vulkano::DeviceSize;
+
+use crate::readback::{Readback, DEFAULT_READBACK_SLOTS};
+use crate::swapchain_format::needs_manual_gamma;
+use crate::vulkan_device::VulkanDevice;
+
+#[derive(Clone, Copy, Debug)]
+pub struct GifSettings {
+    pub width: u32,
+    pub frame_rate: u32,
+    pub duration: f32,
+}
+
+impl Default for GifSettings {
+    fn default() -> Self {
+        Self {
+            width: 480,
+            frame_rate: 15,
+            duration: 4.0,
+        }
+    }
+}
+
+impl GifSettings {
+    fn capacity(&self) -> usize {
+        (self.duration * self.frame_rate as f32).ceil() as usize
+    }
+}
+
+// Keeps the last few seconds of downscaled frames around so a burst can be saved after the fact.
+pub struct GifRecorder {
+    settings: GifSettings,
+    downscaled_view: Option<Arc<ImageView>>,
+    readback: Option<Readback<u8>>,
+    frames: VecDeque<RgbaImage>,
+    previous_capture_time: Option<Instant>,
+}
+
+impl GifRecorder {
+    pub fn new(settings: GifSettings) -> Self {
+        Self {
+            settings,
+            downscaled_view: None,
+            readback: None,
+            frames: VecDeque::with_capacity(settings.capacity()),
+            previous_capture_time: None,
+        }
+    }
+
+    pub fn settings_mut(&mut self) -> &mut GifSettings {
+        &mut self.settings
+    }
+
+    // Frames come back a few frames after they were recorded, in order.
+    pub fn poll(&mut self) {
+        let (Some(view), Some(readback)) = (&self.downscaled_view, &mut self.readback) else {
+            return;
+        };
+
+        let extent = view.image().extent();
+        while let Some(data) = readback.poll() {
+            self.frames
+                .push_back(RgbaImage::from_raw(extent[0], extent[1], data).unwrap());
+            while self.frames.len() > self.settings.capacity() {
+                self.frames.pop_front();
+            }
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        image: &Arc<Image>,
+    ) -> Result<()> {
+        let now = Instant::now();
+        let interval = 1.0 / self.settings.frame_rate as f32;
+        if self
+            .readback
+            .as_ref()
+            .is_some_and(|readback| !readback.has_free_slot())
+            || self
+                .previous_capture_time
+                .is_some_and(|previous_capture_time| {
+                    (now - previous_capture_time).as_secs_f32() < interval
+                })
+        {
+            return Ok(());
+        }
+        self.previous_capture_time = Some(now);
+
+        let source_extent = image.extent();
+        let width = self.settings.width.min(source_extent[0]).max(1);
+        let height =
+            (source_extent[1] as u64 * width as u64 / source_extent[0] as u64).max(1) as u32;
+        // Blitting between matching encodings copies the gamma-encoded values as they are.
+        let format = if needs_manual_gamma(image.format()) {
+            Format::R8G8B8A8_UNORM
+        } else {
+            Format::R8G8B8A8_SRGB
+        };
+
+        if self.downscaled_view.as_ref().is_none_or(|view| {
+            view.image().extent()[..2] != [width, height] || view.format() != format
+        }) {
+            self.frames.clear();
+            self.downscaled_view = Some(vulkan_device.create_attachment(
+                format,
+                [width, height],
+                ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+                SampleCount::Sample1,
+            )?);
+            self.readback = Some(Readback::new(
+                vulkan_device,
+                width as DeviceSize * height as DeviceSize * 4,
+                DEFAULT_READBACK_SLOTS,
+            )?);
+        }
+
+        let downscaled = Arc::clone(self.downscaled_view.as_ref().unwrap().image());
+        builder.blit_image(BlitImageInfo {
+            filter: Filter::Linear,
+            ..BlitImageInfo::images(Arc::clone(image), Arc::clone(&downscaled))
+        })?;
+        self.readback
+            .as_mut()
+            .unwrap()
+            .record_image(builder, &downscaled)?;
+
+        Ok(())
+    }
+
+    pub fn save(&self, directory: impl AsRef<Path>) -> Result<PathBuf> {
+        std::fs::create_dir_all(&directory)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let path = directory.as_ref().join(format!("capture_{timestamp}.gif"));
+
+        let frames = self.frames.iter().cloned().collect::<Vec<_>>();
+        let delay = Delay::from_numer_denom_ms(1000, self.settings.frame_rate);
+        let file = File::create(&path)?;
+        let output = path.clone();
+
+        // Quantizing every frame takes far longer than a frame budget, so encoding happens off the render thread.
+        std::thread::spawn(move || {
+            let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+            let result = encoder.set_repeat(Repeat::Infinite).and_then(|_| {
+                encoder.encode_frames(
+                    frames
+                        .into_iter()
+                        .map(|frame| Frame::from_parts(frame, 0, 0, delay)),
+                )
+            });
+            match result {
+                Ok(()) => info!("Saved {}", output.display()),
+                Err(error) => error!("Failed to encode {}: {error}", output.display()),
+            }
+        });
+
+        Ok(path)
+    }
+}
@@ -0,0 +1,259 @@
+use std::f32::consts::TAU;
+
+use nalgebra::{Matrix4, Point3, Rotation3, Unit, Vector3};
+
+use crate::debug_draw::DebugDraw;
+
+const AXES: [Vector3<f32>; 3] = [
+    Vector3::new(1.0, 0.0, 0.0),
+    Vector3::new(0.0, 1.0, 0.0),
+    Vector3::new(0.0, 0.0, 1.0),
+];
+const AXIS_COLORS: [[f32; 4]; 3] = [
+    [1.0, 0.2, 0.2, 1.0],
+    [0.2, 1.0, 0.2, 1.0],
+    [0.2, 0.4, 1.0, 1.0],
+];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.9, 0.1, 1.0];
+const SCREEN_SIZE: f32 = 0.15;
+const PICK_TOLERANCE: f32 = 0.08;
+const RING_SEGMENTS: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn from_screen(view_projection: &Matrix4<f32>, position: [f32; 2]) -> Option<Self> {
+        let inverse = view_projection.try_inverse()?;
+        let [x, y] = position.map(|coordinate| coordinate * 2.0 - 1.0);
+        let near = inverse.transform_point(&Point3::new(x, y, -1.0));
+        let far = inverse.transform_point(&Point3::new(x, y, 1.0));
+        Some(Self {
+            origin: near,
+            direction: (far - near).try_normalize(f32::EPSILON)?,
+        })
+    }
+
+    fn closest_to_line(&self, origin: &Point3<f32>, axis: &Vector3<f32>) -> Option<(Point3<f32>, f32)> {
+        let offset = origin - self.origin;
+        let alignment = axis.dot(&self.direction);
+        let denominator = 1.0 - alignment * alignment;
+        if denominator < 1e-6 {
+            return None;
+        }
+
+        let axis_offset = axis.dot(&offset);
+        let ray_offset = self.direction.dot(&offset);
+        let axis_parameter = (alignment * ray_offset - axis_offset) / denominator;
+        let ray_parameter = (ray_offset - alignment * axis_offset) / denominator;
+
+        let axis_point = origin + axis * axis_parameter;
+        let ray_point = self.origin + self.direction * ray_parameter.max(0.0);
+        Some((axis_point, (axis_point - ray_point).norm()))
+    }
+
+    fn intersect_plane(&self, origin: &Point3<f32>, normal: &Vector3<f32>) -> Option<Point3<f32>> {
+        let denominator = normal.dot(&self.direction);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+
+        let distance = normal.dot(&(origin - self.origin)) / denominator;
+        (distance >= 0.0).then(|| self.origin + self.direction * distance)
+    }
+}
+
+struct Drag {
+    axis: usize,
+    start_transform: Matrix4<f32>,
+    start_point: Point3<f32>,
+}
+
+pub struct Gizmo {
+    mode: GizmoMode,
+    transform: Matrix4<f32>,
+    size: f32,
+    hovered: Option<usize>,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    pub fn new(transform: Matrix4<f32>) -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            transform,
+            size: 1.0,
+            hovered: None,
+            drag: None,
+        }
+    }
+
+    pub fn mode(&self) -> GizmoMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: GizmoMode) {
+        self.mode = mode;
+        self.drag = None;
+    }
+
+    pub fn transform(&self) -> &Matrix4<f32> {
+        &self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Matrix4<f32>) {
+        self.transform = transform;
+        self.drag = None;
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    pub fn update(&mut self, ray: &Ray, camera_position: &Point3<f32>) {
+        let center = self.center();
+        self.size = (center - camera_position).norm() * SCREEN_SIZE;
+
+        let Some(drag) = &self.drag else {
+            self.hovered = self.pick(ray);
+            return;
+        };
+
+        let axis = AXES[drag.axis];
+        let start_center = drag.start_transform.transform_point(&Point3::origin());
+        let Some(point) = self.project(ray, &start_center, drag.axis) else {
+            return;
+        };
+
+        self.transform = match self.mode {
+            GizmoMode::Translate => {
+                let offset = axis * axis.dot(&(point - drag.start_point));
+                Matrix4::new_translation(&offset) * drag.start_transform
+            }
+            GizmoMode::Scale => {
+                let start_extent = axis.dot(&(drag.start_point - start_center));
+                if start_extent.abs() < f32::EPSILON {
+                    return;
+                }
+                let factor = (axis.dot(&(point - start_center)) / start_extent).max(0.01);
+                let mut scaling = Vector3::repeat(1.0);
+                scaling[drag.axis] = factor;
+                drag.start_transform * Matrix4::new_nonuniform_scaling(&scaling)
+            }
+            GizmoMode::Rotate => {
+                let start = drag.start_point - start_center;
+                let current = point - start_center;
+                let angle = start.cross(&current).dot(&axis).atan2(start.dot(&current));
+                let rotation =
+                    Rotation3::from_axis_angle(&Unit::new_unchecked(axis), angle).to_homogeneous();
+                Matrix4::new_translation(&start_center.coords)
+                    * rotation
+                    * Matrix4::new_translation(&-start_center.coords)
+                    * drag.start_transform
+            }
+        };
+    }
+
+    pub fn begin_drag(&mut self, ray: &Ray) -> bool {
+        let Some(axis) = self.hovered else {
+            return false;
+        };
+        let Some(start_point) = self.project(ray, &self.center(), axis) else {
+            return false;
+        };
+
+        self.drag = Some(Drag {
+            axis,
+            start_transform: self.transform,
+            start_point,
+        });
+        true
+    }
+
+    pub fn end_drag(&mut self) {
+        self.drag = None;
+    }
+
+    pub fn draw(&self, debug_draw: &mut DebugDraw) {
+        let center = self.center();
+        let active = self.drag.as_ref().map(|drag| drag.axis).or(self.hovered);
+
+        for (index, axis) in AXES.iter().enumerate() {
+            let color = if active == Some(index) {
+                HIGHLIGHT_COLOR
+            } else {
+                AXIS_COLORS[index]
+            };
+
+            match self.mode {
+                GizmoMode::Translate => {
+                    debug_draw.line(&center, &(center + axis * self.size), color);
+                }
+                GizmoMode::Scale => {
+                    let end = center + axis * self.size;
+                    let handle = Vector3::repeat(self.size * 0.05);
+                    debug_draw.line(&center, &end, color);
+                    debug_draw.wire_box(&(end - handle), &(end + handle), color);
+                }
+                GizmoMode::Rotate => {
+                    let tangent = AXES[(index + 1) % 3];
+                    let bitangent = AXES[(index + 2) % 3];
+                    let point = |segment: usize| {
+                        let angle = segment as f32 / RING_SEGMENTS as f32 * TAU;
+                        center + (tangent * angle.cos() + bitangent * angle.sin()) * self.size
+                    };
+                    for segment in 0..RING_SEGMENTS {
+                        debug_draw.line(&point(segment), &point(segment + 1), color);
+                    }
+                }
+            }
+        }
+    }
+
+    fn center(&self) -> Point3<f32> {
+        self.transform.transform_point(&Point3::origin())
+    }
+
+    fn project(&self, ray: &Ray, center: &Point3<f32>, axis: usize) -> Option<Point3<f32>> {
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => ray
+                .closest_to_line(center, &AXES[axis])
+                .map(|(point, _)| point),
+            GizmoMode::Rotate => ray.intersect_plane(center, &AXES[axis]),
+        }
+    }
+
+    fn pick(&self, ray: &Ray) -> Option<usize> {
+        let center = self.center();
+        let tolerance = self.size * PICK_TOLERANCE;
+
+        AXES.iter()
+            .enumerate()
+            .filter_map(|(index, axis)| {
+                let distance = match self.mode {
+                    GizmoMode::Translate | GizmoMode::Scale => {
+                        let (point, distance) = ray.closest_to_line(&center, axis)?;
+                        let extent = axis.dot(&(point - center));
+                        (0.0..=self.size).contains(&extent).then_some(distance)?
+                    }
+                    GizmoMode::Rotate => {
+                        let point = ray.intersect_plane(&center, axis)?;
+                        ((point - center).norm() - self.size).abs()
+                    }
+                };
+                (distance <= tolerance).then_some((index, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytemuck::Pod;
+use nalgebra::Matrix4;
+use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, BufferCopy, CopyBufferInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::descriptor_set::layout::{
+    DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DescriptorType,
+};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::compute::ComputePipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    ComputePipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo,
+};
+use vulkano::shader::{EntryPoint, ShaderStages};
+use vulkano::DeviceSize;
+
+use crate::light::{DirectionalLight, DirectionalLightUniform};
+use crate::material::SceneMaterial;
+use crate::mesh_pool::MeshAllocation;
+use crate::picking::ObjectId;
+use crate::scene_graph::SceneGraph;
+use crate::vulkan_device::{vs, VulkanDevice};
+
+// Room for this many elements per array before the first reallocation.
+const INITIAL_CAPACITY: DeviceSize = 64;
+
+// Shaders read the scene through this set:
+//   binding 0: GpuObject objects[]
+//   binding 1: GpuMaterial materials[]
+//   binding 2: DirectionalLightUniform lights[]
+//   binding 3: GpuMesh meshes[]
+// Pipelines have to take the set layout from `create_set_layout`, since a layout derived from
+// their shaders would only list the stages that happen to read it.
+pub const GPU_SCENE_SET: u32 = 3;
+
+// Object 0 is the scene mesh as loaded, for draws that aren't part of a node, and the scene graph's
+// primitive `n` is object `n + 1`.
+pub fn object_index(primitive: usize) -> u32 {
+    primitive as u32 + 1
+}
+
+pub fn create_set_layout(device: &Arc<Device>) -> Result<Arc<DescriptorSetLayout>> {
+    Ok(DescriptorSetLayout::new(
+        Arc::clone(device),
+        DescriptorSetLayoutCreateInfo {
+            bindings: (0..4)
+                .map(|binding| {
+                    (
+                        binding,
+                        DescriptorSetLayoutBinding {
+                            stages: ShaderStages::all_graphics() | ShaderStages::COMPUTE,
+                            ..DescriptorSetLayoutBinding::descriptor_type(
+                                DescriptorType::StorageBuffer,
+                            )
+                        },
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        },
+    )?)
+}
+
+// Like a layout derived from the stages, but with the shared scene set layout, which the stages
+// have to read from.
+pub fn create_pipeline_layout<'a>(
+    device: &Arc<Device>,
+    stages: impl IntoIterator<Item = &'a PipelineShaderStageCreateInfo>,
+    set_layout: &Arc<DescriptorSetLayout>,
+) -> Result<Arc<PipelineLayout>> {
+    let mut layout_create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(stages)
+        .into_pipeline_layout_create_info(Arc::clone(device))
+        .unwrap();
+    layout_create_info.set_layouts[GPU_SCENE_SET as usize] = Arc::clone(set_layout);
+    Ok(PipelineLayout::new(Arc::clone(device), layout_create_info)?)
+}
+
+pub fn create_compute_pipeline(
+    device: &Arc<Device>,
+    cache: Option<Arc<PipelineCache>>,
+    shader: EntryPoint,
+    set_layout: &Arc<DescriptorSetLayout>,
+) -> Result<Arc<ComputePipeline>> {
+    let stage = PipelineShaderStageCreateInfo::new(shader);
+    let layout = create_pipeline_layout(device, [&stage], set_layout)?;
+    Ok(ComputePipeline::new(
+        Arc::clone(device),
+        cache,
+        ComputePipelineCreateInfo::stage_layout(stage, layout),
+    )?)
+}
+
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct GpuObject {
+    pub transform: [[f32; 4]; 4],
+    // Last frame's, for motion vectors.
+    pub previous_transform: [[f32; 4]; 4],
+    pub mesh: u32,
+    pub material: u32,
+    // What picking writes for the object's node; zero for the scene mesh, which has none.
+    pub pick_id: u32,
+    pub _padding: u32,
+}
+
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct GpuMaterial {
+    pub base_color: [f32; 4],
+    pub alpha_cutoff: f32,
+    pub point_size: f32,
+    pub _padding: [f32; 2],
+}
+
+impl From<&SceneMaterial> for GpuMaterial {
+    fn from(material: &SceneMaterial) -> Self {
+        Self {
+            base_color: material.base_color,
+            alpha_cutoff: material.alpha_cutoff(),
+            point_size: material.point_size,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct GpuMesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    pub _padding: u32,
+}
+
+impl From<&MeshAllocation> for GpuMesh {
+    fn from(mesh: &MeshAllocation) -> Self {
+        Self {
+            first_index: mesh.first_index,
+            index_count: mesh.index_count,
+            vertex_offset: mesh.vertex_offset,
+            _padding: 0,
+        }
+    }
+}
+
+// One primitive drawn as an object, which the vertex shader looks up by the draw's first instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectDraw {
+    pub object: u32,
+    pub mesh: MeshAllocation,
+    // Index into the scene materials.
+    pub material: u32,
+}
+
+impl ObjectDraw {
+    pub fn record<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) -> Result<()> {
+        unsafe {
+            builder.draw_indexed(
+                self.mesh.index_count,
+                1,
+                self.mesh.first_index,
+                self.mesh.vertex_offset,
+                self.object,
+            )
+        }?;
+        Ok(())
+    }
+}
+
+// Draws of the scene's objects, with the GPU scene set their shaders read them from.
+#[derive(Clone, Copy)]
+pub struct SceneObjects<'a> {
+    pub set: &'a Arc<DescriptorSet>,
+    pub draws: &'a [ObjectDraw],
+}
+
+impl SceneObjects<'_> {
+    // Binds the set for a pipeline that declares it at `GPU_SCENE_SET`, then draws every object.
+    pub fn record<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        layout: &Arc<PipelineLayout>,
+    ) -> Result<()> {
+        builder.bind_descriptor_sets(
+            PipelineBindPoint::Graphics,
+            Arc::clone(layout),
+            GPU_SCENE_SET,
+            Arc::clone(self.set),
+        )?;
+        for draw in self.draws {
+            draw.record(builder)?;
+        }
+        Ok(())
+    }
+}
+
+// The fragment shader reads material parameters from the push constants, so they're pushed again,
+// along with the material's dynamic state, whenever the next draw's material differs from the last
+// one's.
+pub fn record_object_draws<L>(
+    builder: &mut AutoCommandBufferBuilder<L>,
+    layout: &Arc<PipelineLayout>,
+    push_constants: vs::PushConstantData,
+    materials: &[SceneMaterial],
+    dynamic_cull_mode: bool,
+    wide_lines: bool,
+    objects: &[ObjectDraw],
+) -> Result<()> {
+    let mut recorded_material = None;
+    for object in objects {
+        if recorded_material != Some(object.material) {
+            let material = &materials[object.material as usize];
+            material.record_state(builder, dynamic_cull_mode, wide_lines)?;
+            builder.push_constants(
+                Arc::clone(layout),
+                0,
+                vs::PushConstantData {
+                    baseColor: material.base_color,
+                    alphaCutoff: material.alpha_cutoff(),
+                    pointSize: material.point_size,
+                    ..push_constants
+                },
+            )?;
+            recorded_material = Some(object.material);
+        }
+        object.record(builder)?;
+    }
+    Ok(())
+}
+
+// A device-local array with a CPU copy of what was last uploaded, so only changed elements are
+// copied.
+struct GpuArray<T> {
+    buffer: Subbuffer<[T]>,
+    uploaded: Vec<T>,
+}
+
+impl<T: BufferContents + Pod> GpuArray<T> {
+    fn new(memory_allocator: &Arc<StandardMemoryAllocator>, capacity: DeviceSize) -> Result<Self> {
+        Ok(Self {
+            buffer: Buffer::new_slice(
+                memory_allocator.clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+                capacity,
+            )?,
+            uploaded: Vec::new(),
+        })
+    }
+
+    // Returns whether the buffer had to grow, which leaves descriptor sets pointing at the old one.
+    fn update(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        staging_allocator: &SubbufferAllocator,
+        elements: &[T],
+    ) -> Result<bool> {
+        let grown = elements.len() as DeviceSize > self.buffer.len();
+        if grown {
+            *self = Self::new(
+                memory_allocator,
+                (elements.len() as DeviceSize).next_power_of_two(),
+            )?;
+        }
+
+        let mut runs = Vec::<Range<usize>>::new();
+        for (index, element) in elements.iter().enumerate() {
+            let unchanged = self.uploaded.get(index).is_some_and(|uploaded| {
+                bytemuck::bytes_of(uploaded) == bytemuck::bytes_of(element)
+            });
+            if unchanged {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.end == index => run.end += 1,
+                _ => runs.push(index..index + 1),
+            }
+        }
+
+        if !runs.is_empty() {
+            let element_size = std::mem::size_of::<T>() as DeviceSize;
+            let changed_count = runs.iter().map(|run| run.len()).sum::<usize>();
+            let staging_buffer =
+                staging_allocator.allocate_slice::<T>(changed_count as DeviceSize)?;
+
+            let mut regions = Vec::with_capacity(runs.len());
+            let mut staged = 0;
+            {
+                let mut staging = staging_buffer.write()?;
+                for run in runs {
+                    staging[staged..staged + run.len()].copy_from_slice(&elements[run.clone()]);
+                    regions.push(BufferCopy {
+                        src_offset: staged as DeviceSize * element_size,
+                        dst_offset: run.start as DeviceSize * element_size,
+                        size: run.len() as DeviceSize * element_size,
+                        ..Default::default()
+                    });
+                    staged += run.len();
+                }
+            }
+
+            builder.copy_buffer(CopyBufferInfo {
+                regions: regions.into_iter().collect(),
+                ..CopyBufferInfo::buffers(staging_buffer, self.buffer.clone())
+            })?;
+        }
+
+        self.uploaded.clear();
+        self.uploaded.extend_from_slice(elements);
+        Ok(grown)
+    }
+}
+
+// A GPU-resident description of the scene: object transforms, materials, lights and mesh ranges
+// in storage buffers, so passes can read the same data instead of each uploading their own copy.
+// Updates diff against the previous frame and only copy what changed.
+pub struct GpuScene {
+    objects: GpuArray<GpuObject>,
+    materials: GpuArray<GpuMaterial>,
+    lights: GpuArray<DirectionalLightUniform>,
+    meshes: GpuArray<GpuMesh>,
+    staging_allocator: SubbufferAllocator,
+    set_layout: Arc<DescriptorSetLayout>,
+    set: Arc<DescriptorSet>,
+}
+
+impl GpuScene {
+    pub fn new(vulkan_device: &VulkanDevice) -> Result<Self> {
+        let memory_allocator = vulkan_device.memory_allocator();
+        let set_layout = Arc::clone(vulkan_device.gpu_scene_set_layout());
+
+        let objects = GpuArray::new(memory_allocator, INITIAL_CAPACITY)?;
+        let materials = GpuArray::new(memory_allocator, INITIAL_CAPACITY)?;
+        let lights = GpuArray::new(memory_allocator, INITIAL_CAPACITY)?;
+        let meshes = GpuArray::new(memory_allocator, INITIAL_CAPACITY)?;
+        let set = Self::create_set(
+            vulkan_device,
+            &set_layout,
+            [
+                objects.buffer.as_bytes(),
+                materials.buffer.as_bytes(),
+                lights.buffer.as_bytes(),
+                meshes.buffer.as_bytes(),
+            ],
+        )?;
+
+        let staging_allocator = SubbufferAllocator::new(
+            Arc::clone(memory_allocator),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::TRANSFER_SRC,
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        Ok(Self {
+            objects,
+            materials,
+            lights,
+            meshes,
+            staging_allocator,
+            set_layout,
+            set,
+        })
+    }
+
+    fn create_set(
+        vulkan_device: &VulkanDevice,
+        set_layout: &Arc<DescriptorSetLayout>,
+        buffers: [&Subbuffer<[u8]>; 4],
+    ) -> Result<Arc<DescriptorSet>> {
+        Ok(DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(set_layout),
+            buffers.into_iter().enumerate().map(|(binding, buffer)| {
+                WriteDescriptorSet::buffer(binding as u32, buffer.clone())
+            }),
+            [],
+        )?)
+    }
+
+    // Every primitive of the scene graph becomes an object, numbered as `object_index` does, with
+    // its node's transform. Draws select theirs with their first instance. Meshes are listed once
+    // however many objects share them, after the scene mesh, and materials are indexed like the
+    // scene materials, whose last one is the default.
+    pub fn update(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        scene_graph: &SceneGraph,
+        materials: &[SceneMaterial],
+        lights: &[DirectionalLight],
+    ) -> Result<()> {
+        let mut meshes = vec![GpuMesh::from(vulkan_device.scene_mesh())];
+        let mut mesh_indices = HashMap::new();
+        let mut objects = vec![(Matrix4::identity(), 0, materials.len() as u32 - 1, 0)];
+        for node in 0..scene_graph.node_count() {
+            for primitive in scene_graph.primitives(node) {
+                let mesh = *mesh_indices.entry(primitive.mesh).or_insert_with(|| {
+                    meshes.push(GpuMesh::from(&primitive.mesh));
+                    meshes.len() as u32 - 1
+                });
+                objects.push((
+                    scene_graph.world_transforms()[node],
+                    mesh,
+                    primitive.material,
+                    ObjectId::node(node).pick_id(),
+                ));
+            }
+        }
+
+        let objects = objects
+            .into_iter()
+            .enumerate()
+            .map(|(index, (transform, mesh, material, pick_id))| {
+                let transform = transform.into();
+                GpuObject {
+                    transform,
+                    previous_transform: self
+                        .objects
+                        .uploaded
+                        .get(index)
+                        .map_or(transform, |object| object.transform),
+                    mesh,
+                    material,
+                    pick_id,
+                    _padding: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+        let materials = materials.iter().map(GpuMaterial::from).collect::<Vec<_>>();
+        let lights = lights
+            .iter()
+            .map(DirectionalLightUniform::from)
+            .collect::<Vec<_>>();
+
+        let memory_allocator = vulkan_device.memory_allocator();
+        let staging_allocator = &self.staging_allocator;
+        let mut grown =
+            self.objects
+                .update(builder, memory_allocator, staging_allocator, &objects)?;
+        grown |= self
+            .materials
+            .update(builder, memory_allocator, staging_allocator, &materials)?;
+        grown |= self
+            .lights
+            .update(builder, memory_allocator, staging_allocator, &lights)?;
+        grown |= self
+            .meshes
+            .update(builder, memory_allocator, staging_allocator, &meshes)?;
+
+        if grown {
+            self.set = Self::create_set(
+                vulkan_device,
+                &self.set_layout,
+                [
+                    self.objects.buffer.as_bytes(),
+                    self.materials.buffer.as_bytes(),
+                    self.lights.buffer.as_bytes(),
+                    self.meshes.buffer.as_bytes(),
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.objects.uploaded.len()
+    }
+
+    pub fn light_count(&self) -> usize {
+        self.lights.uploaded.len()
+    }
+
+    pub fn set_layout(&self) -> &Arc<DescriptorSetLayout> {
+        &self.set_layout
+    }
+
+    pub fn set(&self) -> &Arc<DescriptorSet> {
+        &self.set
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        self.objects.buffer.size()
+            + self.materials.buffer.size()
+            + self.lights.buffer.size()
+            + self.meshes.buffer.size()
+    }
+}
@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::SampleCount;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{
+    AttachmentBlend, ColorBlendAttachmentState, ColorBlendState,
+};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineShaderStageCreateInfo,
+};
+
+use crate::vulkan_device::{VulkanDevice, DEPTH_FORMAT, HDR_FORMAT};
+
+mod grid_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) out vec2 uv;
+
+                void main() {
+                    uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+                    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+                }
+            ",
+    }
+}
+
+mod grid_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(push_constant) uniform GridParameters {
+                    vec4 color;
+                    float cellSize;
+                    float majorLineEvery;
+                    float fadeDistance;
+                } parameters;
+
+                float gridLines(vec2 coordinates, float spacing) {
+                    vec2 scaled = coordinates / spacing;
+                    vec2 derivative = fwidth(scaled);
+                    vec2 distanceToLine = abs(fract(scaled - 0.5) - 0.5) / derivative;
+                    return 1.0 - min(min(distanceToLine.x, distanceToLine.y), 1.0);
+                }
+
+                void main() {
+                    mat4 inverseView = inverse(uniforms.view);
+                    vec4 target = uniforms.inverse_projection * vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+                    vec3 origin = inverseView[3].xyz;
+                    vec3 direction = normalize(mat3(inverseView) * (target.xyz / target.w));
+
+                    float distance = -origin.y / direction.y;
+                    if (distance <= 0.0) {
+                        discard;
+                    }
+
+                    vec3 position = origin + direction * distance;
+                    vec4 clipPosition = uniforms.view_projection * vec4(position, 1.0);
+                    gl_FragDepth = clipPosition.z / clipPosition.w;
+
+                    float minor = gridLines(position.xz, parameters.cellSize);
+                    float major = gridLines(position.xz, parameters.cellSize * parameters.majorLineEvery);
+                    vec4 color = vec4(parameters.color.rgb, parameters.color.a * max(minor * 0.5, major));
+
+                    vec2 axisDerivative = fwidth(position.xz);
+                    if (abs(position.z) < axisDerivative.y) {
+                        color = vec4(1.0, 0.1, 0.1, parameters.color.a);
+                    }
+                    if (abs(position.x) < axisDerivative.x) {
+                        color = vec4(0.1, 0.1, 1.0, parameters.color.a);
+                    }
+
+                    float fade = 1.0 - smoothstep(0.0, parameters.fadeDistance, distance);
+                    color.a *= fade;
+                    if (color.a <= 0.0) {
+                        discard;
+                    }
+
+                    outColor = color;
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GridSettings {
+    pub enabled: bool,
+    pub cell_size: f32,
+    pub major_line_every: u32,
+    pub fade_distance: f32,
+    pub color: [f32; 4],
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cell_size: 1.0,
+            major_line_every: 10,
+            fade_distance: 100.0,
+            color: [0.6, 0.6, 0.6, 0.8],
+        }
+    }
+}
+
+pub struct GridPass {
+    pipeline: Arc<GraphicsPipeline>,
+}
+
+impl GridPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        samples: SampleCount,
+    ) -> Result<Self> {
+        let stages = [
+            PipelineShaderStageCreateInfo::new(
+                grid_vs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+            PipelineShaderStageCreateInfo::new(
+                grid_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(Arc::clone(device))
+                .unwrap(),
+        )?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: CompareOp::LessOrEqual,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: samples,
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    },
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        Ok(Self { pipeline })
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        settings: &GridSettings,
+    ) -> Result<()> {
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone())],
+            [],
+        )?;
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                grid_fs::GridParameters {
+                    color: settings.color,
+                    cellSize: settings.cell_size,
+                    majorLineEvery: settings.major_line_every as f32,
+                    fadeDistance: settings.fade_distance,
+                },
+            )?;
+        unsafe { builder.draw(3, 1, 0, 0) }?;
+
+        Ok(())
+    }
+}
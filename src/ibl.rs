@@ -0,0 +1,505 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, ClearColorImageInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::DeviceOwned;
+use vulkano::format::ClearColorValue;
+use vulkano::image::sampler::{
+    Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode, LOD_CLAMP_NONE,
+};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::Pipeline;
+
+use crate::compute::{bind_compute, create_compute_pipeline};
+use crate::environment::{create_cube_view, create_cubemap, create_face_array_view};
+use crate::light::{DirectionalLight, DirectionalLightUniform};
+use crate::lightmap::Lightmap;
+use crate::reflection_probe::{ReflectionProbe, ReflectionProbesUniform, MAX_REFLECTION_PROBES};
+use crate::sh_probes::ShProbeBuffers;
+use crate::vulkan_device::{VulkanDevice, HDR_FORMAT};
+
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTERED_SIZE: u32 = 128;
+pub const PREFILTERED_MIP_LEVELS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 512;
+
+mod irradiance_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0) uniform samplerCube environment;
+                layout(set = 0, binding = 1, rgba16f) uniform writeonly image2DArray irradiance;
+
+                const float PI = 3.14159265359;
+
+                vec3 cubeDirection(uvec3 id, uint faceSize) {
+                    vec2 uv = (vec2(id.xy) + 0.5) / float(faceSize) * 2.0 - 1.0;
+                    switch (id.z) {
+                        case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+                        case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+                        case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+                        case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+                        case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+                        default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+                    }
+                }
+
+                void main() {
+                    uint faceSize = imageSize(irradiance).x;
+                    if (any(greaterThanEqual(gl_GlobalInvocationID.xy, uvec2(faceSize)))) {
+                        return;
+                    }
+
+                    vec3 normal = cubeDirection(gl_GlobalInvocationID, faceSize);
+                    vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+                    vec3 right = normalize(cross(up, normal));
+                    up = cross(normal, right);
+
+                    float sampleLod = max(log2(float(textureSize(environment, 0).x) / 32.0), 0.0);
+
+                    vec3 sum = vec3(0.0);
+                    float sampleCount = 0.0;
+                    for (float phi = 0.0; phi < 2.0 * PI; phi += 0.05) {
+                        for (float theta = 0.0; theta < 0.5 * PI; theta += 0.05) {
+                            vec3 direction = sin(theta) * cos(phi) * right
+                                + sin(theta) * sin(phi) * up
+                                + cos(theta) * normal;
+                            sum += textureLod(environment, direction, sampleLod).rgb
+                                * cos(theta) * sin(theta);
+                            sampleCount += 1.0;
+                        }
+                    }
+
+                    imageStore(irradiance, ivec3(gl_GlobalInvocationID), vec4(PI * sum / sampleCount, 1.0));
+                }
+            ",
+    }
+}
+
+mod prefilter_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0) uniform samplerCube environment;
+                layout(set = 0, binding = 1, rgba16f) uniform writeonly image2DArray prefiltered;
+
+                layout(push_constant) uniform PrefilterParameters {
+                    float roughness;
+                } parameters;
+
+                const float PI = 3.14159265359;
+                const uint SAMPLE_COUNT = 512u;
+
+                vec3 cubeDirection(uvec3 id, uint faceSize) {
+                    vec2 uv = (vec2(id.xy) + 0.5) / float(faceSize) * 2.0 - 1.0;
+                    switch (id.z) {
+                        case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+                        case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+                        case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+                        case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+                        case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+                        default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+                    }
+                }
+
+                vec2 hammersley(uint i, uint count) {
+                    uint bits = bitfieldReverse(i);
+                    return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+                }
+
+                vec3 importanceSampleGgx(vec2 xi, vec3 normal, float roughness) {
+                    float a = roughness * roughness;
+                    float phi = 2.0 * PI * xi.x;
+                    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+                    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+
+                    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+                    vec3 tangent = normalize(cross(up, normal));
+                    vec3 bitangent = cross(normal, tangent);
+
+                    return normalize(
+                        tangent * cos(phi) * sinTheta + bitangent * sin(phi) * sinTheta + normal * cosTheta
+                    );
+                }
+
+                float distributionGgx(float normalDotHalf, float roughness) {
+                    float a = roughness * roughness;
+                    float a2 = a * a;
+                    float denominator = normalDotHalf * normalDotHalf * (a2 - 1.0) + 1.0;
+                    return a2 / (PI * denominator * denominator);
+                }
+
+                void main() {
+                    uint faceSize = imageSize(prefiltered).x;
+                    if (any(greaterThanEqual(gl_GlobalInvocationID.xy, uvec2(faceSize)))) {
+                        return;
+                    }
+
+                    vec3 normal = cubeDirection(gl_GlobalInvocationID, faceSize);
+                    float environmentSize = float(textureSize(environment, 0).x);
+                    float texelSolidAngle = 4.0 * PI / (6.0 * environmentSize * environmentSize);
+
+                    vec3 sum = vec3(0.0);
+                    float weight = 0.0;
+                    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+                        vec3 halfway = importanceSampleGgx(hammersley(i, SAMPLE_COUNT), normal, parameters.roughness);
+                        vec3 light = normalize(2.0 * dot(normal, halfway) * halfway - normal);
+                        float normalDotLight = dot(normal, light);
+                        if (normalDotLight <= 0.0) {
+                            continue;
+                        }
+
+                        float normalDotHalf = max(dot(normal, halfway), 0.0);
+                        float pdf = distributionGgx(normalDotHalf, parameters.roughness) * 0.25 + 0.0001;
+                        float sampleSolidAngle = 1.0 / (float(SAMPLE_COUNT) * pdf + 0.0001);
+                        float lod = parameters.roughness == 0.0
+                            ? 0.0
+                            : max(0.5 * log2(sampleSolidAngle / texelSolidAngle), 0.0);
+
+                        sum += textureLod(environment, light, lod).rgb * normalDotLight;
+                        weight += normalDotLight;
+                    }
+
+                    imageStore(prefiltered, ivec3(gl_GlobalInvocationID), vec4(sum / max(weight, 0.0001), 1.0));
+                }
+            ",
+    }
+}
+
+mod brdf_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0, rgba16f) uniform writeonly image2D brdfLut;
+
+                const float PI = 3.14159265359;
+                const uint SAMPLE_COUNT = 1024u;
+
+                vec2 hammersley(uint i, uint count) {
+                    uint bits = bitfieldReverse(i);
+                    return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+                }
+
+                vec3 importanceSampleGgx(vec2 xi, float roughness) {
+                    float a = roughness * roughness;
+                    float phi = 2.0 * PI * xi.x;
+                    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+                    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+                    return vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+                }
+
+                float geometrySchlickGgx(float normalDotDirection, float roughness) {
+                    float k = roughness * roughness * 0.5;
+                    return normalDotDirection / (normalDotDirection * (1.0 - k) + k);
+                }
+
+                void main() {
+                    ivec2 size = imageSize(brdfLut);
+                    if (any(greaterThanEqual(ivec2(gl_GlobalInvocationID.xy), size))) {
+                        return;
+                    }
+
+                    vec2 uv = (vec2(gl_GlobalInvocationID.xy) + 0.5) / vec2(size);
+                    float normalDotView = uv.x;
+                    float roughness = uv.y;
+                    vec3 view = vec3(sqrt(1.0 - normalDotView * normalDotView), 0.0, normalDotView);
+
+                    vec2 sum = vec2(0.0);
+                    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+                        vec3 halfway = importanceSampleGgx(hammersley(i, SAMPLE_COUNT), roughness);
+                        vec3 light = normalize(2.0 * dot(view, halfway) * halfway - view);
+
+                        float normalDotLight = max(light.z, 0.0);
+                        if (normalDotLight <= 0.0) {
+                            continue;
+                        }
+
+                        float normalDotHalf = max(halfway.z, 0.0);
+                        float viewDotHalf = max(dot(view, halfway), 0.0);
+                        float geometry = geometrySchlickGgx(normalDotView, roughness)
+                            * geometrySchlickGgx(normalDotLight, roughness);
+                        float visibility = geometry * viewDotHalf / (normalDotHalf * normalDotView);
+                        float fresnel = pow(1.0 - viewDotHalf, 5.0);
+
+                        sum += vec2((1.0 - fresnel) * visibility, fresnel * visibility);
+                    }
+
+                    imageStore(brdfLut, ivec2(gl_GlobalInvocationID.xy), vec4(sum / float(SAMPLE_COUNT), 0.0, 1.0));
+                }
+            ",
+    }
+}
+
+pub struct ImageBasedLighting {
+    irradiance_view: Arc<ImageView>,
+    prefiltered_view: Arc<ImageView>,
+    brdf_lut_view: Arc<ImageView>,
+    sampler: Arc<Sampler>,
+}
+
+impl ImageBasedLighting {
+    pub fn new(
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        descriptor_set_allocator: &Arc<StandardDescriptorSetAllocator>,
+        pipeline_cache: &Arc<PipelineCache>,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        environment: &Arc<ImageView>,
+    ) -> Result<Self> {
+        let device = memory_allocator.device();
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                lod: 0.0..=LOD_CLAMP_NONE,
+                ..Default::default()
+            },
+        )?;
+
+        let usage = ImageUsage::STORAGE | ImageUsage::SAMPLED;
+        let irradiance = create_cubemap(Arc::clone(&memory_allocator), IRRADIANCE_SIZE, 1, usage)?;
+        let prefiltered = create_cubemap(
+            Arc::clone(&memory_allocator),
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_LEVELS,
+            usage,
+        )?;
+        let brdf_lut = Image::new(
+            Arc::clone(&memory_allocator),
+            ImageCreateInfo {
+                format: HDR_FORMAT,
+                extent: [BRDF_LUT_SIZE, BRDF_LUT_SIZE, 1],
+                usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?;
+
+        let irradiance_pipeline = create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            irradiance_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+        let irradiance_set = DescriptorSet::new(
+            descriptor_set_allocator.clone(),
+            Arc::clone(&irradiance_pipeline.layout().set_layouts()[0]),
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    Arc::clone(environment),
+                    Arc::clone(&sampler),
+                ),
+                WriteDescriptorSet::image_view(1, create_face_array_view(&irradiance, 0)?),
+            ],
+            [],
+        )?;
+        bind_compute(builder, &irradiance_pipeline, irradiance_set)?;
+        unsafe { builder.dispatch([IRRADIANCE_SIZE.div_ceil(8), IRRADIANCE_SIZE.div_ceil(8), 6]) }?;
+
+        let prefilter_pipeline = create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            prefilter_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+        for mip_level in 0..PREFILTERED_MIP_LEVELS {
+            let face_size = (PREFILTERED_SIZE >> mip_level).max(1);
+            let prefilter_set = DescriptorSet::new(
+                descriptor_set_allocator.clone(),
+                Arc::clone(&prefilter_pipeline.layout().set_layouts()[0]),
+                [
+                    WriteDescriptorSet::image_view_sampler(
+                        0,
+                        Arc::clone(environment),
+                        Arc::clone(&sampler),
+                    ),
+                    WriteDescriptorSet::image_view(
+                        1,
+                        create_face_array_view(&prefiltered, mip_level)?,
+                    ),
+                ],
+                [],
+            )?;
+            bind_compute(builder, &prefilter_pipeline, prefilter_set)?.push_constants(
+                Arc::clone(prefilter_pipeline.layout()),
+                0,
+                prefilter_cs::PrefilterParameters {
+                    roughness: mip_level as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32,
+                },
+            )?;
+            unsafe { builder.dispatch([face_size.div_ceil(8), face_size.div_ceil(8), 6]) }?;
+        }
+
+        let brdf_pipeline = create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            brdf_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+        let brdf_lut_view = ImageView::new_default(brdf_lut)?;
+        let brdf_set = DescriptorSet::new(
+            descriptor_set_allocator.clone(),
+            Arc::clone(&brdf_pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::image_view(0, Arc::clone(&brdf_lut_view))],
+            [],
+        )?;
+        bind_compute(builder, &brdf_pipeline, brdf_set)?;
+        unsafe { builder.dispatch([BRDF_LUT_SIZE.div_ceil(8), BRDF_LUT_SIZE.div_ceil(8), 1]) }?;
+
+        Ok(Self {
+            irradiance_view: create_cube_view(&irradiance)?,
+            prefiltered_view: create_cube_view(&prefiltered)?,
+            brdf_lut_view,
+            sampler,
+        })
+    }
+
+    pub fn uniform(
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        descriptor_set_allocator: &Arc<StandardDescriptorSetAllocator>,
+        pipeline_cache: &Arc<PipelineCache>,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        color: [f32; 3],
+    ) -> Result<Self> {
+        let environment = create_cubemap(
+            Arc::clone(&memory_allocator),
+            1,
+            1,
+            ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+        )?;
+
+        builder.clear_color_image(ClearColorImageInfo {
+            clear_value: ClearColorValue::Float([color[0], color[1], color[2], 1.0]),
+            ..ClearColorImageInfo::image(Arc::clone(&environment))
+        })?;
+
+        Self::new(
+            memory_allocator,
+            descriptor_set_allocator,
+            pipeline_cache,
+            builder,
+            &create_cube_view(&environment)?,
+        )
+    }
+
+    // Probes that weren't captured yet are skipped, and unused probe slots get this environment.
+    pub fn create_set(
+        &self,
+        vulkan_device: &VulkanDevice,
+        sun: &DirectionalLight,
+        reflection_probes: &[ReflectionProbe],
+        lightmap: &Lightmap,
+        sh_probes: &ShProbeBuffers,
+    ) -> Result<Arc<DescriptorSet>> {
+        let sun_buffer = Buffer::from_data(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            DirectionalLightUniform::from(sun),
+        )?;
+
+        let captured_probes = reflection_probes
+            .iter()
+            .filter(|probe| probe.lighting().is_some())
+            .take(MAX_REFLECTION_PROBES)
+            .collect::<Vec<_>>();
+        let reflection_probes_buffer = Buffer::from_data(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            ReflectionProbesUniform::new(captured_probes.iter().copied()),
+        )?;
+        let reflection_probe_maps = (0..MAX_REFLECTION_PROBES).map(|index| {
+            let lighting = captured_probes
+                .get(index)
+                .and_then(|probe| probe.lighting())
+                .unwrap_or(self);
+            (
+                Arc::clone(&lighting.prefiltered_view),
+                Arc::clone(&self.sampler),
+            )
+        });
+
+        Ok(DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&vulkan_device.graphics_pipeline().layout().set_layouts()[2]),
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    Arc::clone(&self.irradiance_view),
+                    Arc::clone(&self.sampler),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    Arc::clone(&self.prefiltered_view),
+                    Arc::clone(&self.sampler),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    2,
+                    Arc::clone(&self.brdf_lut_view),
+                    Arc::clone(&self.sampler),
+                ),
+                WriteDescriptorSet::buffer(3, sun_buffer),
+                WriteDescriptorSet::image_view_sampler_array(4, 0, reflection_probe_maps),
+                WriteDescriptorSet::buffer(5, reflection_probes_buffer),
+            ]
+            .into_iter()
+            .chain(lightmap.descriptor_writes())
+            .chain(sh_probes.descriptor_writes()),
+            [],
+        )?)
+    }
+
+    pub fn irradiance_view(&self) -> &Arc<ImageView> {
+        &self.irradiance_view
+    }
+
+    pub fn prefiltered_view(&self) -> &Arc<ImageView> {
+        &self.prefiltered_view
+    }
+
+    pub fn brdf_lut_view(&self) -> &Arc<ImageView> {
+        &self.brdf_lut_view
+    }
+}
@@ -0,0 +1,185 @@
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_SIZE: usize = 24;
+const VK_FORMAT_R16G16B16A16_SFLOAT: u32 = 97;
+const TEXEL_SIZE: usize = 8;
+
+// A single-level 2D image of VK_FORMAT_R16G16B16A16_SFLOAT texels, the only kind the lightmap baker
+// writes. Key/value entries carry extra data, sorted by key when written.
+#[derive(Clone, Debug, Default)]
+pub struct Ktx2Image {
+    pub extent: [u32; 2],
+    pub key_values: Vec<(String, Vec<u8>)>,
+    // Half floats in little endian, rows top to bottom.
+    pub data: Vec<u8>,
+}
+
+impl Ktx2Image {
+    pub fn value(&self, key: &str) -> Option<&[u8]> {
+        self.key_values
+            .iter()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let [width, height] = self.extent;
+        ensure!(
+            self.data.len() == width as usize * height as usize * TEXEL_SIZE,
+            "{} bytes don't make a {width}x{height} image",
+            self.data.len()
+        );
+
+        let descriptor = data_format_descriptor();
+        let mut key_values = self.key_values.clone();
+        key_values.push(("KTXwriter".to_owned(), b"vulkanox\0".to_vec()));
+        key_values.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut key_value_data = Vec::new();
+        for (key, value) in &key_values {
+            push_u32(&mut key_value_data, (key.len() + 1 + value.len()) as u32);
+            key_value_data.extend_from_slice(key.as_bytes());
+            key_value_data.push(0);
+            key_value_data.extend_from_slice(value);
+            key_value_data.resize(key_value_data.len().next_multiple_of(4), 0);
+        }
+
+        let descriptor_offset = HEADER_SIZE + LEVEL_INDEX_SIZE;
+        let key_value_offset = descriptor_offset + descriptor.len();
+        let data_offset = (key_value_offset + key_value_data.len()).next_multiple_of(TEXEL_SIZE);
+
+        let mut file = IDENTIFIER.to_vec();
+        push_u32(&mut file, VK_FORMAT_R16G16B16A16_SFLOAT);
+        push_u32(&mut file, 2);
+        // A 2D image with one face and one level, not supercompressed.
+        for value in [width, height, 0, 0, 1, 1, 0] {
+            push_u32(&mut file, value);
+        }
+        push_u32(&mut file, descriptor_offset as u32);
+        push_u32(&mut file, descriptor.len() as u32);
+        push_u32(&mut file, key_value_offset as u32);
+        push_u32(&mut file, key_value_data.len() as u32);
+        // No supercompression global data.
+        push_u64(&mut file, 0);
+        push_u64(&mut file, 0);
+        push_u64(&mut file, data_offset as u64);
+        push_u64(&mut file, self.data.len() as u64);
+        push_u64(&mut file, self.data.len() as u64);
+        file.extend_from_slice(&descriptor);
+        file.extend_from_slice(&key_value_data);
+        file.resize(data_offset, 0);
+        file.extend_from_slice(&self.data);
+
+        std::fs::write(path, file).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    // Only reads back what `write` produces: one uncompressed level in the same format.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        ensure!(
+            file.len() >= HEADER_SIZE + LEVEL_INDEX_SIZE && file[..12] == IDENTIFIER,
+            "{} is not a KTX2 file",
+            path.display()
+        );
+
+        let [format, _, width, height, depth, layers, faces, levels, supercompression] =
+            std::array::from_fn(|index| read_u32(&file, 12 + index * 4));
+        if format != VK_FORMAT_R16G16B16A16_SFLOAT {
+            bail!(
+                "{} has format {format}, expected R16G16B16A16_SFLOAT",
+                path.display()
+            );
+        }
+        ensure!(
+            depth == 0 && layers == 0 && faces == 1 && levels <= 1 && supercompression == 0,
+            "{} is not a single uncompressed 2D image",
+            path.display()
+        );
+
+        let key_value_offset = read_u32(&file, 56) as usize;
+        let key_value_length = read_u32(&file, 60) as usize;
+        let data_offset = read_u64(&file, HEADER_SIZE) as usize;
+        let data_length = read_u64(&file, HEADER_SIZE + 8) as usize;
+        ensure!(
+            data_length == width as usize * height as usize * TEXEL_SIZE
+                && data_offset + data_length <= file.len()
+                && key_value_offset + key_value_length <= file.len(),
+            "{} is truncated",
+            path.display()
+        );
+
+        let mut key_values = Vec::new();
+        let mut entries = &file[key_value_offset..key_value_offset + key_value_length];
+        while entries.len() >= 4 {
+            let length = read_u32(entries, 0) as usize;
+            let entry = entries
+                .get(4..4 + length)
+                .with_context(|| format!("{} has a truncated key/value entry", path.display()))?;
+            let separator = entry
+                .iter()
+                .position(|&byte| byte == 0)
+                .with_context(|| format!("{} has a key without a terminator", path.display()))?;
+            key_values.push((
+                String::from_utf8(entry[..separator].to_vec())?,
+                entry[separator + 1..].to_vec(),
+            ));
+            entries = &entries[(4 + length).next_multiple_of(4).min(entries.len())..];
+        }
+
+        Ok(Self {
+            extent: [width, height],
+            key_values,
+            data: file[data_offset..data_offset + data_length].to_vec(),
+        })
+    }
+}
+
+// A basic data format descriptor for linear RGBA half floats with BT.709 primaries.
+fn data_format_descriptor() -> Vec<u8> {
+    const SAMPLE_COUNT: usize = 4;
+    let block_size = 24 + 16 * SAMPLE_COUNT;
+    let mut descriptor = Vec::new();
+    push_u32(&mut descriptor, (4 + block_size) as u32);
+    // Khronos vendor, basic descriptor type.
+    push_u32(&mut descriptor, 0);
+    descriptor.extend_from_slice(&2u16.to_le_bytes());
+    descriptor.extend_from_slice(&(block_size as u16).to_le_bytes());
+    // RGBSDA color model, BT.709 primaries, linear transfer and straight alpha.
+    descriptor.extend_from_slice(&[1, 1, 1, 0]);
+    // A 1x1x1 texel block of 8 bytes in a single plane.
+    descriptor.extend_from_slice(&[0; 4]);
+    descriptor.extend_from_slice(&[TEXEL_SIZE as u8, 0, 0, 0, 0, 0, 0, 0]);
+    for (index, channel) in [0u8, 1, 2, 15].into_iter().enumerate() {
+        descriptor.extend_from_slice(&(index as u16 * 16).to_le_bytes());
+        // 16 bits stored as the length minus one, and a signed float channel.
+        descriptor.extend_from_slice(&[15, channel | 0xC0]);
+        descriptor.extend_from_slice(&[0; 4]);
+        push_u32(&mut descriptor, (-1.0f32).to_bits());
+        push_u32(&mut descriptor, 1.0f32.to_bits());
+    }
+    descriptor
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
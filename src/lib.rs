@@ -0,0 +1,86 @@
+#![feature(iterator_try_collect)]
+
+pub mod acceleration_structure;
+pub mod allocator_stats;
+pub mod app;
+pub mod background;
+pub mod benchmark;
+pub mod billboard;
+pub mod capture;
+pub mod color_audit;
+pub mod color_lut;
+pub mod color_management;
+pub mod compute;
+pub mod contact_shadows;
+pub mod debug_draw;
+pub mod download;
+pub mod dynamic_mesh;
+pub mod embedded_assets;
+pub mod environment;
+pub mod foliage;
+pub mod frame_pacing;
+pub mod fullscreen;
+pub mod fxaa;
+pub mod gif_recorder;
+pub mod gizmo;
+pub mod gpu_scene;
+pub mod grid;
+pub mod ibl;
+pub mod ktx2;
+pub mod light;
+pub mod lightmap;
+pub mod material;
+pub mod memory;
+pub mod mesh_pool;
+pub mod meshlet;
+pub mod metrics;
+pub mod minimap;
+pub mod motion_blur;
+pub mod normal_visualization;
+pub mod outline;
+pub mod parallel_recording;
+pub mod particles;
+pub mod path_tracing;
+#[cfg(feature = "physics")]
+pub mod physics;
+pub mod picking;
+pub mod picture_in_picture;
+pub mod pixel_inspector;
+pub mod post_process;
+pub mod pre_rotation;
+pub mod primitives;
+pub mod punctual_light;
+pub mod ray_query;
+pub mod ray_tracing;
+pub mod readback;
+pub mod reflection_probe;
+pub mod render_pass_plugin;
+pub mod render_thread;
+pub mod render_texture;
+pub mod scene_camera;
+pub mod scene_file;
+pub mod scene_graph;
+pub mod scene_tracer;
+pub mod scripting;
+pub mod sky;
+pub mod sh_probes;
+pub mod shading_rate;
+pub mod shadow_map;
+pub mod simulation;
+pub mod skybox;
+pub mod split_screen;
+pub mod sprite;
+pub mod ssao;
+pub mod stereo;
+pub mod swapchain_format;
+pub mod tessellation;
+pub mod tonemap;
+pub mod transient_pool;
+pub mod transparency;
+pub mod vertex_pulling;
+pub mod vulkan_device;
+pub mod vulkan_instance;
+pub mod vulkan_renderer;
+pub mod water;
+pub mod window_mode;
+pub mod window_title;
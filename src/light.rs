@@ -0,0 +1,34 @@
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct DirectionalLight {
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: Vector3::new(0.4, 0.8, 0.6).normalize(),
+            color: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct DirectionalLightUniform {
+    direction: [f32; 4],
+    color: [f32; 4],
+}
+
+impl From<&DirectionalLight> for DirectionalLightUniform {
+    fn from(light: &DirectionalLight) -> Self {
+        let direction = light.direction.normalize();
+        Self {
+            direction: [direction.x, direction.y, direction.z, 0.0],
+            color: [light.color[0], light.color[1], light.color[2], 1.0],
+        }
+    }
+}
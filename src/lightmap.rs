@@ -0,0 +1,419 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use nalgebra::{Point3, Vector3};
+use rayon::prelude::*;
+use tracing::info;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::CopyBufferToImageInfo;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::DeviceSize;
+
+use crate::ktx2::Ktx2Image;
+use crate::memory::image_size;
+use crate::scene_tracer::{
+    cosine_sample, load_scene_triangles, BakeLighting, Random, SceneTracer, Triangle,
+};
+use crate::vulkan_device::VulkanDevice;
+
+const LIGHTMAP_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+// The charts are stored next to the texels, so a lightmap loads without the scene mesh.
+const CHARTS_KEY: &str = "vulkanox.lightmapCharts";
+// Texels around each cell that repeat the triangle's edge, so bilinear filtering doesn't bleed.
+const PADDING: u32 = 1;
+const MAX_EXTENT: u32 = 16384;
+
+#[derive(Clone, Debug)]
+pub struct LightmapBakeSettings {
+    pub output_path: PathBuf,
+    // Hemisphere samples per texel.
+    pub samples: u32,
+    // Texels along each triangle's square cell, padding included.
+    pub cell_size: u32,
+    pub lighting: BakeLighting,
+}
+
+impl LightmapBakeSettings {
+    pub fn new(output_path: impl Into<PathBuf>) -> Self {
+        Self {
+            output_path: output_path.into(),
+            samples: 64,
+            cell_size: 8,
+            lighting: BakeLighting::default(),
+        }
+    }
+
+    // Accepts `--bake-lightmap <output.ktx2> [samples]` and `--lightmap-cell-size <texels>`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>> {
+        let mut settings = None;
+        let mut cell_size = None;
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bake-lightmap" => {
+                    let output_path = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--bake-lightmap needs an output path"))?;
+                    let samples = args
+                        .next_if(|arg| !arg.starts_with("--"))
+                        .map(|samples| samples.parse())
+                        .transpose()?;
+                    settings = Some((output_path, samples));
+                }
+                "--lightmap-cell-size" => {
+                    cell_size = args.next().map(|size| size.parse()).transpose()?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(settings.map(|(output_path, samples)| {
+            let defaults = Self::new(output_path);
+            Self {
+                samples: samples.unwrap_or(defaults.samples),
+                cell_size: cell_size.unwrap_or(defaults.cell_size),
+                ..defaults
+            }
+        }))
+    }
+}
+
+// Maps world positions on one triangle into its cell of the atlas, which makes the charts the
+// lightmap's UV set. The scene shader looks them up by primitive ID.
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct LightmapChart {
+    // Rows taking a world position to the triangle's 0..1 square.
+    u: [f32; 4],
+    v: [f32; 4],
+    // The triangle's plane, which tells fragments of other triangles apart.
+    plane: [f32; 4],
+    // The square's inner area in atlas UVs, as offset and size.
+    cell: [f32; 4],
+}
+
+impl LightmapChart {
+    // A cell without area, which keeps the dynamic lighting.
+    const NONE: Self = Self {
+        u: [0.0; 4],
+        v: [0.0; 4],
+        plane: [0.0; 4],
+        cell: [0.0; 4],
+    };
+}
+
+// Baked sun and sky light of the static scene mesh, sampled by the scene shader in place of the
+// sun and the irradiance map. Draws that don't line up with the baked triangles stay dynamic.
+pub struct Lightmap {
+    view: Arc<ImageView>,
+    sampler: Arc<Sampler>,
+    charts: Subbuffer<[LightmapChart]>,
+}
+
+impl Lightmap {
+    // Bound while no lightmap is loaded.
+    pub fn empty(vulkan_device: &VulkanDevice) -> Result<Self> {
+        Self::new(vulkan_device, [1, 1], &[0; 8], &[LightmapChart::NONE])
+    }
+
+    pub fn load(vulkan_device: &VulkanDevice, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let image = Ktx2Image::read(path)?;
+        let chart_size = std::mem::size_of::<LightmapChart>();
+        let charts = image
+            .value(CHARTS_KEY)
+            .filter(|charts| !charts.is_empty() && charts.len() % chart_size == 0)
+            .with_context(|| format!("{} has no lightmap charts", path.display()))?
+            .chunks_exact(chart_size)
+            .map(bytemuck::pod_read_unaligned)
+            .collect::<Vec<LightmapChart>>();
+        info!(
+            "Loaded a {}x{} lightmap of {} triangles from {}",
+            image.extent[0],
+            image.extent[1],
+            charts.len(),
+            path.display()
+        );
+        Self::new(vulkan_device, image.extent, &image.data, &charts)
+    }
+
+    fn new(
+        vulkan_device: &VulkanDevice,
+        extent: [u32; 2],
+        texels: &[u8],
+        charts: &[LightmapChart],
+    ) -> Result<Self> {
+        let staging_buffer = Buffer::from_iter(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            texels.iter().copied(),
+        )?;
+        let image = Image::new(
+            vulkan_device.memory_allocator().clone(),
+            ImageCreateInfo {
+                format: LIGHTMAP_FORMAT,
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?;
+        vulkan_device.submit_and_wait(|builder| {
+            builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+                staging_buffer,
+                Arc::clone(&image),
+            ))?;
+            Ok(())
+        })?;
+
+        let charts = Buffer::from_iter(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            charts.iter().copied(),
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(vulkan_device.queue().device()),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            view: ImageView::new_default(image)?,
+            sampler,
+            charts,
+        })
+    }
+
+    // The texture and the charts, at the bindings the scene shader expects them in the lighting set.
+    pub fn descriptor_writes(&self) -> [WriteDescriptorSet; 2] {
+        [
+            WriteDescriptorSet::image_view_sampler(
+                6,
+                Arc::clone(&self.view),
+                Arc::clone(&self.sampler),
+            ),
+            WriteDescriptorSet::buffer(7, self.charts.clone()),
+        ]
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        image_size(self.view.image()) + self.charts.size()
+    }
+}
+
+// Bakes the default scene's mesh on the CPU and writes the lightmap as KTX2. The values are
+// irradiance in the units the scene shader multiplies with the albedo.
+pub fn bake_lightmap(settings: &LightmapBakeSettings) -> Result<()> {
+    ensure!(
+        settings.cell_size > PADDING * 2,
+        "Lightmap cells need more than {} texels",
+        PADDING * 2
+    );
+    let tracer = SceneTracer::new(load_scene_triangles()?);
+    let triangles = tracer.triangles();
+
+    let columns = (triangles.len() as f32).sqrt().ceil() as u32;
+    let rows = (triangles.len() as u32).div_ceil(columns);
+    let extent = [columns * settings.cell_size, rows * settings.cell_size];
+    if extent.iter().any(|&size| size > MAX_EXTENT) {
+        bail!(
+            "A {}x{} lightmap is too large, use smaller cells",
+            extent[0],
+            extent[1]
+        );
+    }
+    info!(
+        "Baking a {}x{} lightmap of {} triangles with {} samples per texel",
+        extent[0],
+        extent[1],
+        triangles.len(),
+        settings.samples
+    );
+
+    let frames = triangles.iter().map(ChartFrame::new).collect::<Vec<_>>();
+    let texels = (0..extent[0] * extent[1])
+        .into_par_iter()
+        .map(|texel| {
+            let (x, y) = (texel % extent[0], texel / extent[0]);
+            let cell = y / settings.cell_size * columns + x / settings.cell_size;
+            let Some((frame, triangle)) = frames
+                .get(cell as usize)
+                .and_then(|frame| frame.as_ref().zip(triangles.get(cell as usize)))
+            else {
+                return [0.0; 3];
+            };
+            let inner_size = (settings.cell_size - PADDING * 2) as f32;
+            let local = [x, y].map(|coordinate| {
+                ((coordinate % settings.cell_size) as f32 + 0.5 - PADDING as f32) / inner_size
+            });
+            // Padding and the half of the cell the triangle doesn't cover repeat its closest point.
+            let position = closest_point(&frame.point(local), triangle);
+            let mut random = Random::new(texel);
+            // Cosine-weighted samples leave out the 1/π, like the scene shader.
+            let gathered = (0..settings.samples)
+                .map(|_| {
+                    let direction = cosine_sample(&frame.normal, &mut random);
+                    tracer.incoming_radiance(&settings.lighting, &position, &direction)
+                })
+                .sum::<Vector3<f32>>()
+                / settings.samples.max(1) as f32;
+            (tracer.sun(&settings.lighting, &position, &frame.normal) + gathered).into()
+        })
+        .collect::<Vec<[f32; 3]>>();
+
+    let charts = frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            let (column, row) = (index as u32 % columns, index as u32 / columns);
+            let cell = [
+                (column * settings.cell_size + PADDING) as f32 / extent[0] as f32,
+                (row * settings.cell_size + PADDING) as f32 / extent[1] as f32,
+                (settings.cell_size - PADDING * 2) as f32 / extent[0] as f32,
+                (settings.cell_size - PADDING * 2) as f32 / extent[1] as f32,
+            ];
+            frame
+                .as_ref()
+                .map_or(LightmapChart::NONE, |frame| frame.chart(cell))
+        })
+        .collect::<Vec<_>>();
+
+    Ktx2Image {
+        extent,
+        key_values: vec![(
+            CHARTS_KEY.to_owned(),
+            bytemuck::cast_slice(&charts).to_vec(),
+        )],
+        data: texels
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0])
+            .flat_map(|channel| half::f16::from_f32(channel).to_bits().to_le_bytes())
+            .collect(),
+    }
+    .write(&settings.output_path)?;
+    info!("Wrote {}", settings.output_path.display());
+    Ok(())
+}
+
+// A triangle's plane with axes along its first edge. Its square spans the triangle's longer side.
+struct ChartFrame {
+    origin: Point3<f32>,
+    u: Vector3<f32>,
+    v: Vector3<f32>,
+    normal: Vector3<f32>,
+    size: f32,
+}
+
+impl ChartFrame {
+    // Degenerate triangles get no chart.
+    fn new(triangle: &Triangle) -> Option<Self> {
+        let [a, b, c] = triangle;
+        let normal = (b - a).cross(&(c - a)).try_normalize(f32::EPSILON)?;
+        let u = (b - a).normalize();
+        let v = normal.cross(&u);
+        let coordinates = triangle.map(|point| [(point - a).dot(&u), (point - a).dot(&v)]);
+        let minimum = [0, 1].map(|axis| {
+            coordinates
+                .iter()
+                .map(|coordinate| coordinate[axis])
+                .fold(f32::INFINITY, f32::min)
+        });
+        let maximum = [0, 1].map(|axis| {
+            coordinates
+                .iter()
+                .map(|coordinate| coordinate[axis])
+                .fold(f32::NEG_INFINITY, f32::max)
+        });
+        Some(Self {
+            origin: a + u * minimum[0] + v * minimum[1],
+            u,
+            v,
+            normal,
+            size: (maximum[0] - minimum[0]).max(maximum[1] - minimum[1]),
+        })
+    }
+
+    fn point(&self, local: [f32; 2]) -> Point3<f32> {
+        self.origin + (self.u * local[0] + self.v * local[1]) * self.size
+    }
+
+    fn chart(&self, cell: [f32; 4]) -> LightmapChart {
+        let row = |axis: &Vector3<f32>| {
+            let axis = axis / self.size;
+            [axis.x, axis.y, axis.z, -axis.dot(&self.origin.coords)]
+        };
+        LightmapChart {
+            u: row(&self.u),
+            v: row(&self.v),
+            plane: [
+                self.normal.x,
+                self.normal.y,
+                self.normal.z,
+                -self.normal.dot(&self.origin.coords),
+            ],
+            cell,
+        }
+    }
+}
+
+// From Real-Time Collision Detection, 5.1.5.
+fn closest_point(point: &Point3<f32>, triangle: &Triangle) -> Point3<f32> {
+    let [a, b, c] = *triangle;
+    let (ab, ac, ap) = (b - a, c - a, point - a);
+    let (d1, d2) = (ab.dot(&ap), ac.dot(&ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = point - b;
+    let (d3, d4) = (ab.dot(&bp), ac.dot(&bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = point - c;
+    let (d5, d6) = (ab.dot(&cp), ac.dot(&cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
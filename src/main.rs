@@ -1,21 +1,29 @@
-#![feature(iterator_try_collect)]
-
-use anyhow::{Context as AnyhowContext, Result};
-use winit::event::{Event, WindowEvent};
+use anyhow::Result;
 use winit::event_loop::EventLoopBuilder;
 
-use crate::app::App;
-
-mod app;
-mod vulkan_device;
-mod vulkan_instance;
-mod vulkan_renderer;
+use vulkanox::app::App;
+use vulkanox::benchmark::{run_traversal_benchmark, BenchmarkSettings, TraversalBenchmarkSettings};
+use vulkanox::lightmap::{bake_lightmap, LightmapBakeSettings};
+use vulkanox::scene_graph::scene_selection_from_args;
+use vulkanox::vulkan_instance::DeviceSelection;
 
 fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
+    // Runs without a window or device, so it exits before the event loop starts.
+    if let Some(settings) = TraversalBenchmarkSettings::from_args(std::env::args().skip(1))? {
+        return run_traversal_benchmark(&settings);
+    }
+    // Bakes on the CPU, so it doesn't need a device either.
+    if let Some(settings) = LightmapBakeSettings::from_args(std::env::args().skip(1))? {
+        return bake_lightmap(&settings);
+    }
+
     let event_loop = EventLoopBuilder::new().build()?;
-    let mut app = App::new(&event_loop)?;
+    let benchmark_settings = BenchmarkSettings::from_args(std::env::args().skip(1))?;
+    let device_selection = DeviceSelection::from_args(std::env::args().skip(1))?;
+    let scene = scene_selection_from_args(std::env::args().skip(1))?;
+    let mut app = App::new(&event_loop, benchmark_settings, device_selection, scene)?;
 
     event_loop.run(move |event, window_target| app.process_event(event, window_target).unwrap())?;
 
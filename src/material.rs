@@ -0,0 +1,219 @@
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::rasterization::CullMode;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    Mask(f32),
+    Blend,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub slope_factor: f32,
+}
+
+impl DepthBias {
+    pub fn record<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) -> Result<()> {
+        builder.set_depth_bias(self.constant_factor, 0.0, self.slope_factor)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneMaterial {
+    pub alpha_mode: AlphaMode,
+    pub base_color: [f32; 4],
+    pub double_sided: bool,
+    pub depth_bias: DepthBias,
+    pub point_size: f32,
+    pub line_width: f32,
+}
+
+impl SceneMaterial {
+    pub fn from_gltf(material: &gltf::Material) -> Self {
+        let alpha_mode = match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => {
+                AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5))
+            }
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        };
+
+        Self {
+            alpha_mode,
+            base_color: material.pbr_metallic_roughness().base_color_factor(),
+            double_sided: material.double_sided(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.alpha_mode == AlphaMode::Blend
+    }
+
+    pub fn cull_mode(&self) -> CullMode {
+        if self.double_sided {
+            CullMode::None
+        } else {
+            CullMode::Back
+        }
+    }
+
+    pub fn alpha_cutoff(&self) -> f32 {
+        match self.alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            _ => 0.0,
+        }
+    }
+
+    // The dynamic state drawing with this material needs. Without dynamic cull mode the
+    // pipeline's applies, and without wide lines they're a pixel wide.
+    pub fn record_state<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        dynamic_cull_mode: bool,
+        wide_lines: bool,
+    ) -> Result<()> {
+        if dynamic_cull_mode {
+            builder.set_cull_mode(self.cull_mode())?;
+        }
+        builder.set_line_width(if wide_lines { self.line_width } else { 1.0 })?;
+        self.depth_bias.record(builder)
+    }
+}
+
+impl Default for SceneMaterial {
+    fn default() -> Self {
+        Self {
+            alpha_mode: AlphaMode::Opaque,
+            base_color: [1.0; 4],
+            double_sided: false,
+            depth_bias: DepthBias::default(),
+            point_size: 1.0,
+            line_width: 1.0,
+        }
+    }
+}
+
+pub fn primitive_topology(mode: gltf::mesh::Mode) -> PrimitiveTopology {
+    match mode {
+        gltf::mesh::Mode::Points => PrimitiveTopology::PointList,
+        gltf::mesh::Mode::Lines => PrimitiveTopology::LineList,
+        gltf::mesh::Mode::LineLoop => {
+            warn!("Line loops are not supported, drawing as a line strip");
+            PrimitiveTopology::LineStrip
+        }
+        gltf::mesh::Mode::LineStrip => PrimitiveTopology::LineStrip,
+        gltf::mesh::Mode::Triangles => PrimitiveTopology::TriangleList,
+        gltf::mesh::Mode::TriangleStrip => PrimitiveTopology::TriangleStrip,
+        gltf::mesh::Mode::TriangleFan => PrimitiveTopology::TriangleFan,
+    }
+}
+
+pub const PRIMITIVE_RESTART_INDEX: u16 = u16::MAX;
+
+pub fn uses_primitive_restart(topology: PrimitiveTopology) -> bool {
+    matches!(
+        topology,
+        PrimitiveTopology::LineStrip
+            | PrimitiveTopology::TriangleStrip
+            | PrimitiveTopology::TriangleFan
+    )
+}
+
+// Strip exports separate their strips with PRIMITIVE_RESTART_INDEX instead of being re-indexed on the CPU.
+pub fn input_assembly_state(topology: PrimitiveTopology) -> InputAssemblyState {
+    InputAssemblyState {
+        topology,
+        primitive_restart_enable: uses_primitive_restart(topology),
+        ..Default::default()
+    }
+}
+
+// With restart enabled, the largest 16-bit index always restarts, so the vertex it would name
+// can't be drawn. Such meshes are turned away on load rather than drawn with holes.
+pub fn check_restart_index(topology: PrimitiveTopology, vertex_count: usize) -> Result<()> {
+    ensure!(
+        !uses_primitive_restart(topology) || vertex_count <= PRIMITIVE_RESTART_INDEX as usize,
+        "{topology:?} meshes can have at most {PRIMITIVE_RESTART_INDEX} vertices, since index \
+         {PRIMITIVE_RESTART_INDEX} restarts the strip, but this one has {vertex_count}"
+    );
+    Ok(())
+}
+
+// The strips or fans between restart indices.
+pub fn restart_runs(indices: &[u16]) -> impl Iterator<Item = &[u16]> {
+    indices.split(|&index| index == PRIMITIVE_RESTART_INDEX)
+}
+
+// Portability implementations such as MoltenVK may lack triangle fans, so fans are rewritten into
+// an equivalent list, one fan per restart-separated run.
+pub fn triangulate_fan(indices: &[u16]) -> Vec<u16> {
+    restart_runs(indices)
+        .flat_map(|fan| {
+            fan.windows(2)
+                .skip(1)
+                .flat_map(move |edge| [fan[0], edge[0], edge[1]])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESTART: u16 = PRIMITIVE_RESTART_INDEX;
+
+    #[test]
+    fn triangulates_a_fan_around_its_first_vertex() {
+        assert_eq!(
+            triangulate_fan(&[0, 1, 2, 3, 4]),
+            [0, 1, 2, 0, 2, 3, 0, 3, 4]
+        );
+    }
+
+    #[test]
+    fn triangulates_each_fan_between_restarts() {
+        assert_eq!(
+            triangulate_fan(&[0, 1, 2, 3, RESTART, 4, 5, 6]),
+            [0, 1, 2, 0, 2, 3, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn drops_fans_too_short_for_a_triangle() {
+        assert!(triangulate_fan(&[]).is_empty());
+        assert_eq!(
+            triangulate_fan(&[0, 1, RESTART, RESTART, 2, 3, 4, RESTART]),
+            [2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn splits_runs_at_restart_indices() {
+        let runs = restart_runs(&[0, 1, 2, RESTART, 3, 4, RESTART, RESTART, 5]).collect::<Vec<_>>();
+        assert_eq!(runs, [&[0, 1, 2][..], &[3, 4], &[], &[5]]);
+        assert_eq!(restart_runs(&[0, 1, 2]).count(), 1);
+    }
+
+    #[test]
+    fn turns_away_strips_that_reach_the_restart_index() {
+        let limit = PRIMITIVE_RESTART_INDEX as usize;
+        for topology in [
+            PrimitiveTopology::LineStrip,
+            PrimitiveTopology::TriangleStrip,
+            PrimitiveTopology::TriangleFan,
+        ] {
+            assert!(check_restart_index(topology, limit).is_ok());
+            assert!(check_restart_index(topology, limit + 1).is_err());
+        }
+        assert!(check_restart_index(PrimitiveTopology::TriangleList, limit + 1).is_ok());
+    }
+}
@@ -0,0 +1,137 @@
+use std::ffi::c_void;
+use std::fmt;
+use std::sync::Arc;
+
+use tracing::warn;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::image::Image;
+use vulkano::memory::MemoryHeapFlags;
+use vulkano::{DeviceSize, Version, VulkanObject};
+
+pub const BUDGET_WARNING_THRESHOLD: f32 = 0.9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryCategory {
+    Meshes,
+    Textures,
+    Attachments,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapBudget {
+    pub heap_index: u32,
+    pub device_local: bool,
+    // Only known when VK_EXT_memory_budget is enabled; otherwise the budget is the heap size.
+    pub usage: Option<DeviceSize>,
+    pub budget: DeviceSize,
+}
+
+impl HeapBudget {
+    pub fn is_near_budget(&self) -> bool {
+        self.usage.is_some_and(|usage| {
+            usage as f64 >= self.budget as f64 * BUDGET_WARNING_THRESHOLD as f64
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    pub heaps: Vec<HeapBudget>,
+    pub categories: Vec<(MemoryCategory, DeviceSize)>,
+}
+
+impl MemoryReport {
+    pub fn warn_if_near_budget(&self) {
+        for heap in self.heaps.iter().filter(|heap| heap.is_near_budget()) {
+            warn!(
+                "Memory heap {} is at {} of its {} budget",
+                heap.heap_index,
+                Bytes(heap.usage.unwrap_or_default()),
+                Bytes(heap.budget)
+            );
+        }
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for heap in &self.heaps {
+            let kind = if heap.device_local { "device" } else { "host" };
+            match heap.usage {
+                Some(usage) => writeln!(
+                    f,
+                    "heap {} ({kind}): {} / {}",
+                    heap.heap_index,
+                    Bytes(usage),
+                    Bytes(heap.budget)
+                )?,
+                None => writeln!(
+                    f,
+                    "heap {} ({kind}): {}",
+                    heap.heap_index,
+                    Bytes(heap.budget)
+                )?,
+            }
+        }
+        for (category, size) in &self.categories {
+            writeln!(f, "{category:?}: {}", Bytes(*size))?;
+        }
+        Ok(())
+    }
+}
+
+struct Bytes(DeviceSize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1} MiB", self.0 as f64 / (1024.0 * 1024.0))
+    }
+}
+
+pub fn image_size(image: &Arc<Image>) -> DeviceSize {
+    image
+        .memory_requirements()
+        .iter()
+        .map(|requirements| requirements.layout.size())
+        .sum()
+}
+
+// vulkano doesn't expose VkPhysicalDeviceMemoryBudgetPropertiesEXT, so it is chained by hand.
+pub fn query_heap_budgets(
+    physical_device: &PhysicalDevice,
+    memory_budget: bool,
+) -> Vec<HeapBudget> {
+    let budget_properties =
+        (memory_budget && physical_device.instance().api_version() >= Version::V1_1).then(|| {
+            let mut budget_properties = ash::vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+            let mut memory_properties = ash::vk::PhysicalDeviceMemoryProperties2 {
+                p_next: &mut budget_properties as *mut _ as *mut c_void,
+                ..Default::default()
+            };
+            unsafe {
+                (physical_device
+                    .instance()
+                    .fns()
+                    .v1_1
+                    .get_physical_device_memory_properties2)(
+                    physical_device.handle(),
+                    &mut memory_properties,
+                );
+            }
+            budget_properties
+        });
+
+    physical_device
+        .memory_properties()
+        .memory_heaps
+        .iter()
+        .enumerate()
+        .map(|(heap_index, heap)| HeapBudget {
+            heap_index: heap_index as u32,
+            device_local: heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL),
+            usage: budget_properties.map(|properties| properties.heap_usage[heap_index]),
+            budget: budget_properties
+                .map_or(heap.size, |properties| properties.heap_budget[heap_index]),
+        })
+        .collect()
+}
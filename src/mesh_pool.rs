@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use vulkano::buffer::allocator::SubbufferAllocator;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CopyBufferInfo, PrimaryAutoCommandBuffer};
+use vulkano::memory::allocator::suballocator::{Region, Suballocation};
+use vulkano::memory::allocator::{
+    AllocationCreateInfo, AllocationType, DeviceLayout, FreeListAllocator, MemoryTypeFilter,
+    StandardMemoryAllocator, Suballocator,
+};
+use vulkano::memory::DeviceAlignment;
+use vulkano::DeviceSize;
+
+use crate::vulkan_device::Vertex;
+
+pub const DEFAULT_VERTEX_CAPACITY: DeviceSize = 1 << 20;
+pub const DEFAULT_INDEX_CAPACITY: DeviceSize = 1 << 21;
+
+// Offsets are in elements rather than bytes, so they can be handed straight to `draw_indexed`.
+// Indices stay relative to the primitive and are rebased through `vertex_offset`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshAllocation {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+}
+
+// Every primitive lives in one vertex and one index buffer, so switching meshes never rebinds.
+pub struct MeshPool {
+    vertex_buffer: Subbuffer<[Vertex]>,
+    index_buffer: Subbuffer<[u16]>,
+    ranges: Mutex<MeshRanges>,
+}
+
+struct MeshRanges {
+    vertex_allocator: FreeListAllocator,
+    index_allocator: FreeListAllocator,
+    // The vertex and index ranges of every live mesh, by first index.
+    meshes: HashMap<u32, (Suballocation, Suballocation)>,
+}
+
+impl MeshPool {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        vertex_capacity: DeviceSize,
+        index_capacity: DeviceSize,
+        device_address: bool,
+    ) -> Result<Self> {
+        // Vertex pulling reads vertices through their device address rather than vertex input.
+        let vertex_usage = if device_address {
+            BufferUsage::VERTEX_BUFFER | BufferUsage::SHADER_DEVICE_ADDRESS
+        } else {
+            BufferUsage::VERTEX_BUFFER
+        };
+        let create_info = |usage| BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST | usage,
+            ..Default::default()
+        };
+        let allocation_info = || AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        };
+
+        Ok(Self {
+            vertex_buffer: Buffer::new_slice(
+                memory_allocator.clone(),
+                create_info(vertex_usage),
+                allocation_info(),
+                vertex_capacity,
+            )?,
+            index_buffer: Buffer::new_slice(
+                memory_allocator,
+                create_info(BufferUsage::INDEX_BUFFER),
+                allocation_info(),
+                index_capacity,
+            )?,
+            ranges: Mutex::new(MeshRanges {
+                vertex_allocator: FreeListAllocator::new(Region::new(0, vertex_capacity).unwrap()),
+                index_allocator: FreeListAllocator::new(Region::new(0, index_capacity).unwrap()),
+                meshes: HashMap::new(),
+            }),
+        })
+    }
+
+    pub fn upload(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        staging_allocator: &SubbufferAllocator,
+        vertices: &[Vertex],
+        indices: &[u16],
+    ) -> Result<MeshAllocation> {
+        ensure!(
+            !vertices.is_empty() && !indices.is_empty(),
+            "Can't add an empty mesh to the mesh pool"
+        );
+
+        let allocate = |allocator: &mut FreeListAllocator, len: usize| {
+            allocator
+                .allocate(
+                    DeviceLayout::from_size_alignment(len as DeviceSize, 1).unwrap(),
+                    AllocationType::Linear,
+                    DeviceAlignment::MIN,
+                )
+                .map_err(|error| anyhow!("Mesh pool is full: {error:?}"))
+        };
+        let mut ranges = self.ranges.lock().unwrap();
+        let vertex_range = allocate(&mut ranges.vertex_allocator, vertices.len())?;
+        let index_range = match allocate(&mut ranges.index_allocator, indices.len()) {
+            Ok(index_range) => index_range,
+            Err(error) => {
+                unsafe { ranges.vertex_allocator.deallocate(vertex_range) };
+                return Err(error);
+            }
+        };
+
+        let vertex_staging_buffer =
+            staging_allocator.allocate_slice::<Vertex>(vertices.len() as DeviceSize)?;
+        let index_staging_buffer =
+            staging_allocator.allocate_slice::<u16>(indices.len() as DeviceSize)?;
+        vertex_staging_buffer.write()?.copy_from_slice(vertices);
+        index_staging_buffer.write()?.copy_from_slice(indices);
+
+        builder
+            .copy_buffer(CopyBufferInfo::buffers(
+                vertex_staging_buffer,
+                self.vertex_buffer
+                    .clone()
+                    .slice(vertex_range.offset..vertex_range.offset + vertices.len() as DeviceSize),
+            ))?
+            .copy_buffer(CopyBufferInfo::buffers(
+                index_staging_buffer,
+                self.index_buffer
+                    .clone()
+                    .slice(index_range.offset..index_range.offset + indices.len() as DeviceSize),
+            ))?;
+
+        ranges
+            .meshes
+            .insert(index_range.offset as u32, (vertex_range, index_range));
+        Ok(MeshAllocation {
+            first_index: index_range.offset as u32,
+            index_count: indices.len() as u32,
+            vertex_offset: vertex_range.offset as i32,
+        })
+    }
+
+    // Returns a mesh's ranges to the pool. The caller must make sure no pending command buffer
+    // still draws it.
+    pub fn free(&self, mesh: MeshAllocation) -> Result<()> {
+        let mut ranges = self.ranges.lock().unwrap();
+        let (vertex_range, index_range) = ranges
+            .meshes
+            .remove(&mesh.first_index)
+            .context("Mesh isn't allocated from this pool")?;
+        unsafe {
+            ranges.vertex_allocator.deallocate(vertex_range);
+            ranges.index_allocator.deallocate(index_range);
+        }
+
+        Ok(())
+    }
+
+    pub fn vertex_buffer(&self) -> &Subbuffer<[Vertex]> {
+        &self.vertex_buffer
+    }
+
+    pub fn index_buffer(&self) -> &Subbuffer<[u16]> {
+        &self.index_buffer
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        self.vertex_buffer.size() + self.index_buffer.size()
+    }
+}
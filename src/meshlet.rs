@@ -0,0 +1,538 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use meshopt::{build_meshlets, compute_meshlet_bounds, VertexDataAdapter};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineShaderStageCreateInfo,
+};
+use vulkano::shader::EntryPoint;
+
+use crate::gpu_scene::{self, SceneObjects, GPU_SCENE_SET};
+use crate::material::SceneMaterial;
+use crate::mesh_pool::MeshAllocation;
+use crate::vulkan_device::{vs, Vertex, VulkanDevice, DEPTH_FORMAT, HDR_FORMAT};
+
+// The meshlet buffers come after the sets the fragment shader and the GPU scene take.
+const MESHLET_SET: u32 = GPU_SCENE_SET + 1;
+
+const MAX_MESHLET_VERTICES: usize = 64;
+const MAX_MESHLET_TRIANGLES: usize = 124;
+const TASK_WORKGROUP_SIZE: u32 = 32;
+
+mod meshlet_ts {
+    vulkano_shaders::shader! {
+        ty: "task",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: r"
+                #version 460
+                #extension GL_EXT_mesh_shader : require
+
+                layout(local_size_x = 32, local_size_y = 1, local_size_z = 1) in;
+
+                struct MeshletBounds {
+                    vec4 sphere;
+                };
+
+                struct TaskPayload {
+                    uint meshletIndices[32];
+                };
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                layout(set = 4, binding = 0) readonly buffer Bounds {
+                    MeshletBounds bounds[];
+                };
+
+                // Starts like the fragment shader's block, whose range it shares.
+                layout(push_constant) uniform MeshletParameters {
+                    float time;
+                    vec2 mousePosition;
+                    vec4 baseColor;
+                    float alphaCutoff;
+                    float pointSize;
+                    uint firstMeshlet;
+                    uint meshletCount;
+                    uint object;
+                } parameters;
+
+                taskPayloadSharedEXT TaskPayload payload;
+
+                shared uint visibleCount;
+
+                bool isVisible(vec4 sphere) {
+                    mat4 matrix = transpose(uniforms.view_projection);
+                    vec4 planes[6] = vec4[](
+                        matrix[3] + matrix[0],
+                        matrix[3] - matrix[0],
+                        matrix[3] + matrix[1],
+                        matrix[3] - matrix[1],
+                        matrix[3] + matrix[2],
+                        matrix[3] - matrix[2]
+                    );
+
+                    for (uint i = 0u; i < 6u; i++) {
+                        if (dot(planes[i].xyz, sphere.xyz) + planes[i].w < -sphere.w * length(planes[i].xyz)) {
+                            return false;
+                        }
+                    }
+                    return true;
+                }
+
+                void main() {
+                    if (gl_LocalInvocationIndex == 0u) {
+                        visibleCount = 0u;
+                    }
+                    barrier();
+
+                    // Bounds are in the mesh's space, so they're moved and scaled like the object.
+                    uint meshletIndex = parameters.firstMeshlet + gl_GlobalInvocationID.x;
+                    if (gl_GlobalInvocationID.x < parameters.meshletCount) {
+                        mat4 transform = scene.objects[parameters.object].transform;
+                        vec4 sphere = bounds[meshletIndex].sphere;
+                        float scale = max(max(length(transform[0].xyz), length(transform[1].xyz)), length(transform[2].xyz));
+                        if (isVisible(vec4((transform * vec4(sphere.xyz, 1.0)).xyz, sphere.w * scale))) {
+                            payload.meshletIndices[atomicAdd(visibleCount, 1u)] = meshletIndex;
+                        }
+                    }
+                    barrier();
+
+                    EmitMeshTasksEXT(visibleCount, 1u, 1u);
+                }
+            ",
+    }
+}
+
+mod meshlet_ms {
+    vulkano_shaders::shader! {
+        ty: "mesh",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: r"
+                #version 460
+                #extension GL_EXT_mesh_shader : require
+
+                layout(local_size_x = 32, local_size_y = 1, local_size_z = 1) in;
+                layout(triangles, max_vertices = 64, max_primitives = 124) out;
+
+                layout(location = 0) out vec3 fragColor[];
+                layout(location = 1) out vec3 viewPosition[];
+
+                struct Meshlet {
+                    uint vertexOffset;
+                    uint triangleOffset;
+                    uint vertexCount;
+                    uint triangleCount;
+                };
+
+                struct TaskPayload {
+                    uint meshletIndices[32];
+                };
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                layout(set = 4, binding = 1) readonly buffer Meshlets {
+                    Meshlet meshlets[];
+                };
+
+                layout(set = 4, binding = 2) readonly buffer MeshletVertices {
+                    uint meshletVertices[];
+                };
+
+                layout(set = 4, binding = 3) readonly buffer MeshletTriangles {
+                    uint meshletTriangles[];
+                };
+
+                layout(set = 4, binding = 4) readonly buffer Positions {
+                    float positions[];
+                };
+
+                layout(push_constant) uniform MeshletParameters {
+                    float time;
+                    vec2 mousePosition;
+                    vec4 baseColor;
+                    float alphaCutoff;
+                    float pointSize;
+                    uint firstMeshlet;
+                    uint meshletCount;
+                    uint object;
+                } parameters;
+
+                taskPayloadSharedEXT TaskPayload payload;
+
+                uint triangleIndex(uint byteIndex) {
+                    return (meshletTriangles[byteIndex / 4u] >> ((byteIndex % 4u) * 8u)) & 0xFFu;
+                }
+
+                void main() {
+                    Meshlet meshlet = meshlets[payload.meshletIndices[gl_WorkGroupID.x]];
+                    SetMeshOutputsEXT(meshlet.vertexCount, meshlet.triangleCount);
+                    mat4 transform = scene.objects[parameters.object].transform;
+
+                    for (uint i = gl_LocalInvocationIndex; i < meshlet.vertexCount; i += 32u) {
+                        uint vertexIndex = meshletVertices[meshlet.vertexOffset + i];
+                        vec3 position = vec3(
+                            positions[vertexIndex * 3u],
+                            positions[vertexIndex * 3u + 1u],
+                            positions[vertexIndex * 3u + 2u]
+                        );
+
+                        vec4 worldPosition = transform * vec4(position, 1.0);
+                        gl_MeshVerticesEXT[i].gl_Position = uniforms.view_projection * worldPosition;
+                        fragColor[i] = worldPosition.xyz;
+                        viewPosition[i] = (uniforms.view * worldPosition).xyz;
+                    }
+
+                    for (uint i = gl_LocalInvocationIndex; i < meshlet.triangleCount; i += 32u) {
+                        uint base = meshlet.triangleOffset + i * 3u;
+                        gl_PrimitiveTriangleIndicesEXT[i] = uvec3(
+                            triangleIndex(base),
+                            triangleIndex(base + 1u),
+                            triangleIndex(base + 2u)
+                        );
+                        // Meshlets reorder the triangles, so they opt out of the scene's lightmap.
+                        gl_MeshPrimitivesEXT[i].gl_PrimitiveID = -1;
+                    }
+                }
+            ",
+    }
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct Meshlet {
+    vertex_offset: u32,
+    triangle_offset: u32,
+    vertex_count: u32,
+    triangle_count: u32,
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct MeshletBounds {
+    sphere: [f32; 4],
+}
+
+pub struct MeshletMesh {
+    // The meshlets each mesh was split into, as its first one and their count.
+    mesh_meshlets: HashMap<MeshAllocation, (u32, u32)>,
+    bounds_buffer: Subbuffer<[MeshletBounds]>,
+    meshlet_buffer: Subbuffer<[Meshlet]>,
+    vertex_index_buffer: Subbuffer<[u32]>,
+    triangle_buffer: Subbuffer<[u32]>,
+    position_buffer: Subbuffer<[f32]>,
+}
+
+impl MeshletMesh {
+    // Splits each of the meshes, which lie in the scene mesh uploaded as `scene_mesh`, into
+    // meshlets once, however many objects share it. Meshlets index the scene mesh's vertices
+    // directly.
+    pub fn build(
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        vertices: &[Vertex],
+        indices: &[u16],
+        scene_mesh: &MeshAllocation,
+        meshes: impl IntoIterator<Item = MeshAllocation>,
+    ) -> Result<Self> {
+        let adapter = VertexDataAdapter::new(
+            bytemuck::cast_slice(vertices),
+            std::mem::size_of::<Vertex>(),
+            0,
+        )?;
+
+        let mut mesh_meshlets = HashMap::new();
+        let mut gpu_meshlets = Vec::new();
+        let mut bounds = Vec::new();
+        let mut vertex_indices = Vec::new();
+        let mut triangles = Vec::new();
+        for mesh in meshes {
+            if mesh_meshlets.contains_key(&mesh) {
+                continue;
+            }
+
+            let first_index = (mesh.first_index - scene_mesh.first_index) as usize;
+            let vertex_offset = (mesh.vertex_offset - scene_mesh.vertex_offset) as u32;
+            let mesh_indices = indices[first_index..first_index + mesh.index_count as usize]
+                .iter()
+                .map(|&index| index as u32 + vertex_offset)
+                .collect::<Vec<_>>();
+            let meshlets = build_meshlets(
+                &mesh_indices,
+                &adapter,
+                MAX_MESHLET_VERTICES,
+                MAX_MESHLET_TRIANGLES,
+                0.0,
+            );
+
+            mesh_meshlets.insert(
+                mesh,
+                (gpu_meshlets.len() as u32, meshlets.meshlets.len() as u32),
+            );
+            bounds.extend(meshlets.iter().map(|meshlet| {
+                let bounds = compute_meshlet_bounds(meshlet, &adapter);
+                let [x, y, z] = bounds.center;
+                MeshletBounds {
+                    sphere: [x, y, z, bounds.radius],
+                }
+            }));
+            gpu_meshlets.extend(meshlets.meshlets.iter().map(|meshlet| Meshlet {
+                vertex_offset: vertex_indices.len() as u32 + meshlet.vertex_offset,
+                triangle_offset: triangles.len() as u32 + meshlet.triangle_offset,
+                vertex_count: meshlet.vertex_count,
+                triangle_count: meshlet.triangle_count,
+            }));
+            vertex_indices.extend_from_slice(&meshlets.vertices);
+            triangles.extend_from_slice(&meshlets.triangles);
+        }
+        triangles.resize(triangles.len().next_multiple_of(4), 0);
+
+        let buffer_info = BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        };
+        let allocation_info = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+
+        Ok(Self {
+            mesh_meshlets,
+            bounds_buffer: Buffer::from_iter(
+                Arc::clone(&memory_allocator),
+                buffer_info.clone(),
+                allocation_info.clone(),
+                bounds,
+            )?,
+            meshlet_buffer: Buffer::from_iter(
+                Arc::clone(&memory_allocator),
+                buffer_info.clone(),
+                allocation_info.clone(),
+                gpu_meshlets,
+            )?,
+            vertex_index_buffer: Buffer::from_iter(
+                Arc::clone(&memory_allocator),
+                buffer_info.clone(),
+                allocation_info.clone(),
+                vertex_indices,
+            )?,
+            triangle_buffer: Buffer::from_iter(
+                Arc::clone(&memory_allocator),
+                buffer_info.clone(),
+                allocation_info.clone(),
+                triangles
+                    .chunks_exact(4)
+                    .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            )?,
+            position_buffer: Buffer::from_iter(
+                memory_allocator,
+                buffer_info,
+                allocation_info,
+                bytemuck::cast_slice::<Vertex, f32>(vertices).iter().copied(),
+            )?,
+        })
+    }
+}
+
+// The objects to draw, with the material parameters the fragment shader reads for each.
+pub struct MeshletObjects<'a> {
+    pub objects: SceneObjects<'a>,
+    pub materials: &'a [SceneMaterial],
+    pub push_constants: vs::PushConstantData,
+}
+
+pub struct MeshletPass {
+    pipeline: Arc<GraphicsPipeline>,
+    mesh: MeshletMesh,
+    mesh_set: Arc<DescriptorSet>,
+}
+
+impl MeshletPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        descriptor_set_allocator: &Arc<StandardDescriptorSetAllocator>,
+        samples: SampleCount,
+        fragment_shader: EntryPoint,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+        mesh: MeshletMesh,
+    ) -> Result<Self> {
+        let stages = [
+            PipelineShaderStageCreateInfo::new(
+                meshlet_ts::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+            PipelineShaderStageCreateInfo::new(
+                meshlet_ms::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+            PipelineShaderStageCreateInfo::new(fragment_shader),
+        ];
+
+        let layout = gpu_scene::create_pipeline_layout(device, &stages, gpu_scene_set_layout)?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: CompareOp::LessOrEqual,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: samples,
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        let mesh_set = DescriptorSet::new(
+            descriptor_set_allocator.clone(),
+            Arc::clone(&pipeline.layout().set_layouts()[MESHLET_SET as usize]),
+            [
+                WriteDescriptorSet::buffer(0, mesh.bounds_buffer.clone()),
+                WriteDescriptorSet::buffer(1, mesh.meshlet_buffer.clone()),
+                WriteDescriptorSet::buffer(2, mesh.vertex_index_buffer.clone()),
+                WriteDescriptorSet::buffer(3, mesh.triangle_buffer.clone()),
+                WriteDescriptorSet::buffer(4, mesh.position_buffer.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(Self {
+            pipeline,
+            mesh,
+            mesh_set,
+        })
+    }
+
+    // Objects whose mesh wasn't split into meshlets when the scene loaded are left out.
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        occlusion_set: &Arc<DescriptorSet>,
+        lighting_set: &Arc<DescriptorSet>,
+        objects: &MeshletObjects,
+    ) -> Result<()> {
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone())],
+            [],
+        )?;
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                vec![
+                    set,
+                    Arc::clone(occlusion_set),
+                    Arc::clone(lighting_set),
+                    Arc::clone(objects.objects.set),
+                    Arc::clone(&self.mesh_set),
+                ],
+            )?;
+
+        let push_constants = objects.push_constants;
+        for object in objects.objects.draws {
+            let Some(&(first_meshlet, meshlet_count)) = self.mesh.mesh_meshlets.get(&object.mesh)
+            else {
+                continue;
+            };
+            let material = &objects.materials[object.material as usize];
+            builder.push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                meshlet_ts::MeshletParameters {
+                    time: push_constants.time,
+                    mousePosition: push_constants.mousePosition,
+                    baseColor: material.base_color,
+                    alphaCutoff: material.alpha_cutoff(),
+                    pointSize: material.point_size,
+                    firstMeshlet: first_meshlet,
+                    meshletCount: meshlet_count,
+                    object: object.object,
+                },
+            )?;
+            unsafe {
+                builder.draw_mesh_tasks([meshlet_count.div_ceil(TASK_WORKGROUP_SIZE), 1, 1])
+            }?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use tracing::info;
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::DeviceSize;
+
+// Triangles only cover draws whose size is known on the CPU; GPU-culled foliage and
+// particles contribute draw calls but no triangles.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameMetrics {
+    pub frame: u64,
+    pub frame_time_ms: f32,
+    pub draw_calls: u32,
+    pub triangles: u64,
+    pub gpu_passes: u32,
+    // Only the window's attachments; buffers, textures and the swapchain aren't counted.
+    pub render_target_bytes: DeviceSize,
+    // Scene graph nodes with a mesh that survived CPU frustum culling, summed over the views
+    // drawn into the window.
+    pub visible_nodes: u32,
+    // Time from queueing the previous present until it reached the display; only measured with
+    // VK_KHR_present_wait in Mailbox or Immediate mode.
+    pub present_latency_ms: Option<f32>,
+}
+
+impl FrameMetrics {
+    pub fn pass(&mut self) {
+        self.gpu_passes += 1;
+    }
+
+    pub fn draw(&mut self, triangles: u64) {
+        self.draw_calls += 1;
+        self.triangles += triangles;
+    }
+}
+
+pub fn triangle_count(topology: PrimitiveTopology, index_count: u32) -> u64 {
+    match topology {
+        PrimitiveTopology::TriangleList => index_count as u64 / 3,
+        PrimitiveTopology::TriangleStrip | PrimitiveTopology::TriangleFan => {
+            index_count.saturating_sub(2) as u64
+        }
+        _ => 0,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MetricsFormat {
+    Csv,
+    Json,
+}
+
+pub struct MetricsRecorder {
+    path: PathBuf,
+    format: MetricsFormat,
+    frames: Vec<FrameMetrics>,
+}
+
+impl MetricsRecorder {
+    // The format follows the file extension, in any case; anything other than `.json` is written
+    // as CSV.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => MetricsFormat::Json,
+            _ => MetricsFormat::Csv,
+        };
+
+        Self {
+            path,
+            format,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, metrics: FrameMetrics) {
+        self.frames.push(metrics);
+    }
+
+    pub fn write(&self) -> Result<()> {
+        std::fs::write(&self.path, self.export()?)?;
+        info!(
+            "Wrote {} frames of metrics to {}",
+            self.frames.len(),
+            self.path.display()
+        );
+
+        Ok(())
+    }
+
+    fn export(&self) -> Result<String> {
+        let mut output = String::new();
+        match self.format {
+            MetricsFormat::Csv => {
+                output.push_str(
+                    "frame,frame_time_ms,draw_calls,triangles,gpu_passes,render_target_bytes,visible_nodes,present_latency_ms\n",
+                );
+                for metrics in &self.frames {
+                    writeln!(
+                        output,
+                        "{},{},{},{},{},{},{},{}",
+                        metrics.frame,
+                        finite_or(metrics.frame_time_ms, ""),
+                        metrics.draw_calls,
+                        metrics.triangles,
+                        metrics.gpu_passes,
+                        metrics.render_target_bytes,
+                        metrics.visible_nodes,
+                        metrics
+                            .present_latency_ms
+                            .map_or_else(String::new, |latency| finite_or(latency, ""))
+                    )?;
+                }
+            }
+            MetricsFormat::Json => {
+                output.push_str("[\n");
+                for (index, metrics) in self.frames.iter().enumerate() {
+                    let separator = if index + 1 == self.frames.len() {
+                        ""
+                    } else {
+                        ","
+                    };
+                    writeln!(
+                        output,
+                        r#"  {{ "frame": {}, "frame_time_ms": {}, "draw_calls": {}, "triangles": {}, "gpu_passes": {}, "render_target_bytes": {}, "visible_nodes": {}, "present_latency_ms": {} }}{separator}"#,
+                        metrics.frame,
+                        finite_or(metrics.frame_time_ms, "null"),
+                        metrics.draw_calls,
+                        metrics.triangles,
+                        metrics.gpu_passes,
+                        metrics.render_target_bytes,
+                        metrics.visible_nodes,
+                        metrics.present_latency_ms.map_or_else(
+                            || "null".to_owned(),
+                            |latency| finite_or(latency, "null")
+                        )
+                    )?;
+                }
+                output.push_str("]\n");
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+// Neither format can represent NaN or infinities, so they're written as missing values.
+fn finite_or(value: f32, missing: &str) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        missing.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder(path: &str) -> MetricsRecorder {
+        let mut recorder = MetricsRecorder::new(path);
+        recorder.push(FrameMetrics {
+            frame: 1,
+            frame_time_ms: 16.5,
+            draw_calls: 3,
+            triangles: 120,
+            gpu_passes: 2,
+            render_target_bytes: 4096,
+            visible_nodes: 5,
+            present_latency_ms: None,
+        });
+        recorder.push(FrameMetrics {
+            frame: 2,
+            present_latency_ms: Some(8.25),
+            ..Default::default()
+        });
+        recorder
+    }
+
+    #[test]
+    fn exports_csv_with_a_header_and_empty_missing_latencies() {
+        assert_eq!(
+            recorder("metrics.csv").export().unwrap(),
+            "frame,frame_time_ms,draw_calls,triangles,gpu_passes,render_target_bytes,visible_nodes,present_latency_ms\n\
+             1,16.5,3,120,2,4096,5,\n\
+             2,0,0,0,0,0,0,8.25\n"
+        );
+    }
+
+    #[test]
+    fn exports_json_with_null_missing_latencies() {
+        assert_eq!(
+            recorder("metrics.json").export().unwrap(),
+            concat!(
+                "[\n",
+                r#"  { "frame": 1, "frame_time_ms": 16.5, "draw_calls": 3, "triangles": 120, "gpu_passes": 2, "render_target_bytes": 4096, "visible_nodes": 5, "present_latency_ms": null },"#,
+                "\n",
+                r#"  { "frame": 2, "frame_time_ms": 0, "draw_calls": 0, "triangles": 0, "gpu_passes": 0, "render_target_bytes": 0, "visible_nodes": 0, "present_latency_ms": 8.25 }"#,
+                "\n]\n",
+            )
+        );
+    }
+
+    #[test]
+    fn exports_csv_for_other_extensions() {
+        for path in ["metrics", "metrics.txt", "metrics.jsonl"] {
+            assert!(MetricsRecorder::new(path)
+                .export()
+                .unwrap()
+                .starts_with("frame,"));
+        }
+    }
+
+    #[test]
+    fn matches_the_json_extension_in_any_case() {
+        for path in ["metrics.JSON", "metrics.Json"] {
+            assert!(MetricsRecorder::new(path)
+                .export()
+                .unwrap()
+                .starts_with('['));
+        }
+    }
+
+    #[test]
+    fn exports_non_finite_values_as_missing() {
+        let frame = FrameMetrics {
+            frame_time_ms: f32::NAN,
+            present_latency_ms: Some(f32::INFINITY),
+            ..Default::default()
+        };
+        let mut csv = MetricsRecorder::new("metrics.csv");
+        csv.push(frame);
+        assert!(csv.export().unwrap().ends_with("\n0,,0,0,0,0,0,\n"));
+
+        let mut json = MetricsRecorder::new("metrics.json");
+        json.push(frame);
+        let json = json.export().unwrap();
+        assert!(json.contains(r#""frame_time_ms": null"#));
+        assert!(json.contains(r#""present_latency_ms": null"#));
+    }
+
+    #[test]
+    fn exports_an_empty_json_array_without_frames() {
+        assert_eq!(
+            MetricsRecorder::new("metrics.json").export().unwrap(),
+            "[\n]\n"
+        );
+    }
+}
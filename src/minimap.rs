@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra::{Orthographic3, Vector3};
+
+use crate::render_texture::RenderTexture;
+use crate::sprite::Sprite;
+use crate::vulkan_device::VulkanDevice;
+
+#[derive(Clone, Copy, Debug)]
+pub struct MinimapSettings {
+    // Side length of the square inset, in pixels.
+    pub size: u32,
+    pub margin: f32,
+    // World-space distance from the scene center to each edge of the map.
+    pub radius: f32,
+}
+
+impl Default for MinimapSettings {
+    fn default() -> Self {
+        Self {
+            size: 256,
+            margin: 16.0,
+            radius: 5.0,
+        }
+    }
+}
+
+// An orthographic top-down view of the scene, drawn into a render texture and composited into the
+// top-right corner by the sprite pass. The sprite pass doesn't tonemap, so bright areas clip.
+pub struct Minimap {
+    render_texture: RenderTexture,
+    settings: MinimapSettings,
+}
+
+impl Minimap {
+    pub fn new(vulkan_device: &VulkanDevice, settings: MinimapSettings) -> Result<Self> {
+        let center = *vulkan_device.scene_center();
+        let radius = settings.radius;
+        let mut render_texture = RenderTexture::new(
+            vulkan_device,
+            [settings.size; 2],
+            center + Vector3::y() * radius * 2.0,
+            center,
+        )?;
+        // Looking straight down, so north on the map is -Z.
+        render_texture.up = -Vector3::z();
+        render_texture.projection = Some(
+            Orthographic3::new(-radius, radius, -radius, radius, 0.01, radius * 4.0).into_inner(),
+        );
+
+        Ok(Self {
+            render_texture,
+            settings,
+        })
+    }
+
+    pub fn render_texture(&self) -> &RenderTexture {
+        &self.render_texture
+    }
+
+    pub fn render_texture_mut(&mut self) -> &mut RenderTexture {
+        &mut self.render_texture
+    }
+
+    pub fn sprite(&self, extent: [u32; 2]) -> Sprite {
+        let size = self.settings.size as f32;
+        Sprite::new(
+            Arc::clone(self.render_texture.texture()),
+            [
+                extent[0] as f32 - size - self.settings.margin,
+                self.settings.margin,
+            ],
+            [size; 2],
+        )
+    }
+}
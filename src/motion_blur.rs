@@ -0,0 +1,181 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+
+use crate::fullscreen::{begin_fullscreen_pass, create_fullscreen_pipeline};
+use crate::post_process::{PostProcessContext, PostProcessEffect};
+use crate::vulkan_device::{VulkanDevice, HDR_FORMAT};
+
+mod motion_blur_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform sampler2D sceneTexture;
+                layout(set = 0, binding = 1) uniform sampler2D velocityTexture;
+
+                layout(push_constant) uniform MotionBlurParameters {
+                    float shutter;
+                    uint sampleCount;
+                } parameters;
+
+                void main() {
+                    vec2 velocity = textureLod(velocityTexture, uv, 0.0).rg * parameters.shutter;
+                    vec4 color = textureLod(sceneTexture, uv, 0.0);
+
+                    if (parameters.sampleCount < 2 || dot(velocity, velocity) < 1e-10) {
+                        outColor = color;
+                        return;
+                    }
+
+                    for (uint i = 0; i < parameters.sampleCount; i++) {
+                        float t = float(i) / float(parameters.sampleCount - 1) - 0.5;
+                        color += textureLod(sceneTexture, uv - velocity * t, 0.0);
+                    }
+
+                    outColor = color / float(parameters.sampleCount + 1);
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MotionBlurSettings {
+    pub shutter: f32,
+    pub sample_count: u32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        Self {
+            shutter: 0.5,
+            sample_count: 8,
+        }
+    }
+}
+
+pub struct MotionBlurPass {
+    pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl MotionBlurPass {
+    pub fn new(device: &Arc<Device>, output_format: Format) -> Result<Self> {
+        let pipeline = create_fullscreen_pipeline(
+            device,
+            motion_blur_fs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+            output_format,
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self { pipeline, sampler })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        input: &Arc<ImageView>,
+        velocity: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+        settings: &MotionBlurSettings,
+    ) -> Result<()> {
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    Arc::clone(input),
+                    Arc::clone(&self.sampler),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    Arc::clone(velocity),
+                    Arc::clone(&self.sampler),
+                ),
+            ],
+            [],
+        )?;
+
+        begin_fullscreen_pass(builder, &self.pipeline, output)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                motion_blur_fs::MotionBlurParameters {
+                    shutter: settings.shutter,
+                    sampleCount: settings.sample_count,
+                },
+            )?;
+        unsafe { builder.draw(3, 1, 0, 0) }?;
+        builder.end_rendering()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MotionBlurEffect {
+    pub settings: MotionBlurSettings,
+}
+
+impl PostProcessEffect for MotionBlurEffect {
+    fn name(&self) -> &'static str {
+        "motion_blur"
+    }
+
+    fn output_format(&self) -> Format {
+        HDR_FORMAT
+    }
+
+    fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        context: &PostProcessContext,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+    ) -> Result<()> {
+        context.vulkan_device.motion_blur_pass().record(
+            builder,
+            context.vulkan_device,
+            input,
+            context.velocity_view,
+            output,
+            &self.settings,
+        )
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
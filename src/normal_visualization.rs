@@ -0,0 +1,260 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::SampleCount;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineShaderStageCreateInfo,
+};
+
+use crate::gpu_scene::{self, SceneObjects};
+use crate::vulkan_device::{Vertex, VulkanDevice, DEPTH_FORMAT, HDR_FORMAT};
+
+mod normal_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 position;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                // Passes the world position on to the geometry shader; the draw's first instance
+                // selects its object.
+                void main() {
+                    gl_Position = scene.objects[gl_InstanceIndex].transform * vec4(position, 1.0);
+                }
+            ",
+    }
+}
+
+mod normal_gs {
+    vulkano_shaders::shader! {
+        ty: "geometry",
+        src: r"
+                #version 460
+
+                layout(triangles) in;
+                layout(line_strip, max_vertices = 4) out;
+
+                layout(location = 0) out vec3 lineColor;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(push_constant) uniform NormalParameters {
+                    vec4 color;
+                    vec4 tangentColor;
+                    float lineLength;
+                    uint tangents;
+                } parameters;
+
+                void emitLine(vec3 start, vec3 direction, vec3 color) {
+                    gl_Position = uniforms.view_projection * vec4(start, 1.0);
+                    lineColor = color;
+                    EmitVertex();
+
+                    gl_Position = uniforms.view_projection * vec4(start + direction * parameters.lineLength, 1.0);
+                    lineColor = color;
+                    EmitVertex();
+
+                    EndPrimitive();
+                }
+
+                void main() {
+                    vec3 p0 = gl_in[0].gl_Position.xyz;
+                    vec3 p1 = gl_in[1].gl_Position.xyz;
+                    vec3 p2 = gl_in[2].gl_Position.xyz;
+                    vec3 center = (p0 + p1 + p2) / 3.0;
+                    vec3 normal = normalize(cross(p1 - p0, p2 - p0));
+
+                    emitLine(center, normal, parameters.color.rgb);
+
+                    // Vertices only carry positions, so the tangent follows the first edge of the face.
+                    if (parameters.tangents != 0u) {
+                        emitLine(center, normalize(p1 - p0), parameters.tangentColor.rgb);
+                    }
+                }
+            ",
+    }
+}
+
+mod normal_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 lineColor;
+
+                layout(location = 0) out vec4 outColor;
+
+                void main() {
+                    outColor = vec4(lineColor, 1.0);
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct NormalVisualizationSettings {
+    pub enabled: bool,
+    pub tangents: bool,
+    pub length: f32,
+    pub color: [f32; 3],
+    pub tangent_color: [f32; 3],
+}
+
+impl Default for NormalVisualizationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tangents: false,
+            length: 0.2,
+            color: [1.0, 0.9, 0.1],
+            tangent_color: [0.9, 0.2, 0.2],
+        }
+    }
+}
+
+pub struct NormalVisualizationPass {
+    pipeline: Arc<GraphicsPipeline>,
+}
+
+impl NormalVisualizationPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        samples: SampleCount,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let vertex_shader = normal_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = Vertex::per_vertex().definition(&vertex_shader).unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(
+                normal_gs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+            PipelineShaderStageCreateInfo::new(
+                normal_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = gpu_scene::create_pipeline_layout(device, &stages, gpu_scene_set_layout)?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: CompareOp::LessOrEqual,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: samples,
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        Ok(Self { pipeline })
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        objects: &SceneObjects,
+        settings: &NormalVisualizationSettings,
+    ) -> Result<()> {
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone())],
+            [],
+        )?;
+
+        let [red, green, blue] = settings.color;
+        let [tangent_red, tangent_green, tangent_blue] = settings.tangent_color;
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_vertex_buffers(0, vulkan_device.vertex_buffer().clone())?
+            .bind_index_buffer(vulkan_device.index_buffer().clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                normal_gs::NormalParameters {
+                    color: [red, green, blue, 1.0],
+                    tangentColor: [tangent_red, tangent_green, tangent_blue, 1.0],
+                    lineLength: settings.length,
+                    tangents: settings.tangents as u32,
+                },
+            )?;
+        objects.record(builder, self.pipeline.layout())?;
+
+        Ok(())
+    }
+}
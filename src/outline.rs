@@ -0,0 +1,300 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::{
+    ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+    PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+
+use crate::compute;
+use crate::gpu_scene::{self, SceneObjects};
+use crate::vulkan_device::{Vertex, VulkanDevice};
+
+pub const MASK_FORMAT: Format = Format::R8_UNORM;
+
+mod mask_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 position;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                // The draw's first instance selects its object.
+                void main() {
+                    mat4 transform = scene.objects[gl_InstanceIndex].transform;
+                    gl_Position = uniforms.view_projection * transform * vec4(position, 1.0);
+                }
+            ",
+    }
+}
+
+mod mask_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) out float outMask;
+
+                void main() {
+                    outMask = 1.0;
+                }
+            ",
+    }
+}
+
+mod outline_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0) uniform sampler2D maskTexture;
+                layout(set = 0, binding = 1, rgba16f) uniform image2D sceneColor;
+
+                layout(push_constant) uniform OutlineParameters {
+                    vec4 color;
+                    int thickness;
+                } parameters;
+
+                void main() {
+                    ivec2 size = imageSize(sceneColor);
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    if (any(greaterThanEqual(pixel, size)) || texelFetch(maskTexture, pixel, 0).r > 0.0) {
+                        return;
+                    }
+
+                    int radiusSquared = parameters.thickness * parameters.thickness;
+                    for (int y = -parameters.thickness; y <= parameters.thickness; y++) {
+                        for (int x = -parameters.thickness; x <= parameters.thickness; x++) {
+                            ivec2 neighbor = pixel + ivec2(x, y);
+                            if (x * x + y * y > radiusSquared || any(lessThan(neighbor, ivec2(0))) || any(greaterThanEqual(neighbor, size))) {
+                                continue;
+                            }
+                            if (texelFetch(maskTexture, neighbor, 0).r > 0.0) {
+                                vec4 color = imageLoad(sceneColor, pixel);
+                                imageStore(sceneColor, pixel, vec4(mix(color.rgb, parameters.color.rgb, parameters.color.a), color.a));
+                                return;
+                            }
+                        }
+                    }
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OutlineSettings {
+    pub color: [f32; 4],
+    pub thickness: u32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.55, 0.1, 1.0],
+            thickness: 3,
+        }
+    }
+}
+
+pub struct OutlineViews<'a> {
+    pub mask: &'a Arc<ImageView>,
+    pub scene_color: &'a Arc<ImageView>,
+}
+
+pub struct OutlinePass {
+    mask_pipeline: Arc<GraphicsPipeline>,
+    outline_pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl OutlinePass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let vertex_shader = mask_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = Vertex::per_vertex().definition(&vertex_shader).unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(
+                mask_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = gpu_scene::create_pipeline_layout(device, &stages, gpu_scene_set_layout)?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(MASK_FORMAT)],
+            ..Default::default()
+        };
+
+        let mask_pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState {
+                    cull_mode: CullMode::None,
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        let outline_pipeline = compute::create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            outline_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            mask_pipeline,
+            outline_pipeline,
+            sampler,
+        })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        objects: &SceneObjects,
+        views: &OutlineViews,
+        settings: &OutlineSettings,
+    ) -> Result<()> {
+        let OutlineViews { mask, scene_color } = views;
+        let [width, height, _] = scene_color.image().extent();
+
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.mask_pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone())],
+            [],
+        )?;
+
+        let viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: [width as f32, height as f32],
+            depth_range: 0.0..=1.0,
+        };
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::Store,
+                    clear_value: Some(ClearValue::Float([0.0; 4])),
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(mask))
+                })],
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport].into_iter().collect())?
+            .bind_pipeline_graphics(Arc::clone(&self.mask_pipeline))?
+            .bind_vertex_buffers(0, vulkan_device.vertex_buffer().clone())?
+            .bind_index_buffer(vulkan_device.index_buffer().clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.mask_pipeline.layout()),
+                0,
+                set,
+            )?;
+        objects.record(builder, self.mask_pipeline.layout())?;
+        builder.end_rendering()?;
+
+        vulkan_device
+            .bind_compute(
+                builder,
+                &self.outline_pipeline,
+                [
+                    WriteDescriptorSet::image_view_sampler(
+                        0,
+                        Arc::clone(mask),
+                        Arc::clone(&self.sampler),
+                    ),
+                    WriteDescriptorSet::image_view(1, Arc::clone(scene_color)),
+                ],
+            )?
+            .push_constants(
+                Arc::clone(self.outline_pipeline.layout()),
+                0,
+                outline_cs::OutlineParameters {
+                    color: settings.color,
+                    thickness: settings.thickness as i32,
+                },
+            )?;
+        unsafe { builder.dispatch([width.div_ceil(8), height.div_ceil(8), 1]) }?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use rayon::prelude::*;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CommandBufferInheritanceInfo, CommandBufferInheritanceRenderingInfo,
+    CommandBufferUsage, PrimaryAutoCommandBuffer, SecondaryCommandBufferAbstract,
+};
+use vulkano::descriptor_set::DescriptorSet;
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+
+use crate::gpu_scene::{record_object_draws, ObjectDraw, GPU_SCENE_SET};
+use crate::material::SceneMaterial;
+use crate::mesh_pool::MeshAllocation;
+use crate::shading_rate::ShadingRate;
+use crate::vulkan_device::{vs, Vertex};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ParallelRecordingSettings {
+    pub enabled: bool,
+    // Indices drawn by each secondary command buffer.
+    pub chunk_size: u32,
+    // Keeps the scene's secondary command buffers across frames and replays them until the scene
+    // draw changes. Works with or without `enabled`; without it the scene is a single chunk.
+    pub reuse: bool,
+}
+
+impl Default for ParallelRecordingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            chunk_size: 3 * 4096,
+            reuse: false,
+        }
+    }
+}
+
+impl ParallelRecordingSettings {
+    pub fn uses_secondaries(&self) -> bool {
+        self.enabled || self.reuse
+    }
+}
+
+// Everything a chunk needs to draw its slice of the scene. The renderer itself can't cross threads,
+// and secondary command buffers inherit no dynamic state, so each chunk sets all of it again.
+#[derive(Clone)]
+pub struct SceneDraw {
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub descriptor_sets: Vec<Arc<DescriptorSet>>,
+    pub scene_set: Arc<DescriptorSet>,
+    pub push_constants: vs::PushConstantData,
+    pub vertex_buffer: Subbuffer<[Vertex]>,
+    pub index_buffer: Subbuffer<[u16]>,
+    pub objects: Vec<ObjectDraw>,
+    pub topology: PrimitiveTopology,
+    pub viewport: Viewport,
+    pub dynamic_cull_mode: bool,
+    pub wide_lines: bool,
+    pub materials: Vec<SceneMaterial>,
+    pub shading_rate: Option<ShadingRate>,
+}
+
+impl SceneDraw {
+    fn record<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        objects: &[ObjectDraw],
+    ) -> Result<()> {
+        builder.set_viewport(0, [self.viewport.clone()].into_iter().collect())?;
+        if let Some(shading_rate) = self.shading_rate {
+            shading_rate.record(builder)?;
+        }
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())?
+            .bind_index_buffer(self.index_buffer.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                self.descriptor_sets.clone(),
+            )?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                GPU_SCENE_SET,
+                Arc::clone(&self.scene_set),
+            )?;
+        record_object_draws(
+            builder,
+            self.pipeline.layout(),
+            self.push_constants,
+            &self.materials,
+            self.dynamic_cull_mode,
+            self.wide_lines,
+            objects,
+        )
+    }
+
+    // Whether command buffers recorded for `other` draw exactly this. Push constants are left out:
+    // the material ones come from `materials`, and the scene shaders don't read the time and mouse
+    // position.
+    fn is_recorded_by(&self, other: &SceneDraw) -> bool {
+        Arc::ptr_eq(&self.pipeline, &other.pipeline)
+            && self.descriptor_sets.len() == other.descriptor_sets.len()
+            && self
+                .descriptor_sets
+                .iter()
+                .zip(&other.descriptor_sets)
+                .all(|(set, other_set)| Arc::ptr_eq(set, other_set))
+            && Arc::ptr_eq(&self.scene_set, &other.scene_set)
+            && Arc::ptr_eq(self.vertex_buffer.buffer(), other.vertex_buffer.buffer())
+            && Arc::ptr_eq(self.index_buffer.buffer(), other.index_buffer.buffer())
+            && self.objects == other.objects
+            && self.topology == other.topology
+            && self.viewport.offset == other.viewport.offset
+            && self.viewport.extent == other.viewport.extent
+            && self.viewport.depth_range == other.viewport.depth_range
+            && self.dynamic_cull_mode == other.dynamic_cull_mode
+            && self.wide_lines == other.wide_lines
+            && self.materials == other.materials
+            && self.shading_rate == other.shading_rate
+    }
+
+    // Objects are packed into chunks of about `chunk_size` indices. Only lists can be cut anywhere
+    // on a primitive boundary; strips and fans stay whole.
+    fn chunks(&self, chunk_size: u32) -> Vec<Vec<ObjectDraw>> {
+        let primitive_size = match self.topology {
+            PrimitiveTopology::PointList => Some(1),
+            PrimitiveTopology::LineList => Some(2),
+            PrimitiveTopology::TriangleList => Some(3),
+            _ => None,
+        };
+        let chunk_size = primitive_size.map_or(chunk_size, |primitive_size| {
+            (chunk_size / primitive_size).max(1) * primitive_size
+        });
+
+        let mut chunks = vec![Vec::new()];
+        let mut filled = 0;
+        for object in &self.objects {
+            let mut mesh = object.mesh;
+            while mesh.index_count > 0 {
+                if filled >= chunk_size {
+                    chunks.push(Vec::new());
+                    filled = 0;
+                }
+                let index_count = match primitive_size {
+                    Some(_) => mesh.index_count.min(chunk_size - filled),
+                    None => mesh.index_count,
+                };
+                chunks.last_mut().unwrap().push(ObjectDraw {
+                    mesh: MeshAllocation {
+                        index_count,
+                        ..mesh
+                    },
+                    ..*object
+                });
+                filled = filled.saturating_add(index_count);
+                mesh.first_index += index_count;
+                mesh.index_count -= index_count;
+            }
+        }
+        chunks
+    }
+}
+
+// Records the chunks into secondary command buffers across rayon's pool.
+fn record_chunks(
+    command_allocator: &Arc<StandardCommandBufferAllocator>,
+    queue_family_index: u32,
+    rendering: &CommandBufferInheritanceRenderingInfo,
+    draw: &SceneDraw,
+    chunk_size: u32,
+    usage: CommandBufferUsage,
+) -> Result<Vec<Arc<dyn SecondaryCommandBufferAbstract>>> {
+    draw.chunks(chunk_size)
+        .into_par_iter()
+        .map(|objects| {
+            let mut secondary = AutoCommandBufferBuilder::secondary(
+                command_allocator.clone(),
+                queue_family_index,
+                usage,
+                CommandBufferInheritanceInfo {
+                    render_pass: Some(rendering.clone().into()),
+                    ..Default::default()
+                },
+            )?;
+            draw.record(&mut secondary, &objects)?;
+            Ok(secondary.build()? as Arc<dyn SecondaryCommandBufferAbstract>)
+        })
+        .collect()
+}
+
+// The secondary command buffers last recorded for one scene pass of one set of render targets, so
+// a static scene is only recorded again after the swapchain or the scene changes.
+#[derive(Default)]
+pub struct RecordedScene {
+    draw: Option<SceneDraw>,
+    command_buffers: Vec<Arc<dyn SecondaryCommandBufferAbstract>>,
+}
+
+impl RecordedScene {
+    // The caller has to have begun rendering with `SubpassContents::SecondaryCommandBuffers` and
+    // formats matching `rendering`.
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        command_allocator: &Arc<StandardCommandBufferAllocator>,
+        queue_family_index: u32,
+        rendering: &CommandBufferInheritanceRenderingInfo,
+        draw: SceneDraw,
+        settings: &ParallelRecordingSettings,
+    ) -> Result<()> {
+        let is_recorded = settings.reuse
+            && self
+                .draw
+                .as_ref()
+                .is_some_and(|recorded| draw.is_recorded_by(recorded));
+        if !is_recorded {
+            // Frames in flight replay the same buffers, which needs simultaneous use.
+            let usage = if settings.reuse {
+                CommandBufferUsage::SimultaneousUse
+            } else {
+                CommandBufferUsage::OneTimeSubmit
+            };
+            let chunk_size = if settings.enabled {
+                settings.chunk_size
+            } else {
+                u32::MAX
+            };
+            self.command_buffers = record_chunks(
+                command_allocator,
+                queue_family_index,
+                rendering,
+                &draw,
+                chunk_size,
+                usage,
+            )?;
+            self.draw = Some(draw);
+        }
+
+        builder.execute_commands_from_vec(self.command_buffers.clone())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,759 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::sampler::{Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{
+    AttachmentBlend, ColorBlendAttachmentState, ColorBlendState,
+};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+
+use crate::billboard::BillboardMode;
+use crate::compute::create_compute_pipeline;
+use crate::vulkan_device::{VulkanDevice, HDR_FORMAT};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+mod update_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+                struct Particle {
+                    vec4 positionLife;
+                    vec4 velocityMaxLife;
+                };
+
+                struct SortKey {
+                    float depth;
+                    uint index;
+                };
+
+                layout(set = 0, binding = 0) buffer Particles {
+                    Particle particles[];
+                };
+
+                layout(set = 0, binding = 1) writeonly buffer SortKeys {
+                    SortKey keys[];
+                };
+
+                layout(set = 0, binding = 2) buffer EmitCounter {
+                    uint emitted;
+                } counter;
+
+                layout(set = 0, binding = 3) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(set = 0, binding = 4) uniform sampler2D depthTexture;
+                layout(set = 0, binding = 5) uniform sampler2D normalTexture;
+
+                layout(push_constant) uniform UpdateParameters {
+                    vec4 originSpread;
+                    vec4 directionDeltaTime;
+                    vec4 gravity;
+                    // The viewport's offset and extent in pixels.
+                    vec4 viewport;
+                    vec2 lifetime;
+                    vec2 speed;
+                    uint emitCount;
+                    uint seed;
+                    // Restitution and thickness.
+                    vec2 collision;
+                    uint collisionMode;
+                } parameters;
+
+                const uint COLLISION_NONE = 0u;
+                const uint COLLISION_KILL = 2u;
+
+                uint hash(uint value) {
+                    value = value * 747796405u + 2891336453u;
+                    uint word = ((value >> ((value >> 28u) + 4u)) ^ value) * 277803737u;
+                    return (word >> 22u) ^ word;
+                }
+
+                float random(inout uint state) {
+                    state = hash(state);
+                    return float(state) / 4294967295.0;
+                }
+
+                vec3 coneDirection(vec3 axis, float spread, inout uint state) {
+                    float cosTheta = mix(1.0, cos(spread), random(state));
+                    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+                    float phi = 6.28318530718 * random(state);
+
+                    vec3 up = abs(axis.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+                    vec3 tangent = normalize(cross(up, axis));
+                    vec3 bitangent = cross(axis, tangent);
+                    return tangent * cos(phi) * sinTheta + bitangent * sin(phi) * sinTheta + axis * cosTheta;
+                }
+
+                float linearDepth(float depth) {
+                    return uniforms.projection[3][2] / (depth + uniforms.projection[2][2]);
+                }
+
+                // Whether the particle went behind the visible surface, but by less than the thickness
+                // assumed for it. Particles off screen or hidden further back can't collide.
+                bool collides(vec3 position, out vec3 worldNormal) {
+                    vec4 clip = uniforms.view_projection * vec4(position, 1.0);
+                    if (clip.w <= 0.0) {
+                        return false;
+                    }
+                    vec3 ndc = clip.xyz / clip.w;
+                    vec2 pixel = parameters.viewport.xy + (ndc.xy * 0.5 + 0.5) * parameters.viewport.zw;
+                    ivec2 texel = ivec2(pixel);
+                    if (any(lessThan(ndc.xy, vec2(-1.0))) || any(greaterThan(ndc.xy, vec2(1.0)))
+                        || any(greaterThanEqual(texel, textureSize(depthTexture, 0)))) {
+                        return false;
+                    }
+
+                    float sceneDepth = texelFetch(depthTexture, texel, 0).r;
+                    float penetration = linearDepth(ndc.z) - linearDepth(sceneDepth);
+                    if (sceneDepth >= 1.0 || penetration < 0.0 || penetration > parameters.collision.y) {
+                        return false;
+                    }
+                    worldNormal = normalize(transpose(mat3(uniforms.view)) * texelFetch(normalTexture, texel, 0).xyz);
+                    return true;
+                }
+
+                void main() {
+                    uint index = gl_GlobalInvocationID.x;
+                    if (index >= particles.length()) {
+                        return;
+                    }
+
+                    Particle particle = particles[index];
+                    float deltaTime = parameters.directionDeltaTime.w;
+
+                    if (particle.positionLife.w <= 0.0) {
+                        if (atomicAdd(counter.emitted, 1u) < parameters.emitCount) {
+                            uint state = hash(index ^ hash(parameters.seed));
+                            float lifetime = mix(parameters.lifetime.x, parameters.lifetime.y, random(state));
+                            float speed = mix(parameters.speed.x, parameters.speed.y, random(state));
+                            vec3 direction = coneDirection(
+                                normalize(parameters.directionDeltaTime.xyz),
+                                parameters.originSpread.w,
+                                state
+                            );
+                            particle.positionLife = vec4(parameters.originSpread.xyz, lifetime);
+                            particle.velocityMaxLife = vec4(direction * speed, lifetime);
+                        }
+                    } else {
+                        vec3 previousPosition = particle.positionLife.xyz;
+                        particle.velocityMaxLife.xyz += parameters.gravity.xyz * deltaTime;
+                        particle.positionLife.xyz += particle.velocityMaxLife.xyz * deltaTime;
+                        particle.positionLife.w -= deltaTime;
+
+                        vec3 normal;
+                        if (parameters.collisionMode != COLLISION_NONE && collides(particle.positionLife.xyz, normal)) {
+                            if (parameters.collisionMode == COLLISION_KILL) {
+                                particle.positionLife.w = 0.0;
+                            } else if (dot(particle.velocityMaxLife.xyz, normal) < 0.0) {
+                                // Back to where it was in front of the surface, moving away from it.
+                                particle.positionLife.xyz = previousPosition;
+                                particle.velocityMaxLife.xyz = reflect(particle.velocityMaxLife.xyz, normal) * parameters.collision.x;
+                            }
+                        }
+                    }
+
+                    particles[index] = particle;
+
+                    float depth = particle.positionLife.w > 0.0
+                        ? length((uniforms.view * vec4(particle.positionLife.xyz, 1.0)).xyz)
+                        : -1.0;
+                    keys[index] = SortKey(depth, index);
+                }
+            ",
+    }
+}
+
+mod sort_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+                struct SortKey {
+                    float depth;
+                    uint index;
+                };
+
+                layout(set = 0, binding = 0) buffer SortKeys {
+                    SortKey keys[];
+                };
+
+                layout(push_constant) uniform SortParameters {
+                    uint distance;
+                    uint blockSize;
+                } parameters;
+
+                void main() {
+                    uint index = gl_GlobalInvocationID.x;
+                    uint partner = index ^ parameters.distance;
+                    if (partner <= index || partner >= keys.length()) {
+                        return;
+                    }
+
+                    SortKey first = keys[index];
+                    SortKey second = keys[partner];
+                    bool isFarthestFirst = (index & parameters.blockSize) == 0u;
+                    if (isFarthestFirst ? first.depth < second.depth : first.depth > second.depth) {
+                        keys[index] = second;
+                        keys[partner] = first;
+                    }
+                }
+            ",
+    }
+}
+
+mod particle_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                struct Particle {
+                    vec4 positionLife;
+                    vec4 velocityMaxLife;
+                };
+
+                struct SortKey {
+                    float depth;
+                    uint index;
+                };
+
+                layout(location = 0) out vec2 corner;
+                layout(location = 1) out vec4 color;
+                layout(location = 2) out vec3 viewPosition;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(set = 0, binding = 1) readonly buffer Particles {
+                    Particle particles[];
+                };
+
+                layout(set = 0, binding = 2) readonly buffer SortKeys {
+                    SortKey keys[];
+                };
+
+                layout(push_constant) uniform RenderParameters {
+                    vec4 startColor;
+                    vec4 endColor;
+                    vec2 size;
+                    float softness;
+                    uint billboardMode;
+                } parameters;
+
+                const vec2 CORNERS[6] = vec2[](
+                    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0),
+                    vec2(-1.0, 1.0), vec2(1.0, -1.0), vec2(1.0, 1.0)
+                );
+
+                void main() {
+                    SortKey key = keys[gl_InstanceIndex];
+                    if (key.depth < 0.0) {
+                        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+                        return;
+                    }
+
+                    Particle particle = particles[key.index];
+                    float age = 1.0 - particle.positionLife.w / particle.velocityMaxLife.w;
+
+                    vec3 right = vec3(uniforms.view[0][0], uniforms.view[1][0], uniforms.view[2][0]);
+                    vec3 up = vec3(uniforms.view[0][1], uniforms.view[1][1], uniforms.view[2][1]);
+                    if (parameters.billboardMode == 1) {
+                        right = normalize(vec3(right.x, 0.0, right.z));
+                        up = vec3(0.0, 1.0, 0.0);
+                    }
+
+                    corner = CORNERS[gl_VertexIndex];
+                    color = mix(parameters.startColor, parameters.endColor, age);
+                    vec2 offset = corner * mix(parameters.size.x, parameters.size.y, age);
+                    vec3 worldPosition = particle.positionLife.xyz + right * offset.x + up * offset.y;
+                    viewPosition = (uniforms.view * vec4(worldPosition, 1.0)).xyz;
+                    gl_Position = uniforms.projection * vec4(viewPosition, 1.0);
+                }
+            ",
+    }
+}
+
+mod particle_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 corner;
+                layout(location = 1) in vec4 color;
+                layout(location = 2) in vec3 viewPosition;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(set = 0, binding = 3) uniform sampler2D depthTexture;
+
+                layout(push_constant) uniform RenderParameters {
+                    vec4 startColor;
+                    vec4 endColor;
+                    vec2 size;
+                    float softness;
+                    uint billboardMode;
+                } parameters;
+
+                void main() {
+                    float sceneDepth = texelFetch(depthTexture, ivec2(gl_FragCoord.xy), 0).r;
+                    if (gl_FragCoord.z > sceneDepth) {
+                        discard;
+                    }
+
+                    vec2 uv = gl_FragCoord.xy / vec2(textureSize(depthTexture, 0));
+                    vec4 scenePosition = uniforms.inverse_projection * vec4(uv * 2.0 - 1.0, sceneDepth, 1.0);
+                    float separation = length(scenePosition.xyz / scenePosition.w) - length(viewPosition);
+                    float fade = clamp(separation / max(parameters.softness, 1e-4), 0.0, 1.0);
+
+                    float falloff = clamp(1.0 - dot(corner, corner), 0.0, 1.0);
+                    outColor = vec4(color.rgb, color.a * falloff * fade);
+                }
+            ",
+    }
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct Particle {
+    position_life: [f32; 4],
+    velocity_max_life: [f32; 4],
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct SortKey {
+    depth: f32,
+    index: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EmitterSettings {
+    pub origin: [f32; 3],
+    pub direction: [f32; 3],
+    pub spread: f32,
+    pub emission_rate: f32,
+    pub lifetime: [f32; 2],
+    pub speed: [f32; 2],
+    pub gravity: [f32; 3],
+    pub size: [f32; 2],
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub softness: f32,
+    pub billboard: BillboardMode,
+    pub collision: ParticleCollision,
+    // The share of the speed kept by bouncing particles.
+    pub restitution: f32,
+    // How far behind the depth buffer surfaces are assumed to reach, so particles passing behind
+    // objects don't collide with them.
+    pub collision_thickness: f32,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            origin: [0.0, 0.0, 0.0],
+            direction: [0.0, 1.0, 0.0],
+            spread: 0.35,
+            emission_rate: 400.0,
+            lifetime: [1.5, 3.0],
+            speed: [1.0, 2.5],
+            gravity: [0.0, -1.0, 0.0],
+            size: [0.05, 0.2],
+            start_color: [4.0, 1.6, 0.4, 1.0],
+            end_color: [0.3, 0.3, 0.3, 0.0],
+            softness: 0.25,
+            billboard: BillboardMode::Spherical,
+            collision: ParticleCollision::Bounce,
+            restitution: 0.4,
+            collision_thickness: 0.5,
+        }
+    }
+}
+
+// The prepass targets particles collide with.
+pub struct CollisionViews<'a> {
+    pub depth: &'a Arc<ImageView>,
+    pub normal: &'a Arc<ImageView>,
+}
+
+// What happens to particles that hit the scene as seen in the depth buffer. Only surfaces visible in
+// the viewport are there to hit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParticleCollision {
+    None,
+    Bounce,
+    Kill,
+}
+
+impl ParticleCollision {
+    fn shader_value(self) -> u32 {
+        match self {
+            ParticleCollision::None => 0,
+            ParticleCollision::Bounce => 1,
+            ParticleCollision::Kill => 2,
+        }
+    }
+}
+
+pub struct ParticleSystem {
+    pub settings: EmitterSettings,
+    capacity: u32,
+    particle_buffer: Subbuffer<[Particle]>,
+    key_buffer: Subbuffer<[SortKey]>,
+    counter_buffer: Subbuffer<[u32]>,
+    emission_accumulator: f32,
+    frame_index: u32,
+}
+
+impl ParticleSystem {
+    pub fn new(
+        vulkan_device: &VulkanDevice,
+        capacity: u32,
+        settings: EmitterSettings,
+    ) -> Result<Self> {
+        let capacity = capacity.max(WORKGROUP_SIZE).next_power_of_two();
+        let memory_allocator = vulkan_device.memory_allocator();
+
+        let particle_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            (0..capacity).map(|_| Particle {
+                position_life: [0.0; 4],
+                velocity_max_life: [0.0, 0.0, 0.0, 1.0],
+            }),
+        )?;
+
+        let device_allocation = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        };
+
+        let key_buffer = Buffer::new_slice(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            device_allocation.clone(),
+            capacity as u64,
+        )?;
+
+        let counter_buffer = Buffer::new_slice(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            device_allocation,
+            1,
+        )?;
+
+        Ok(Self {
+            settings,
+            capacity,
+            particle_buffer,
+            key_buffer,
+            counter_buffer,
+            emission_accumulator: 0.0,
+            frame_index: 0,
+        })
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+}
+
+pub struct ParticlePass {
+    update_pipeline: Arc<ComputePipeline>,
+    sort_pipeline: Arc<ComputePipeline>,
+    render_pipeline: Arc<GraphicsPipeline>,
+    depth_sampler: Arc<Sampler>,
+}
+
+impl ParticlePass {
+    pub fn new(device: &Arc<Device>, pipeline_cache: &Arc<PipelineCache>) -> Result<Self> {
+        let update_pipeline = create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            update_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+        let sort_pipeline = create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            sort_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(
+                particle_vs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+            PipelineShaderStageCreateInfo::new(
+                particle_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(Arc::clone(device))
+                .unwrap(),
+        )?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            ..Default::default()
+        };
+
+        let render_pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    },
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        let depth_sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            update_pipeline,
+            sort_pipeline,
+            render_pipeline,
+            depth_sampler,
+        })
+    }
+
+    pub fn simulate(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        system: &mut ParticleSystem,
+        views: &CollisionViews,
+        viewport: &Viewport,
+        delta_time: f32,
+    ) -> Result<()> {
+        let settings = system.settings;
+
+        system.emission_accumulator += settings.emission_rate * delta_time;
+        let emit_count = system.emission_accumulator.floor();
+        system.emission_accumulator -= emit_count;
+        system.frame_index = system.frame_index.wrapping_add(1);
+
+        builder.fill_buffer(system.counter_buffer.clone(), 0)?;
+
+        let [origin_x, origin_y, origin_z] = settings.origin;
+        let [direction_x, direction_y, direction_z] = settings.direction;
+        let [gravity_x, gravity_y, gravity_z] = settings.gravity;
+        let [viewport_x, viewport_y] = viewport.offset;
+        let [viewport_width, viewport_height] = viewport.extent;
+        let group_count = system.capacity / WORKGROUP_SIZE;
+
+        vulkan_device
+            .bind_compute(
+                builder,
+                &self.update_pipeline,
+                [
+                    WriteDescriptorSet::buffer(0, system.particle_buffer.clone()),
+                    WriteDescriptorSet::buffer(1, system.key_buffer.clone()),
+                    WriteDescriptorSet::buffer(2, system.counter_buffer.clone()),
+                    WriteDescriptorSet::buffer(3, vulkan_device.uniform_buffer().clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        4,
+                        Arc::clone(views.depth),
+                        Arc::clone(&self.depth_sampler),
+                    ),
+                    WriteDescriptorSet::image_view_sampler(
+                        5,
+                        Arc::clone(views.normal),
+                        Arc::clone(&self.depth_sampler),
+                    ),
+                ],
+            )?
+            .push_constants(
+                Arc::clone(self.update_pipeline.layout()),
+                0,
+                update_cs::UpdateParameters {
+                    originSpread: [origin_x, origin_y, origin_z, settings.spread],
+                    directionDeltaTime: [direction_x, direction_y, direction_z, delta_time],
+                    gravity: [gravity_x, gravity_y, gravity_z, 0.0],
+                    viewport: [viewport_x, viewport_y, viewport_width, viewport_height],
+                    lifetime: settings.lifetime,
+                    speed: settings.speed,
+                    emitCount: emit_count as u32,
+                    seed: system.frame_index,
+                    collision: [settings.restitution, settings.collision_thickness],
+                    collisionMode: settings.collision.shader_value(),
+                },
+            )?;
+        unsafe { builder.dispatch([group_count, 1, 1]) }?;
+
+        vulkan_device.bind_compute(
+            builder,
+            &self.sort_pipeline,
+            [WriteDescriptorSet::buffer(0, system.key_buffer.clone())],
+        )?;
+
+        let mut block_size = 2;
+        while block_size <= system.capacity {
+            let mut distance = block_size / 2;
+            while distance > 0 {
+                builder.push_constants(
+                    Arc::clone(self.sort_pipeline.layout()),
+                    0,
+                    sort_cs::SortParameters {
+                        distance,
+                        blockSize: block_size,
+                    },
+                )?;
+                unsafe { builder.dispatch([group_count, 1, 1]) }?;
+                distance /= 2;
+            }
+            block_size *= 2;
+        }
+
+        Ok(())
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        system: &ParticleSystem,
+        scene_color: &Arc<ImageView>,
+        depth: &Arc<ImageView>,
+        viewport: &Viewport,
+    ) -> Result<()> {
+        let settings = &system.settings;
+
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.render_pipeline.layout().set_layouts()[0]),
+            [
+                WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone()),
+                WriteDescriptorSet::buffer(1, system.particle_buffer.clone()),
+                WriteDescriptorSet::buffer(2, system.key_buffer.clone()),
+                WriteDescriptorSet::image_view_sampler(
+                    3,
+                    Arc::clone(depth),
+                    Arc::clone(&self.depth_sampler),
+                ),
+            ],
+            [],
+        )?;
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(scene_color))
+                })],
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport.clone()].into_iter().collect())?
+            .bind_pipeline_graphics(Arc::clone(&self.render_pipeline))?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.render_pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(self.render_pipeline.layout()),
+                0,
+                particle_vs::RenderParameters {
+                    startColor: settings.start_color,
+                    endColor: settings.end_color,
+                    size: settings.size,
+                    softness: settings.softness,
+                    billboardMode: settings.billboard.shader_value(),
+                },
+            )?;
+        unsafe { builder.draw(6, system.capacity, 0, 0) }?;
+        builder.end_rendering()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nalgebra::Point3;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{
+    Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode,
+};
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageUsage, SampleCount};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+
+use crate::gpu_scene::{self, GPU_SCENE_SET};
+use crate::ibl::ImageBasedLighting;
+use crate::light::DirectionalLight;
+use crate::vulkan_device::VulkanDevice;
+
+const ACCUMULATION_FORMAT: Format = Format::R32G32B32A32_SFLOAT;
+
+mod path_trace_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: r"
+                #version 460
+                #extension GL_EXT_ray_query : require
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0) uniform accelerationStructureEXT scene;
+                layout(set = 0, binding = 1, rgba32f) uniform image2D accumulation;
+                layout(set = 0, binding = 2, rgba16f) uniform writeonly image2D outputImage;
+                layout(set = 0, binding = 3) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+                layout(set = 0, binding = 4) readonly buffer Positions {
+                    float positions[];
+                };
+                layout(set = 0, binding = 5) readonly buffer Indices {
+                    uint indices[];
+                };
+                layout(set = 0, binding = 6) uniform samplerCube environmentMap;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                struct GpuMaterial {
+                    vec4 baseColor;
+                    float alphaCutoff;
+                    float pointSize;
+                };
+
+                struct GpuMesh {
+                    uint firstIndex;
+                    uint indexCount;
+                    int vertexOffset;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } sceneObjects;
+
+                layout(set = 3, binding = 1) readonly buffer Materials {
+                    GpuMaterial materials[];
+                } sceneMaterials;
+
+                layout(set = 3, binding = 3) readonly buffer Meshes {
+                    GpuMesh meshes[];
+                } sceneMeshes;
+
+                layout(push_constant) uniform PathTraceParameters {
+                    vec3 sunDirection;
+                    float environmentIntensity;
+                    vec3 sunColor;
+                    uint sampleIndex;
+                    uint maxBounces;
+                } parameters;
+
+                const float PI = 3.14159265359;
+                const float RAY_EPSILON = 1e-3;
+                const float RAY_DISTANCE = 1e4;
+
+                uint state;
+
+                float random() {
+                    state = state * 747796405u + 2891336453u;
+                    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+                    return float((word >> 22u) ^ word) / 4294967296.0;
+                }
+
+                vec3 vertexPosition(uint index) {
+                    return vec3(positions[index * 3], positions[index * 3 + 1], positions[index * 3 + 2]);
+                }
+
+                vec3 cosineHemisphere(vec3 normal) {
+                    vec3 tangent = normalize(abs(normal.y) < 0.99 ? cross(normal, vec3(0.0, 1.0, 0.0)) : cross(normal, vec3(1.0, 0.0, 0.0)));
+                    vec3 bitangent = cross(normal, tangent);
+                    float u = random();
+                    float angle = 2.0 * PI * random();
+                    float radius = sqrt(u);
+                    return normalize(tangent * radius * cos(angle) + bitangent * radius * sin(angle) + normal * sqrt(1.0 - u));
+                }
+
+                bool occluded(vec3 origin, vec3 direction) {
+                    rayQueryEXT rayQuery;
+                    rayQueryInitializeEXT(
+                        rayQuery, scene,
+                        gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsOpaqueEXT,
+                        0xFF, origin, RAY_EPSILON, direction, RAY_DISTANCE
+                    );
+                    while (rayQueryProceedEXT(rayQuery)) {}
+                    return rayQueryGetIntersectionTypeEXT(rayQuery, true) != gl_RayQueryCommittedIntersectionNoneEXT;
+                }
+
+                vec3 trace(vec3 origin, vec3 direction) {
+                    vec3 radiance = vec3(0.0);
+                    vec3 throughput = vec3(1.0);
+                    vec3 lightDirection = normalize(parameters.sunDirection);
+
+                    for (uint bounce = 0u; bounce <= parameters.maxBounces; bounce++) {
+                        rayQueryEXT rayQuery;
+                        rayQueryInitializeEXT(
+                            rayQuery, scene, gl_RayFlagsOpaqueEXT,
+                            0xFF, origin, bounce == 0u ? 0.0 : RAY_EPSILON, direction, RAY_DISTANCE
+                        );
+                        while (rayQueryProceedEXT(rayQuery)) {}
+
+                        if (rayQueryGetIntersectionTypeEXT(rayQuery, true) == gl_RayQueryCommittedIntersectionNoneEXT) {
+                            radiance += throughput * textureLod(environmentMap, direction, 0.0).rgb * parameters.environmentIntensity;
+                            break;
+                        }
+
+                        // Instances are the scene's objects, and the positions and indices are the scene
+                        // mesh's, so a mesh's triangles start where it does in the mesh pool minus where the
+                        // scene mesh, mesh 0, does.
+                        GpuObject object = sceneObjects.objects[rayQueryGetIntersectionInstanceCustomIndexEXT(rayQuery, true)];
+                        uint firstIndex = sceneMeshes.meshes[object.mesh].firstIndex - sceneMeshes.meshes[0].firstIndex
+                            + rayQueryGetIntersectionPrimitiveIndexEXT(rayQuery, true) * 3;
+                        mat4x3 objectToWorld = rayQueryGetIntersectionObjectToWorldEXT(rayQuery, true);
+                        vec3 p0 = objectToWorld * vec4(vertexPosition(indices[firstIndex]), 1.0);
+                        vec3 p1 = objectToWorld * vec4(vertexPosition(indices[firstIndex + 1]), 1.0);
+                        vec3 p2 = objectToWorld * vec4(vertexPosition(indices[firstIndex + 2]), 1.0);
+                        vec3 normal = normalize(cross(p1 - p0, p2 - p0));
+                        if (dot(normal, direction) > 0.0) {
+                            normal = -normal;
+                        }
+
+                        vec3 position = origin + direction * rayQueryGetIntersectionTEXT(rayQuery, true) + normal * RAY_EPSILON;
+                        // Clamped to keep the estimator energy conserving.
+                        vec3 albedo = clamp(sceneMaterials.materials[object.material].baseColor.rgb, 0.0, 1.0);
+
+                        float lightCosine = max(dot(normal, lightDirection), 0.0);
+                        if (lightCosine > 0.0 && !occluded(position, lightDirection)) {
+                            radiance += throughput * albedo * parameters.sunColor * lightCosine;
+                        }
+
+                        throughput *= albedo;
+                        if (bounce >= 2u) {
+                            float survival = max(throughput.r, max(throughput.g, throughput.b));
+                            if (random() >= survival) {
+                                break;
+                            }
+                            throughput /= survival;
+                        }
+
+                        origin = position;
+                        direction = cosineHemisphere(normal);
+                    }
+
+                    return radiance;
+                }
+
+                void main() {
+                    ivec2 size = imageSize(outputImage);
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    if (any(greaterThanEqual(pixel, size))) {
+                        return;
+                    }
+
+                    state = uint(pixel.y * size.x + pixel.x) * 9781u + parameters.sampleIndex * 6271u + 1u;
+
+                    mat4 inverseView = inverse(uniforms.view);
+                    vec2 uv = (vec2(pixel) + vec2(random(), random())) / vec2(size);
+                    vec4 target = uniforms.inverse_projection * vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+                    vec3 origin = inverseView[3].xyz;
+                    vec3 direction = normalize(mat3(inverseView) * (target.xyz / target.w));
+
+                    vec3 accumulated = trace(origin, direction);
+                    if (parameters.sampleIndex > 0u) {
+                        accumulated += imageLoad(accumulation, pixel).rgb;
+                    }
+
+                    imageStore(accumulation, pixel, vec4(accumulated, 1.0));
+                    imageStore(outputImage, pixel, vec4(accumulated / float(parameters.sampleIndex + 1u), 1.0));
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PathTracingSettings {
+    pub max_bounces: u32,
+    pub max_samples: u32,
+}
+
+impl Default for PathTracingSettings {
+    fn default() -> Self {
+        Self {
+            max_bounces: 4,
+            max_samples: 4096,
+        }
+    }
+}
+
+// The GPU scene's objects and how they're lit.
+pub struct PathTracedScene<'a> {
+    pub objects: &'a Arc<DescriptorSet>,
+    pub environment: &'a ImageBasedLighting,
+    pub sun: &'a DirectionalLight,
+    pub environment_intensity: f32,
+}
+
+pub struct PathAccumulation {
+    view: Arc<ImageView>,
+    sample_count: u32,
+    camera_position: Point3<f32>,
+}
+
+impl PathAccumulation {
+    pub fn new(vulkan_device: &VulkanDevice, extent: [u32; 2]) -> Result<Self> {
+        Ok(Self {
+            view: vulkan_device.create_attachment(
+                ACCUMULATION_FORMAT,
+                extent,
+                ImageUsage::STORAGE,
+                SampleCount::Sample1,
+            )?,
+            sample_count: 0,
+            camera_position: *vulkan_device.camera_position(),
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.sample_count = 0;
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+}
+
+pub struct PathTracingPass {
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl PathTracingPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let pipeline = gpu_scene::create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            path_trace_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+            gpu_scene_set_layout,
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self { pipeline, sampler })
+    }
+
+    pub fn trace(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        accumulation: &mut PathAccumulation,
+        scene: &PathTracedScene,
+        target: &Arc<ImageView>,
+        settings: &PathTracingSettings,
+    ) -> Result<()> {
+        if accumulation.camera_position != *vulkan_device.camera_position() {
+            accumulation.camera_position = *vulkan_device.camera_position();
+            accumulation.reset();
+        }
+
+        // The target keeps the last estimate once the sample budget is spent.
+        if accumulation.sample_count >= settings.max_samples {
+            return Ok(());
+        }
+
+        let acceleration = vulkan_device
+            .scene_acceleration()
+            .context("Scene acceleration structure has not been built")?;
+        let top_level = acceleration
+            .top_level()
+            .context("Scene top level acceleration structure has not been built")?;
+        let [width, height, _] = target.image().extent();
+        let sun_direction = scene.sun.direction.normalize();
+
+        vulkan_device
+            .bind_compute(
+                builder,
+                &self.pipeline,
+                [
+                    WriteDescriptorSet::acceleration_structure(0, Arc::clone(top_level)),
+                    WriteDescriptorSet::image_view(1, Arc::clone(&accumulation.view)),
+                    WriteDescriptorSet::image_view(2, Arc::clone(target)),
+                    WriteDescriptorSet::buffer(3, vulkan_device.uniform_buffer().clone()),
+                    WriteDescriptorSet::buffer(4, acceleration.position_buffer().clone()),
+                    WriteDescriptorSet::buffer(5, acceleration.index_buffer().clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        6,
+                        Arc::clone(scene.environment.prefiltered_view()),
+                        Arc::clone(&self.sampler),
+                    ),
+                ],
+            )?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                Arc::clone(self.pipeline.layout()),
+                GPU_SCENE_SET,
+                Arc::clone(scene.objects),
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                path_trace_cs::PathTraceParameters {
+                    sunDirection: sun_direction.into(),
+                    environmentIntensity: scene.environment_intensity,
+                    sunColor: scene.sun.color,
+                    sampleIndex: accumulation.sample_count,
+                    maxBounces: settings.max_bounces,
+                },
+            )?;
+        unsafe { builder.dispatch([width.div_ceil(8), height.div_ceil(8), 1]) }?;
+
+        accumulation.sample_count += 1;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use nalgebra::{Isometry3, Matrix3, Matrix4, Translation3, UnitQuaternion, Vector3};
+use rapier3d::prelude::*;
+
+use crate::scene_graph::SceneGraph;
+
+// Steps that don't fit a frame are dropped past this many, so a long stall doesn't make every
+// following frame slower too.
+const MAX_STEPS_PER_UPDATE: u32 = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicsSettings {
+    pub gravity: Vector3<f32>,
+    pub timestep: f32,
+    // A fixed floor at the bottom of the scene's bounds, so bodies have something to land on.
+    pub ground: bool,
+}
+
+impl Default for PhysicsSettings {
+    fn default() -> Self {
+        Self {
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            timestep: 1.0 / 60.0,
+            ground: true,
+        }
+    }
+}
+
+// Every mesh node of the scene graph as a dynamic rigid body with a box collider fitted to its
+// bounds. Bodies carry the node's rotation and translation; its scale stays on the node.
+pub struct PhysicsWorld {
+    settings: PhysicsSettings,
+    accumulator: f32,
+    pipeline: PhysicsPipeline,
+    integration_parameters: IntegrationParameters,
+    islands: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    node_count: usize,
+    // The scene node each body moves, with the scale the body can't represent.
+    node_bodies: Vec<(usize, RigidBodyHandle, Vector3<f32>)>,
+}
+
+impl PhysicsWorld {
+    pub fn new(scene_graph: &SceneGraph, settings: PhysicsSettings) -> Self {
+        let mut bodies = RigidBodySet::new();
+        let mut colliders = ColliderSet::new();
+        let mut node_bodies = Vec::new();
+
+        for index in scene_graph.mesh_nodes() {
+            let Some(bounds) = scene_graph.local_bounds(index) else {
+                continue;
+            };
+            let (isometry, scale) = decompose(&scene_graph.world_transforms()[index]);
+            let half_extents = (bounds.max - bounds.min).component_mul(&scale) * 0.5;
+            let body = bodies.insert(RigidBodyBuilder::dynamic().position(isometry).build());
+            colliders.insert_with_parent(
+                ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+                    .translation(bounds.center().coords.component_mul(&scale))
+                    .build(),
+                body,
+                &mut bodies,
+            );
+            node_bodies.push((index, body, scale));
+        }
+
+        if let Some(bounds) = scene_graph.world_bounds().filter(|_| settings.ground) {
+            let half_extents = (bounds.max - bounds.min) * 0.5;
+            let thickness = 0.5;
+            colliders.insert(
+                ColliderBuilder::cuboid(half_extents.x * 4.0, thickness, half_extents.z * 4.0)
+                    .translation(Vector3::new(
+                        bounds.center().x,
+                        bounds.min.y - thickness,
+                        bounds.center().z,
+                    ))
+                    .build(),
+            );
+        }
+
+        Self {
+            settings,
+            accumulator: 0.0,
+            pipeline: PhysicsPipeline::new(),
+            integration_parameters: IntegrationParameters {
+                dt: settings.timestep,
+                ..Default::default()
+            },
+            islands: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies,
+            colliders,
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            node_count: scene_graph.node_count(),
+            node_bodies,
+        }
+    }
+
+    pub fn settings(&self) -> &PhysicsSettings {
+        &self.settings
+    }
+
+    pub fn body_count(&self) -> usize {
+        self.node_bodies.len()
+    }
+
+    // Advances by whole fixed timesteps and carries the remainder to the next update. Returns the
+    // number of steps taken.
+    pub fn update(&mut self, delta_time: f32) -> u32 {
+        self.accumulator += delta_time;
+        let mut steps = 0;
+        while self.accumulator >= self.settings.timestep {
+            self.accumulator -= self.settings.timestep;
+            if steps == MAX_STEPS_PER_UPDATE {
+                continue;
+            }
+            self.pipeline.step(
+                &self.settings.gravity,
+                &self.integration_parameters,
+                &mut self.islands,
+                &mut self.broad_phase,
+                &mut self.narrow_phase,
+                &mut self.bodies,
+                &mut self.colliders,
+                &mut self.impulse_joints,
+                &mut self.multibody_joints,
+                &mut self.ccd_solver,
+                None,
+                &(),
+                &(),
+            );
+            steps += 1;
+        }
+        steps
+    }
+
+    // The bodies' current poses, which can be sent to scene graphs owned by another thread.
+    pub fn snapshot(&self) -> PhysicsSnapshot {
+        PhysicsSnapshot {
+            node_count: self.node_count,
+            poses: self
+                .node_bodies
+                .iter()
+                .map(|&(index, body, scale)| (index, *self.bodies[body].position(), scale))
+                .collect(),
+        }
+    }
+}
+
+// The poses of the nodes the bodies move, in the order the bodies were created.
+#[derive(Clone, Debug)]
+pub struct PhysicsSnapshot {
+    node_count: usize,
+    poses: Vec<(usize, Isometry3<f32>, Vector3<f32>)>,
+}
+
+impl PhysicsSnapshot {
+    // Blends towards a later snapshot of the same world, `alpha` being how far along it is.
+    pub fn interpolate(&self, next: &Self, alpha: f32) -> Self {
+        Self {
+            node_count: self.node_count,
+            poses: self
+                .poses
+                .iter()
+                .zip(&next.poses)
+                .map(|(&(index, isometry, scale), (_, next_isometry, _))| {
+                    // Opposite rotations have no single path between them, so those snap.
+                    let isometry = isometry
+                        .try_lerp_slerp(next_isometry, alpha, f32::EPSILON)
+                        .unwrap_or(*next_isometry);
+                    (index, isometry, scale)
+                })
+                .collect(),
+        }
+    }
+
+    // Nodes are written parents first, so each one is placed relative to its parent's new pose.
+    // A graph of another scene is left alone, as is the node being dragged, if any.
+    pub fn write_back(&self, scene_graph: &mut SceneGraph, dragged_node: Option<usize>) {
+        if scene_graph.node_count() != self.node_count {
+            return;
+        }
+        for (index, isometry, scale) in &self.poses {
+            if Some(*index) == dragged_node {
+                continue;
+            }
+            scene_graph.set_world_transform(
+                *index,
+                isometry.to_homogeneous() * Matrix4::new_nonuniform_scaling(scale),
+            );
+        }
+    }
+}
+
+fn decompose(transform: &Matrix4<f32>) -> (Isometry3<f32>, Vector3<f32>) {
+    let linear = transform.fixed_view::<3, 3>(0, 0).into_owned();
+    let scale = Vector3::from_fn(|axis, _| linear.column(axis).norm());
+    let rotation = Matrix3::from_fn(|row, column| linear[(row, column)] / scale[column]);
+    (
+        Isometry3::from_parts(
+            Translation3::from(transform.fixed_view::<3, 1>(0, 3).into_owned()),
+            UnitQuaternion::from_matrix(&rotation),
+        ),
+        scale,
+    )
+}
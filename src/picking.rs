@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageUsage, SampleCount};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+
+use crate::gpu_scene::{self, SceneObjects};
+use crate::material::input_assembly_state;
+use crate::readback::{Readback, DEFAULT_READBACK_SLOTS};
+use crate::vulkan_device::{Vertex, VulkanDevice, DEPTH_FORMAT};
+
+pub const PICKING_FORMAT: Format = Format::R32G32_UINT;
+
+mod picking_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 position;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                    uint pickId;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                layout(location = 0) flat out uint objectId;
+
+                // The draw's first instance selects its object.
+                void main() {
+                    GpuObject object = scene.objects[gl_InstanceIndex];
+                    gl_Position = uniforms.view_projection * object.transform * vec4(position, 1.0);
+                    objectId = object.pickId;
+                }
+            ",
+    }
+}
+
+mod picking_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) flat in uint objectId;
+
+                layout(location = 0) out uvec2 outId;
+
+                void main() {
+                    outId = uvec2(objectId, uint(gl_PrimitiveID));
+                }
+            ",
+    }
+}
+
+// Identifies a scene graph node; zero, which the picking target is cleared to, means nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectId(u32);
+
+impl ObjectId {
+    pub fn node(node: usize) -> Self {
+        Self(node as u32 + 1)
+    }
+
+    pub fn node_index(&self) -> usize {
+        self.0 as usize - 1
+    }
+
+    pub fn pick_id(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PickResult {
+    pub object: ObjectId,
+    pub primitive: u32,
+}
+
+pub struct Picker {
+    id_view: Arc<ImageView>,
+    depth_view: Arc<ImageView>,
+    readback: Readback<u32>,
+    result: Option<PickResult>,
+}
+
+impl Picker {
+    pub fn new(vulkan_device: &VulkanDevice) -> Result<Self> {
+        Ok(Self {
+            id_view: vulkan_device.create_attachment(
+                PICKING_FORMAT,
+                [1, 1],
+                ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+                SampleCount::Sample1,
+            )?,
+            depth_view: vulkan_device.create_attachment(
+                DEPTH_FORMAT,
+                [1, 1],
+                ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+                SampleCount::Sample1,
+            )?,
+            readback: Readback::new(vulkan_device, 2, DEFAULT_READBACK_SLOTS)?,
+            result: None,
+        })
+    }
+
+    pub fn result(&self) -> Option<PickResult> {
+        self.result
+    }
+
+    // Picks lag a few frames behind the cursor, and only the newest finished one matters.
+    pub fn poll(&mut self) {
+        if let Some(data) = self.readback.poll_latest() {
+            self.result = (data[0] != 0).then(|| PickResult {
+                object: ObjectId(data[0]),
+                primitive: data[1],
+            });
+        }
+    }
+}
+
+pub struct PickingPass {
+    pipeline: Arc<GraphicsPipeline>,
+}
+
+impl PickingPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        topology: PrimitiveTopology,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let vertex_shader = picking_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = Vertex::per_vertex().definition(&vertex_shader).unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(
+                picking_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = gpu_scene::create_pipeline_layout(device, &stages, gpu_scene_set_layout)?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(PICKING_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(input_assembly_state(topology)),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState {
+                    cull_mode: CullMode::None,
+                    ..Default::default()
+                }),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        Ok(Self { pipeline })
+    }
+
+    // Offsetting the viewport by the cursor position rasterizes only the pixel under it into the 1x1 target.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        picker: &mut Picker,
+        objects: &SceneObjects,
+        cursor: [u32; 2],
+        extent: [u32; 2],
+    ) -> Result<()> {
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone())],
+            [],
+        )?;
+
+        let viewport = Viewport {
+            offset: [-(cursor[0] as f32), -(cursor[1] as f32)],
+            extent: [extent[0] as f32, extent[1] as f32],
+            depth_range: 0.0..=1.0,
+        };
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::Store,
+                    clear_value: Some(ClearValue::Uint([0; 4])),
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(&picker.id_view))
+                })],
+                depth_attachment: Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::DontCare,
+                    clear_value: Some(1.0f32.into()),
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(&picker.depth_view))
+                }),
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport].into_iter().collect())?
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_vertex_buffers(0, vulkan_device.vertex_buffer().clone())?
+            .bind_index_buffer(vulkan_device.index_buffer().clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                set,
+            )?;
+        objects.record(builder, self.pipeline.layout())?;
+        builder.end_rendering()?;
+        picker
+            .readback
+            .record_image(builder, picker.id_view.image())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra::Matrix4;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+
+use crate::render_texture::RenderTexture;
+use crate::scene_camera::SceneCamera;
+use crate::sprite::Sprite;
+use crate::vulkan_device::VulkanDevice;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PictureInPictureSettings {
+    // The inset's size in pixels.
+    pub size: [u32; 2],
+    pub margin: f32,
+}
+
+impl Default for PictureInPictureSettings {
+    fn default() -> Self {
+        Self {
+            size: [320, 240],
+            margin: 16.0,
+        }
+    }
+}
+
+// Shows a scene camera in the bottom-right corner. Swapping moves that camera to the main view and
+// the main camera into the inset.
+pub struct PictureInPicture {
+    camera: SceneCamera,
+    render_texture: RenderTexture,
+    settings: PictureInPictureSettings,
+    swapped: bool,
+    previous_view_projection: Option<Matrix4<f32>>,
+}
+
+impl PictureInPicture {
+    pub fn new(
+        vulkan_device: &VulkanDevice,
+        camera: SceneCamera,
+        settings: PictureInPictureSettings,
+    ) -> Result<Self> {
+        let render_texture =
+            RenderTexture::new(vulkan_device, settings.size, camera.eye, camera.target)?;
+        let mut picture_in_picture = Self {
+            camera,
+            render_texture,
+            settings,
+            swapped: false,
+            previous_view_projection: None,
+        };
+        picture_in_picture.aim_inset(vulkan_device);
+        Ok(picture_in_picture)
+    }
+
+    pub fn camera(&self) -> &SceneCamera {
+        &self.camera
+    }
+
+    pub fn set_camera(&mut self, vulkan_device: &VulkanDevice, camera: SceneCamera) {
+        self.camera = camera;
+        self.previous_view_projection = None;
+        self.aim_inset(vulkan_device);
+    }
+
+    // Whether the scene camera is in the main view.
+    pub fn is_swapped(&self) -> bool {
+        self.swapped
+    }
+
+    pub fn swap(&mut self, vulkan_device: &VulkanDevice) {
+        self.swapped = !self.swapped;
+        self.previous_view_projection = None;
+        self.aim_inset(vulkan_device);
+    }
+
+    fn aim_inset(&mut self, vulkan_device: &VulkanDevice) {
+        let render_texture = &mut self.render_texture;
+        if self.swapped {
+            render_texture.eye = *vulkan_device.camera_position();
+            render_texture.target = *vulkan_device.camera_target();
+            render_texture.projection = None;
+        } else {
+            render_texture.eye = self.camera.eye;
+            render_texture.target = self.camera.target;
+            render_texture.projection = Some(self.camera.projection);
+        }
+    }
+
+    pub fn render_texture(&self) -> &RenderTexture {
+        &self.render_texture
+    }
+
+    pub fn render_texture_mut(&mut self) -> &mut RenderTexture {
+        &mut self.render_texture
+    }
+
+    // Overrides whatever the main view was set to this frame while swapped, returning the
+    // view-projection it was overridden with.
+    pub fn record_main_camera(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        pre_rotation: &Matrix4<f32>,
+    ) -> Result<Option<Matrix4<f32>>> {
+        if !self.swapped {
+            return Ok(None);
+        }
+
+        let projection = pre_rotation * self.camera.projection;
+        let previous_view_projection = self
+            .previous_view_projection
+            .unwrap_or_else(|| projection * self.camera.view());
+        let view_projection = vulkan_device.record_view(
+            builder,
+            self.camera.view(),
+            projection,
+            &previous_view_projection,
+        )?;
+        self.previous_view_projection = Some(view_projection);
+        Ok(Some(view_projection))
+    }
+
+    pub fn sprite(&self, extent: [u32; 2]) -> Sprite {
+        let [width, height] = self.settings.size.map(|size| size as f32);
+        Sprite::new(
+            Arc::clone(self.render_texture.texture()),
+            [
+                extent[0] as f32 - width - self.settings.margin,
+                extent[1] as f32 - height - self.settings.margin,
+            ],
+            [width, height],
+        )
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, BufferImageCopy, CopyImageToBufferInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::image::view::ImageView;
+
+use crate::download::decode_texel;
+use crate::picking::PickResult;
+use crate::readback::{Readback, DEFAULT_READBACK_SLOTS};
+use crate::vulkan_device::{VulkanDevice, DEPTH_FORMAT, HDR_FORMAT, NORMAL_FORMAT};
+
+// Where each target's texel lands in a staging buffer, aligned to the texel sizes.
+const COLOR_OFFSET: u64 = 0;
+const NORMAL_OFFSET: u64 = 8;
+const DEPTH_OFFSET: u64 = 16;
+const SAMPLE_SIZE: u64 = 20;
+
+// The raw values under the cursor, for tracking down lighting and precision issues.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelSample {
+    pub pixel: [u32; 2],
+    // The HDR scene color before post-processing.
+    pub color: [f32; 4],
+    pub depth: f32,
+    // In view space.
+    pub normal: [f32; 3],
+    pub pick: Option<PickResult>,
+}
+
+impl fmt::Display for PixelSample {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let [x, y] = self.pixel;
+        let [red, green, blue, alpha] = self.color;
+        let [normal_x, normal_y, normal_z] = self.normal;
+        write!(
+            formatter,
+            "({x}, {y}) color {red:.4} {green:.4} {blue:.4} {alpha:.2} depth {:.7}",
+            self.depth
+        )?;
+        write!(
+            formatter,
+            " normal {normal_x:.3} {normal_y:.3} {normal_z:.3}"
+        )?;
+        match self.pick {
+            Some(pick) => write!(
+                formatter,
+                " object {:?} primitive {}",
+                pick.object, pick.primitive
+            ),
+            None => write!(formatter, " no object"),
+        }
+    }
+}
+
+// Copies the texel under the cursor out of the scene's targets every frame while enabled. The
+// samples come back a few frames later, which is plenty for a cursor moved by hand.
+pub struct PixelInspector {
+    enabled: bool,
+    readback: Readback<u8>,
+    // The pixel each copy in flight was taken at, oldest first.
+    pixels: VecDeque<[u32; 2]>,
+    sample: Option<PixelSample>,
+}
+
+pub struct InspectedTargets<'a> {
+    pub color: &'a Arc<ImageView>,
+    pub depth: &'a Arc<ImageView>,
+    pub normal: &'a Arc<ImageView>,
+}
+
+impl PixelInspector {
+    pub fn new(vulkan_device: &VulkanDevice) -> Result<Self> {
+        Ok(Self {
+            enabled: false,
+            readback: Readback::new(vulkan_device, SAMPLE_SIZE, DEFAULT_READBACK_SLOTS)?,
+            pixels: VecDeque::with_capacity(DEFAULT_READBACK_SLOTS),
+            sample: None,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.sample = None;
+        }
+    }
+
+    pub fn sample(&self) -> Option<&PixelSample> {
+        self.sample.as_ref()
+    }
+
+    // The pick result comes from the picker's own readback, so it may be a frame apart.
+    pub fn poll(&mut self, pick: Option<PickResult>) -> Result<()> {
+        while let Some(data) = self.readback.poll() {
+            let pixel = self.pixels.pop_front().unwrap();
+            if !self.enabled {
+                continue;
+            }
+
+            let texel = |offset: u64, size: u64| &data[offset as usize..(offset + size) as usize];
+            let [normal_x, normal_y, normal_z, _] =
+                decode_texel(NORMAL_FORMAT, texel(NORMAL_OFFSET, 8))?;
+            self.sample = Some(PixelSample {
+                pixel,
+                color: decode_texel(HDR_FORMAT, texel(COLOR_OFFSET, 8))?,
+                depth: decode_texel(DEPTH_FORMAT, texel(DEPTH_OFFSET, 4))?[0],
+                normal: [normal_x, normal_y, normal_z],
+                pick,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        targets: &InspectedTargets,
+        pixel: [u32; 2],
+    ) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let recorded = self.readback.record(builder, |builder, destination| {
+            for (view, offset, size) in [
+                (targets.color, COLOR_OFFSET, 8),
+                (targets.normal, NORMAL_OFFSET, 8),
+                (targets.depth, DEPTH_OFFSET, 4),
+            ] {
+                copy_texel(
+                    builder,
+                    view,
+                    destination.clone().slice(offset..offset + size),
+                    pixel,
+                )?;
+            }
+            Ok(())
+        })?;
+        if recorded {
+            self.pixels.push_back(pixel);
+        }
+
+        Ok(())
+    }
+}
+
+fn copy_texel(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    view: &Arc<ImageView>,
+    destination: Subbuffer<[u8]>,
+    pixel: [u32; 2],
+) -> Result<()> {
+    let image = view.image();
+    builder.copy_image_to_buffer(CopyImageToBufferInfo {
+        regions: [BufferImageCopy {
+            image_subresource: image.subresource_layers(),
+            image_offset: [pixel[0], pixel[1], 0],
+            image_extent: [1, 1, 1],
+            ..Default::default()
+        }]
+        .into(),
+        ..CopyImageToBufferInfo::image_buffer(Arc::clone(image), destination)
+    })?;
+    Ok(())
+}
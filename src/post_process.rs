@@ -0,0 +1,175 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, BlitImageInfo, PrimaryAutoCommandBuffer};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::ImageUsage;
+
+use crate::transient_pool::TransientImagePool;
+use crate::vulkan_device::VulkanDevice;
+
+pub struct PostProcessContext<'a> {
+    pub vulkan_device: &'a VulkanDevice,
+    pub depth_view: &'a Arc<ImageView>,
+    pub normal_view: &'a Arc<ImageView>,
+    pub velocity_view: &'a Arc<ImageView>,
+}
+
+// Effects move with their renderer to the render thread, so they have to be Send.
+pub trait PostProcessEffect: Any + Send {
+    fn name(&self) -> &'static str;
+
+    fn output_format(&self) -> Format;
+
+    fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        context: &PostProcessContext,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+    ) -> Result<()>;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+struct PostProcessEntry {
+    effect: Box<dyn PostProcessEffect>,
+    is_enabled: bool,
+}
+
+#[derive(Default)]
+pub struct PostProcessStack {
+    entries: Vec<PostProcessEntry>,
+}
+
+impl PostProcessStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, effect: impl PostProcessEffect, is_enabled: bool) {
+        self.insert(self.entries.len(), effect, is_enabled);
+    }
+
+    pub fn insert(&mut self, index: usize, effect: impl PostProcessEffect, is_enabled: bool) {
+        self.entries.insert(
+            index,
+            PostProcessEntry {
+                effect: Box::new(effect),
+                is_enabled,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn PostProcessEffect>> {
+        let index = self.position(name)?;
+        Some(self.entries.remove(index).effect)
+    }
+
+    pub fn move_to(&mut self, name: &str, index: usize) -> bool {
+        let Some(current_index) = self.position(name) else {
+            return false;
+        };
+        let entry = self.entries.remove(current_index);
+        self.entries.insert(index.min(self.entries.len()), entry);
+        true
+    }
+
+    pub fn set_enabled(&mut self, name: &str, is_enabled: bool) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.effect.name() == name) {
+            Some(entry) => {
+                entry.is_enabled = is_enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn toggle(&mut self, name: &str) -> bool {
+        let is_enabled = !self.is_enabled(name);
+        self.set_enabled(name, is_enabled) && is_enabled
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.effect.name() == name && entry.is_enabled)
+    }
+
+    pub fn enabled_count(&self) -> usize {
+        self.entries.iter().filter(|entry| entry.is_enabled).count()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.entries.iter().map(|entry| entry.effect.name())
+    }
+
+    pub fn effect_mut<T: PostProcessEffect>(&mut self) -> Option<&mut T> {
+        self.entries
+            .iter_mut()
+            .find_map(|entry| entry.effect.as_any_mut().downcast_mut::<T>())
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.effect.name() == name)
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        context: &PostProcessContext,
+        transient_pool: &mut TransientImagePool,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+    ) -> Result<()> {
+        let effects = self
+            .entries
+            .iter()
+            .filter(|entry| entry.is_enabled)
+            .map(|entry| &entry.effect)
+            .collect::<Vec<_>>();
+
+        // With every effect off the scene goes out untouched, converted to the output's format.
+        let Some((last_effect, effects)) = effects.split_last() else {
+            builder.blit_image(BlitImageInfo::images(
+                Arc::clone(input.image()),
+                Arc::clone(output.image()),
+            ))?;
+            return Ok(());
+        };
+
+        if last_effect.output_format() != output.format() {
+            bail!(
+                "Last post-processing effect {} outputs {:?}, expected {:?}",
+                last_effect.name(),
+                last_effect.output_format(),
+                output.format()
+            );
+        }
+
+        let extent = output.image().extent();
+        let mut current_input = Arc::clone(input);
+
+        for effect in effects {
+            let effect_output = transient_pool.acquire(
+                context.vulkan_device,
+                effect.output_format(),
+                [extent[0], extent[1]],
+                ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            )?;
+
+            effect.record(builder, context, &current_input, &effect_output)?;
+
+            if !Arc::ptr_eq(&current_input, input) {
+                transient_pool.release(&current_input);
+            }
+            current_input = effect_output;
+        }
+
+        last_effect.record(builder, context, &current_input, output)
+    }
+}
@@ -0,0 +1,38 @@
+use nalgebra::{Matrix4, Rotation3, Vector3};
+use vulkano::swapchain::SurfaceTransform;
+
+// Rotated Android displays report their extent in the rotated orientation, while a pre-rotated
+// swapchain keeps the panel's native orientation. Rendering straight into that orientation lets
+// the compositor scan the image out directly instead of rotating it every frame.
+fn quarter_turns(transform: SurfaceTransform) -> u32 {
+    match transform {
+        SurfaceTransform::Rotate90 => 1,
+        SurfaceTransform::Rotate180 => 2,
+        SurfaceTransform::Rotate270 => 3,
+        _ => 0,
+    }
+}
+
+pub fn pre_rotated_extent(extent: [u32; 2], transform: SurfaceTransform) -> [u32; 2] {
+    match quarter_turns(transform) % 2 {
+        1 => [extent[1], extent[0]],
+        _ => extent,
+    }
+}
+
+// Applied in clip space, after the projection.
+pub fn pre_rotation_matrix(transform: SurfaceTransform) -> Matrix4<f32> {
+    let angle = quarter_turns(transform) as f32 * std::f32::consts::FRAC_PI_2;
+    Rotation3::from_axis_angle(&Vector3::z_axis(), angle).to_homogeneous()
+}
+
+// Maps a normalized window position onto the pre-rotated swapchain image.
+pub fn pre_rotate_position(position: [f32; 2], transform: SurfaceTransform) -> [f32; 2] {
+    let [u, v] = position;
+    match quarter_turns(transform) {
+        1 => [1.0 - v, u],
+        2 => [1.0 - u, 1.0 - v],
+        3 => [v, 1.0 - u],
+        _ => position,
+    }
+}
@@ -0,0 +1,224 @@
+use std::f32::consts::{PI, TAU};
+
+use anyhow::{bail, Result};
+use nalgebra::Vector3;
+
+use crate::mesh_pool::MeshAllocation;
+use crate::vulkan_device::{Vertex, VulkanDevice};
+
+// A point of a parametric surface. Surfaces are parametrised so that `u` runs right and `v` runs
+// down when seen from the front, which makes the grid's triangles counter-clockwise like glTF's.
+struct SurfacePoint {
+    position: Vector3<f32>,
+    normal: Vector3<f32>,
+    tangent: Vector3<f32>,
+    uv: [f32; 2],
+}
+
+// Generated triangle lists with glTF's attribute conventions: UVs start at the top left and tangents
+// point along +u with a handedness of 1. The mesh pool only stores positions so far, so the other
+// attributes are kept for when the vertex format grows.
+#[derive(Clone, Debug, Default)]
+pub struct PrimitiveMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub tangents: Vec<[f32; 4]>,
+    pub indices: Vec<u16>,
+}
+
+impl PrimitiveMesh {
+    pub fn plane(width: f32, depth: f32, subdivisions: u32) -> Result<Self> {
+        let mut mesh = Self::default();
+        mesh.add_face(
+            &Vector3::zeros(),
+            &Vector3::y(),
+            &Vector3::x(),
+            [width, depth],
+            subdivisions,
+        );
+        mesh.finish()
+    }
+
+    pub fn cube(size: f32, subdivisions: u32) -> Result<Self> {
+        let mut mesh = Self::default();
+        // Each face's down direction is `right × normal`, so the sides have +y up.
+        for (normal, right) in [
+            (Vector3::x(), -Vector3::z()),
+            (-Vector3::x(), Vector3::z()),
+            (Vector3::y(), Vector3::x()),
+            (-Vector3::y(), Vector3::x()),
+            (Vector3::z(), Vector3::x()),
+            (-Vector3::z(), -Vector3::x()),
+        ] {
+            mesh.add_face(
+                &(normal * size * 0.5),
+                &normal,
+                &right,
+                [size, size],
+                subdivisions,
+            );
+        }
+        mesh.finish()
+    }
+
+    pub fn sphere(radius: f32, segments: u32, rings: u32) -> Result<Self> {
+        let mut mesh = Self::default();
+        mesh.add_surface(segments, rings, |u, v| {
+            let (theta, phi) = (u * TAU, v * PI);
+            let normal = Vector3::new(phi.sin() * theta.sin(), phi.cos(), phi.sin() * theta.cos());
+            SurfacePoint {
+                position: normal * radius,
+                normal,
+                tangent: Vector3::new(theta.cos(), 0.0, -theta.sin()),
+                uv: [u, v],
+            }
+        });
+        mesh.finish()
+    }
+
+    pub fn cylinder(radius: f32, height: f32, segments: u32) -> Result<Self> {
+        let mut mesh = Self::default();
+        mesh.add_surface(segments, 1, |u, v| {
+            let theta = u * TAU;
+            let normal = Vector3::new(theta.sin(), 0.0, theta.cos());
+            SurfacePoint {
+                position: normal * radius + Vector3::y() * height * (0.5 - v),
+                normal,
+                tangent: Vector3::new(theta.cos(), 0.0, -theta.sin()),
+                uv: [u, v],
+            }
+        });
+        mesh.add_cap(radius, height * 0.5, segments, true);
+        mesh.add_cap(radius, -height * 0.5, segments, false);
+        mesh.finish()
+    }
+
+    // The apex is on top, where each column keeps its own normal.
+    pub fn cone(radius: f32, height: f32, segments: u32) -> Result<Self> {
+        let mut mesh = Self::default();
+        mesh.add_surface(segments, 1, |u, v| {
+            let theta = u * TAU;
+            let radial = Vector3::new(theta.sin(), 0.0, theta.cos());
+            SurfacePoint {
+                position: radial * radius * v + Vector3::y() * height * (0.5 - v),
+                normal: (radial * height + Vector3::y() * radius).normalize(),
+                tangent: Vector3::new(theta.cos(), 0.0, -theta.sin()),
+                uv: [u, v],
+            }
+        });
+        mesh.add_cap(radius, -height * 0.5, segments, false);
+        mesh.finish()
+    }
+
+    // Lies in the XZ plane; `segments` go around the ring and `sides` around the tube.
+    pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> Result<Self> {
+        let mut mesh = Self::default();
+        mesh.add_surface(segments, sides, |u, v| {
+            let (theta, phi) = (u * TAU, v * TAU);
+            let radial = Vector3::new(theta.sin(), 0.0, theta.cos());
+            let normal = radial * phi.cos() - Vector3::y() * phi.sin();
+            SurfacePoint {
+                position: radial * major_radius + normal * minor_radius,
+                normal,
+                tangent: Vector3::new(theta.cos(), 0.0, -theta.sin()),
+                uv: [u, v],
+            }
+        });
+        mesh.finish()
+    }
+
+    // What the mesh pool stores of each vertex.
+    pub fn vertices(&self) -> Vec<Vertex> {
+        self.positions.iter().copied().map(Vertex::from).collect()
+    }
+
+    pub fn upload(&self, vulkan_device: &VulkanDevice) -> Result<MeshAllocation> {
+        vulkan_device.upload_mesh(&self.vertices(), &self.indices)
+    }
+
+    fn add_face(
+        &mut self,
+        center: &Vector3<f32>,
+        normal: &Vector3<f32>,
+        right: &Vector3<f32>,
+        size: [f32; 2],
+        subdivisions: u32,
+    ) {
+        let down = right.cross(normal);
+        let cells = subdivisions + 1;
+        self.add_surface(cells, cells, |u, v| SurfacePoint {
+            position: center + right * size[0] * (u - 0.5) + down * size[1] * (v - 0.5),
+            normal: *normal,
+            tangent: *right,
+            uv: [u, v],
+        });
+    }
+
+    // A disc from its center out to the rim, mapped planarly so the texture isn't pinched.
+    fn add_cap(&mut self, radius: f32, y: f32, segments: u32, facing_up: bool) {
+        self.add_surface(segments, 1, |u, v| {
+            let theta = u * TAU;
+            // Walking inwards flips the winding for the disc facing down.
+            let distance = radius * if facing_up { v } else { 1.0 - v };
+            let (x, z) = (theta.sin() * distance, theta.cos() * distance);
+            let (normal, v_sign) = if facing_up {
+                (Vector3::y(), 1.0)
+            } else {
+                (-Vector3::y(), -1.0)
+            };
+            SurfacePoint {
+                position: Vector3::new(x, y, z),
+                normal,
+                tangent: Vector3::x(),
+                uv: [0.5 + x / (2.0 * radius), 0.5 + v_sign * z / (2.0 * radius)],
+            }
+        });
+    }
+
+    // A (columns + 1) × (rows + 1) grid of vertices; seams get duplicated vertices so each side
+    // keeps its own UVs.
+    fn add_surface(&mut self, columns: u32, rows: u32, point: impl Fn(f32, f32) -> SurfacePoint) {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        let base = self.positions.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let point = point(column as f32 / columns as f32, row as f32 / rows as f32);
+                self.positions.push(point.position.into());
+                self.normals.push(point.normal.into());
+                self.tangents
+                    .push([point.tangent.x, point.tangent.y, point.tangent.z, 1.0]);
+                self.uvs.push(point.uv);
+            }
+        }
+
+        let stride = columns + 1;
+        for row in 0..rows {
+            for column in 0..columns {
+                let top_left = base + row * stride + column;
+                let bottom_left = top_left + stride;
+                self.indices.extend(
+                    [
+                        top_left,
+                        bottom_left,
+                        top_left + 1,
+                        top_left + 1,
+                        bottom_left,
+                        bottom_left + 1,
+                    ]
+                    .map(|index| index as u16),
+                );
+            }
+        }
+    }
+
+    fn finish(self) -> Result<Self> {
+        if self.positions.len() > u16::MAX as usize + 1 {
+            bail!(
+                "A primitive with {} vertices doesn't fit 16-bit indices",
+                self.positions.len()
+            );
+        }
+        Ok(self)
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use gltf::khr_lights_punctual::Kind;
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use crate::billboard::{Billboard, BillboardId, Billboards};
+use crate::debug_draw::DebugDraw;
+use crate::sprite::{SpriteTexture, TextureEncoding};
+use crate::vulkan_device::VulkanDevice;
+
+const ICON_EXTENT: u32 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PunctualLightKind {
+    Directional,
+    Point,
+    // Angles in radians from the light's axis.
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+// A `KHR_lights_punctual` light placed by the scene. The renderer only shades with the sun so far,
+// so these are loaded for inspection.
+#[derive(Clone, Debug)]
+pub struct PunctualLight {
+    pub name: String,
+    pub kind: PunctualLightKind,
+    pub position: Point3<f32>,
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // Unset means the light reaches infinitely far.
+    pub range: Option<f32>,
+}
+
+impl PunctualLight {
+    // Lights shine down their node's -Z axis.
+    pub fn from_gltf(
+        light: &gltf::khr_lights_punctual::Light,
+        transform: &Matrix4<f32>,
+        index: usize,
+    ) -> Self {
+        Self {
+            name: light
+                .name()
+                .map_or_else(|| format!("light {index}"), str::to_owned),
+            kind: match light.kind() {
+                Kind::Directional => PunctualLightKind::Directional,
+                Kind::Point => PunctualLightKind::Point,
+                Kind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                } => PunctualLightKind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                },
+            },
+            position: transform.transform_point(&Point3::origin()),
+            direction: transform.transform_vector(&-Vector3::z()).normalize(),
+            color: light.color(),
+            intensity: light.intensity(),
+            range: light.range(),
+        }
+    }
+}
+
+// Every light instanced by the document's scenes, with the node hierarchy's transforms applied.
+pub fn load_punctual_lights(document: &gltf::Document) -> Vec<PunctualLight> {
+    fn visit(node: gltf::Node, parent: &Matrix4<f32>, lights: &mut Vec<PunctualLight>) {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if let Some(light) = node.light() {
+            lights.push(PunctualLight::from_gltf(&light, &transform, lights.len()));
+        }
+        for child in node.children() {
+            visit(child, &transform, lights);
+        }
+    }
+
+    let mut lights = Vec::new();
+    for node in document.scenes().flat_map(|scene| scene.nodes()) {
+        visit(node, &Matrix4::identity(), &mut lights);
+    }
+    lights
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LightVisualizationSettings {
+    pub enabled: bool,
+    // The icons' size in world units.
+    pub icon_size: f32,
+    // How far lights without a range are drawn, and how long directional lights' arrows are.
+    pub unbounded_range: f32,
+}
+
+impl Default for LightVisualizationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            icon_size: 0.3,
+            unbounded_range: 2.0,
+        }
+    }
+}
+
+// Billboards at the lights' positions, tinted with their color. They're attached while the
+// visualization is enabled and detached again when it's turned off.
+#[derive(Default)]
+pub struct LightIcons {
+    texture: Option<Arc<SpriteTexture>>,
+    billboards: Vec<BillboardId>,
+}
+
+impl LightIcons {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(
+        &mut self,
+        vulkan_device: &VulkanDevice,
+        billboards: &mut Billboards,
+        lights: &[PunctualLight],
+        settings: &LightVisualizationSettings,
+    ) -> Result<()> {
+        if !settings.enabled {
+            for id in self.billboards.drain(..) {
+                billboards.detach(id);
+            }
+            return Ok(());
+        }
+        if !self.billboards.is_empty() || lights.is_empty() {
+            return Ok(());
+        }
+
+        let texture = match &self.texture {
+            Some(texture) => Arc::clone(texture),
+            None => Arc::clone(self.texture.insert(Arc::new(SpriteTexture::from_rgba(
+                vulkan_device,
+                [ICON_EXTENT; 2],
+                &icon_texels(),
+                TextureEncoding::Srgb,
+            )?))),
+        };
+        self.billboards = lights
+            .iter()
+            .map(|light| {
+                let mut billboard = Billboard::new(
+                    Arc::clone(&texture),
+                    light.position,
+                    [settings.icon_size; 2],
+                );
+                billboard.color = [light.color[0], light.color[1], light.color[2], 1.0];
+                billboards.attach(billboard)
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+// Spheres for point lights, cones for spot lights and arrows for directional lights, in the
+// lights' colors.
+pub fn draw_light_ranges(
+    debug_draw: &mut DebugDraw,
+    lights: &[PunctualLight],
+    settings: &LightVisualizationSettings,
+) {
+    for light in lights {
+        let color = [light.color[0], light.color[1], light.color[2], 1.0];
+        let range = light.range.unwrap_or(settings.unbounded_range);
+        match light.kind {
+            PunctualLightKind::Directional => {
+                let end = light.position + light.direction * settings.unbounded_range;
+                debug_draw.line(&light.position, &end, color);
+                debug_draw.cone(&end, &-light.direction, settings.icon_size, 0.4, color);
+            }
+            PunctualLightKind::Point => debug_draw.sphere(&light.position, range, color),
+            PunctualLightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                // The inner cone, where the falloff starts, is fainter.
+                for (angle, alpha) in [(outer_cone_angle, 1.0), (inner_cone_angle, 0.4)] {
+                    debug_draw.cone(
+                        &light.position,
+                        &light.direction,
+                        range,
+                        angle,
+                        [color[0], color[1], color[2], alpha],
+                    );
+                }
+            }
+        }
+    }
+}
+
+// A white disc with a soft edge, so the billboard's color is the light's.
+fn icon_texels() -> Vec<u8> {
+    let center = (ICON_EXTENT as f32 - 1.0) * 0.5;
+    (0..ICON_EXTENT * ICON_EXTENT)
+        .flat_map(|texel| {
+            let (x, y) = ((texel % ICON_EXTENT) as f32, (texel / ICON_EXTENT) as f32);
+            let distance = ((x - center).powi(2) + (y - center).powi(2)).sqrt() / center;
+            let alpha = ((1.0 - distance) * 4.0).clamp(0.0, 1.0);
+            [255, 255, 255, (alpha * 255.0) as u8]
+        })
+        .collect()
+}
@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::{ComputePipeline, Pipeline};
+
+use crate::compute;
+use crate::light::DirectionalLight;
+use crate::vulkan_device::VulkanDevice;
+
+pub const VISIBILITY_FORMAT: Format = Format::R8G8B8A8_UNORM;
+
+mod visibility_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: r"
+                #version 460
+                #extension GL_EXT_ray_query : require
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0) uniform accelerationStructureEXT scene;
+                layout(set = 0, binding = 1, rgba8) uniform writeonly image2D visibility;
+                layout(set = 0, binding = 2) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+                layout(set = 0, binding = 3) uniform sampler2D depthTexture;
+                layout(set = 0, binding = 4) uniform sampler2D normalTexture;
+
+                layout(push_constant) uniform VisibilityParameters {
+                    vec3 sunDirection;
+                    float occlusionRadius;
+                    uint occlusionRayCount;
+                    uint shadows;
+                    uint ambientOcclusion;
+                } parameters;
+
+                const float PI = 3.14159265359;
+                const float RAY_EPSILON = 1e-3;
+                const float SHADOW_DISTANCE = 1e4;
+
+                bool occluded(vec3 origin, vec3 direction, float distance) {
+                    rayQueryEXT rayQuery;
+                    rayQueryInitializeEXT(
+                        rayQuery, scene,
+                        gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsOpaqueEXT,
+                        0xFF, origin, RAY_EPSILON, direction, distance
+                    );
+                    while (rayQueryProceedEXT(rayQuery)) {}
+                    return rayQueryGetIntersectionTypeEXT(rayQuery, true) != gl_RayQueryCommittedIntersectionNoneEXT;
+                }
+
+                float interleavedGradientNoise(vec2 pixel) {
+                    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+                }
+
+                vec3 cosineHemisphere(vec3 normal, vec2 random) {
+                    vec3 tangent = normalize(abs(normal.y) < 0.99 ? cross(normal, vec3(0.0, 1.0, 0.0)) : cross(normal, vec3(1.0, 0.0, 0.0)));
+                    vec3 bitangent = cross(normal, tangent);
+                    float radius = sqrt(random.x);
+                    float angle = 2.0 * PI * random.y;
+                    return normalize(tangent * radius * cos(angle) + bitangent * radius * sin(angle) + normal * sqrt(1.0 - random.x));
+                }
+
+                void main() {
+                    ivec2 size = imageSize(visibility);
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    if (any(greaterThanEqual(pixel, size))) {
+                        return;
+                    }
+
+                    float depth = texelFetch(depthTexture, pixel, 0).r;
+                    if (depth >= 1.0) {
+                        imageStore(visibility, pixel, vec4(1.0));
+                        return;
+                    }
+
+                    mat4 inverseView = inverse(uniforms.view);
+                    vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+                    vec4 viewPosition = uniforms.inverse_projection * vec4(uv * 2.0 - 1.0, depth, 1.0);
+                    vec3 normal = normalize(mat3(inverseView) * texelFetch(normalTexture, pixel, 0).xyz);
+                    vec3 position = (inverseView * vec4(viewPosition.xyz / viewPosition.w, 1.0)).xyz + normal * RAY_EPSILON;
+
+                    float occlusion = 1.0;
+                    if (parameters.ambientOcclusion != 0u) {
+                        float noise = interleavedGradientNoise(vec2(pixel));
+                        uint hits = 0u;
+                        for (uint i = 0u; i < parameters.occlusionRayCount; i++) {
+                            vec2 random = fract(vec2((float(i) + noise) / float(parameters.occlusionRayCount), noise + float(i) * 0.618034));
+                            if (occluded(position, cosineHemisphere(normal, random), parameters.occlusionRadius)) {
+                                hits++;
+                            }
+                        }
+                        occlusion = 1.0 - float(hits) / float(max(parameters.occlusionRayCount, 1u));
+                    }
+
+                    float shadow = 1.0;
+                    if (parameters.shadows != 0u && occluded(position, normalize(parameters.sunDirection), SHADOW_DISTANCE)) {
+                        shadow = 0.0;
+                    }
+
+                    imageStore(visibility, pixel, vec4(occlusion, shadow, 0.0, 1.0));
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayQuerySettings {
+    pub shadows: bool,
+    pub ambient_occlusion: bool,
+    pub occlusion_radius: f32,
+    pub occlusion_ray_count: u32,
+}
+
+impl Default for RayQuerySettings {
+    fn default() -> Self {
+        Self {
+            shadows: false,
+            ambient_occlusion: false,
+            occlusion_radius: 0.5,
+            occlusion_ray_count: 8,
+        }
+    }
+}
+
+impl RayQuerySettings {
+    pub fn enabled(&self) -> bool {
+        self.shadows || self.ambient_occlusion
+    }
+}
+
+pub struct RayQueryViews<'a> {
+    pub visibility: &'a Arc<ImageView>,
+    pub depth: &'a Arc<ImageView>,
+    pub normal: &'a Arc<ImageView>,
+}
+
+pub struct RayQueryPass {
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl RayQueryPass {
+    pub fn new(device: &Arc<Device>, pipeline_cache: &Arc<PipelineCache>) -> Result<Self> {
+        let pipeline = compute::create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            visibility_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self { pipeline, sampler })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        views: &RayQueryViews,
+        settings: &RayQuerySettings,
+        sun: &DirectionalLight,
+    ) -> Result<()> {
+        let RayQueryViews {
+            visibility,
+            depth,
+            normal,
+        } = views;
+
+        let scene = vulkan_device
+            .scene_acceleration()
+            .context("Scene acceleration structure has not been built")?;
+        let top_level = scene
+            .top_level()
+            .context("Scene top level acceleration structure has not been built")?;
+        let [width, height, _] = visibility.image().extent();
+        let sun_direction = sun.direction.normalize();
+
+        vulkan_device
+            .bind_compute(
+                builder,
+                &self.pipeline,
+                [
+                    WriteDescriptorSet::acceleration_structure(0, Arc::clone(top_level)),
+                    WriteDescriptorSet::image_view(1, Arc::clone(visibility)),
+                    WriteDescriptorSet::buffer(2, vulkan_device.uniform_buffer().clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        3,
+                        Arc::clone(depth),
+                        Arc::clone(&self.sampler),
+                    ),
+                    WriteDescriptorSet::image_view_sampler(
+                        4,
+                        Arc::clone(normal),
+                        Arc::clone(&self.sampler),
+                    ),
+                ],
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                visibility_cs::VisibilityParameters {
+                    sunDirection: sun_direction.into(),
+                    occlusionRadius: settings.occlusion_radius,
+                    occlusionRayCount: settings.occlusion_ray_count,
+                    shadows: settings.shadows as u32,
+                    ambientOcclusion: settings.ambient_occlusion as u32,
+                },
+            )?;
+        unsafe { builder.dispatch([width.div_ceil(8), height.div_ceil(8), 1]) }?;
+
+        Ok(())
+    }
+}
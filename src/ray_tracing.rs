@@ -0,0 +1,435 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use nalgebra::Matrix4;
+use vulkano::acceleration_structure::AccelerationStructure;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::sampler::{
+    Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode,
+};
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::DeviceSize;
+
+use crate::acceleration_structure::{
+    upload_geometry, AccelerationStructureManager, BottomLevelId, InstanceId,
+};
+use crate::gpu_scene::{self, object_index, GPU_SCENE_SET};
+use crate::ibl::ImageBasedLighting;
+use crate::light::DirectionalLight;
+use crate::mesh_pool::MeshAllocation;
+use crate::scene_graph::SceneGraph;
+use crate::vulkan_device::{Vertex, VulkanDevice};
+
+mod trace_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: r"
+                #version 460
+                #extension GL_EXT_ray_query : require
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0) uniform accelerationStructureEXT scene;
+                layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D outputImage;
+                layout(set = 0, binding = 2) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+                layout(set = 0, binding = 3) readonly buffer Positions {
+                    float positions[];
+                };
+                layout(set = 0, binding = 4) readonly buffer Indices {
+                    uint indices[];
+                };
+                layout(set = 0, binding = 5) uniform samplerCube irradianceMap;
+                layout(set = 0, binding = 6) uniform samplerCube prefilteredMap;
+                layout(set = 0, binding = 7) uniform sampler2D brdfLut;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                struct GpuMaterial {
+                    vec4 baseColor;
+                    float alphaCutoff;
+                    float pointSize;
+                };
+
+                struct GpuMesh {
+                    uint firstIndex;
+                    uint indexCount;
+                    int vertexOffset;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } sceneObjects;
+
+                layout(set = 3, binding = 1) readonly buffer Materials {
+                    GpuMaterial materials[];
+                } sceneMaterials;
+
+                layout(set = 3, binding = 3) readonly buffer Meshes {
+                    GpuMesh meshes[];
+                } sceneMeshes;
+
+                layout(push_constant) uniform TraceParameters {
+                    vec3 sunDirection;
+                    float environmentIntensity;
+                    vec3 sunColor;
+                } parameters;
+
+                const float METALLIC = 0.0;
+                const float ROUGHNESS = 0.5;
+                const float RAY_EPSILON = 1e-3;
+                const float RAY_DISTANCE = 1e4;
+
+                vec3 vertexPosition(uint index) {
+                    return vec3(positions[index * 3], positions[index * 3 + 1], positions[index * 3 + 2]);
+                }
+
+                vec3 fresnelSchlickRoughness(float cosTheta, vec3 f0, float roughness) {
+                    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - cosTheta, 5.0);
+                }
+
+                vec3 ambientLighting(vec3 albedo, vec3 normal, vec3 viewDirection) {
+                    float normalDotView = max(dot(normal, viewDirection), 0.0);
+
+                    vec3 f0 = mix(vec3(0.04), albedo, METALLIC);
+                    vec3 fresnel = fresnelSchlickRoughness(normalDotView, f0, ROUGHNESS);
+                    vec3 diffuseWeight = (1.0 - fresnel) * (1.0 - METALLIC);
+
+                    vec3 irradiance = textureLod(irradianceMap, normal, 0.0).rgb;
+                    float maxLod = float(textureQueryLevels(prefilteredMap) - 1);
+                    vec3 prefiltered = textureLod(prefilteredMap, reflect(-viewDirection, normal), ROUGHNESS * maxLod).rgb;
+                    vec2 brdf = textureLod(brdfLut, vec2(normalDotView, ROUGHNESS), 0.0).rg;
+
+                    return diffuseWeight * irradiance * albedo + prefiltered * (fresnel * brdf.x + brdf.y);
+                }
+
+                bool occluded(vec3 origin, vec3 direction) {
+                    rayQueryEXT rayQuery;
+                    rayQueryInitializeEXT(
+                        rayQuery, scene,
+                        gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsOpaqueEXT,
+                        0xFF, origin, RAY_EPSILON, direction, RAY_DISTANCE
+                    );
+                    while (rayQueryProceedEXT(rayQuery)) {}
+                    return rayQueryGetIntersectionTypeEXT(rayQuery, true) != gl_RayQueryCommittedIntersectionNoneEXT;
+                }
+
+                void main() {
+                    ivec2 size = imageSize(outputImage);
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    if (any(greaterThanEqual(pixel, size))) {
+                        return;
+                    }
+
+                    mat4 inverseView = inverse(uniforms.view);
+                    vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+                    vec4 target = uniforms.inverse_projection * vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+                    vec3 origin = inverseView[3].xyz;
+                    vec3 direction = normalize(mat3(inverseView) * (target.xyz / target.w));
+
+                    rayQueryEXT rayQuery;
+                    rayQueryInitializeEXT(
+                        rayQuery, scene, gl_RayFlagsOpaqueEXT,
+                        0xFF, origin, 0.0, direction, RAY_DISTANCE
+                    );
+                    while (rayQueryProceedEXT(rayQuery)) {}
+
+                    if (rayQueryGetIntersectionTypeEXT(rayQuery, true) == gl_RayQueryCommittedIntersectionNoneEXT) {
+                        vec3 background = textureLod(prefilteredMap, direction, 0.0).rgb * parameters.environmentIntensity;
+                        imageStore(outputImage, pixel, vec4(background, 1.0));
+                        return;
+                    }
+
+                    // Instances are the scene's objects, and the positions and indices are the scene
+                    // mesh's, so a mesh's triangles start where it does in the mesh pool minus where the
+                    // scene mesh, mesh 0, does.
+                    GpuObject object = sceneObjects.objects[rayQueryGetIntersectionInstanceCustomIndexEXT(rayQuery, true)];
+                    uint firstIndex = sceneMeshes.meshes[object.mesh].firstIndex - sceneMeshes.meshes[0].firstIndex
+                        + rayQueryGetIntersectionPrimitiveIndexEXT(rayQuery, true) * 3;
+                    mat4x3 objectToWorld = rayQueryGetIntersectionObjectToWorldEXT(rayQuery, true);
+                    vec3 p0 = objectToWorld * vec4(vertexPosition(indices[firstIndex]), 1.0);
+                    vec3 p1 = objectToWorld * vec4(vertexPosition(indices[firstIndex + 1]), 1.0);
+                    vec3 p2 = objectToWorld * vec4(vertexPosition(indices[firstIndex + 2]), 1.0);
+                    vec3 normal = normalize(cross(p1 - p0, p2 - p0));
+                    if (dot(normal, direction) > 0.0) {
+                        normal = -normal;
+                    }
+
+                    vec3 position = origin + direction * rayQueryGetIntersectionTEXT(rayQuery, true);
+                    vec3 albedo = sceneMaterials.materials[object.material].baseColor.rgb;
+
+                    vec3 lightDirection = normalize(parameters.sunDirection);
+                    float shadow = occluded(position + normal * RAY_EPSILON, lightDirection) ? 0.0 : 1.0;
+
+                    vec3 ambient = ambientLighting(albedo, normal, -direction);
+                    vec3 diffuse = max(dot(normal, lightDirection), 0.0) * albedo * parameters.sunColor * shadow;
+                    imageStore(outputImage, pixel, vec4(ambient + diffuse, 1.0));
+                }
+            ",
+    }
+}
+
+pub struct SceneAccelerationStructure {
+    manager: AccelerationStructureManager,
+    position_buffer: Subbuffer<[Vertex]>,
+    index_buffer: Subbuffer<[u32]>,
+    scene_mesh: MeshAllocation,
+    bottom_levels: HashMap<MeshAllocation, BottomLevelId>,
+    // What the top level was last built from: each instance's object, mesh and transform.
+    instances: Vec<(u32, MeshAllocation, Matrix4<f32>)>,
+    instance_ids: Vec<InstanceId>,
+}
+
+impl SceneAccelerationStructure {
+    // Every mesh, which lies in the scene mesh uploaded as `scene_mesh`, gets a bottom level built
+    // from a slice of the scene's indices. Those are rebased onto the scene's first vertex, so
+    // shaders find a mesh's triangles from its first index alone. The scene mesh gets one too, for
+    // scenes whose graph has no primitives.
+    pub fn build(
+        vulkan_device: &VulkanDevice,
+        vertices: &[Vertex],
+        indices: &[u16],
+        scene_mesh: &MeshAllocation,
+        meshes: impl IntoIterator<Item = MeshAllocation>,
+    ) -> Result<Self> {
+        let meshes = std::iter::once(*scene_mesh)
+            .chain(meshes)
+            .collect::<HashSet<_>>();
+        let mut rebased_indices = indices
+            .iter()
+            .map(|&index| index as u32)
+            .collect::<Vec<_>>();
+        let mut rebased_ranges = HashMap::new();
+        for mesh in meshes.iter().filter(|&mesh| mesh != scene_mesh) {
+            match rebased_ranges.insert(mesh.first_index, mesh.vertex_offset) {
+                Some(vertex_offset) if vertex_offset == mesh.vertex_offset => continue,
+                Some(_) => {
+                    bail!("Primitives sharing indices must share their vertices to be ray traced")
+                }
+                None => {}
+            }
+            let first_index = (mesh.first_index - scene_mesh.first_index) as usize;
+            let vertex_offset = (mesh.vertex_offset - scene_mesh.vertex_offset) as u32;
+            for index in &mut rebased_indices[first_index..first_index + mesh.index_count as usize]
+            {
+                *index += vertex_offset;
+            }
+        }
+        let (position_buffer, index_buffer) =
+            upload_geometry(vulkan_device, vertices, &rebased_indices)?;
+
+        let mut manager = AccelerationStructureManager::new();
+        let mut bottom_levels = HashMap::new();
+        for mesh in meshes {
+            let first_index = (mesh.first_index - scene_mesh.first_index) as DeviceSize;
+            let bottom_level = manager.add_bottom_level(
+                vulkan_device,
+                position_buffer.clone(),
+                index_buffer
+                    .clone()
+                    .slice(first_index..first_index + mesh.index_count as DeviceSize),
+            )?;
+            bottom_levels.insert(mesh, bottom_level);
+        }
+        manager.release_scratch_buffers();
+
+        Ok(Self {
+            manager,
+            position_buffer,
+            index_buffer,
+            scene_mesh: *scene_mesh,
+            bottom_levels,
+            instances: Vec::new(),
+            instance_ids: Vec::new(),
+        })
+    }
+
+    // Every primitive of the graph becomes an instance of its mesh with its node's transform, and
+    // its object as the custom index, numbered as `object_index` does. The top level is only
+    // rebuilt when one of them changed, and updated in place when they only moved.
+    pub fn update(&mut self, vulkan_device: &VulkanDevice, scene_graph: &SceneGraph) -> Result<()> {
+        let mut instances = (0..scene_graph.node_count())
+            .flat_map(|node| {
+                scene_graph
+                    .primitives(node)
+                    .iter()
+                    .map(move |primitive| (primitive.mesh, scene_graph.world_transforms()[node]))
+            })
+            .enumerate()
+            .map(|(index, (mesh, transform))| (object_index(index), mesh, transform))
+            .collect::<Vec<_>>();
+        if instances.is_empty() {
+            instances.push((0, self.scene_mesh, Matrix4::identity()));
+        }
+        if self.manager.top_level().is_some() && instances == self.instances {
+            return Ok(());
+        }
+
+        let moved_only = instances.len() == self.instances.len()
+            && instances
+                .iter()
+                .zip(&self.instances)
+                .all(|(instance, built)| instance.0 == built.0 && instance.1 == built.1);
+        if moved_only {
+            for ((_, _, transform), &id) in instances.iter().zip(&self.instance_ids) {
+                self.manager.set_instance_transform(id, transform);
+            }
+        } else {
+            self.manager.clear_instances();
+            self.instance_ids.clear();
+            for (object, mesh, transform) in &instances {
+                let bottom_level = *self
+                    .bottom_levels
+                    .get(mesh)
+                    .context("Mesh has no bottom level acceleration structure")?;
+                self.instance_ids
+                    .push(self.manager.add_instance(bottom_level, *object, transform));
+            }
+        }
+        self.manager.build_top_level(vulkan_device)?;
+        self.instances = instances;
+
+        Ok(())
+    }
+
+    pub fn top_level(&self) -> Option<&Arc<AccelerationStructure>> {
+        self.manager.top_level()
+    }
+
+    pub fn position_buffer(&self) -> &Subbuffer<[Vertex]> {
+        &self.position_buffer
+    }
+
+    pub fn index_buffer(&self) -> &Subbuffer<[u32]> {
+        &self.index_buffer
+    }
+
+    pub fn manager_mut(&mut self) -> &mut AccelerationStructureManager {
+        &mut self.manager
+    }
+}
+
+// The GPU scene's objects and how they're lit.
+pub struct RayTracedScene<'a> {
+    pub objects: &'a Arc<DescriptorSet>,
+    pub environment: &'a ImageBasedLighting,
+    pub sun: &'a DirectionalLight,
+    pub environment_intensity: f32,
+}
+
+// A preview of the scene rendered with ray queries from a compute shader: primary rays and sun
+// shadows are traced inline, with each object's base color and no textures. This isn't a
+// VK_KHR_ray_tracing_pipeline backend.
+pub struct RayTracingPass {
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl RayTracingPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let pipeline = gpu_scene::create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            trace_cs::load(Arc::clone(device))?.entry_point("main").unwrap(),
+            gpu_scene_set_layout,
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self { pipeline, sampler })
+    }
+
+    pub fn trace(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        scene: &RayTracedScene,
+        target: &Arc<ImageView>,
+    ) -> Result<()> {
+        let acceleration = vulkan_device
+            .scene_acceleration()
+            .context("Scene acceleration structure has not been built")?;
+        let top_level = acceleration
+            .top_level()
+            .context("Scene top level acceleration structure has not been built")?;
+        let [width, height, _] = target.image().extent();
+        let sun_direction = scene.sun.direction.normalize();
+
+        vulkan_device
+            .bind_compute(
+                builder,
+                &self.pipeline,
+                [
+                    WriteDescriptorSet::acceleration_structure(0, Arc::clone(top_level)),
+                    WriteDescriptorSet::image_view(1, Arc::clone(target)),
+                    WriteDescriptorSet::buffer(2, vulkan_device.uniform_buffer().clone()),
+                    WriteDescriptorSet::buffer(3, acceleration.position_buffer().clone()),
+                    WriteDescriptorSet::buffer(4, acceleration.index_buffer().clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        5,
+                        Arc::clone(scene.environment.irradiance_view()),
+                        Arc::clone(&self.sampler),
+                    ),
+                    WriteDescriptorSet::image_view_sampler(
+                        6,
+                        Arc::clone(scene.environment.prefiltered_view()),
+                        Arc::clone(&self.sampler),
+                    ),
+                    WriteDescriptorSet::image_view_sampler(
+                        7,
+                        Arc::clone(scene.environment.brdf_lut_view()),
+                        Arc::clone(&self.sampler),
+                    ),
+                ],
+            )?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                Arc::clone(self.pipeline.layout()),
+                GPU_SCENE_SET,
+                Arc::clone(scene.objects),
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                trace_cs::TraceParameters {
+                    sunDirection: sun_direction.into(),
+                    environmentIntensity: scene.environment_intensity,
+                    sunColor: scene.sun.color,
+                },
+            )?;
+        unsafe { builder.dispatch([width.div_ceil(8), height.div_ceil(8), 1]) }?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CopyImageToBufferInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::image::Image;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::DeviceSize;
+
+use crate::vulkan_device::VulkanDevice;
+
+// Enough for the copy of the frame being recorded plus the ones still in flight.
+pub const DEFAULT_READBACK_SLOTS: usize = 3;
+
+// Gets GPU data back to the CPU a few frames later without stalling. Each copy goes into the next
+// free staging buffer of a ring, and polling hands them back in the order they were recorded once
+// their submission has finished.
+//
+// A staging buffer stays locked by the frame's future until its fence has signaled and the future
+// is cleaned up, which is what `poll` checks, so it has to run before the next frame is recorded
+// rather than between recording and submitting one.
+pub struct Readback<T: BufferContents> {
+    slots: Vec<Subbuffer<[T]>>,
+    // The oldest copy still waiting to be read, and how many follow it.
+    first: usize,
+    pending: usize,
+}
+
+impl<T: BufferContents + Copy> Readback<T> {
+    pub fn new(vulkan_device: &VulkanDevice, len: DeviceSize, slot_count: usize) -> Result<Self> {
+        ensure!(
+            slot_count > 0,
+            "A readback needs at least one staging buffer"
+        );
+        Ok(Self {
+            slots: create_slots(vulkan_device, len, slot_count)?,
+            first: 0,
+            pending: 0,
+        })
+    }
+
+    // Whether a copy can be recorded without waiting on an earlier one.
+    pub fn has_free_slot(&self) -> bool {
+        self.pending < self.slots.len()
+    }
+
+    // Reallocates the staging buffers for `len` elements each, dropping the copies in flight.
+    pub fn resize(&mut self, vulkan_device: &VulkanDevice, len: DeviceSize) -> Result<()> {
+        self.slots = create_slots(vulkan_device, len, self.slots.len())?;
+        self.first = 0;
+        self.pending = 0;
+        Ok(())
+    }
+
+    // Lets `copy` record whatever fills the next free staging buffer. Returns false without
+    // recording anything while every buffer is still in flight.
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        copy: impl FnOnce(
+            &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+            Subbuffer<[T]>,
+        ) -> Result<()>,
+    ) -> Result<bool> {
+        if !self.has_free_slot() {
+            return Ok(false);
+        }
+
+        let slot = (self.first + self.pending) % self.slots.len();
+        copy(builder, self.slots[slot].clone())?;
+        self.pending += 1;
+        Ok(true)
+    }
+
+    // The staging buffers have to be sized for the image's first mip level.
+    pub fn record_image(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: &Arc<Image>,
+    ) -> Result<bool> {
+        self.record(builder, |builder, destination| {
+            builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                Arc::clone(image),
+                destination,
+            ))?;
+            Ok(())
+        })
+    }
+
+    // The oldest copy once the GPU has finished it, or `None` while it's still in flight.
+    pub fn poll(&mut self) -> Option<Vec<T>> {
+        if self.pending == 0 {
+            return None;
+        }
+
+        let data = self.slots[self.first].read().ok()?.to_vec();
+        self.first = (self.first + 1) % self.slots.len();
+        self.pending -= 1;
+        Some(data)
+    }
+
+    // Skips to the most recent finished copy, for results where only the newest one matters.
+    pub fn poll_latest(&mut self) -> Option<Vec<T>> {
+        std::iter::from_fn(|| self.poll()).last()
+    }
+}
+
+fn create_slots<T: BufferContents>(
+    vulkan_device: &VulkanDevice,
+    len: DeviceSize,
+    slot_count: usize,
+) -> Result<Vec<Subbuffer<[T]>>> {
+    (0..slot_count)
+        .map(|_| {
+            Ok(Buffer::new_slice(
+                vulkan_device.memory_allocator().clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::TRANSFER_DST,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                        | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                    ..Default::default()
+                },
+                len,
+            )?)
+        })
+        .collect()
+}
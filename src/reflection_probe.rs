@@ -0,0 +1,226 @@
+use std::f32::consts::FRAC_PI_2;
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Perspective3, Point3, Vector3};
+use vulkano::buffer::BufferContents;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CopyImageInfo, ImageCopy, PrimaryAutoCommandBuffer,
+};
+use vulkano::image::{Image, ImageSubresourceLayers, ImageUsage};
+use vulkano::DeviceSize;
+
+use crate::environment::{create_cube_view, create_cubemap, record_cubemap_mips};
+use crate::ibl::ImageBasedLighting;
+use crate::memory::image_size;
+use crate::render_texture::RenderTexture;
+use crate::scene_graph::Aabb;
+use crate::vulkan_device::VulkanDevice;
+
+// Has to match the size of the probe arrays in the scene's fragment shader.
+pub const MAX_REFLECTION_PROBES: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReflectionProbeSettings {
+    // Where the cubemap is captured from; has to be inside the bounds.
+    pub position: Point3<f32>,
+    // The box reflections are projected onto and the probe's area of influence, usually the room
+    // it's placed in.
+    pub bounds: Aabb,
+    // How far inside the bounds the probe fades in, so neighbouring probes blend.
+    pub blend_distance: f32,
+    pub face_size: u32,
+}
+
+impl ReflectionProbeSettings {
+    // A probe in the middle of the box.
+    pub fn new(bounds: Aabb) -> Self {
+        Self {
+            position: bounds.center(),
+            bounds,
+            blend_distance: 0.5,
+            face_size: 128,
+        }
+    }
+}
+
+// A cubemap of the scene around a point, prefiltered like the environment so the scene shader can
+// use it for reflections inside the probe's bounds. The capture reuses a single render texture for
+// all six faces and happens at the start of the next frame after creation or `request_capture`.
+pub struct ReflectionProbe {
+    settings: ReflectionProbeSettings,
+    capture: RenderTexture,
+    cubemap: Arc<Image>,
+    lighting: Option<ImageBasedLighting>,
+    needs_capture: bool,
+}
+
+impl ReflectionProbe {
+    pub fn new(vulkan_device: &VulkanDevice, settings: ReflectionProbeSettings) -> Result<Self> {
+        let mut capture = RenderTexture::new(
+            vulkan_device,
+            [settings.face_size; 2],
+            settings.position,
+            settings.position + Vector3::x(),
+        )?;
+        let camera_projection = vulkan_device.camera_projection();
+        capture.projection = Some(
+            Perspective3::new(
+                1.0,
+                FRAC_PI_2,
+                camera_projection.znear(),
+                camera_projection.zfar(),
+            )
+            .into_inner(),
+        );
+
+        let cubemap = create_cubemap(
+            vulkan_device.memory_allocator().clone(),
+            settings.face_size,
+            u32::BITS - settings.face_size.leading_zeros(),
+            ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
+        )?;
+
+        Ok(Self {
+            settings,
+            capture,
+            cubemap,
+            lighting: None,
+            needs_capture: true,
+        })
+    }
+
+    pub fn settings(&self) -> &ReflectionProbeSettings {
+        &self.settings
+    }
+
+    // Moving a probe or its bounds doesn't recapture it by itself.
+    pub fn set_placement(&mut self, position: Point3<f32>, bounds: Aabb) {
+        self.settings.position = position;
+        self.settings.bounds = bounds;
+    }
+
+    pub fn request_capture(&mut self) {
+        self.needs_capture = true;
+    }
+
+    pub fn needs_capture(&self) -> bool {
+        self.needs_capture
+    }
+
+    // Unset until the first capture was recorded.
+    pub fn lighting(&self) -> Option<&ImageBasedLighting> {
+        self.lighting.as_ref()
+    }
+
+    // `record_face` draws the scene into the render texture, which is already aimed at the face
+    // with the given view-projection.
+    pub fn record_capture(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        mut record_face: impl FnMut(
+            &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+            &mut RenderTexture,
+            &Matrix4<f32>,
+        ) -> Result<()>,
+    ) -> Result<()> {
+        for (face, (forward, up)) in cube_faces().into_iter().enumerate() {
+            self.capture.eye = self.settings.position;
+            self.capture.target = self.settings.position + forward;
+            self.capture.up = up;
+            let view_projection = self.capture.record_camera(builder, vulkan_device)?;
+            record_face(builder, &mut self.capture, &view_projection)?;
+
+            let scene_color = self.capture.texture().view().image();
+            let face = face as u32;
+            builder.copy_image(CopyImageInfo {
+                regions: [ImageCopy {
+                    src_subresource: scene_color.subresource_layers(),
+                    dst_subresource: ImageSubresourceLayers {
+                        array_layers: face..face + 1,
+                        ..self.cubemap.subresource_layers()
+                    },
+                    extent: [self.settings.face_size, self.settings.face_size, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyImageInfo::images(Arc::clone(scene_color), Arc::clone(&self.cubemap))
+            })?;
+        }
+
+        record_cubemap_mips(builder, &self.cubemap)?;
+        self.lighting = Some(ImageBasedLighting::new(
+            vulkan_device.memory_allocator().clone(),
+            vulkan_device.descriptor_set_allocator(),
+            vulkan_device.pipeline_cache(),
+            builder,
+            &create_cube_view(&self.cubemap)?,
+        )?);
+        self.needs_capture = false;
+        Ok(())
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        self.capture.memory_size()
+            + image_size(&self.cubemap)
+            + self.lighting.as_ref().map_or(0, |lighting| {
+                [
+                    lighting.irradiance_view(),
+                    lighting.prefiltered_view(),
+                    lighting.brdf_lut_view(),
+                ]
+                .map(|view| image_size(view.image()))
+                .into_iter()
+                .sum()
+            })
+    }
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+pub struct ReflectionProbesUniform {
+    // The minimum's w is the blend distance.
+    box_minimum: [[f32; 4]; MAX_REFLECTION_PROBES],
+    box_maximum: [[f32; 4]; MAX_REFLECTION_PROBES],
+    position: [[f32; 4]; MAX_REFLECTION_PROBES],
+    count: u32,
+}
+
+impl ReflectionProbesUniform {
+    // Probes past the maximum are left out.
+    pub fn new<'a>(probes: impl IntoIterator<Item = &'a ReflectionProbe>) -> Self {
+        let mut uniform = Self {
+            box_minimum: [[0.0; 4]; MAX_REFLECTION_PROBES],
+            box_maximum: [[0.0; 4]; MAX_REFLECTION_PROBES],
+            position: [[0.0; 4]; MAX_REFLECTION_PROBES],
+            count: 0,
+        };
+        for (index, probe) in probes.into_iter().take(MAX_REFLECTION_PROBES).enumerate() {
+            let ReflectionProbeSettings {
+                position,
+                bounds,
+                blend_distance,
+                ..
+            } = probe.settings;
+            uniform.box_minimum[index] = [bounds.min.x, bounds.min.y, bounds.min.z, blend_distance];
+            uniform.box_maximum[index] = [bounds.max.x, bounds.max.y, bounds.max.z, 0.0];
+            uniform.position[index] = [position.x, position.y, position.z, 1.0];
+            uniform.count += 1;
+        }
+        uniform
+    }
+}
+
+// The faces in layer order as forward and up vectors. The scene's projection leaves +Y pointing
+// down the framebuffer, which is what puts the side faces' up at -Y.
+fn cube_faces() -> [(Vector3<f32>, Vector3<f32>); 6] {
+    [
+        (Vector3::x(), -Vector3::y()),
+        (-Vector3::x(), -Vector3::y()),
+        (Vector3::y(), Vector3::z()),
+        (-Vector3::y(), -Vector3::z()),
+        (Vector3::z(), -Vector3::y()),
+        (-Vector3::z(), -Vector3::y()),
+    ]
+}
@@ -0,0 +1,102 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::image::view::ImageView;
+
+use crate::gpu_scene::GpuScene;
+use crate::vulkan_device::VulkanDevice;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PluginStage {
+    BeforePostProcess,
+    AfterPostProcess,
+}
+
+pub struct RenderPassContext<'a> {
+    pub vulkan_device: &'a VulkanDevice,
+    pub gpu_scene: &'a GpuScene,
+    pub scene_color_view: &'a Arc<ImageView>,
+    pub depth_view: &'a Arc<ImageView>,
+    pub normal_view: &'a Arc<ImageView>,
+    pub velocity_view: &'a Arc<ImageView>,
+    pub swapchain_image_view: &'a Arc<ImageView>,
+}
+
+// Plugins move with their renderer to the render thread, so they have to be Send.
+pub trait RenderPassPlugin: Any + Send {
+    fn name(&self) -> &'static str;
+
+    fn stage(&self) -> PluginStage;
+
+    fn setup(&mut self, _vulkan_device: &VulkanDevice, _extent: [u32; 2]) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&mut self, _vulkan_device: &VulkanDevice, _extent: [u32; 2]) -> Result<()> {
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        context: &RenderPassContext,
+    ) -> Result<()>;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+#[derive(Default)]
+pub struct RenderPassPlugins {
+    plugins: Vec<Box<dyn RenderPassPlugin>>,
+}
+
+impl RenderPassPlugins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(
+        &mut self,
+        vulkan_device: &VulkanDevice,
+        extent: [u32; 2],
+        mut plugin: impl RenderPassPlugin,
+    ) -> Result<()> {
+        plugin.setup(vulkan_device, extent)?;
+        self.plugins.push(Box::new(plugin));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn RenderPassPlugin>> {
+        let index = self
+            .plugins
+            .iter()
+            .position(|plugin| plugin.name() == name)?;
+        Some(self.plugins.remove(index))
+    }
+
+    pub fn plugin_mut<T: RenderPassPlugin>(&mut self) -> Option<&mut T> {
+        self.plugins
+            .iter_mut()
+            .find_map(|plugin| plugin.as_any_mut().downcast_mut::<T>())
+    }
+
+    pub fn resize(&mut self, vulkan_device: &VulkanDevice, extent: [u32; 2]) -> Result<()> {
+        self.plugins
+            .iter_mut()
+            .try_for_each(|plugin| plugin.resize(vulkan_device, extent))
+    }
+
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        context: &RenderPassContext,
+        stage: PluginStage,
+    ) -> Result<()> {
+        self.plugins
+            .iter_mut()
+            .filter(|plugin| plugin.stage() == stage)
+            .try_for_each(|plugin| plugin.record(builder, context))
+    }
+}
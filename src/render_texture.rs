@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra::{Isometry3, Matrix4, Point3, Vector3};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::DeviceSize;
+
+use crate::sprite::SpriteTexture;
+use crate::vulkan_device::VulkanDevice;
+use crate::vulkan_renderer::RenderTargets;
+
+// A secondary camera that draws the scene into its own targets each frame, for mirrors, portals
+// and security monitors. The texture holds linear HDR color, so it belongs on surfaces drawn in
+// the scene pass, such as billboards.
+pub struct RenderTexture {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    // A camera looking straight down needs something other than the default +Y.
+    pub up: Vector3<f32>,
+    // Replaces the scene camera's perspective, e.g. with an orthographic projection.
+    pub projection: Option<Matrix4<f32>>,
+    targets: RenderTargets,
+    texture: Arc<SpriteTexture>,
+    previous_view_projection: Option<Matrix4<f32>>,
+}
+
+impl RenderTexture {
+    pub fn new(
+        vulkan_device: &VulkanDevice,
+        extent: [u32; 2],
+        eye: Point3<f32>,
+        target: Point3<f32>,
+    ) -> Result<Self> {
+        let targets = RenderTargets::new(vulkan_device, extent)?;
+        let texture = Arc::new(SpriteTexture::from_view(Arc::clone(
+            targets.scene_color_view(),
+        )));
+        Ok(Self {
+            eye,
+            target,
+            up: Vector3::y(),
+            projection: None,
+            targets,
+            texture,
+            previous_view_projection: None,
+        })
+    }
+
+    pub fn texture(&self) -> &Arc<SpriteTexture> {
+        &self.texture
+    }
+
+    pub fn view(&self) -> Matrix4<f32> {
+        Isometry3::look_at_rh(&self.eye, &self.target, &self.up).to_homogeneous()
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        self.targets.memory_size()
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        let [width, height] = self.texture.extent();
+        Viewport {
+            offset: [0.0, 0.0],
+            extent: [width as f32, height as f32],
+            depth_range: 0.0..=1.0,
+        }
+    }
+
+    // The renderer swaps these in for its own while drawing from this camera.
+    pub fn targets_mut(&mut self) -> &mut RenderTargets {
+        &mut self.targets
+    }
+
+    // The texture is never presented, so its camera ignores the swapchain's pre-rotation.
+    pub fn record_camera(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+    ) -> Result<Matrix4<f32>> {
+        let previous_view_projection = self
+            .previous_view_projection
+            .unwrap_or(*vulkan_device.view_projection());
+        let view = self.view();
+        let projection = self
+            .projection
+            .unwrap_or_else(|| vulkan_device.camera_projection().into_inner());
+        let view_projection =
+            vulkan_device.record_view(builder, view, projection, &previous_view_projection)?;
+        self.previous_view_projection = Some(view_projection);
+        Ok(view_projection)
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, bail, Result};
+use winit::event_loop::EventLoopProxy;
+use winit::window::WindowId;
+
+use crate::frame_pacing::FramePacer;
+use crate::metrics::FrameMetrics;
+use crate::pixel_inspector::PixelSample;
+use crate::vulkan_renderer::VulkanRenderer;
+
+pub type Renderers = HashMap<WindowId, VulkanRenderer>;
+
+type RenderCommand = Box<dyn FnOnce(&mut Renderers) -> Result<()> + Send>;
+
+// What the event loop needs to hear about each rendered frame.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameReport {
+    pub window_id: WindowId,
+    pub frame_metrics: FrameMetrics,
+    pub pixel_sample: Option<PixelSample>,
+    pub benchmark_finished: bool,
+    // Surfaces can only be created on the event loop's thread, which then hands over a new one.
+    pub surface_lost: bool,
+}
+
+impl FrameReport {
+    pub fn new(window_id: WindowId, vulkan_renderer: &VulkanRenderer) -> Self {
+        Self {
+            window_id,
+            frame_metrics: *vulkan_renderer.frame_metrics(),
+            pixel_sample: vulkan_renderer.pixel_inspector().sample().copied(),
+            benchmark_finished: vulkan_renderer
+                .benchmark()
+                .is_some_and(|benchmark| benchmark.is_finished()),
+            surface_lost: vulkan_renderer.surface_lost(),
+        }
+    }
+}
+
+// Where the windows' renderers live. Locally they render on the event loop's redraw requests;
+// on a render thread they render back to back and take changes as commands between frames.
+pub enum RendererHost {
+    Local(Renderers),
+    Threaded(RenderThread),
+}
+
+impl RendererHost {
+    pub fn is_threaded(&self) -> bool {
+        matches!(self, RendererHost::Threaded(_))
+    }
+
+    // Runs right away locally, and before the next frame on the render thread, where an error
+    // stops the thread and comes back from a later call.
+    pub fn run(
+        &mut self,
+        command: impl FnOnce(&mut Renderers) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        match self {
+            RendererHost::Local(renderers) => command(renderers),
+            RendererHost::Threaded(render_thread) => render_thread.send(Box::new(command)),
+        }
+    }
+
+    // Runs against a single window's renderer, if it still has one.
+    pub fn run_on(
+        &mut self,
+        window_id: WindowId,
+        command: impl FnOnce(&mut VulkanRenderer) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        self.run(move |renderers| match renderers.get_mut(&window_id) {
+            Some(vulkan_renderer) => command(vulkan_renderer),
+            None => Ok(()),
+        })
+    }
+
+    pub fn run_on_all(
+        &mut self,
+        mut command: impl FnMut(&mut VulkanRenderer) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        self.run(move |renderers| renderers.values_mut().try_for_each(&mut command))
+    }
+
+    // Like `run`, but hands back the result, so on the render thread it waits for up to a frame.
+    pub fn query<T: Send + 'static>(
+        &mut self,
+        query: impl FnOnce(&mut Renderers) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        match self {
+            RendererHost::Local(renderers) => query(renderers),
+            RendererHost::Threaded(render_thread) => {
+                let (sender, receiver) = mpsc::channel();
+                render_thread.send(Box::new(move |renderers| {
+                    // The caller only stops listening once the thread has already failed.
+                    let _ = sender.send(query(renderers));
+                    Ok(())
+                }))?;
+                match receiver.recv() {
+                    Ok(result) => result,
+                    Err(_) => render_thread.join(),
+                }
+            }
+        }
+    }
+
+    pub fn query_on<T: Send + 'static>(
+        &mut self,
+        window_id: WindowId,
+        query: impl FnOnce(&mut VulkanRenderer) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        self.query(move |renderers| {
+            query(
+                renderers
+                    .get_mut(&window_id)
+                    .ok_or_else(|| anyhow!("The window has no renderer"))?,
+            )
+        })
+    }
+}
+
+// Owns the renderers on a thread of its own, so a heavy frame never holds up input and window
+// events. It paces and renders every window in turn, then wakes the event loop to take the
+// frames' reports and simulate the next one.
+pub struct RenderThread {
+    commands: Option<Sender<RenderCommand>>,
+    reports: Receiver<FrameReport>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl RenderThread {
+    pub fn spawn(
+        renderers: Renderers,
+        frame_pacer: FramePacer,
+        primary_window_id: WindowId,
+        event_loop_proxy: EventLoopProxy<()>,
+    ) -> Result<Self> {
+        let (commands, command_receiver) = mpsc::channel();
+        let (report_sender, reports) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("render".to_owned())
+            .spawn(move || {
+                render_loop(
+                    renderers,
+                    frame_pacer,
+                    primary_window_id,
+                    command_receiver,
+                    report_sender,
+                    event_loop_proxy,
+                )
+            })?;
+
+        Ok(Self {
+            commands: Some(commands),
+            reports,
+            thread: Some(thread),
+        })
+    }
+
+    // The reports of the frames finished since the last call, and the thread's error if it
+    // stopped.
+    pub fn take_reports(&mut self) -> Result<Vec<FrameReport>> {
+        if self
+            .thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+        {
+            return self.join();
+        }
+        Ok(self.reports.try_iter().collect())
+    }
+
+    fn send(&mut self, command: RenderCommand) -> Result<()> {
+        if let Some(commands) = &self.commands {
+            if commands.send(command).is_ok() {
+                return Ok(());
+            }
+        }
+        self.join()
+    }
+
+    // Only called once the thread has stopped or should stop, so it always ends in an error.
+    fn join<T>(&mut self) -> Result<T> {
+        self.commands = None;
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(Err(error))) => Err(error),
+            Some(Err(_)) => bail!("The render thread panicked"),
+            _ => bail!("The render thread has stopped"),
+        }
+    }
+}
+
+// Closing the command channel stops the thread after its current frame.
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        self.commands = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn render_loop(
+    mut renderers: Renderers,
+    mut frame_pacer: FramePacer,
+    primary_window_id: WindowId,
+    commands: Receiver<RenderCommand>,
+    reports: Sender<FrameReport>,
+    event_loop_proxy: EventLoopProxy<()>,
+) -> Result<()> {
+    loop {
+        // Without renderers, such as while suspended, there's nothing to do until told otherwise.
+        if renderers.is_empty() {
+            match commands.recv() {
+                Ok(command) => command(&mut renderers)?,
+                Err(_) => return Ok(()),
+            }
+        }
+        loop {
+            match commands.try_recv() {
+                Ok(command) => command(&mut renderers)?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        if renderers.is_empty() {
+            continue;
+        }
+
+        for vulkan_renderer in renderers.values_mut() {
+            vulkan_renderer.wait_for_previous_present()?;
+        }
+        // Windows on other displays follow the primary window's cadence.
+        if let Some(vulkan_renderer) = renderers.get(&primary_window_id) {
+            frame_pacer.set_refresh_interval(vulkan_renderer.refresh_interval());
+        }
+        frame_pacer.pace();
+
+        for (&window_id, vulkan_renderer) in &mut renderers {
+            vulkan_renderer.render()?;
+            if reports
+                .send(FrameReport::new(window_id, vulkan_renderer))
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+        if event_loop_proxy.send_event(()).is_err() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use gltf::camera::Projection;
+use nalgebra::{Isometry3, Matrix4, Orthographic3, Perspective3, Point3, Vector3};
+
+// glTF leaves these optional; the aspect matches the picture-in-picture inset.
+const DEFAULT_ASPECT_RATIO: f32 = 4.0 / 3.0;
+const DEFAULT_FAR_PLANE: f32 = 1000.0;
+
+// A viewpoint other than the main camera, either loaded from the glTF scene or created by hand.
+#[derive(Clone, Debug)]
+pub struct SceneCamera {
+    pub name: String,
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub projection: Matrix4<f32>,
+}
+
+impl SceneCamera {
+    pub fn new(
+        name: impl Into<String>,
+        eye: Point3<f32>,
+        target: Point3<f32>,
+        projection: Matrix4<f32>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            eye,
+            target,
+            projection,
+        }
+    }
+
+    // glTF cameras look down their node's -Z axis.
+    pub fn from_gltf(camera: &gltf::Camera, transform: &Matrix4<f32>, index: usize) -> Self {
+        let eye = transform.transform_point(&Point3::origin());
+        let forward = transform.transform_vector(&-Vector3::z());
+        let projection = match camera.projection() {
+            Projection::Perspective(perspective) => Perspective3::new(
+                perspective.aspect_ratio().unwrap_or(DEFAULT_ASPECT_RATIO),
+                perspective.yfov(),
+                perspective.znear(),
+                perspective.zfar().unwrap_or(DEFAULT_FAR_PLANE),
+            )
+            .into_inner(),
+            Projection::Orthographic(orthographic) => Orthographic3::new(
+                -orthographic.xmag(),
+                orthographic.xmag(),
+                -orthographic.ymag(),
+                orthographic.ymag(),
+                orthographic.znear(),
+                orthographic.zfar(),
+            )
+            .into_inner(),
+        };
+
+        Self::new(
+            camera
+                .name()
+                .map_or_else(|| format!("camera {index}"), str::to_owned),
+            eye,
+            eye + forward,
+            projection,
+        )
+    }
+
+    pub fn view(&self) -> Matrix4<f32> {
+        Isometry3::look_at_rh(&self.eye, &self.target, &Vector3::y()).to_homogeneous()
+    }
+}
+
+// Cameras are picked by name, or by their index in load order.
+pub fn find_scene_camera(cameras: &[SceneCamera], name: &str) -> Result<SceneCamera> {
+    cameras
+        .iter()
+        .find(|camera| camera.name == name)
+        .or_else(|| cameras.get(name.parse::<usize>().ok()?))
+        .cloned()
+        .with_context(|| format!("Unknown scene camera: {name}"))
+}
+
+// Every camera instanced by the document's scenes, with the node hierarchy's transforms applied.
+pub fn load_scene_cameras(document: &gltf::Document) -> Vec<SceneCamera> {
+    fn visit(node: gltf::Node, parent: &Matrix4<f32>, cameras: &mut Vec<SceneCamera>) {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if let Some(camera) = node.camera() {
+            cameras.push(SceneCamera::from_gltf(&camera, &transform, cameras.len()));
+        }
+        for child in node.children() {
+            visit(child, &transform, cameras);
+        }
+    }
+
+    let mut cameras = Vec::new();
+    for node in document.scenes().flat_map(|scene| scene.nodes()) {
+        visit(node, &Matrix4::identity(), &mut cameras);
+    }
+    cameras
+}
@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nalgebra::{Matrix4, Point3};
+use serde::{Deserialize, Serialize};
+
+use crate::light::DirectionalLight;
+use crate::material::SceneMaterial;
+
+pub const DEFAULT_SCENE_FILE_PATH: &str = "scene.ron";
+// Bound to the number keys 1 to 9.
+pub const CAMERA_BOOKMARK_SLOTS: usize = 9;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CameraState {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+}
+
+// The runtime changes made on top of the loaded glTF document. Node transforms are indexed like the
+// selected scene's graph, so a file only restores onto the document it was saved from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneFile {
+    pub scene: usize,
+    pub local_transforms: Vec<Matrix4<f32>>,
+    #[serde(default)]
+    pub sun: Option<DirectionalLight>,
+    #[serde(default)]
+    pub camera: Option<CameraState>,
+    #[serde(default)]
+    pub camera_bookmarks: [Option<CameraState>; CAMERA_BOOKMARK_SLOTS],
+    // Indexed like the document's materials, followed by the default one. Files without them keep
+    // the materials as loaded.
+    #[serde(default)]
+    pub materials: Vec<SceneMaterial>,
+}
+
+impl SceneFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        ron::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
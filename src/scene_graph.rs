@@ -0,0 +1,384 @@
+use std::ops::Range;
+
+use anyhow::{Context, Result};
+use nalgebra::{Matrix4, Point3, Vector4};
+use rayon::prelude::*;
+
+use crate::mesh_pool::MeshAllocation;
+use crate::vulkan_device::Vertex;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraversalSettings {
+    pub parallel: bool,
+    // Levels and scenes smaller than this stay on the calling thread, where rayon's overhead
+    // would outweigh the work.
+    pub min_parallel_nodes: usize,
+}
+
+impl Default for TraversalSettings {
+    fn default() -> Self {
+        Self {
+            parallel: true,
+            min_parallel_nodes: 1024,
+        }
+    }
+}
+
+impl TraversalSettings {
+    fn is_parallel(&self, node_count: usize) -> bool {
+        self.parallel && node_count >= self.min_parallel_nodes
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn transform(&self, transform: &Matrix4<f32>) -> Aabb {
+        let corner = |index: usize| {
+            let select = |axis: usize| {
+                if (index >> axis) & 1 == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            };
+            transform.transform_point(&Point3::new(select(0), select(1), select(2)))
+        };
+        (1..8).fold(Aabb::from(corner(0)), |bounds, index| {
+            bounds.union(&Aabb::from(corner(index)))
+        })
+    }
+}
+
+impl From<Point3<f32>> for Aabb {
+    fn from(point: Point3<f32>) -> Self {
+        Self {
+            min: point,
+            max: point,
+        }
+    }
+}
+
+// Planes face inwards, extracted from a GL-convention view projection.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn new(view_projection: &Matrix4<f32>) -> Self {
+        let row = |index: usize| view_projection.row(index).transpose();
+        let w = row(3);
+        Self {
+            planes: [
+                w + row(0),
+                w - row(0),
+                w + row(1),
+                w - row(1),
+                w + row(2),
+                w - row(2),
+            ],
+        }
+    }
+
+    // Conservative: boxes straddling two planes outside a corner still count as visible.
+    pub fn intersects(&self, bounds: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            let (min, max) = (&bounds.min.coords, &bounds.max.coords);
+            let farthest =
+                min.zip_zip_map(max, &normal, |min, max, n| if n >= 0.0 { max } else { min });
+            normal.dot(&farthest) + plane.w >= 0.0
+        })
+    }
+}
+
+// The glTF node hierarchy flattened breadth-first, so each depth is a contiguous range whose
+// parents all come before it. Nodes within a depth are independent and update in parallel.
+// Where a primitive lies in the scene mesh, and the scene material it's drawn with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScenePrimitive {
+    pub mesh: MeshAllocation,
+    pub material: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SceneGraph {
+    parents: Vec<Option<usize>>,
+    local_transforms: Vec<Matrix4<f32>>,
+    world_transforms: Vec<Matrix4<f32>>,
+    // Only nodes with a mesh have bounds.
+    local_bounds: Vec<Option<Aabb>>,
+    primitives: Vec<Vec<ScenePrimitive>>,
+    // Where each node's primitives start when they're numbered across the graph.
+    first_primitives: Vec<usize>,
+    levels: Vec<Range<usize>>,
+}
+
+impl SceneGraph {
+    // Every scene of the document in one graph.
+    pub fn from_gltf(document: &gltf::Document) -> Self {
+        Self::from_root_nodes(
+            document.scenes().flat_map(|scene| scene.nodes()),
+            document.materials().len(),
+        )
+    }
+
+    pub fn from_gltf_scene(scene: &gltf::Scene, material_count: usize) -> Self {
+        Self::from_root_nodes(scene.nodes(), material_count)
+    }
+
+    fn from_root_nodes<'a>(
+        root_nodes: impl Iterator<Item = gltf::Node<'a>>,
+        material_count: usize,
+    ) -> Self {
+        let mut scene_graph = Self::default();
+        let mut level = root_nodes.map(|node| (node, None)).collect::<Vec<_>>();
+
+        while !level.is_empty() {
+            let start = scene_graph.parents.len();
+            let mut next_level = Vec::new();
+            for (node, parent) in level {
+                let index = scene_graph.parents.len();
+                scene_graph.parents.push(parent);
+                scene_graph
+                    .local_transforms
+                    .push(Matrix4::from(node.transform().matrix()));
+                scene_graph
+                    .local_bounds
+                    .push(node.mesh().and_then(|mesh| mesh_bounds(&mesh)));
+                scene_graph.primitives.push(
+                    node.mesh()
+                        .map_or_else(Vec::new, |mesh| mesh_primitives(&mesh, material_count)),
+                );
+                next_level.extend(node.children().map(|child| (child, Some(index))));
+            }
+            scene_graph.levels.push(start..scene_graph.parents.len());
+            level = next_level;
+        }
+
+        scene_graph.first_primitives = scene_graph
+            .primitives
+            .iter()
+            .scan(0, |first, primitives| {
+                let node_first = *first;
+                *first += primitives.len();
+                Some(node_first)
+            })
+            .collect();
+        scene_graph.world_transforms = scene_graph.local_transforms.clone();
+        scene_graph.update_world_transforms(&TraversalSettings::default());
+        scene_graph
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn local_transforms(&self) -> &[Matrix4<f32>] {
+        &self.local_transforms
+    }
+
+    pub fn local_transforms_mut(&mut self) -> &mut [Matrix4<f32>] {
+        &mut self.local_transforms
+    }
+
+    pub fn world_transforms(&self) -> &[Matrix4<f32>] {
+        &self.world_transforms
+    }
+
+    pub fn mesh_nodes(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.node_count()).filter(|&index| self.local_bounds[index].is_some())
+    }
+
+    pub fn local_bounds(&self, index: usize) -> Option<Aabb> {
+        self.local_bounds[index]
+    }
+
+    pub fn primitives(&self, index: usize) -> &[ScenePrimitive] {
+        &self.primitives[index]
+    }
+
+    // Every primitive's mesh, node by node, repeated however many primitives share it.
+    pub fn meshes(&self) -> impl Iterator<Item = MeshAllocation> + '_ {
+        self.primitives
+            .iter()
+            .flatten()
+            .map(|primitive| primitive.mesh)
+    }
+
+    // Numbers primitives across the graph, node by node.
+    pub fn primitive_index(&self, node: usize, primitive: usize) -> usize {
+        self.first_primitives[node] + primitive
+    }
+
+    // Primitives are read relative to the merged scene mesh, so they move with it once it's
+    // uploaded, or rewritten.
+    pub fn remap_primitives(&mut self, mut remap: impl FnMut(MeshAllocation) -> MeshAllocation) {
+        self.primitives
+            .iter_mut()
+            .flatten()
+            .for_each(|primitive| primitive.mesh = remap(primitive.mesh));
+    }
+
+    // Places a node in world space by rewriting its local transform against its parent's current
+    // world transform. Descendants follow on the next `update_world_transforms`.
+    pub fn set_world_transform(&mut self, index: usize, world: Matrix4<f32>) {
+        let parent_world = self.parents[index].map(|parent| self.world_transforms[parent]);
+        self.local_transforms[index] = match parent_world {
+            Some(parent_world) => {
+                parent_world.try_inverse().unwrap_or_else(Matrix4::identity) * world
+            }
+            None => world,
+        };
+        self.world_transforms[index] = world;
+    }
+
+    pub fn world_bounds(&self) -> Option<Aabb> {
+        (0..self.node_count())
+            .filter_map(|index| self.node_bounds(index))
+            .reduce(|bounds, node_bounds| bounds.union(&node_bounds))
+    }
+
+    pub fn node_bounds(&self, index: usize) -> Option<Aabb> {
+        self.local_bounds[index].map(|bounds| bounds.transform(&self.world_transforms[index]))
+    }
+
+    pub fn update_world_transforms(&mut self, settings: &TraversalSettings) {
+        for level in &self.levels {
+            let (ancestors, level_transforms) = self.world_transforms.split_at_mut(level.start);
+            let ancestors = &*ancestors;
+            let update =
+                |((world, parent), local): ((&mut Matrix4<f32>, &Option<usize>), &Matrix4<f32>)| {
+                    *world = parent.map_or(*local, |parent| ancestors[parent] * local);
+                };
+
+            let level_transforms = &mut level_transforms[..level.len()];
+            let parents = &self.parents[level.clone()];
+            let locals = &self.local_transforms[level.clone()];
+            if settings.is_parallel(level.len()) {
+                level_transforms
+                    .par_iter_mut()
+                    .zip(parents)
+                    .zip(locals)
+                    .for_each(update);
+            } else {
+                level_transforms
+                    .iter_mut()
+                    .zip(parents)
+                    .zip(locals)
+                    .for_each(update);
+            }
+        }
+    }
+
+    // Indices of the mesh nodes whose world bounds touch the frustum, in node order.
+    pub fn cull(&self, view_projection: &Matrix4<f32>, settings: &TraversalSettings) -> Vec<usize> {
+        let frustum = Frustum::new(view_projection);
+        let is_visible = |&index: &usize| {
+            self.node_bounds(index)
+                .is_some_and(|bounds| frustum.intersects(&bounds))
+        };
+
+        if settings.is_parallel(self.node_count()) {
+            (0..self.node_count())
+                .into_par_iter()
+                .filter(is_visible)
+                .collect()
+        } else {
+            (0..self.node_count()).filter(is_visible).collect()
+        }
+    }
+}
+
+// One of the document's scenes, which are shown one at a time.
+#[derive(Clone, Debug)]
+pub struct GltfScene {
+    pub name: String,
+    pub graph: SceneGraph,
+}
+
+pub fn load_scenes(document: &gltf::Document) -> Vec<GltfScene> {
+    document
+        .scenes()
+        .map(|scene| GltfScene {
+            name: scene
+                .name()
+                .map_or_else(|| scene.index().to_string(), str::to_owned),
+            graph: SceneGraph::from_gltf_scene(&scene, document.materials().len()),
+        })
+        .collect()
+}
+
+// Scenes are picked by name, or by their index in the document.
+pub fn find_scene(scenes: &[GltfScene], name: &str) -> Result<usize> {
+    scenes
+        .iter()
+        .position(|scene| scene.name == name)
+        .or_else(|| {
+            name.parse::<usize>()
+                .ok()
+                .filter(|&index| index < scenes.len())
+        })
+        .with_context(|| format!("Unknown scene: {name}"))
+}
+
+// `VULKANOX_SCENE` is overridden by `--scene <index|name>`. Without either the document's default
+// scene is shown.
+pub fn scene_selection_from_args(args: impl IntoIterator<Item = String>) -> Result<Option<String>> {
+    let mut selection = std::env::var("VULKANOX_SCENE").ok();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--scene" {
+            selection = Some(args.next().context("--scene needs an index or name")?);
+        }
+    }
+    Ok(selection)
+}
+
+// The merged scene mesh keeps every primitive's indices in one buffer view and its positions in
+// another, so accessor offsets are offsets into the merged mesh. Scene materials are the
+// document's, followed by the default for primitives without one.
+fn mesh_primitives(mesh: &gltf::Mesh, material_count: usize) -> Vec<ScenePrimitive> {
+    mesh.primitives()
+        .filter_map(|primitive| {
+            let indices = primitive.indices()?;
+            let positions = primitive.get(&gltf::Semantic::Positions)?;
+            Some(ScenePrimitive {
+                mesh: MeshAllocation {
+                    first_index: (indices.offset() / std::mem::size_of::<u16>()) as u32,
+                    index_count: indices.count() as u32,
+                    vertex_offset: (positions.offset() / std::mem::size_of::<Vertex>()) as i32,
+                },
+                material: primitive.material().index().unwrap_or(material_count) as u32,
+            })
+        })
+        .collect()
+}
+
+fn mesh_bounds(mesh: &gltf::Mesh) -> Option<Aabb> {
+    mesh.primitives()
+        .map(|primitive| {
+            let bounds = primitive.bounding_box();
+            Aabb {
+                min: bounds.min.into(),
+                max: bounds.max.into(),
+            }
+        })
+        .reduce(|bounds, primitive_bounds| bounds.union(&primitive_bounds))
+}
@@ -0,0 +1,342 @@
+use std::f32::consts::TAU;
+
+use anyhow::{bail, ensure, Context, Result};
+use gltf::mesh::Mode;
+use nalgebra::{Point3, Vector3};
+
+use crate::embedded_assets::{import_scene, SCENE_PATH};
+use crate::light::DirectionalLight;
+use crate::material::{check_restart_index, primitive_topology, restart_runs, triangulate_fan};
+use crate::scene_graph::Aabb;
+use crate::vulkan_device::{scene_mesh_data, Vertex};
+
+const LEAF_TRIANGLES: usize = 4;
+
+pub type Triangle = [Point3<f32>; 3];
+
+// The light baked into lightmaps and probes.
+#[derive(Clone, Copy, Debug)]
+pub struct BakeLighting {
+    pub sun: DirectionalLight,
+    // The radiance of rays that leave the scene.
+    pub sky_color: [f32; 3],
+    // Adds one bounce of sunlight off the surfaces the rays hit.
+    pub bounce: bool,
+}
+
+impl Default for BakeLighting {
+    fn default() -> Self {
+        Self {
+            sun: DirectionalLight::default(),
+            sky_color: [0.3, 0.35, 0.4],
+            bounce: true,
+        }
+    }
+}
+
+// The default scene's triangles, in the order the GPU numbers the primitives of its draw.
+pub fn load_scene_triangles() -> Result<Vec<Triangle>> {
+    let (document, buffers, _) = import_scene(SCENE_PATH)?;
+    let buffer = &buffers.first().context("The scene has no buffers")?.0;
+    let (vertices, indices) = scene_mesh_data(&document, buffer);
+    let mode = document
+        .meshes()
+        .next()
+        .and_then(|mesh| mesh.primitives().next())
+        .map_or(Mode::Triangles, |primitive| primitive.mode());
+    let triangles = scene_triangles(vertices, indices, mode)?;
+    ensure!(!triangles.is_empty(), "The scene has no triangles to bake");
+    Ok(triangles)
+}
+
+fn scene_triangles(vertices: &[Vertex], indices: &[u16], mode: Mode) -> Result<Vec<Triangle>> {
+    let triangle_indices = match mode {
+        Mode::Triangles => indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect::<Vec<_>>(),
+        Mode::TriangleFan => triangulate_fan(indices)
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect(),
+        // Every other triangle of a strip is flipped to keep the winding.
+        Mode::TriangleStrip => restart_runs(indices)
+            .flat_map(|strip| {
+                strip.windows(3).enumerate().map(|(index, triangle)| {
+                    if index % 2 == 0 {
+                        [triangle[0], triangle[1], triangle[2]]
+                    } else {
+                        [triangle[1], triangle[0], triangle[2]]
+                    }
+                })
+            })
+            .collect(),
+        mode => bail!("Baking needs triangles, the scene is drawn as {mode:?}"),
+    };
+    check_restart_index(primitive_topology(mode), vertices.len())?;
+
+    triangle_indices
+        .into_iter()
+        .map(|triangle| {
+            let mut positions = [Point3::origin(); 3];
+            for (position, index) in positions.iter_mut().zip(triangle) {
+                let vertex = vertices
+                    .get(index as usize)
+                    .with_context(|| format!("Index {index} is past the scene's vertices"))?;
+                *position = Point3::from(vertex.position());
+            }
+            Ok(positions)
+        })
+        .collect()
+}
+
+// Interior nodes have no triangles and their left child right after them.
+struct BvhNode {
+    bounds: Aabb,
+    start: usize,
+    count: usize,
+    right: usize,
+}
+
+// Casts rays against the static scene on the CPU for baking, through a bounding volume hierarchy.
+// Triangles are hit from either side.
+pub struct SceneTracer {
+    triangles: Vec<Triangle>,
+    nodes: Vec<BvhNode>,
+    order: Vec<usize>,
+    // How far rays start off the surface, relative to the scene's size.
+    bias: f32,
+}
+
+impl SceneTracer {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        let mut tracer = Self {
+            order: (0..triangles.len()).collect(),
+            triangles,
+            nodes: Vec::new(),
+            bias: 0.0,
+        };
+        tracer.build(0, tracer.triangles.len());
+        let bounds = tracer.bounds();
+        tracer.bias = (bounds.max - bounds.min).norm().max(f32::EPSILON) * 1e-4;
+        tracer
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+
+    // The distance to and index of the closest triangle along the ray.
+    pub fn intersect(
+        &self,
+        origin: &Point3<f32>,
+        direction: &Vector3<f32>,
+    ) -> Option<(f32, usize)> {
+        let inverse_direction = direction.map(|component| 1.0 / component);
+        let mut closest = None;
+        let mut max_distance = f32::INFINITY;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !intersects_bounds(&node.bounds, origin, &inverse_direction, max_distance) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.right);
+                stack.push(index + 1);
+                continue;
+            }
+            for &triangle in &self.order[node.start..node.start + node.count] {
+                if let Some(distance) =
+                    intersect_triangle(&self.triangles[triangle], origin, direction)
+                {
+                    if distance < max_distance {
+                        max_distance = distance;
+                        closest = Some((distance, triangle));
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    // Sunlight on a surface, without the 1/π the scene shader leaves out too.
+    pub fn sun(
+        &self,
+        lighting: &BakeLighting,
+        position: &Point3<f32>,
+        normal: &Vector3<f32>,
+    ) -> Vector3<f32> {
+        let direction = lighting.sun.direction.normalize();
+        let cosine = normal.dot(&direction);
+        if cosine <= 0.0
+            || self
+                .intersect(&(position + normal * self.bias), &direction)
+                .is_some()
+        {
+            return Vector3::zeros();
+        }
+        Vector3::from(lighting.sun.color) * cosine
+    }
+
+    // The light arriving at `position` from `direction`: the sky where the ray leaves the scene,
+    // and the sunlight reflected by the surface it hits otherwise.
+    pub fn incoming_radiance(
+        &self,
+        lighting: &BakeLighting,
+        position: &Point3<f32>,
+        direction: &Vector3<f32>,
+    ) -> Vector3<f32> {
+        let origin = position + direction * self.bias;
+        match self.intersect(&origin, direction) {
+            None => Vector3::from(lighting.sky_color),
+            Some((distance, triangle)) if lighting.bounce => {
+                let [a, b, c] = self.triangles[triangle];
+                let mut hit_normal = (b - a).cross(&(c - a)).normalize();
+                if hit_normal.dot(direction) > 0.0 {
+                    hit_normal = -hit_normal;
+                }
+                // The vertex position doubles as the albedo, like in the scene shader.
+                let hit = origin + direction * distance;
+                let albedo = hit.coords.map(|channel| channel.clamp(0.0, 1.0));
+                albedo.component_mul(&self.sun(lighting, &hit, &hit_normal))
+            }
+            Some(_) => Vector3::zeros(),
+        }
+    }
+
+    // Splits at the median along the longest axis of the triangles' centroids.
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let triangles = &self.triangles;
+        let index = self.nodes.len();
+        let triangle_bounds = |triangle: &Triangle| {
+            Aabb::from(triangle[0])
+                .union(&Aabb::from(triangle[1]))
+                .union(&Aabb::from(triangle[2]))
+        };
+        let bounds = self.order[start + 1..end].iter().fold(
+            triangle_bounds(&triangles[self.order[start]]),
+            |bounds, &triangle| bounds.union(&triangle_bounds(&triangles[triangle])),
+        );
+        self.nodes.push(BvhNode {
+            bounds,
+            start,
+            count: end - start,
+            right: 0,
+        });
+        if end - start <= LEAF_TRIANGLES {
+            return index;
+        }
+
+        let centroid = |triangle: usize| {
+            let [a, b, c] = triangles[triangle];
+            (a.coords + b.coords + c.coords) / 3.0
+        };
+        let centroid_bounds = self.order[start..end]
+            .iter()
+            .map(|&triangle| Aabb::from(Point3::from(centroid(triangle))))
+            .reduce(|bounds, centroid| bounds.union(&centroid))
+            .unwrap();
+        let axis = (centroid_bounds.max - centroid_bounds.min).imax();
+        if centroid_bounds.max[axis] <= centroid_bounds.min[axis] {
+            return index;
+        }
+
+        let middle = (start + end) / 2;
+        self.order[start..end].select_nth_unstable_by(middle - start, |&a, &b| {
+            centroid(a)[axis].total_cmp(&centroid(b)[axis])
+        });
+        self.nodes[index].count = 0;
+        self.build(start, middle);
+        self.nodes[index].right = self.build(middle, end);
+        index
+    }
+}
+
+fn intersects_bounds(
+    bounds: &Aabb,
+    origin: &Point3<f32>,
+    inverse_direction: &Vector3<f32>,
+    max_distance: f32,
+) -> bool {
+    let mut near = 0.0f32;
+    let mut far = max_distance;
+    for axis in 0..3 {
+        let first = (bounds.min[axis] - origin[axis]) * inverse_direction[axis];
+        let second = (bounds.max[axis] - origin[axis]) * inverse_direction[axis];
+        near = near.max(first.min(second));
+        far = far.min(first.max(second));
+    }
+    near <= far
+}
+
+// Möller–Trumbore.
+fn intersect_triangle(
+    triangle: &Triangle,
+    origin: &Point3<f32>,
+    direction: &Vector3<f32>,
+) -> Option<f32> {
+    let [a, b, c] = triangle;
+    let (edge1, edge2) = (b - a, c - a);
+    let p = direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let offset = origin - a;
+    let u = offset.dot(&p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = offset.cross(&edge1);
+    let v = direction.dot(&q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(&q) * inverse_determinant;
+    (distance > 0.0).then_some(distance)
+}
+
+pub fn cosine_sample(normal: &Vector3<f32>, random: &mut Random) -> Vector3<f32> {
+    let helper = if normal.x.abs() > 0.9 {
+        Vector3::y()
+    } else {
+        Vector3::x()
+    };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+    let (radius, angle) = (random.next_f32().sqrt(), random.next_f32() * TAU);
+    (tangent * (radius * angle.cos())
+        + bitangent * (radius * angle.sin())
+        + normal * (1.0 - radius * radius).max(0.0).sqrt())
+    .normalize()
+}
+
+pub fn sphere_sample(random: &mut Random) -> Vector3<f32> {
+    let z = random.next_f32() * 2.0 - 1.0;
+    let radius = (1.0 - z * z).max(0.0).sqrt();
+    let angle = random.next_f32() * TAU;
+    Vector3::new(radius * angle.cos(), radius * angle.sin(), z)
+}
+
+// Xorshift, seeded per texel or probe so bakes are reproducible.
+pub struct Random(u32);
+
+impl Random {
+    // Any seed works, zero included.
+    pub fn new(seed: u32) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9) | 1)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
@@ -0,0 +1,186 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use nalgebra::{Point3, Vector3};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST, FLOAT, INT};
+
+use crate::light::DirectionalLight;
+use crate::vulkan_device::DebugView;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ScriptCommand {
+    SetEnvironmentIntensity(f32),
+    SetSun(DirectionalLight),
+    SetWireframe(bool),
+    SetGrid(bool),
+    SetDebugView(DebugView),
+    DrawLine(Point3<f32>, Point3<f32>, [f32; 4]),
+    DrawSphere(Point3<f32>, f32, [f32; 4]),
+    SelectScene(usize),
+}
+
+pub struct ScriptHost {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    commands: Rc<RefCell<Vec<ScriptCommand>>>,
+}
+
+impl ScriptHost {
+    pub fn find_in(directory: impl AsRef<Path>) -> Option<PathBuf> {
+        std::fs::read_dir(directory)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .find(|path| path.extension().and_then(|extension| extension.to_str()) == Some("rhai"))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let commands = Rc::new(RefCell::new(Vec::new()));
+        let engine = create_engine(&commands);
+
+        let ast = engine
+            .compile_file(path.as_ref().to_path_buf())
+            .map_err(|error| anyhow!("Failed to compile {}: {error}", path.as_ref().display()))?;
+
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|error| anyhow!("Failed to run {}: {error}", path.as_ref().display()))?;
+
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            commands,
+        })
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        self.call("on_start", ())
+    }
+
+    pub fn update(&mut self, time: f32, delta_time: f32) -> Result<()> {
+        self.call("on_update", (time as FLOAT, delta_time as FLOAT))
+    }
+
+    pub fn drain_commands(&self) -> Vec<ScriptCommand> {
+        self.commands.borrow_mut().drain(..).collect()
+    }
+
+    fn call(&mut self, name: &str, arguments: impl rhai::FuncArgs) -> Result<()> {
+        if !self.ast.iter_functions().any(|function| function.name == name) {
+            return Ok(());
+        }
+
+        // Whatever the callback returns is ignored.
+        self.engine
+            .call_fn::<Dynamic>(&mut self.scope, &self.ast, name, arguments)
+            .map(drop)
+            .map_err(|error| anyhow!("Script callback {name} failed: {error}"))
+    }
+}
+
+fn create_engine(commands: &Rc<RefCell<Vec<ScriptCommand>>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let queue = Rc::clone(commands);
+    engine.register_fn("set_environment_intensity", move |intensity: FLOAT| {
+        queue
+            .borrow_mut()
+            .push(ScriptCommand::SetEnvironmentIntensity(intensity as f32));
+    });
+
+    let queue = Rc::clone(commands);
+    engine.register_fn(
+        "set_sun",
+        move |direction: Array, color: Array| -> Result<(), Box<EvalAltResult>> {
+            queue.borrow_mut().push(ScriptCommand::SetSun(DirectionalLight {
+                direction: Vector3::from(components::<3>(&direction)?).normalize(),
+                color: components(&color)?,
+            }));
+            Ok(())
+        },
+    );
+
+    let queue = Rc::clone(commands);
+    engine.register_fn("set_wireframe", move |enabled: bool| {
+        queue.borrow_mut().push(ScriptCommand::SetWireframe(enabled));
+    });
+
+    let queue = Rc::clone(commands);
+    engine.register_fn("set_grid", move |enabled: bool| {
+        queue.borrow_mut().push(ScriptCommand::SetGrid(enabled));
+    });
+
+    let queue = Rc::clone(commands);
+    engine.register_fn(
+        "set_debug_view",
+        move |debug_view: &str| -> Result<(), Box<EvalAltResult>> {
+            let debug_view = debug_view
+                .parse()
+                .map_err(|error: anyhow::Error| error.to_string())?;
+            queue
+                .borrow_mut()
+                .push(ScriptCommand::SetDebugView(debug_view));
+            Ok(())
+        },
+    );
+
+    let queue = Rc::clone(commands);
+    engine.register_fn(
+        "draw_line",
+        move |start: Array, end: Array, color: Array| -> Result<(), Box<EvalAltResult>> {
+            queue.borrow_mut().push(ScriptCommand::DrawLine(
+                Point3::from(components::<3>(&start)?),
+                Point3::from(components::<3>(&end)?),
+                components(&color)?,
+            ));
+            Ok(())
+        },
+    );
+
+    let queue = Rc::clone(commands);
+    engine.register_fn(
+        "draw_sphere",
+        move |center: Array, radius: FLOAT, color: Array| -> Result<(), Box<EvalAltResult>> {
+            queue.borrow_mut().push(ScriptCommand::DrawSphere(
+                Point3::from(components::<3>(&center)?),
+                radius as f32,
+                components(&color)?,
+            ));
+            Ok(())
+        },
+    );
+
+    let queue = Rc::clone(commands);
+    engine.register_fn(
+        "select_scene",
+        move |index: INT| -> Result<(), Box<EvalAltResult>> {
+            let index =
+                usize::try_from(index).map_err(|_| format!("Invalid scene index {index}"))?;
+            queue.borrow_mut().push(ScriptCommand::SelectScene(index));
+            Ok(())
+        },
+    );
+
+    engine
+}
+
+fn components<const N: usize>(array: &Array) -> Result<[f32; N], Box<EvalAltResult>> {
+    if array.len() != N {
+        return Err(format!("Expected {N} components, got {}", array.len()).into());
+    }
+
+    let mut components = [0.0; N];
+    for (component, value) in components.iter_mut().zip(array) {
+        *component = value
+            .as_float()
+            .or_else(|_| value.as_int().map(|value| value as FLOAT))
+            .map_err(|type_name| format!("Expected a number, got {type_name}"))?
+            as f32;
+    }
+
+    Ok(components)
+}
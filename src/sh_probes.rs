@@ -0,0 +1,250 @@
+use anyhow::{ensure, Result};
+use nalgebra::{Point3, Vector3};
+use rayon::prelude::*;
+use tracing::info;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::DeviceSize;
+
+use crate::scene_graph::Aabb;
+use crate::scene_tracer::{load_scene_triangles, sphere_sample, BakeLighting, Random, SceneTracer};
+use crate::vulkan_device::VulkanDevice;
+
+const SH_COEFFICIENTS: usize = 9;
+// The clamped cosine's spherical harmonics per band, over π since the scene shader leaves that out.
+const BAND_CONVOLUTION: [f32; SH_COEFFICIENTS] = [
+    1.0,
+    2.0 / 3.0,
+    2.0 / 3.0,
+    2.0 / 3.0,
+    0.25,
+    0.25,
+    0.25,
+    0.25,
+    0.25,
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShProbeGridSettings {
+    // Probes along each axis of the scene's bounds.
+    pub resolution: [u32; 3],
+    // Sphere samples per probe.
+    pub samples: u32,
+    pub lighting: BakeLighting,
+}
+
+impl Default for ShProbeGridSettings {
+    fn default() -> Self {
+        Self {
+            resolution: [4; 3],
+            samples: 512,
+            lighting: BakeLighting::default(),
+        }
+    }
+}
+
+// Second-order spherical harmonics of the light arriving at a point, already convolved with the
+// clamped cosine, so evaluating them in a normal's direction gives the diffuse irradiance.
+#[derive(Clone, Copy, Debug)]
+pub struct ShIrradiance {
+    coefficients: [Vector3<f32>; SH_COEFFICIENTS],
+}
+
+impl ShIrradiance {
+    // A Monte Carlo projection of the radiance arriving from each direction.
+    pub fn project(
+        samples: u32,
+        random: &mut Random,
+        mut radiance: impl FnMut(&Vector3<f32>) -> Vector3<f32>,
+    ) -> Self {
+        let mut coefficients = [Vector3::zeros(); SH_COEFFICIENTS];
+        for _ in 0..samples {
+            let direction = sphere_sample(random);
+            let radiance = radiance(&direction);
+            for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(&direction)) {
+                *coefficient += radiance * basis;
+            }
+        }
+
+        let weight = 4.0 * std::f32::consts::PI / samples.max(1) as f32;
+        for (coefficient, convolution) in coefficients.iter_mut().zip(BAND_CONVOLUTION) {
+            *coefficient *= weight * convolution;
+        }
+        Self { coefficients }
+    }
+}
+
+// The real spherical harmonics up to the second band, in the order the scene shader expects.
+fn sh_basis(direction: &Vector3<f32>) -> [f32; SH_COEFFICIENTS] {
+    let direction = direction.normalize();
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3.0 * z * z - 1.0),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+// Irradiance probes at the centers of a grid over the static scene, baked on the CPU. The scene
+// shader interpolates them where the lightmap doesn't apply, such as on dynamic meshes moving
+// through the scene, and falls back to the irradiance map without them.
+pub struct ShProbeGrid {
+    bounds: Aabb,
+    resolution: [u32; 3],
+    probes: Vec<ShIrradiance>,
+}
+
+impl ShProbeGrid {
+    pub fn bake(settings: &ShProbeGridSettings) -> Result<Self> {
+        ensure!(
+            settings.resolution.iter().all(|&count| count > 0),
+            "A probe grid needs at least one probe along each axis"
+        );
+        let tracer = SceneTracer::new(load_scene_triangles()?);
+        let [width, height, depth] = settings.resolution;
+        let probe_count = width * height * depth;
+        info!(
+            "Baking {width}x{height}x{depth} SH probes with {} samples each",
+            settings.samples
+        );
+
+        let mut grid = Self {
+            bounds: tracer.bounds(),
+            resolution: settings.resolution,
+            probes: Vec::new(),
+        };
+        grid.probes = (0..probe_count)
+            .into_par_iter()
+            .map(|index| {
+                let position = grid.probe_position(index);
+                let mut random = Random::new(index);
+                ShIrradiance::project(settings.samples, &mut random, |direction| {
+                    tracer.incoming_radiance(&settings.lighting, &position, direction)
+                })
+            })
+            .collect();
+        Ok(grid)
+    }
+
+    pub fn bounds(&self) -> &Aabb {
+        &self.bounds
+    }
+
+    pub fn resolution(&self) -> [u32; 3] {
+        self.resolution
+    }
+
+    // Probes are numbered x first, then y and z.
+    pub fn probe_position(&self, index: u32) -> Point3<f32> {
+        let [width, height, _] = self.resolution;
+        let cell = [
+            index % width,
+            index / width % height,
+            index / (width * height),
+        ];
+        Point3::from(Vector3::from_fn(|axis, _| {
+            let offset = (cell[axis] as f32 + 0.5) / self.resolution[axis] as f32;
+            self.bounds.min[axis] + (self.bounds.max[axis] - self.bounds.min[axis]) * offset
+        }))
+    }
+
+    pub fn upload(&self, vulkan_device: &VulkanDevice) -> Result<ShProbeBuffers> {
+        let [width, height, depth] = self.resolution;
+        ShProbeBuffers::new(
+            vulkan_device,
+            ShProbeGridUniform {
+                bounds_minimum: self.bounds.min.to_homogeneous().into(),
+                bounds_maximum: self.bounds.max.to_homogeneous().into(),
+                resolution: [width, height, depth, 0],
+            },
+            self.probes
+                .iter()
+                .flat_map(|probe| probe.coefficients)
+                .map(|coefficient| coefficient.push(0.0).into())
+                .collect(),
+        )
+    }
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct ShProbeGridUniform {
+    bounds_minimum: [f32; 4],
+    bounds_maximum: [f32; 4],
+    // A zero resolution turns the probes off.
+    resolution: [u32; 4],
+}
+
+// A probe grid as bound in the scene's lighting set.
+pub struct ShProbeBuffers {
+    grid: Subbuffer<ShProbeGridUniform>,
+    coefficients: Subbuffer<[[f32; 4]]>,
+}
+
+impl ShProbeBuffers {
+    // Bound while no probes are baked.
+    pub fn empty(vulkan_device: &VulkanDevice) -> Result<Self> {
+        Self::new(
+            vulkan_device,
+            ShProbeGridUniform {
+                bounds_minimum: [0.0; 4],
+                bounds_maximum: [0.0; 4],
+                resolution: [0; 4],
+            },
+            vec![[0.0; 4]],
+        )
+    }
+
+    fn new(
+        vulkan_device: &VulkanDevice,
+        grid: ShProbeGridUniform,
+        coefficients: Vec<[f32; 4]>,
+    ) -> Result<Self> {
+        let grid = Buffer::from_data(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            grid,
+        )?;
+        let coefficients = Buffer::from_iter(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            coefficients,
+        )?;
+        Ok(Self { grid, coefficients })
+    }
+
+    // At the bindings the scene shader expects them in the lighting set.
+    pub fn descriptor_writes(&self) -> [WriteDescriptorSet; 2] {
+        [
+            WriteDescriptorSet::buffer(8, self.grid.clone()),
+            WriteDescriptorSet::buffer(9, self.coefficients.clone()),
+        ]
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        self.grid.size() + self.coefficients.size()
+    }
+}
@@ -0,0 +1,81 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::pipeline::graphics::fragment_shading_rate::FragmentShadingRateCombinerOp;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShadingRate {
+    width: u32,
+    height: u32,
+}
+
+impl ShadingRate {
+    pub const FULL: Self = Self {
+        width: 1,
+        height: 1,
+    };
+
+    pub fn fragment_size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+
+    pub fn record<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) -> Result<()> {
+        builder.set_fragment_shading_rate(
+            self.fragment_size(),
+            [
+                FragmentShadingRateCombinerOp::Keep,
+                FragmentShadingRateCombinerOp::Keep,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl Default for ShadingRate {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+impl FromStr for ShadingRate {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let Some((width, height)) = value.split_once('x') else {
+            bail!("Shading rate must be formatted as WIDTHxHEIGHT: {value}");
+        };
+        let (width, height) = (width.parse()?, height.parse()?);
+        if ![1, 2, 4].contains(&width) || ![1, 2, 4].contains(&height) {
+            bail!("Unsupported shading rate: {value}");
+        }
+        Ok(Self { width, height })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadingRateSettings {
+    pub near_rate: ShadingRate,
+    pub far_rate: ShadingRate,
+    pub far_distance: f32,
+}
+
+impl Default for ShadingRateSettings {
+    fn default() -> Self {
+        Self {
+            near_rate: ShadingRate::FULL,
+            far_rate: ShadingRate::FULL,
+            far_distance: 20.0,
+        }
+    }
+}
+
+impl ShadingRateSettings {
+    pub fn rate_at(&self, distance: f32) -> ShadingRate {
+        if distance >= self.far_distance {
+            self.far_rate
+        } else {
+            self.near_rate
+        }
+    }
+}
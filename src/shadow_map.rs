@@ -0,0 +1,477 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use nalgebra::{Isometry3, Matrix4, Orthographic3, Point3, Vector3};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageUsage, SampleCount};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::depth_stencil::{DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::{
+    ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+use vulkano::DeviceSize;
+
+use crate::compute;
+use crate::gpu_scene::{self, SceneObjects};
+use crate::light::DirectionalLight;
+use crate::material::input_assembly_state;
+use crate::memory::image_size;
+use crate::vulkan_device::{Vertex, VulkanDevice, DEPTH_FORMAT};
+
+mod shadow_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 position;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                layout(push_constant) uniform ShadowCasterParameters {
+                    mat4 lightViewProjection;
+                } parameters;
+
+                // The draw's first instance selects its object.
+                void main() {
+                    mat4 transform = scene.objects[gl_InstanceIndex].transform;
+                    gl_Position = parameters.lightViewProjection * transform * vec4(position, 1.0);
+                }
+            ",
+    }
+}
+
+mod shadow_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0, rgba8) uniform image2D visibility;
+                layout(set = 0, binding = 1) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+                layout(set = 0, binding = 2) uniform sampler2D depthTexture;
+                layout(set = 0, binding = 3) uniform sampler2D shadowMap;
+
+                layout(push_constant) uniform ShadowParameters {
+                    mat4 lightViewProjection;
+                    // Penumbra width in shadow map coordinates per unit of shadow map depth between
+                    // the blocker and the receiver.
+                    float penumbraScale;
+                    float depthBias;
+                    uint blockerSampleCount;
+                    uint filterSampleCount;
+                    uint soft;
+                } parameters;
+
+                const float PI = 3.14159265359;
+                const float GOLDEN_ANGLE = 2.39996323;
+                const float MAX_SEARCH_TEXELS = 32.0;
+
+                float interleavedGradientNoise(vec2 pixel) {
+                    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+                }
+
+                // Evenly spread points on the unit disk, rotated per pixel.
+                vec2 vogelDisk(uint index, uint count, float rotation) {
+                    float radius = sqrt((float(index) + 0.5) / float(count));
+                    float angle = float(index) * GOLDEN_ANGLE + rotation;
+                    return radius * vec2(cos(angle), sin(angle));
+                }
+
+                // The average depth of the texels closer to the light than the receiver, or -1 where
+                // nothing blocks it.
+                float averageBlockerDepth(vec2 uv, float receiver, float radius, float rotation) {
+                    float sum = 0.0;
+                    uint count = 0u;
+                    for (uint i = 0u; i < parameters.blockerSampleCount; i++) {
+                        vec2 offset = vogelDisk(i, parameters.blockerSampleCount, rotation) * radius;
+                        float depth = textureLod(shadowMap, uv + offset, 0.0).r;
+                        if (depth < receiver - parameters.depthBias) {
+                            sum += depth;
+                            count++;
+                        }
+                    }
+                    return count == 0u ? -1.0 : sum / float(count);
+                }
+
+                float percentageCloser(vec2 uv, float receiver, float radius, float bias, float rotation) {
+                    float lit = 0.0;
+                    for (uint i = 0u; i < parameters.filterSampleCount; i++) {
+                        vec2 offset = vogelDisk(i, parameters.filterSampleCount, rotation) * radius;
+                        lit += step(receiver - bias, textureLod(shadowMap, uv + offset, 0.0).r);
+                    }
+                    return lit / float(max(parameters.filterSampleCount, 1u));
+                }
+
+                void main() {
+                    ivec2 size = imageSize(visibility);
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    if (any(greaterThanEqual(pixel, size))) {
+                        return;
+                    }
+
+                    vec4 current = imageLoad(visibility, pixel);
+                    float depth = texelFetch(depthTexture, pixel, 0).r;
+                    if (depth >= 1.0 || current.g == 0.0) {
+                        return;
+                    }
+
+                    vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+                    vec4 viewPosition = uniforms.inverse_projection * vec4(uv * 2.0 - 1.0, depth, 1.0);
+                    vec4 worldPosition = inverse(uniforms.view) * vec4(viewPosition.xyz / viewPosition.w, 1.0);
+                    vec4 lightPosition = parameters.lightViewProjection * worldPosition;
+                    vec3 shadowCoord = lightPosition.xyz / lightPosition.w;
+                    vec2 shadowUv = shadowCoord.xy * 0.5 + 0.5;
+                    // Outside the shadow map counts as lit.
+                    if (any(lessThan(shadowUv, vec2(0.0))) || any(greaterThan(shadowUv, vec2(1.0))) || shadowCoord.z >= 1.0) {
+                        return;
+                    }
+
+                    float receiver = shadowCoord.z;
+                    float texel = 1.0 / float(textureSize(shadowMap, 0).x);
+                    float rotation = 2.0 * PI * interleavedGradientNoise(vec2(pixel));
+
+                    // Plain PCF filters over a fixed footprint.
+                    float radius = 1.5 * texel;
+                    if (parameters.soft != 0u) {
+                        // Blockers can be anywhere between the light and the receiver, so the search
+                        // covers the widest penumbra they could cast.
+                        float searchRadius = clamp(receiver * parameters.penumbraScale, texel, MAX_SEARCH_TEXELS * texel);
+                        float blocker = averageBlockerDepth(shadowUv, receiver, searchRadius, rotation);
+                        if (blocker < 0.0) {
+                            return;
+                        }
+                        radius = max((receiver - blocker) * parameters.penumbraScale, texel);
+                    }
+
+                    // Wider footprints reach further across sloped receivers, so they need more bias.
+                    float bias = parameters.depthBias * (1.0 + radius / texel);
+                    float shadow = percentageCloser(shadowUv, receiver, radius, bias, rotation);
+                    imageStore(visibility, pixel, vec4(current.r, current.g * shadow, current.ba));
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShadowQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ShadowQuality {
+    // Samples for the blocker search and the filter.
+    fn sample_counts(self) -> (u32, u32) {
+        match self {
+            ShadowQuality::Low => (8, 12),
+            ShadowQuality::Medium => (16, 24),
+            ShadowQuality::High => (32, 64),
+        }
+    }
+}
+
+impl FromStr for ShadowQuality {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "low" => ShadowQuality::Low,
+            "medium" => ShadowQuality::Medium,
+            "high" => ShadowQuality::High,
+            _ => bail!("Unknown shadow quality {value}, expected low, medium or high"),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowMapSettings {
+    pub enabled: bool,
+    pub resolution: u32,
+    // World-space distance from the scene center to the edges of the shadow map.
+    pub radius: f32,
+    // Percentage-closer soft shadows, which widen the penumbra with the distance to the blocker.
+    // Plain PCF blurs every shadow edge the same.
+    pub soft: bool,
+    // The sun's angular diameter in radians, which sets how fast penumbrae widen.
+    pub light_angle: f32,
+    // In shadow map depth, which spans four times the radius.
+    pub depth_bias: f32,
+    pub quality: ShadowQuality,
+}
+
+impl Default for ShadowMapSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resolution: 2048,
+            radius: 10.0,
+            soft: true,
+            light_angle: 0.03,
+            depth_bias: 0.0002,
+            quality: ShadowQuality::default(),
+        }
+    }
+}
+
+impl ShadowMapSettings {
+    // An orthographic view of the scene from the sun, with Vulkan's depth range.
+    pub fn light_view_projection(
+        &self,
+        center: &Point3<f32>,
+        sun: &DirectionalLight,
+    ) -> Matrix4<f32> {
+        let direction = sun.direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            Vector3::z()
+        } else {
+            Vector3::y()
+        };
+        let eye = center + direction * self.radius * 2.0;
+        let view = Isometry3::look_at_rh(&eye, center, &up).to_homogeneous();
+        let projection = Orthographic3::new(
+            -self.radius,
+            self.radius,
+            -self.radius,
+            self.radius,
+            0.0,
+            self.radius * 4.0,
+        )
+        .into_inner();
+        // Maps the GL-style depth from -1..1 to 0..1.
+        let depth_correction = Matrix4::new_translation(&Vector3::new(0.0, 0.0, 0.5))
+            * Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, 1.0, 0.5));
+        depth_correction * projection * view
+    }
+}
+
+// The depth of the static scene as seen from the sun.
+pub struct ShadowMap {
+    depth_view: Arc<ImageView>,
+    resolution: u32,
+}
+
+impl ShadowMap {
+    pub fn new(vulkan_device: &VulkanDevice, resolution: u32) -> Result<Self> {
+        Ok(Self {
+            depth_view: vulkan_device.create_attachment(
+                DEPTH_FORMAT,
+                [resolution; 2],
+                ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::SAMPLED,
+                SampleCount::Sample1,
+            )?,
+            resolution,
+        })
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        image_size(self.depth_view.image())
+    }
+}
+
+// The scene's objects that fall inside the light's view, as drawn into the shadow map.
+pub struct ShadowCasters<'a> {
+    pub light_view_projection: Matrix4<f32>,
+    pub objects: SceneObjects<'a>,
+}
+
+pub struct ShadowViews<'a> {
+    pub visibility: &'a Arc<ImageView>,
+    pub depth: &'a Arc<ImageView>,
+}
+
+pub struct ShadowMapPass {
+    caster_pipeline: Arc<GraphicsPipeline>,
+    sampling_pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl ShadowMapPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        scene_topology: PrimitiveTopology,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let vertex_shader = shadow_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+        let vertex_input_state = Vertex::per_vertex().definition(&vertex_shader).unwrap();
+        // Depth only, so there's no fragment shader.
+        let stages = [PipelineShaderStageCreateInfo::new(vertex_shader)];
+        let layout = gpu_scene::create_pipeline_layout(device, &stages, gpu_scene_set_layout)?;
+        let subpass = PipelineRenderingCreateInfo {
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+        let caster_pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(input_assembly_state(scene_topology)),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState {
+                    cull_mode: CullMode::None,
+                    ..Default::default()
+                }),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        let sampling_pipeline = compute::create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            shadow_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            caster_pipeline,
+            sampling_pipeline,
+            sampler,
+        })
+    }
+
+    // Renders the shadow map, then darkens the sun visibility in the visibility image's green
+    // channel where it's shadowed. Only the scene's objects cast shadows, each with its own
+    // transform.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        shadow_map: &ShadowMap,
+        casters: &ShadowCasters,
+        views: &ShadowViews,
+        settings: &ShadowMapSettings,
+    ) -> Result<()> {
+        let light_view_projection = casters.light_view_projection;
+        let viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: [shadow_map.resolution as f32; 2],
+            depth_range: 0.0..=1.0,
+        };
+
+        builder
+            .begin_rendering(RenderingInfo {
+                depth_attachment: Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::Store,
+                    clear_value: Some(1.0f32.into()),
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(&shadow_map.depth_view))
+                }),
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport].into_iter().collect())?
+            .bind_pipeline_graphics(Arc::clone(&self.caster_pipeline))?
+            .bind_vertex_buffers(0, vulkan_device.vertex_buffer().clone())?
+            .bind_index_buffer(vulkan_device.index_buffer().clone())?
+            .push_constants(
+                Arc::clone(self.caster_pipeline.layout()),
+                0,
+                shadow_vs::ShadowCasterParameters {
+                    lightViewProjection: light_view_projection.into(),
+                },
+            )?;
+        casters
+            .objects
+            .record(builder, self.caster_pipeline.layout())?;
+        builder.end_rendering()?;
+
+        // Depth spans twice the map's width, and penumbrae widen by twice the tangent of half the
+        // sun's angle per unit of distance.
+        let penumbra_scale = 4.0 * (settings.light_angle * 0.5).tan();
+        let (blocker_sample_count, filter_sample_count) = settings.quality.sample_counts();
+        let [width, height, _] = views.visibility.image().extent();
+        vulkan_device
+            .bind_compute(
+                builder,
+                &self.sampling_pipeline,
+                [
+                    WriteDescriptorSet::image_view(0, Arc::clone(views.visibility)),
+                    WriteDescriptorSet::buffer(1, vulkan_device.uniform_buffer().clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        2,
+                        Arc::clone(views.depth),
+                        Arc::clone(&self.sampler),
+                    ),
+                    WriteDescriptorSet::image_view_sampler(
+                        3,
+                        Arc::clone(&shadow_map.depth_view),
+                        Arc::clone(&self.sampler),
+                    ),
+                ],
+            )?
+            .push_constants(
+                Arc::clone(self.sampling_pipeline.layout()),
+                0,
+                shadow_cs::ShadowParameters {
+                    lightViewProjection: light_view_projection.into(),
+                    penumbraScale: penumbra_scale,
+                    depthBias: settings.depth_bias,
+                    blockerSampleCount: blocker_sample_count,
+                    filterSampleCount: filter_sample_count,
+                    soft: settings.soft as u32,
+                },
+            )?;
+        unsafe { builder.dispatch([width.div_ceil(8), height.div_ceil(8), 1]) }?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+
+#[cfg(feature = "physics")]
+use crate::physics::{PhysicsSnapshot, PhysicsWorld};
+use crate::scripting::{ScriptCommand, ScriptHost};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SimulationSettings {
+    // Physics steps once per simulation step, so its world is best created with the same one.
+    pub timestep: f32,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            timestep: 1.0 / 60.0,
+        }
+    }
+}
+
+// What the simulation thread runs.
+pub struct SimulationSetup {
+    pub script_path: Option<PathBuf>,
+    #[cfg(feature = "physics")]
+    pub physics: Option<PhysicsWorld>,
+}
+
+// The state of the simulated world after a step, which renderers can pick up on their own
+// schedule.
+#[derive(Clone, Debug)]
+pub struct WorldSnapshot {
+    #[cfg(feature = "physics")]
+    pub physics: Option<PhysicsSnapshot>,
+    // The script's drawing during the step, repeated every frame until the next one.
+    pub debug_draw: Vec<ScriptCommand>,
+}
+
+impl WorldSnapshot {
+    // Blends towards the next step's snapshot. Drawing isn't blended and comes from the later one.
+    pub fn interpolate(&self, next: &Self, alpha: f32) -> Self {
+        // Only physics poses are blended.
+        #[cfg(not(feature = "physics"))]
+        let _ = alpha;
+        Self {
+            #[cfg(feature = "physics")]
+            physics: match (&self.physics, &next.physics) {
+                (Some(physics), Some(next_physics)) => {
+                    Some(physics.interpolate(next_physics, alpha))
+                }
+                _ => next.physics.clone(),
+            },
+            debug_draw: next.debug_draw.clone(),
+        }
+    }
+}
+
+// The two latest steps; the simulation overwrites the older one while frames blend between them.
+struct Snapshots {
+    previous: WorldSnapshot,
+    current: WorldSnapshot,
+    published: Instant,
+}
+
+// Runs the script and physics at a fixed timestep on a thread of their own, so neither a slow
+// frame nor a slow step holds up the other. Frames show the world a step behind the simulation,
+// blended between the two latest snapshots by how long ago the newer one was published, which
+// keeps motion smooth at any frame rate.
+pub struct SimulationThread {
+    settings: SimulationSettings,
+    snapshots: Arc<Mutex<Snapshots>>,
+    // Everything the script issued besides drawing, which has to be applied exactly once.
+    commands: Receiver<ScriptCommand>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl SimulationThread {
+    // The script is loaded on the thread, since it can't move between threads once loaded. Its
+    // loading and `on_start` errors come back from here.
+    pub fn spawn(settings: SimulationSettings, setup: SimulationSetup) -> Result<Self> {
+        let initial = WorldSnapshot {
+            #[cfg(feature = "physics")]
+            physics: setup.physics.as_ref().map(PhysicsWorld::snapshot),
+            debug_draw: Vec::new(),
+        };
+        let snapshots = Arc::new(Mutex::new(Snapshots {
+            previous: initial.clone(),
+            current: initial,
+            published: Instant::now(),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (command_sender, commands) = mpsc::channel();
+        let (started_sender, started) = mpsc::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("simulation".to_owned())
+            .spawn({
+                let snapshots = Arc::clone(&snapshots);
+                let stop = Arc::clone(&stop);
+                move || {
+                    let script = match setup.script_path.map(load_script).transpose() {
+                        Ok(script) => {
+                            let _ = started_sender.send(Ok(()));
+                            script
+                        }
+                        Err(error) => {
+                            let _ = started_sender.send(Err(error));
+                            return Ok(());
+                        }
+                    };
+                    Simulation {
+                        settings,
+                        script,
+                        #[cfg(feature = "physics")]
+                        physics: setup.physics,
+                        snapshots,
+                        commands: command_sender,
+                        stop,
+                    }
+                    .run()
+                }
+            })?;
+        started.recv()??;
+
+        Ok(Self {
+            settings,
+            snapshots,
+            commands,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    // The world as of now, and the commands the script has issued since the last call.
+    pub fn latest(&mut self) -> Result<(WorldSnapshot, Vec<ScriptCommand>)> {
+        if self
+            .thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+        {
+            return self.join();
+        }
+
+        let snapshots = self.snapshots.lock().unwrap();
+        let alpha = (snapshots.published.elapsed().as_secs_f32() / self.settings.timestep).min(1.0);
+        let snapshot = snapshots.previous.interpolate(&snapshots.current, alpha);
+        drop(snapshots);
+        Ok((snapshot, self.commands.try_iter().collect()))
+    }
+
+    // Only called once the thread has stopped, so it always ends in an error.
+    fn join<T>(&mut self) -> Result<T> {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(Err(error))) => Err(error),
+            Some(Err(_)) => bail!("The simulation thread panicked"),
+            _ => bail!("The simulation thread has stopped"),
+        }
+    }
+}
+
+impl Drop for SimulationThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn load_script(path: PathBuf) -> Result<ScriptHost> {
+    let mut script = ScriptHost::load(path)?;
+    script.start()?;
+    Ok(script)
+}
+
+struct Simulation {
+    settings: SimulationSettings,
+    script: Option<ScriptHost>,
+    #[cfg(feature = "physics")]
+    physics: Option<PhysicsWorld>,
+    snapshots: Arc<Mutex<Snapshots>>,
+    commands: Sender<ScriptCommand>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Simulation {
+    fn run(mut self) -> Result<()> {
+        let timestep = Duration::from_secs_f32(self.settings.timestep);
+        let start = Instant::now();
+        let mut step_count = 0u32;
+
+        while !self.stop.load(Ordering::Relaxed) {
+            let snapshot = self.step(step_count)?;
+            {
+                let mut snapshots = self.snapshots.lock().unwrap();
+                snapshots.previous = std::mem::replace(&mut snapshots.current, snapshot);
+                snapshots.published = Instant::now();
+            }
+            step_count += 1;
+
+            // Steps are scheduled from the start rather than from each other, so sleeping a little
+            // long now and then doesn't make the world run slow.
+            let next_step = start + timestep * step_count;
+            if let Some(wait) = next_step.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, step_count: u32) -> Result<WorldSnapshot> {
+        let time = step_count as f32 * self.settings.timestep;
+        let mut debug_draw = Vec::new();
+        if let Some(script) = &mut self.script {
+            script.update(time, self.settings.timestep)?;
+            for command in script.drain_commands() {
+                match command {
+                    ScriptCommand::DrawLine(..) | ScriptCommand::DrawSphere(..) => {
+                        debug_draw.push(command)
+                    }
+                    // The receiver only goes away with the thread's owner, which stops it anyway.
+                    _ => {
+                        let _ = self.commands.send(command);
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "physics")]
+        if let Some(physics) = &mut self.physics {
+            physics.update(self.settings.timestep);
+        }
+
+        Ok(WorldSnapshot {
+            #[cfg(feature = "physics")]
+            physics: self.physics.as_ref().map(PhysicsWorld::snapshot),
+            debug_draw,
+        })
+    }
+}
@@ -0,0 +1,246 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra::Vector3;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::Pipeline;
+
+use crate::light::DirectionalLight;
+use crate::vulkan_device::VulkanDevice;
+
+const PLANET_RADIUS: f32 = 6360.0;
+const ATMOSPHERE_RADIUS: f32 = 6460.0;
+const RAYLEIGH_SCALE_HEIGHT: f32 = 8.0;
+const MIE_SCALE_HEIGHT: f32 = 1.2;
+const MIE_EXTINCTION_RATIO: f32 = 1.11;
+const TRANSMITTANCE_STEP_COUNT: u32 = 32;
+
+mod sky_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0, rgba16f) uniform writeonly image2DArray sky;
+
+                layout(push_constant) uniform SkyParameters {
+                    vec3 sunDirection;
+                    float sunIntensity;
+                    vec3 rayleighScattering;
+                    float mieScattering;
+                    float mieAnisotropy;
+                    float sunAngularRadius;
+                } parameters;
+
+                const float PI = 3.14159265359;
+                const float PLANET_RADIUS = 6360.0;
+                const float ATMOSPHERE_RADIUS = 6460.0;
+                const float RAYLEIGH_SCALE_HEIGHT = 8.0;
+                const float MIE_SCALE_HEIGHT = 1.2;
+                const float MIE_EXTINCTION_RATIO = 1.11;
+                const uint VIEW_STEP_COUNT = 32u;
+                const uint LIGHT_STEP_COUNT = 8u;
+
+                vec3 cubeDirection(uvec3 id, uint faceSize) {
+                    vec2 uv = (vec2(id.xy) + 0.5) / float(faceSize) * 2.0 - 1.0;
+                    switch (id.z) {
+                        case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+                        case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+                        case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+                        case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+                        case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+                        default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+                    }
+                }
+
+                // Returns the distances to the near and far intersections, or (-1, -1) on a miss.
+                vec2 raySphere(vec3 origin, vec3 direction, float radius) {
+                    float b = dot(origin, direction);
+                    float c = dot(origin, origin) - radius * radius;
+                    float discriminant = b * b - c;
+                    if (discriminant < 0.0) {
+                        return vec2(-1.0);
+                    }
+                    float root = sqrt(discriminant);
+                    return vec2(-b - root, -b + root);
+                }
+
+                vec3 extinction(float height) {
+                    float rayleighDensity = exp(-height / RAYLEIGH_SCALE_HEIGHT);
+                    float mieDensity = exp(-height / MIE_SCALE_HEIGHT);
+                    return parameters.rayleighScattering * rayleighDensity
+                        + parameters.mieScattering * MIE_EXTINCTION_RATIO * mieDensity;
+                }
+
+                vec3 sunTransmittance(vec3 position) {
+                    float distance = raySphere(position, parameters.sunDirection, ATMOSPHERE_RADIUS).y;
+                    if (raySphere(position, parameters.sunDirection, PLANET_RADIUS).x > 0.0) {
+                        return vec3(0.0);
+                    }
+
+                    float stepSize = distance / float(LIGHT_STEP_COUNT);
+                    vec3 opticalDepth = vec3(0.0);
+                    for (uint i = 0u; i < LIGHT_STEP_COUNT; i++) {
+                        vec3 samplePosition = position + parameters.sunDirection * (float(i) + 0.5) * stepSize;
+                        opticalDepth += extinction(length(samplePosition) - PLANET_RADIUS) * stepSize;
+                    }
+                    return exp(-opticalDepth);
+                }
+
+                vec3 atmosphere(vec3 direction) {
+                    vec3 origin = vec3(0.0, PLANET_RADIUS + 0.001, 0.0);
+                    float distance = raySphere(origin, direction, ATMOSPHERE_RADIUS).y;
+                    float groundDistance = raySphere(origin, direction, PLANET_RADIUS).x;
+                    if (groundDistance > 0.0) {
+                        distance = groundDistance;
+                    }
+
+                    float cosTheta = dot(direction, parameters.sunDirection);
+                    float rayleighPhase = 3.0 / (16.0 * PI) * (1.0 + cosTheta * cosTheta);
+                    float g = parameters.mieAnisotropy;
+                    float miePhase = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + cosTheta * cosTheta))
+                        / ((2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * cosTheta, 1.5));
+
+                    float stepSize = distance / float(VIEW_STEP_COUNT);
+                    vec3 transmittance = vec3(1.0);
+                    vec3 radiance = vec3(0.0);
+                    for (uint i = 0u; i < VIEW_STEP_COUNT; i++) {
+                        vec3 samplePosition = origin + direction * (float(i) + 0.5) * stepSize;
+                        float height = length(samplePosition) - PLANET_RADIUS;
+
+                        vec3 scattering = parameters.rayleighScattering * exp(-height / RAYLEIGH_SCALE_HEIGHT) * rayleighPhase
+                            + parameters.mieScattering * exp(-height / MIE_SCALE_HEIGHT) * miePhase;
+                        vec3 sampleTransmittance = exp(-extinction(height) * stepSize);
+
+                        vec3 inScattering = scattering * sunTransmittance(samplePosition);
+                        radiance += transmittance * (inScattering - inScattering * sampleTransmittance)
+                            / max(extinction(height), vec3(1e-6));
+                        transmittance *= sampleTransmittance;
+                    }
+
+                    if (groundDistance <= 0.0 && cosTheta > cos(parameters.sunAngularRadius)) {
+                        radiance += transmittance / (PI * parameters.sunAngularRadius * parameters.sunAngularRadius);
+                    }
+
+                    return radiance * parameters.sunIntensity;
+                }
+
+                void main() {
+                    uint faceSize = imageSize(sky).x;
+                    if (any(greaterThanEqual(gl_GlobalInvocationID.xy, uvec2(faceSize)))) {
+                        return;
+                    }
+
+                    vec3 direction = cubeDirection(gl_GlobalInvocationID, faceSize);
+                    imageStore(sky, ivec3(gl_GlobalInvocationID), vec4(atmosphere(direction), 1.0));
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SkySettings {
+    pub sun_direction: Vector3<f32>,
+    pub sun_intensity: f32,
+    pub sun_angular_radius: f32,
+    pub rayleigh_scattering: [f32; 3],
+    pub mie_scattering: f32,
+    pub mie_anisotropy: f32,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            sun_direction: DirectionalLight::default().direction,
+            sun_intensity: 20.0,
+            sun_angular_radius: 0.00465,
+            rayleigh_scattering: [5.802e-3, 13.558e-3, 33.1e-3],
+            mie_scattering: 3.996e-3,
+            mie_anisotropy: 0.8,
+        }
+    }
+}
+
+impl SkySettings {
+    pub fn sun_light(&self) -> DirectionalLight {
+        let direction = self.sun_direction.normalize();
+        let origin = Vector3::new(0.0, PLANET_RADIUS + 0.001, 0.0);
+
+        if ray_sphere(&origin, &direction, PLANET_RADIUS).is_some_and(|(near, _)| near > 0.0) {
+            return DirectionalLight {
+                direction,
+                color: [0.0; 3],
+            };
+        }
+
+        let distance = ray_sphere(&origin, &direction, ATMOSPHERE_RADIUS).map_or(0.0, |(_, far)| far);
+        let step_size = distance / TRANSMITTANCE_STEP_COUNT as f32;
+
+        let (rayleigh_depth, mie_depth) = (0..TRANSMITTANCE_STEP_COUNT)
+            .map(|i| {
+                let position = origin + direction * (i as f32 + 0.5) * step_size;
+                let height = position.norm() - PLANET_RADIUS;
+                (
+                    (-height / RAYLEIGH_SCALE_HEIGHT).exp() * step_size,
+                    (-height / MIE_SCALE_HEIGHT).exp() * step_size,
+                )
+            })
+            .fold((0.0, 0.0), |(rayleigh, mie), (r, m)| (rayleigh + r, mie + m));
+
+        let mie_extinction = self.mie_scattering * MIE_EXTINCTION_RATIO * mie_depth;
+
+        DirectionalLight {
+            direction,
+            color: self
+                .rayleigh_scattering
+                .map(|scattering| (-(scattering * rayleigh_depth + mie_extinction)).exp()),
+        }
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        target: Arc<ImageView>,
+    ) -> Result<()> {
+        let device = vulkan_device.queue().device();
+        let face_size = target.image().extent()[0];
+        let sun_direction = self.sun_direction.normalize();
+
+        let pipeline = vulkan_device.create_compute_pipeline(
+            sky_cs::load(Arc::clone(device))?.entry_point("main").unwrap(),
+        )?;
+
+        vulkan_device
+            .bind_compute(builder, &pipeline, [WriteDescriptorSet::image_view(0, target)])?
+            .push_constants(
+                Arc::clone(pipeline.layout()),
+                0,
+                sky_cs::SkyParameters {
+                    sunDirection: sun_direction.into(),
+                    sunIntensity: self.sun_intensity,
+                    rayleighScattering: self.rayleigh_scattering,
+                    mieScattering: self.mie_scattering,
+                    mieAnisotropy: self.mie_anisotropy,
+                    sunAngularRadius: self.sun_angular_radius,
+                },
+            )?;
+        unsafe { builder.dispatch([face_size.div_ceil(8), face_size.div_ceil(8), 6]) }?;
+
+        Ok(())
+    }
+}
+
+fn ray_sphere(origin: &Vector3<f32>, direction: &Vector3<f32>, radius: f32) -> Option<(f32, f32)> {
+    let b = origin.dot(direction);
+    let c = origin.norm_squared() - radius * radius;
+    let discriminant = b * b - c;
+    (discriminant >= 0.0).then(|| {
+        let root = discriminant.sqrt();
+        (-b - root, -b + root)
+    })
+}
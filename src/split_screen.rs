@@ -0,0 +1,129 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, Rotation3, Vector3};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::pipeline::graphics::viewport::Viewport;
+
+use crate::vulkan_device::{DebugView, VulkanDevice};
+
+// A region of the window rendered from its own camera.
+#[derive(Clone, Debug)]
+pub struct SplitViewport {
+    // Normalized to the window as [x, y, width, height].
+    pub rect: [f32; 4],
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    // Overrides the renderer's debug view inside this viewport.
+    pub debug_view: Option<DebugView>,
+    previous_view_projection: Option<Matrix4<f32>>,
+}
+
+impl SplitViewport {
+    pub fn new(rect: [f32; 4], eye: Point3<f32>, target: Point3<f32>) -> Self {
+        Self {
+            rect,
+            eye,
+            target,
+            debug_view: None,
+            previous_view_projection: None,
+        }
+    }
+
+    // Snapped to whole pixels, so neighbouring viewports neither overlap nor leave gaps.
+    pub fn viewport(&self, extent: [u32; 2]) -> Viewport {
+        let [x, y, width, height] = self.rect;
+        let offset = [x * extent[0] as f32, y * extent[1] as f32].map(f32::round);
+        let end = [
+            (x + width) * extent[0] as f32,
+            (y + height) * extent[1] as f32,
+        ]
+        .map(f32::round);
+        Viewport {
+            offset,
+            extent: [end[0] - offset[0], end[1] - offset[1]],
+            depth_range: 0.0..=1.0,
+        }
+    }
+
+    // Viewports are drawn one after another, so each rewrites the shared camera uniform first.
+    pub fn record_camera(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        projection: &Perspective3<f32>,
+        pre_rotation: &Matrix4<f32>,
+    ) -> Result<Matrix4<f32>> {
+        // Without history the first frame reads as not having moved.
+        let previous_view_projection = self.previous_view_projection.unwrap_or_else(|| {
+            let view = Isometry3::look_at_rh(&self.eye, &self.target, &Vector3::y());
+            pre_rotation * projection.as_matrix() * view.to_homogeneous()
+        });
+        let view_projection = vulkan_device.record_camera(
+            builder,
+            &self.eye,
+            &self.target,
+            projection,
+            &previous_view_projection,
+            pre_rotation,
+        )?;
+        self.previous_view_projection = Some(view_projection);
+        Ok(view_projection)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SplitScreenLayout {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl FromStr for SplitScreenLayout {
+    type Err = anyhow::Error;
+
+    // Written as `<columns>x<rows>`, e.g. `2x2`.
+    fn from_str(value: &str) -> Result<Self> {
+        let (columns, rows) = value
+            .to_ascii_lowercase()
+            .split_once('x')
+            .map(|(columns, rows)| (columns.parse(), rows.parse()))
+            .with_context(|| format!("Unknown split screen layout: {value}"))?;
+        match (columns?, rows?) {
+            (0, _) | (_, 0) => bail!("Split screen layouts need at least one cell: {value}"),
+            (columns, rows) => Ok(Self { columns, rows }),
+        }
+    }
+}
+
+impl SplitScreenLayout {
+    // Cells are filled row by row, each orbiting a further step around `target` from `eye`, and
+    // take the debug view at their index if there is one.
+    pub fn viewports(
+        self,
+        eye: &Point3<f32>,
+        target: &Point3<f32>,
+        debug_views: &[DebugView],
+    ) -> Vec<SplitViewport> {
+        let count = self.columns * self.rows;
+        let [width, height] = [1.0 / self.columns as f32, 1.0 / self.rows as f32];
+        (0..count)
+            .map(|index| {
+                let angle = index as f32 / count as f32 * std::f32::consts::TAU;
+                let rotation = Rotation3::from_axis_angle(&Vector3::y_axis(), angle);
+                SplitViewport {
+                    debug_view: debug_views.get(index as usize).copied(),
+                    ..SplitViewport::new(
+                        [
+                            (index % self.columns) as f32 * width,
+                            (index / self.columns) as f32 * height,
+                            width,
+                            height,
+                        ],
+                        target + rotation * (eye - target),
+                        *target,
+                    )
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,493 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tracing::warn;
+use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CopyBufferToImageInfo, PrimaryAutoCommandBuffer,
+    RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{
+    AttachmentBlend, ColorBlendAttachmentState, ColorBlendState,
+};
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+use vulkano::DeviceSize;
+
+use crate::allocator_stats::{ArenaStatistics, TrackedSubbufferAllocator};
+use crate::color_audit::{audit_texture, suggested_encoding, ColorAuditIssue};
+use crate::swapchain_format::{needs_manual_gamma, OutputPipelines};
+use crate::vulkan_device::{specialize, VulkanDevice};
+
+// How a texture's 8-bit values turn into what shaders sample. Color is stored as sRGB so it's
+// decoded to linear, while data such as normals, roughness and masks is sampled as it is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureEncoding {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl TextureEncoding {
+    // Going by words like `normal` or `albedo` in the file name, and color otherwise.
+    pub fn for_path(path: impl AsRef<Path>) -> Self {
+        suggested_encoding(path.as_ref()).unwrap_or_default()
+    }
+
+    pub fn format(self) -> Format {
+        match self {
+            TextureEncoding::Srgb => Format::R8G8B8A8_SRGB,
+            TextureEncoding::Linear => Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
+mod sprite_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 position;
+                layout(location = 1) in vec2 size;
+                layout(location = 2) in vec2 uvMin;
+                layout(location = 3) in vec2 uvMax;
+                layout(location = 4) in vec4 color;
+                layout(location = 5) in uint flagged;
+
+                layout(location = 0) out vec2 uv;
+                layout(location = 1) out vec4 spriteColor;
+                layout(location = 2) flat out uint spriteFlagged;
+
+                layout(push_constant) uniform SpriteParameters {
+                    vec2 screenSize;
+                } parameters;
+
+                void main() {
+                    vec2 corner = vec2(gl_VertexIndex & 1, (gl_VertexIndex >> 1) & 1);
+                    vec2 pixel = position + corner * size;
+                    gl_Position = vec4(pixel / parameters.screenSize * 2.0 - 1.0, 0.0, 1.0);
+                    uv = mix(uvMin, uvMax, corner);
+                    spriteColor = color;
+                    spriteFlagged = flagged;
+                }
+            ",
+    }
+}
+
+mod sprite_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+                layout(location = 1) in vec4 spriteColor;
+                layout(location = 2) flat in uint spriteFlagged;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform sampler2D spriteTexture;
+
+                layout(constant_id = 0) const bool MANUAL_GAMMA = false;
+
+                vec3 linearToSrgb(vec3 color) {
+                    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+                }
+
+                void main() {
+                    outColor = texture(spriteTexture, uv) * spriteColor;
+                    // Textures the color audit flagged are striped magenta.
+                    if (spriteFlagged != 0 && mod(floor((gl_FragCoord.x + gl_FragCoord.y) / 8.0), 2.0) == 0.0) {
+                        outColor.rgb = mix(outColor.rgb, vec3(1.0, 0.0, 1.0), 0.75);
+                    }
+                    if (MANUAL_GAMMA) {
+                        outColor.rgb = linearToSrgb(outColor.rgb);
+                    }
+                }
+            ",
+    }
+}
+
+#[derive(BufferContents, VertexInputVertex, Clone, Copy)]
+#[repr(C)]
+struct SpriteInstance {
+    #[format(R32G32_SFLOAT)]
+    position: [f32; 2],
+    #[format(R32G32_SFLOAT)]
+    size: [f32; 2],
+    #[format(R32G32_SFLOAT)]
+    uv_min: [f32; 2],
+    #[format(R32G32_SFLOAT)]
+    uv_max: [f32; 2],
+    #[format(R32G32B32A32_SFLOAT)]
+    color: [f32; 4],
+    #[format(R32_UINT)]
+    flagged: u32,
+}
+
+pub struct SpriteTexture {
+    view: Arc<ImageView>,
+    audit_issue: Option<ColorAuditIssue>,
+}
+
+impl SpriteTexture {
+    pub fn load(
+        vulkan_device: &VulkanDevice,
+        path: impl AsRef<Path>,
+        encoding: TextureEncoding,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let image = image::open(path)?.to_rgba8();
+        let mut texture = Self::from_rgba(
+            vulkan_device,
+            image.dimensions().into(),
+            image.as_raw(),
+            encoding,
+        )?;
+        // The file name can only be checked here.
+        texture.audit_issue = audit_texture(Some(path), image.as_raw(), encoding);
+        if let Some(audit_issue) = texture.audit_issue {
+            warn!("{} {audit_issue}", path.display());
+        }
+        Ok(texture)
+    }
+
+    pub fn white(vulkan_device: &VulkanDevice) -> Result<Self> {
+        Self::from_rgba(vulkan_device, [1, 1], &[255; 4], TextureEncoding::Srgb)
+    }
+
+    pub fn from_rgba(
+        vulkan_device: &VulkanDevice,
+        extent: [u32; 2],
+        texels: &[u8],
+        encoding: TextureEncoding,
+    ) -> Result<Self> {
+        let staging_buffer = Buffer::from_iter(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            texels.iter().copied(),
+        )?;
+
+        let image = Image::new(
+            vulkan_device.memory_allocator().clone(),
+            ImageCreateInfo {
+                format: encoding.format(),
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?;
+
+        vulkan_device.submit_and_wait(|builder| {
+            builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+                staging_buffer,
+                Arc::clone(&image),
+            ))?;
+            Ok(())
+        })?;
+
+        Ok(Self {
+            view: ImageView::new_default(image)?,
+            audit_issue: audit_texture(None, texels, encoding),
+        })
+    }
+
+    // Wraps an image the renderer draws into, such as a render texture.
+    pub fn from_view(view: Arc<ImageView>) -> Self {
+        Self {
+            view,
+            audit_issue: None,
+        }
+    }
+
+    pub fn view(&self) -> &Arc<ImageView> {
+        &self.view
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        let [width, height, _] = self.view.image().extent();
+        [width, height]
+    }
+
+    pub fn audit_issue(&self) -> Option<ColorAuditIssue> {
+        self.audit_issue
+    }
+}
+
+#[derive(Clone)]
+pub struct Sprite {
+    pub texture: Arc<SpriteTexture>,
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    pub color: [f32; 4],
+    pub layer: i32,
+}
+
+impl Sprite {
+    pub fn new(texture: Arc<SpriteTexture>, position: [f32; 2], size: [f32; 2]) -> Self {
+        Self {
+            texture,
+            position,
+            size,
+            uv_min: [0.0, 0.0],
+            uv_max: [1.0, 1.0],
+            color: [1.0; 4],
+            layer: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SpriteBatch {
+    sprites: Vec<Sprite>,
+}
+
+impl SpriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn draw(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+}
+
+pub struct SpritePass {
+    pipelines: OutputPipelines,
+    sampler: Arc<Sampler>,
+    instance_allocator: TrackedSubbufferAllocator,
+}
+
+impl SpritePass {
+    pub fn new(
+        device: &Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        pipeline_cache: &Arc<PipelineCache>,
+    ) -> Result<Self> {
+        let vertex_shader = sprite_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+        let fragment_shader = sprite_fs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = SpriteInstance::per_instance()
+            .definition(&vertex_shader)
+            .unwrap();
+
+        let pipelines = OutputPipelines::new(|color_format| {
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vertex_shader.clone()),
+                PipelineShaderStageCreateInfo::new(specialize(
+                    &fragment_shader,
+                    [(0, needs_manual_gamma(color_format).into())],
+                )?),
+            ];
+
+            let layout = PipelineLayout::new(
+                Arc::clone(device),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(Arc::clone(device))
+                    .unwrap(),
+            )?;
+
+            let subpass = PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(color_format)],
+                ..Default::default()
+            };
+
+            Ok(GraphicsPipeline::new(
+                Arc::clone(device),
+                Some(Arc::clone(pipeline_cache)),
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(vertex_input_state.clone()),
+                    input_assembly_state: Some(InputAssemblyState {
+                        topology: PrimitiveTopology::TriangleStrip,
+                        ..Default::default()
+                    }),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        1,
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend::alpha()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(subpass.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )?)
+        })?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        let instance_allocator = TrackedSubbufferAllocator::new(SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::VERTEX_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        ));
+
+        Ok(Self {
+            pipelines,
+            sampler,
+            instance_allocator,
+        })
+    }
+
+    pub fn arena_statistics(&self) -> ArenaStatistics {
+        self.instance_allocator.statistics("sprite instances")
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        batch: &SpriteBatch,
+        target: &Arc<ImageView>,
+        color_audit: bool,
+    ) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let pipeline = self.pipelines.get(target.format())?;
+        let mut sprites = batch.sprites.iter().collect::<Vec<_>>();
+        sprites.sort_by_key(|sprite| sprite.layer);
+
+        let instances = self
+            .instance_allocator
+            .allocate_slice::<SpriteInstance>(sprites.len() as DeviceSize)?;
+        {
+            let mut instances = instances.write()?;
+            for (instance, sprite) in instances.iter_mut().zip(&sprites) {
+                *instance = SpriteInstance {
+                    position: sprite.position,
+                    size: sprite.size,
+                    uv_min: sprite.uv_min,
+                    uv_max: sprite.uv_max,
+                    color: sprite.color,
+                    flagged: (color_audit && sprite.texture.audit_issue.is_some()) as u32,
+                };
+            }
+        }
+
+        let [width, height, _] = target.image().extent();
+        let viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: [width as f32, height as f32],
+            depth_range: 0.0..=1.0,
+        };
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(target))
+                })],
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport].into_iter().collect())?
+            .bind_pipeline_graphics(Arc::clone(pipeline))?
+            .bind_vertex_buffers(0, instances)?
+            .push_constants(
+                Arc::clone(pipeline.layout()),
+                0,
+                sprite_vs::SpriteParameters {
+                    screenSize: [width as f32, height as f32],
+                },
+            )?;
+
+        // Consecutive sprites sharing a texture after the layer sort are drawn with a single instanced call.
+        let mut first = 0;
+        while first < sprites.len() {
+            let texture = &sprites[first].texture;
+            let count = sprites[first..]
+                .iter()
+                .take_while(|sprite| Arc::ptr_eq(&sprite.texture, texture))
+                .count();
+
+            let set = DescriptorSet::new(
+                vulkan_device.descriptor_set_allocator().clone(),
+                Arc::clone(&pipeline.layout().set_layouts()[0]),
+                [WriteDescriptorSet::image_view_sampler(
+                    0,
+                    Arc::clone(&texture.view),
+                    Arc::clone(&self.sampler),
+                )],
+                [],
+            )?;
+
+            builder.bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(pipeline.layout()),
+                0,
+                set,
+            )?;
+            unsafe { builder.draw(4, count as u32, 0, first as u32) }?;
+
+            first += count;
+        }
+
+        builder.end_rendering()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageUsage, SampleCount};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+
+use crate::fullscreen::{begin_fullscreen_pass, create_fullscreen_pipeline};
+use crate::vulkan_device::VulkanDevice;
+
+pub const OCCLUSION_FORMAT: Format = Format::R8_UNORM;
+
+mod ssao_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out float outOcclusion;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(set = 0, binding = 1) uniform sampler2D depthTexture;
+                layout(set = 0, binding = 2) uniform sampler2D normalTexture;
+
+                layout(push_constant) uniform SsaoParameters {
+                    float radius;
+                    float bias;
+                    float intensity;
+                    uint directionCount;
+                    uint stepCount;
+                } parameters;
+
+                const float PI = 3.14159265359;
+
+                vec3 viewPosition(vec2 coord) {
+                    float depth = textureLod(depthTexture, coord, 0.0).r;
+                    vec4 position = uniforms.inverse_projection * vec4(coord * 2.0 - 1.0, depth, 1.0);
+                    return position.xyz / position.w;
+                }
+
+                float hash(vec2 p) {
+                    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+                }
+
+                void main() {
+                    if (textureLod(depthTexture, uv, 0.0).r >= 1.0) {
+                        outOcclusion = 1.0;
+                        return;
+                    }
+
+                    vec3 position = viewPosition(uv);
+                    vec3 normal = normalize(textureLod(normalTexture, uv, 0.0).xyz);
+
+                    float screenRadius = 0.5 * parameters.radius * uniforms.projection[0][0] / abs(position.z);
+                    float rotation = hash(gl_FragCoord.xy) * 2.0 * PI;
+                    float jitter = hash(gl_FragCoord.yx + 0.5);
+
+                    float occlusion = 0.0;
+                    for (uint d = 0; d < parameters.directionCount; d++) {
+                        float angle = rotation + 2.0 * PI * float(d) / float(parameters.directionCount);
+                        vec2 direction = vec2(cos(angle), sin(angle));
+
+                        float horizon = 0.0;
+                        for (uint s = 0; s < parameters.stepCount; s++) {
+                            float t = (float(s) + jitter) / float(parameters.stepCount);
+                            vec3 delta = viewPosition(uv + direction * screenRadius * t) - position;
+                            float distance = length(delta);
+                            if (distance < 1e-4 || distance > parameters.radius) {
+                                continue;
+                            }
+
+                            float elevation = dot(normal, delta / distance) - parameters.bias;
+                            float falloff = 1.0 - (distance * distance) / (parameters.radius * parameters.radius);
+                            horizon = max(horizon, elevation * falloff);
+                        }
+                        occlusion += horizon;
+                    }
+
+                    occlusion /= float(parameters.directionCount);
+                    outOcclusion = clamp(1.0 - occlusion * parameters.intensity, 0.0, 1.0);
+                }
+            ",
+    }
+}
+
+mod blur_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out float outOcclusion;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(set = 0, binding = 1) uniform sampler2D occlusionTexture;
+                layout(set = 0, binding = 2) uniform sampler2D depthTexture;
+
+                layout(push_constant) uniform BlurParameters {
+                    vec2 direction;
+                    float sharpness;
+                } parameters;
+
+                float linearDepth(vec2 coord) {
+                    float depth = textureLod(depthTexture, coord, 0.0).r;
+                    return uniforms.projection[3][2] / (depth + uniforms.projection[2][2]);
+                }
+
+                void main() {
+                    vec2 texelSize = 1.0 / vec2(textureSize(occlusionTexture, 0));
+                    float centerDepth = linearDepth(uv);
+
+                    float total = 0.0;
+                    float totalWeight = 0.0;
+                    for (int i = -4; i <= 4; i++) {
+                        vec2 coord = uv + parameters.direction * texelSize * float(i);
+                        float depthDelta = (linearDepth(coord) - centerDepth) / centerDepth;
+                        float weight = exp(-float(i * i) / 18.0 - depthDelta * depthDelta * parameters.sharpness);
+                        total += textureLod(occlusionTexture, coord, 0.0).r * weight;
+                        totalWeight += weight;
+                    }
+
+                    outOcclusion = total / totalWeight;
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    pub radius: f32,
+    pub bias: f32,
+    pub intensity: f32,
+    pub direction_count: u32,
+    pub step_count: u32,
+    pub blur_sharpness: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 0.5,
+            bias: 0.1,
+            intensity: 1.5,
+            direction_count: 8,
+            step_count: 4,
+            blur_sharpness: 400.0,
+        }
+    }
+}
+
+pub struct SsaoPass {
+    ssao_pipeline: Arc<GraphicsPipeline>,
+    blur_pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl SsaoPass {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let ssao_pipeline = create_fullscreen_pipeline(
+            device,
+            ssao_fs::load(Arc::clone(device))?.entry_point("main").unwrap(),
+            OCCLUSION_FORMAT,
+        )?;
+        let blur_pipeline = create_fullscreen_pipeline(
+            device,
+            blur_fs::load(Arc::clone(device))?.entry_point("main").unwrap(),
+            OCCLUSION_FORMAT,
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            ssao_pipeline,
+            blur_pipeline,
+            sampler,
+        })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        targets: &SsaoTargets,
+        settings: &SsaoSettings,
+    ) -> Result<()> {
+        if !settings.enabled {
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(RenderingAttachmentInfo {
+                        load_op: AttachmentLoadOp::Clear,
+                        store_op: AttachmentStoreOp::Store,
+                        clear_value: Some(ClearValue::Float([1.0, 1.0, 1.0, 1.0])),
+                        ..RenderingAttachmentInfo::image_view(Arc::clone(&targets.occlusion_view))
+                    })],
+                    ..Default::default()
+                })?
+                .end_rendering()?;
+            return Ok(());
+        }
+
+        begin_fullscreen_pass(builder, &self.ssao_pipeline, &targets.occlusion_view)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.ssao_pipeline.layout()),
+                0,
+                Arc::clone(&targets.ssao_set),
+            )?
+            .push_constants(
+                Arc::clone(self.ssao_pipeline.layout()),
+                0,
+                ssao_fs::SsaoParameters {
+                    radius: settings.radius,
+                    bias: settings.bias,
+                    intensity: settings.intensity,
+                    directionCount: settings.direction_count,
+                    stepCount: settings.step_count,
+                },
+            )?;
+        unsafe { builder.draw(3, 1, 0, 0) }?;
+        builder.end_rendering()?;
+
+        for (target, set, direction) in [
+            (&targets.blur_view, &targets.blur_horizontal_set, [1.0, 0.0]),
+            (&targets.occlusion_view, &targets.blur_vertical_set, [0.0, 1.0]),
+        ] {
+            begin_fullscreen_pass(builder, &self.blur_pipeline, target)?
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    Arc::clone(self.blur_pipeline.layout()),
+                    0,
+                    Arc::clone(set),
+                )?
+                .push_constants(
+                    Arc::clone(self.blur_pipeline.layout()),
+                    0,
+                    blur_fs::BlurParameters {
+                        direction,
+                        sharpness: settings.blur_sharpness,
+                    },
+                )?;
+            unsafe { builder.draw(3, 1, 0, 0) }?;
+            builder.end_rendering()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn sampler(&self) -> &Arc<Sampler> {
+        &self.sampler
+    }
+}
+
+pub struct SsaoTargets {
+    occlusion_view: Arc<ImageView>,
+    blur_view: Arc<ImageView>,
+    ssao_set: Arc<DescriptorSet>,
+    blur_horizontal_set: Arc<DescriptorSet>,
+    blur_vertical_set: Arc<DescriptorSet>,
+}
+
+impl SsaoTargets {
+    pub fn new(
+        vulkan_device: &VulkanDevice,
+        extent: [u32; 2],
+        depth_view: &Arc<ImageView>,
+        normal_view: &Arc<ImageView>,
+    ) -> Result<Self> {
+        let ssao_pass = vulkan_device.ssao_pass();
+        let sampler = ssao_pass.sampler();
+
+        let occlusion_view = vulkan_device.create_attachment(
+            OCCLUSION_FORMAT,
+            extent,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            SampleCount::Sample1,
+        )?;
+        let blur_view = vulkan_device.create_attachment(
+            OCCLUSION_FORMAT,
+            extent,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            SampleCount::Sample1,
+        )?;
+
+        let ssao_set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&ssao_pass.ssao_pipeline.layout().set_layouts()[0]),
+            [
+                WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone()),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    Arc::clone(depth_view),
+                    Arc::clone(sampler),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    2,
+                    Arc::clone(normal_view),
+                    Arc::clone(sampler),
+                ),
+            ],
+            [],
+        )?;
+
+        let blur_set = |source: &Arc<ImageView>| {
+            DescriptorSet::new(
+                vulkan_device.descriptor_set_allocator().clone(),
+                Arc::clone(&ssao_pass.blur_pipeline.layout().set_layouts()[0]),
+                [
+                    WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        1,
+                        Arc::clone(source),
+                        Arc::clone(sampler),
+                    ),
+                    WriteDescriptorSet::image_view_sampler(
+                        2,
+                        Arc::clone(depth_view),
+                        Arc::clone(sampler),
+                    ),
+                ],
+                [],
+            )
+        };
+
+        let blur_horizontal_set = blur_set(&occlusion_view)?;
+        let blur_vertical_set = blur_set(&blur_view)?;
+
+        Ok(Self {
+            occlusion_view,
+            blur_view,
+            ssao_set,
+            blur_horizontal_set,
+            blur_vertical_set,
+        })
+    }
+
+    pub fn occlusion_view(&self) -> &Arc<ImageView> {
+        &self.occlusion_view
+    }
+}
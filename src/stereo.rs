@@ -0,0 +1,375 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nalgebra::{Isometry3, Matrix4, Vector3};
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, BlitImageInfo, ImageBlit, PrimaryAutoCommandBuffer,
+    RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::sampler::Filter;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageAspects, ImageCreateInfo, ImageSubresourceLayers, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+
+use crate::gpu_scene::{self, SceneObjects};
+use crate::light::DirectionalLight;
+use crate::material::input_assembly_state;
+use crate::vulkan_device::{Vertex, VulkanDevice, DEPTH_FORMAT, HDR_FORMAT};
+
+// One bit per eye; view 0 is the left eye.
+const VIEW_MASK: u32 = 0b11;
+const VIEW_COUNT: u32 = VIEW_MASK.count_ones();
+
+mod stereo_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+                #extension GL_EXT_multiview : require
+
+                layout(location = 0) in vec3 position;
+
+                layout(location = 0) out vec3 fragColor;
+                layout(location = 1) out vec3 worldPosition;
+
+                layout(set = 0, binding = 0) uniform StereoCamera {
+                    mat4 viewProjection[2];
+                } camera;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                // The draw's first instance selects its object.
+                void main() {
+                    vec4 world = scene.objects[gl_InstanceIndex].transform * vec4(position, 1.0);
+                    gl_Position = camera.viewProjection[gl_ViewIndex] * world;
+                    fragColor = world.xyz;
+                    worldPosition = world.xyz;
+                }
+            ",
+    }
+}
+
+mod stereo_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 fragColor;
+                layout(location = 1) in vec3 worldPosition;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(push_constant) uniform StereoParameters {
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                } parameters;
+
+                const float AMBIENT = 0.1;
+
+                void main() {
+                    // Derivatives are taken per view, so both eyes agree on the face normal.
+                    vec3 normal = normalize(cross(dFdx(worldPosition), dFdy(worldPosition)));
+                    float diffuse = abs(dot(normal, normalize(parameters.sunDirection.xyz)));
+                    outColor = vec4(fragColor * (AMBIENT + diffuse * parameters.sunColor.rgb), 1.0);
+                }
+            ",
+    }
+}
+
+// The scene's objects, drawn once for both eyes, and the sun lighting them.
+pub struct StereoScene<'a> {
+    pub objects: SceneObjects<'a>,
+    pub sun: &'a DirectionalLight,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StereoSettings {
+    // Distance between the eyes in world units, a typical interpupillary distance in metres.
+    pub eye_separation: f32,
+}
+
+impl Default for StereoSettings {
+    fn default() -> Self {
+        Self {
+            eye_separation: 0.064,
+        }
+    }
+}
+
+// Layered attachments with one layer per eye, plus the per-eye camera they're drawn with.
+pub struct StereoTargets {
+    color_image: Arc<Image>,
+    color_view: Arc<ImageView>,
+    depth_view: Arc<ImageView>,
+    camera_buffer: Subbuffer<stereo_vs::StereoCamera>,
+    camera_set: Arc<DescriptorSet>,
+}
+
+impl StereoTargets {
+    // `extent` is the size of a single eye's view.
+    pub fn new(vulkan_device: &VulkanDevice, extent: [u32; 2]) -> Result<Self> {
+        let create_layered = |format: Format, usage: ImageUsage| -> Result<Arc<ImageView>> {
+            Ok(ImageView::new_default(Image::new(
+                vulkan_device.memory_allocator().clone(),
+                ImageCreateInfo {
+                    format,
+                    extent: [extent[0], extent[1], 1],
+                    array_layers: VIEW_COUNT,
+                    usage,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )?)?)
+        };
+        let color_view = create_layered(
+            HDR_FORMAT,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+        )?;
+        let depth_view = create_layered(DEPTH_FORMAT, ImageUsage::DEPTH_STENCIL_ATTACHMENT)?;
+
+        let camera_buffer = Buffer::new_sized(
+            vulkan_device.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST | BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )?;
+        let stereo_pass = vulkan_device
+            .stereo_pass()
+            .context("Stereo rendering needs multiview support")?;
+        let camera_set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&stereo_pass.pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(0, camera_buffer.clone())],
+            [],
+        )?;
+
+        Ok(Self {
+            color_image: Arc::clone(color_view.image()),
+            color_view,
+            depth_view,
+            camera_buffer,
+            camera_set,
+        })
+    }
+
+    fn eye_extent(&self) -> [u32; 2] {
+        let [width, height, _] = self.color_image.extent();
+        [width, height]
+    }
+}
+
+// Draws both eyes in a single pass with VK_KHR_multiview, then lays them out side by side.
+pub struct StereoPass {
+    pipeline: Arc<GraphicsPipeline>,
+}
+
+impl StereoPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        topology: PrimitiveTopology,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let vertex_shader = stereo_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = Vertex::per_vertex().definition(&vertex_shader).unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(
+                stereo_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = gpu_scene::create_pipeline_layout(device, &stages, gpu_scene_set_layout)?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            view_mask: VIEW_MASK,
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(input_assembly_state(topology)),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        Ok(Self { pipeline })
+    }
+
+    // The eyes sit either side of the main camera with parallel view axes, which keeps vertical
+    // parallax out of the pair.
+    fn eye_view_projections(
+        vulkan_device: &VulkanDevice,
+        settings: &StereoSettings,
+    ) -> [Matrix4<f32>; 2] {
+        let eye = vulkan_device.camera_position();
+        let target = vulkan_device.camera_target();
+        let right = (target - eye).cross(&Vector3::y()).normalize();
+        let projection = vulkan_device.camera_projection().into_inner();
+        [-0.5, 0.5].map(|side| {
+            let offset = right * side * settings.eye_separation;
+            projection
+                * Isometry3::look_at_rh(&(eye + offset), &(target + offset), &Vector3::y())
+                    .to_homogeneous()
+        })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        targets: &StereoTargets,
+        output: &Arc<ImageView>,
+        scene: &StereoScene,
+        settings: &StereoSettings,
+    ) -> Result<()> {
+        let StereoScene { objects, sun } = scene;
+        let [left, right] = Self::eye_view_projections(vulkan_device, settings);
+        builder.update_buffer(
+            targets.camera_buffer.clone(),
+            Box::new(stereo_vs::StereoCamera {
+                viewProjection: [left.into(), right.into()],
+            }),
+        )?;
+
+        let eye_extent = targets.eye_extent();
+        let sun_direction = sun.direction.normalize();
+        let [sun_r, sun_g, sun_b] = sun.color;
+
+        builder
+            .begin_rendering(RenderingInfo {
+                view_mask: VIEW_MASK,
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::Store,
+                    clear_value: Some(ClearValue::Float([0.1, 0.1, 0.1, 1.0])),
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(&targets.color_view))
+                })],
+                depth_attachment: Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::DontCare,
+                    clear_value: Some(1.0f32.into()),
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(&targets.depth_view))
+                }),
+                ..Default::default()
+            })?
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: eye_extent.map(|extent| extent as f32),
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )?
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_vertex_buffers(0, vulkan_device.vertex_buffer().clone())?
+            .bind_index_buffer(vulkan_device.index_buffer().clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                Arc::clone(&targets.camera_set),
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                stereo_fs::StereoParameters {
+                    sunDirection: [sun_direction.x, sun_direction.y, sun_direction.z, 0.0],
+                    sunColor: [sun_r, sun_g, sun_b, 1.0],
+                },
+            )?;
+        objects.record(builder, self.pipeline.layout())?;
+        builder.end_rendering()?;
+
+        // Side-by-side output; a VR runtime would take the layers as they are instead.
+        let [output_width, output_height, _] = output.image().extent();
+        let half_width = output_width / 2;
+        builder.blit_image(BlitImageInfo {
+            regions: (0..VIEW_COUNT)
+                .map(|view| ImageBlit {
+                    src_subresource: ImageSubresourceLayers {
+                        aspects: ImageAspects::COLOR,
+                        mip_level: 0,
+                        array_layers: view..view + 1,
+                    },
+                    src_offsets: [[0, 0, 0], [eye_extent[0], eye_extent[1], 1]],
+                    dst_subresource: ImageSubresourceLayers {
+                        aspects: ImageAspects::COLOR,
+                        mip_level: 0,
+                        array_layers: 0..1,
+                    },
+                    dst_offsets: [
+                        [half_width * view, 0, 0],
+                        [half_width * (view + 1), output_height, 1],
+                    ],
+                    ..Default::default()
+                })
+                .collect(),
+            filter: Filter::Linear,
+            ..BlitImageInfo::images(Arc::clone(&targets.color_image), Arc::clone(output.image()))
+        })?;
+
+        Ok(())
+    }
+}
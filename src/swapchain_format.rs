@@ -0,0 +1,101 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use vulkano::format::{Format, NumericFormat};
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::swapchain::ColorSpace;
+
+// Every format the final post-process and overlay passes have pipelines for.
+pub const OUTPUT_FORMATS: [Format; 4] = [
+    Format::B8G8R8A8_SRGB,
+    Format::B8G8R8A8_UNORM,
+    Format::A2B10G10R10_UNORM_PACK32,
+    Format::R16G16B16A16_SFLOAT,
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwapchainFormat {
+    // Gamma is encoded by the hardware on store; surfaces without an sRGB format fall back to UNORM.
+    #[default]
+    Srgb,
+    // The final pass writes gamma-encoded values itself.
+    Unorm,
+    // 10 bits per channel for smoother SDR gradients, falling back to 8-bit formats when unsupported.
+    TenBit,
+}
+
+impl FromStr for SwapchainFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "srgb" => SwapchainFormat::Srgb,
+            "unorm" | "manual-gamma" => SwapchainFormat::Unorm,
+            "10bit" | "10-bit" => SwapchainFormat::TenBit,
+            _ => bail!("Unknown swapchain format: {value}"),
+        })
+    }
+}
+
+impl SwapchainFormat {
+    pub fn candidates(self) -> &'static [Format] {
+        match self {
+            SwapchainFormat::Srgb => &[Format::B8G8R8A8_SRGB, Format::B8G8R8A8_UNORM],
+            SwapchainFormat::Unorm => &[Format::B8G8R8A8_UNORM],
+            SwapchainFormat::TenBit => &[
+                Format::A2B10G10R10_UNORM_PACK32,
+                Format::B8G8R8A8_SRGB,
+                Format::B8G8R8A8_UNORM,
+            ],
+        }
+    }
+
+    pub fn select(self, surface_formats: &[(Format, ColorSpace)]) -> Result<Format> {
+        self.candidates()
+            .iter()
+            .copied()
+            .find(|format| surface_formats.contains(&(*format, ColorSpace::SrgbNonLinear)))
+            .with_context(|| format!("The surface supports no {self:?} swapchain format"))
+    }
+}
+
+// UNORM targets store exactly what the shader writes, so the final pass has to encode gamma.
+// Floating-point targets are presented in a linear color space.
+pub fn needs_manual_gamma(format: Format) -> bool {
+    !matches!(
+        format.numeric_format_color(),
+        Some(NumericFormat::SRGB | NumericFormat::SFLOAT)
+    )
+}
+
+// The size of one code value in the target, which is how far dithering has to spread a gradient.
+pub fn quantization_step(format: Format) -> f32 {
+    1.0 / ((1u32 << format.components()[0]) - 1) as f32
+}
+
+// Passes that write straight into the swapchain image keep one pipeline per output format, since
+// the format is only known once a surface has been created.
+pub struct OutputPipelines {
+    pipelines: Vec<(Format, Arc<GraphicsPipeline>)>,
+}
+
+impl OutputPipelines {
+    pub fn new(
+        mut create_pipeline: impl FnMut(Format) -> Result<Arc<GraphicsPipeline>>,
+    ) -> Result<Self> {
+        let pipelines = OUTPUT_FORMATS
+            .into_iter()
+            .map(|format| Ok((format, create_pipeline(format)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self { pipelines })
+    }
+
+    pub fn get(&self, format: Format) -> Result<&Arc<GraphicsPipeline>> {
+        self.pipelines
+            .iter()
+            .find(|(pipeline_format, _)| *pipeline_format == format)
+            .map(|(_, pipeline)| pipeline)
+            .with_context(|| format!("No output pipeline for {format:?}"))
+    }
+}
@@ -0,0 +1,370 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::SampleCount;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::tessellation::TessellationState;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineShaderStageCreateInfo,
+};
+
+use crate::light::DirectionalLight;
+use crate::vulkan_device::{VulkanDevice, DEPTH_FORMAT, HDR_FORMAT};
+
+const PATCH_CONTROL_POINTS: u32 = 4;
+
+mod plane_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(push_constant) uniform PlaneParameters {
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                    float height;
+                    float extent;
+                    float displacementScale;
+                    float maxTessellationLevel;
+                    float lodDistance;
+                    uint resolution;
+                } parameters;
+
+                void main() {
+                    uint patchIndex = gl_VertexIndex / 4u;
+                    uint corner = gl_VertexIndex % 4u;
+                    uvec2 cell = uvec2(patchIndex % parameters.resolution, patchIndex / parameters.resolution);
+                    vec2 offset = vec2(corner == 1u || corner == 2u ? 1.0 : 0.0, corner >= 2u ? 1.0 : 0.0);
+                    vec2 position = ((vec2(cell) + offset) / float(parameters.resolution) * 2.0 - 1.0)
+                        * parameters.extent;
+
+                    gl_Position = vec4(position.x, parameters.height, position.y, 1.0);
+                }
+            ",
+    }
+}
+
+mod plane_tcs {
+    vulkano_shaders::shader! {
+        ty: "tess_ctrl",
+        src: r"
+                #version 460
+
+                layout(vertices = 4) out;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(push_constant) uniform PlaneParameters {
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                    float height;
+                    float extent;
+                    float displacementScale;
+                    float maxTessellationLevel;
+                    float lodDistance;
+                    uint resolution;
+                } parameters;
+
+                float edgeLevel(vec3 cameraPosition, vec3 first, vec3 second) {
+                    float distanceFactor = 1.0 - distance((first + second) * 0.5, cameraPosition) / parameters.lodDistance;
+                    return clamp(parameters.maxTessellationLevel * distanceFactor, 1.0, parameters.maxTessellationLevel);
+                }
+
+                void main() {
+                    gl_out[gl_InvocationID].gl_Position = gl_in[gl_InvocationID].gl_Position;
+
+                    if (gl_InvocationID == 0) {
+                        vec3 cameraPosition = inverse(uniforms.view)[3].xyz;
+                        vec3 p0 = gl_in[0].gl_Position.xyz;
+                        vec3 p1 = gl_in[1].gl_Position.xyz;
+                        vec3 p2 = gl_in[2].gl_Position.xyz;
+                        vec3 p3 = gl_in[3].gl_Position.xyz;
+
+                        gl_TessLevelOuter[0] = edgeLevel(cameraPosition, p0, p3);
+                        gl_TessLevelOuter[1] = edgeLevel(cameraPosition, p0, p1);
+                        gl_TessLevelOuter[2] = edgeLevel(cameraPosition, p1, p2);
+                        gl_TessLevelOuter[3] = edgeLevel(cameraPosition, p3, p2);
+                        gl_TessLevelInner[0] = max(gl_TessLevelOuter[1], gl_TessLevelOuter[3]);
+                        gl_TessLevelInner[1] = max(gl_TessLevelOuter[0], gl_TessLevelOuter[2]);
+                    }
+                }
+            ",
+    }
+}
+
+mod plane_tes {
+    vulkano_shaders::shader! {
+        ty: "tess_eval",
+        src: r"
+                #version 460
+
+                layout(quads, fractional_odd_spacing, ccw) in;
+
+                layout(location = 0) out vec3 worldNormal;
+                layout(location = 1) out float displacement;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(push_constant) uniform PlaneParameters {
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                    float height;
+                    float extent;
+                    float displacementScale;
+                    float maxTessellationLevel;
+                    float lodDistance;
+                    uint resolution;
+                } parameters;
+
+                const uint OCTAVE_COUNT = 5u;
+                const float NORMAL_EPSILON = 0.05;
+
+                float displacementAt(vec2 position) {
+                    float sum = 0.0;
+                    float amplitude = 1.0;
+                    float frequency = 0.15;
+                    for (uint i = 0u; i < OCTAVE_COUNT; i++) {
+                        sum += amplitude * sin(position.x * frequency + float(i) * 1.7)
+                            * cos(position.y * frequency * 1.3 - float(i) * 0.9);
+                        amplitude *= 0.5;
+                        frequency *= 2.1;
+                    }
+                    return sum * parameters.displacementScale;
+                }
+
+                void main() {
+                    vec2 uv = gl_TessCoord.xy;
+                    vec3 position = mix(
+                        mix(gl_in[0].gl_Position.xyz, gl_in[1].gl_Position.xyz, uv.x),
+                        mix(gl_in[3].gl_Position.xyz, gl_in[2].gl_Position.xyz, uv.x),
+                        uv.y
+                    );
+
+                    displacement = displacementAt(position.xz);
+                    position.y += displacement;
+
+                    float left = displacementAt(position.xz - vec2(NORMAL_EPSILON, 0.0));
+                    float right = displacementAt(position.xz + vec2(NORMAL_EPSILON, 0.0));
+                    float back = displacementAt(position.xz - vec2(0.0, NORMAL_EPSILON));
+                    float front = displacementAt(position.xz + vec2(0.0, NORMAL_EPSILON));
+                    worldNormal = normalize(vec3(left - right, 2.0 * NORMAL_EPSILON, back - front));
+
+                    gl_Position = uniforms.view_projection * vec4(position, 1.0);
+                }
+            ",
+    }
+}
+
+mod plane_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 worldNormal;
+                layout(location = 1) in float displacement;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(push_constant) uniform PlaneParameters {
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                    float height;
+                    float extent;
+                    float displacementScale;
+                    float maxTessellationLevel;
+                    float lodDistance;
+                    uint resolution;
+                } parameters;
+
+                void main() {
+                    float elevation = clamp(displacement / max(parameters.displacementScale, 1e-4) * 0.5 + 0.5, 0.0, 1.0);
+                    vec3 albedo = mix(vec3(0.25, 0.2, 0.15), vec3(0.6, 0.58, 0.55), elevation);
+                    vec3 normal = normalize(worldNormal);
+                    float diffuse = max(dot(normal, parameters.sunDirection.xyz), 0.0);
+                    outColor = vec4(albedo * (0.3 + diffuse * parameters.sunColor.rgb), 1.0);
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TessellationSettings {
+    pub enabled: bool,
+    pub height: f32,
+    pub extent: f32,
+    pub resolution: u32,
+    pub displacement_scale: f32,
+    pub max_tessellation_level: f32,
+    pub lod_distance: f32,
+}
+
+impl Default for TessellationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            height: -1.0,
+            extent: 20.0,
+            resolution: 16,
+            displacement_scale: 0.5,
+            max_tessellation_level: 32.0,
+            lod_distance: 40.0,
+        }
+    }
+}
+
+pub struct TessellationPass {
+    pipeline: Arc<GraphicsPipeline>,
+}
+
+impl TessellationPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        samples: SampleCount,
+    ) -> Result<Self> {
+        let stages = [
+            PipelineShaderStageCreateInfo::new(
+                plane_vs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+            PipelineShaderStageCreateInfo::new(
+                plane_tcs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+            PipelineShaderStageCreateInfo::new(
+                plane_tes::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+            PipelineShaderStageCreateInfo::new(
+                plane_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(Arc::clone(device))
+                .unwrap(),
+        )?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState {
+                    topology: PrimitiveTopology::PatchList,
+                    ..Default::default()
+                }),
+                tessellation_state: Some(TessellationState {
+                    patch_control_points: PATCH_CONTROL_POINTS,
+                    ..Default::default()
+                }),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: true,
+                        compare_op: CompareOp::LessOrEqual,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: samples,
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        Ok(Self { pipeline })
+    }
+
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        settings: &TessellationSettings,
+        sun: &DirectionalLight,
+    ) -> Result<()> {
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone())],
+            [],
+        )?;
+
+        let sun_direction = sun.direction.normalize();
+        let [sun_r, sun_g, sun_b] = sun.color;
+        let patch_count = settings.resolution * settings.resolution;
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                plane_vs::PlaneParameters {
+                    sunDirection: [sun_direction.x, sun_direction.y, sun_direction.z, 0.0],
+                    sunColor: [sun_r, sun_g, sun_b, 1.0],
+                    height: settings.height,
+                    extent: settings.extent,
+                    displacementScale: settings.displacement_scale,
+                    maxTessellationLevel: settings.max_tessellation_level,
+                    lodDistance: settings.lod_distance,
+                    resolution: settings.resolution,
+                },
+            )?;
+        unsafe { builder.draw(patch_count * PATCH_CONTROL_POINTS, 1, 0, 0) }?;
+
+        Ok(())
+    }
+}
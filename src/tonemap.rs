@@ -0,0 +1,327 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::{Pipeline, PipelineBindPoint};
+
+use crate::color_lut::ColorLut;
+use crate::color_management::{OutputColorSpace, WorkingColorSpace};
+use crate::fullscreen::{begin_fullscreen_pass, create_fullscreen_pipeline};
+use crate::post_process::{PostProcessContext, PostProcessEffect};
+use crate::swapchain_format::{needs_manual_gamma, quantization_step, OutputPipelines};
+use crate::vulkan_device::VulkanDevice;
+
+mod tonemap_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform sampler2D sceneTexture;
+                layout(set = 0, binding = 1) uniform sampler3D colorLut;
+
+                layout(push_constant) uniform TonemapParameters {
+                    float exposure;
+                    float lutIntensity;
+                    float lutSize;
+                    float ditherAmplitude;
+                    uint manualGamma;
+                    uint preserveAlpha;
+                    uint workingColorSpace;
+                    uint outputColorSpace;
+                    // The inputs the LUT covers; w is unused.
+                    vec4 lutDomainMin;
+                    vec4 lutDomainMax;
+                } parameters;
+
+                // Column-major, with chromatic adaptation between ACES' D60 white and D65.
+                const mat3 REC709_TO_ACESCG = mat3(
+                    0.6131, 0.0702, 0.0206,
+                    0.3395, 0.9164, 0.1096,
+                    0.0474, 0.0134, 0.8698
+                );
+                const mat3 ACESCG_TO_REC709 = mat3(
+                    1.7049, -0.1301, -0.0240,
+                    -0.6217, 1.1407, -0.1290,
+                    -0.0833, -0.0106, 1.1530
+                );
+                const mat3 REC709_TO_DISPLAY_P3 = mat3(
+                    0.8225, 0.0332, 0.0171,
+                    0.1774, 0.9669, 0.0724,
+                    0.0000, 0.0000, 0.9108
+                );
+                const mat3 REC709_TO_REC2020 = mat3(
+                    0.6274, 0.0691, 0.0164,
+                    0.3293, 0.9195, 0.0880,
+                    0.0433, 0.0114, 0.8956
+                );
+
+                vec3 aces(vec3 x) {
+                    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+                }
+
+                vec3 linearToSrgb(vec3 color) {
+                    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+                }
+
+                vec3 srgbToLinear(vec3 color) {
+                    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+                }
+
+                vec3 toWorking(vec3 color) {
+                    return parameters.workingColorSpace == 1 ? REC709_TO_ACESCG * color : color;
+                }
+
+                vec3 toOutput(vec3 color) {
+                    vec3 rec709 = parameters.workingColorSpace == 1 ? ACESCG_TO_REC709 * color : color;
+                    switch (parameters.outputColorSpace) {
+                        case 1: return REC709_TO_DISPLAY_P3 * rec709;
+                        case 2: return REC709_TO_REC2020 * rec709;
+                        default: return rec709;
+                    }
+                }
+
+                vec3 outputLuminanceWeights() {
+                    switch (parameters.outputColorSpace) {
+                        case 1: return vec3(0.2290, 0.6917, 0.0793);
+                        case 2: return vec3(0.2627, 0.6780, 0.0593);
+                        default: return vec3(0.2126, 0.7152, 0.0722);
+                    }
+                }
+
+                // Pulls colors outside the output's gamut towards their luminance until they fit, which
+                // keeps their hue and brightness where clipping each channel would shift both.
+                vec3 gamutMap(vec3 color) {
+                    float luminance = clamp(dot(color, outputLuminanceWeights()), 0.0, 1.0);
+                    float low = min(min(color.r, color.g), color.b);
+                    float high = max(max(color.r, color.g), color.b);
+                    float amount = 1.0;
+                    if (low < 0.0) {
+                        amount = min(amount, luminance / (luminance - low));
+                    }
+                    if (high > 1.0) {
+                        amount = min(amount, (1.0 - luminance) / (high - luminance));
+                    }
+                    return clamp(mix(vec3(luminance), color, amount), 0.0, 1.0);
+                }
+
+                float interleavedGradientNoise(vec2 position) {
+                    return fract(52.9829189 * fract(dot(position, vec2(0.06711056, 0.00583715))));
+                }
+
+                vec3 triangularNoise(vec2 position) {
+                    vec3 first = vec3(
+                        interleavedGradientNoise(position),
+                        interleavedGradientNoise(position + vec2(17.0, 59.0)),
+                        interleavedGradientNoise(position + vec2(83.0, 29.0))
+                    );
+                    vec3 second = vec3(
+                        interleavedGradientNoise(position + vec2(47.0, 7.0)),
+                        interleavedGradientNoise(position + vec2(13.0, 101.0)),
+                        interleavedGradientNoise(position + vec2(71.0, 41.0))
+                    );
+                    return first + second - 1.0;
+                }
+
+                void main() {
+                    vec4 scene = textureLod(sceneTexture, uv, 0.0);
+                    vec3 hdr = toWorking(scene.rgb * parameters.exposure);
+                    float alpha = parameters.preserveAlpha != 0 ? scene.a : 1.0;
+                    // Grading LUTs are authored against the display, so they apply after gamut mapping.
+                    vec3 encoded = linearToSrgb(gamutMap(toOutput(aces(hdr))));
+
+                    vec3 lutInput = clamp((encoded - parameters.lutDomainMin.rgb) / (parameters.lutDomainMax.rgb - parameters.lutDomainMin.rgb), 0.0, 1.0);
+                    vec3 lutCoord = (lutInput * (parameters.lutSize - 1.0) + 0.5) / parameters.lutSize;
+                    vec3 graded = textureLod(colorLut, lutCoord, 0.0).rgb;
+                    encoded = mix(encoded, graded, parameters.lutIntensity);
+                    // Scaled by coverage so fully transparent pixels stay black for the compositor.
+                    encoded += triangularNoise(gl_FragCoord.xy) * parameters.ditherAmplitude * alpha;
+
+                    // sRGB targets encode on store and floating-point ones are presented linearly, so only
+                    // UNORM targets receive the encoded value directly.
+                    outColor = vec4(parameters.manualGamma != 0 ? encoded : srgbToLinear(encoded), alpha);
+                }
+            ",
+    }
+}
+
+#[derive(Clone)]
+pub struct TonemapSettings {
+    pub exposure: f32,
+    pub color_lut: Option<Arc<ColorLut>>,
+    pub lut_intensity: f32,
+    pub dither: bool,
+    // Carries the scene's coverage into the output for alpha-composited windows.
+    pub preserve_alpha: bool,
+    pub working_color_space: WorkingColorSpace,
+    // Follows the swapchain's color space, which may have fallen back to sRGB.
+    pub output_color_space: OutputColorSpace,
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            color_lut: None,
+            lut_intensity: 1.0,
+            dither: true,
+            preserve_alpha: false,
+            working_color_space: WorkingColorSpace::default(),
+            output_color_space: OutputColorSpace::default(),
+        }
+    }
+}
+
+pub struct TonemapPass {
+    pipelines: OutputPipelines,
+    sampler: Arc<Sampler>,
+}
+
+impl TonemapPass {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let fragment_shader = tonemap_fs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+        let pipelines = OutputPipelines::new(|output_format| {
+            create_fullscreen_pipeline(device, fragment_shader.clone(), output_format)
+        })?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self { pipelines, sampler })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+        settings: &TonemapSettings,
+    ) -> Result<()> {
+        let color_lut = settings
+            .color_lut
+            .as_deref()
+            .unwrap_or(vulkan_device.identity_color_lut());
+        let [lut_domain_min, lut_domain_max] = [color_lut.domain_min(), color_lut.domain_max()]
+            .map(|[red, green, blue]| [red, green, blue, 0.0]);
+        let output_format = output.format();
+        let pipeline = self.pipelines.get(output_format)?;
+
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&pipeline.layout().set_layouts()[0]),
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    Arc::clone(input),
+                    Arc::clone(&self.sampler),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    Arc::clone(color_lut.view()),
+                    Arc::clone(&self.sampler),
+                ),
+            ],
+            [],
+        )?;
+
+        begin_fullscreen_pass(builder, pipeline, output)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(pipeline.layout()),
+                0,
+                tonemap_fs::TonemapParameters {
+                    exposure: settings.exposure,
+                    lutIntensity: if settings.color_lut.is_some() {
+                        settings.lut_intensity
+                    } else {
+                        0.0
+                    },
+                    lutSize: color_lut.size() as f32,
+                    ditherAmplitude: if settings.dither {
+                        quantization_step(output_format)
+                    } else {
+                        0.0
+                    },
+                    manualGamma: needs_manual_gamma(output_format) as u32,
+                    preserveAlpha: settings.preserve_alpha as u32,
+                    workingColorSpace: settings.working_color_space as u32,
+                    outputColorSpace: settings.output_color_space as u32,
+                    lutDomainMin: lut_domain_min,
+                    lutDomainMax: lut_domain_max,
+                },
+            )?;
+        unsafe { builder.draw(3, 1, 0, 0) }?;
+        builder.end_rendering()?;
+
+        Ok(())
+    }
+}
+
+pub struct TonemapEffect {
+    pub settings: TonemapSettings,
+    output_format: Format,
+}
+
+impl TonemapEffect {
+    pub fn new(output_format: Format) -> Self {
+        Self {
+            settings: TonemapSettings::default(),
+            output_format,
+        }
+    }
+}
+
+impl PostProcessEffect for TonemapEffect {
+    fn name(&self) -> &'static str {
+        "tonemap"
+    }
+
+    fn output_format(&self) -> Format {
+        self.output_format
+    }
+
+    fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        context: &PostProcessContext,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+    ) -> Result<()> {
+        context.vulkan_device.tonemap_pass().record(
+            builder,
+            context.vulkan_device,
+            input,
+            output,
+            &self.settings,
+        )
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageUsage, SampleCount};
+
+use crate::vulkan_device::VulkanDevice;
+
+struct TransientImage {
+    format: Format,
+    extent: [u32; 2],
+    usage: ImageUsage,
+    view: Arc<ImageView>,
+    is_in_use: bool,
+}
+
+#[derive(Default)]
+pub struct TransientImagePool {
+    images: Vec<TransientImage>,
+}
+
+impl TransientImagePool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.images
+            .iter_mut()
+            .for_each(|image| image.is_in_use = false);
+    }
+
+    pub fn acquire(
+        &mut self,
+        vulkan_device: &VulkanDevice,
+        format: Format,
+        extent: [u32; 2],
+        usage: ImageUsage,
+    ) -> Result<Arc<ImageView>> {
+        if let Some(image) = self.images.iter_mut().find(|image| {
+            !image.is_in_use
+                && image.format == format
+                && image.extent == extent
+                && image.usage.contains(usage)
+        }) {
+            image.is_in_use = true;
+            return Ok(Arc::clone(&image.view));
+        }
+
+        let view = vulkan_device.create_attachment(format, extent, usage, SampleCount::Sample1)?;
+        self.images.push(TransientImage {
+            format,
+            extent,
+            usage,
+            view: Arc::clone(&view),
+            is_in_use: true,
+        });
+
+        Ok(view)
+    }
+
+    pub fn release(&mut self, view: &Arc<ImageView>) {
+        if let Some(image) = self
+            .images
+            .iter_mut()
+            .find(|image| Arc::ptr_eq(&image.view, view))
+        {
+            image.is_in_use = false;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.images.clear();
+    }
+}
@@ -0,0 +1,585 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use nalgebra::{Matrix4, Point3};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::SampleCount;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{
+    AttachmentBlend, BlendFactor, BlendOp, ColorBlendAttachmentState, ColorBlendState,
+};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, DepthBiasState, RasterizationState};
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::{
+    ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+    PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+
+use crate::compute;
+use crate::gpu_scene::{self, ObjectDraw, GPU_SCENE_SET};
+use crate::light::DirectionalLight;
+use crate::material::DepthBias;
+use crate::vulkan_device::{Vertex, VulkanDevice, DEPTH_FORMAT, HDR_FORMAT, SCENE_FRONT_FACE};
+
+mod transparent_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 position;
+
+                layout(location = 0) out vec3 worldPosition;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                // The draw's first instance selects its object.
+                void main() {
+                    vec4 world = scene.objects[gl_InstanceIndex].transform * vec4(position, 1.0);
+                    gl_Position = uniforms.view_projection * world;
+                    worldPosition = world.xyz;
+                }
+            ",
+    }
+}
+
+mod transparent_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 worldPosition;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(push_constant) uniform TransparentParameters {
+                    vec4 baseColor;
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                } parameters;
+
+                void main() {
+                    vec3 normal = normalize(cross(dFdx(worldPosition), dFdy(worldPosition)));
+                    if (!gl_FrontFacing) {
+                        normal = -normal;
+                    }
+
+                    float diffuse = abs(dot(normal, parameters.sunDirection.xyz));
+                    vec3 albedo = parameters.baseColor.rgb;
+                    outColor = vec4(albedo * (0.3 + diffuse * parameters.sunColor.rgb), parameters.baseColor.a);
+                }
+            ",
+    }
+}
+
+pub const ACCUMULATION_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+pub const REVEALAGE_FORMAT: Format = Format::R16_SFLOAT;
+
+mod weighted_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 worldPosition;
+
+                layout(location = 0) out vec4 outAccumulation;
+                layout(location = 1) out float outRevealage;
+
+                layout(push_constant) uniform TransparentParameters {
+                    vec4 baseColor;
+                    vec4 sunDirection;
+                    vec4 sunColor;
+                } parameters;
+
+                void main() {
+                    vec3 normal = normalize(cross(dFdx(worldPosition), dFdy(worldPosition)));
+                    float diffuse = abs(dot(normal, parameters.sunDirection.xyz));
+                    vec3 color = parameters.baseColor.rgb * (0.3 + diffuse * parameters.sunColor.rgb);
+                    float alpha = parameters.baseColor.a;
+
+                    float depthWeight = pow(1.0 - gl_FragCoord.z * 0.9, 3.0);
+                    float weight = clamp(pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 1e8 * depthWeight, 1e-2, 3e3);
+
+                    outAccumulation = vec4(color * alpha, alpha) * weight;
+                    outRevealage = alpha;
+                }
+            ",
+    }
+}
+
+mod composite_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(set = 0, binding = 0) uniform sampler2D accumulationTexture;
+                layout(set = 0, binding = 1) uniform sampler2D revealageTexture;
+                layout(set = 0, binding = 2, rgba16f) uniform image2D sceneColor;
+
+                void main() {
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    if (any(greaterThanEqual(pixel, imageSize(sceneColor)))) {
+                        return;
+                    }
+
+                    float revealage = texelFetch(revealageTexture, pixel, 0).r;
+                    if (revealage >= 1.0) {
+                        return;
+                    }
+
+                    vec4 accumulation = texelFetch(accumulationTexture, pixel, 0);
+                    vec3 average = accumulation.rgb / max(accumulation.a, 1e-5);
+                    vec4 color = imageLoad(sceneColor, pixel);
+                    imageStore(sceneColor, pixel, vec4(mix(average, color.rgb, revealage), color.a));
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransparencyMode {
+    #[default]
+    Sorted,
+    WeightedBlended,
+}
+
+impl FromStr for TransparencyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "sorted" => TransparencyMode::Sorted,
+            "weighted" | "wboit" | "oit" => TransparencyMode::WeightedBlended,
+            _ => bail!("Unknown transparency mode: {value}"),
+        })
+    }
+}
+
+pub struct WeightedBlendedViews<'a> {
+    pub accumulation: &'a Arc<ImageView>,
+    pub revealage: &'a Arc<ImageView>,
+    pub depth: &'a Arc<ImageView>,
+    pub scene_color: &'a Arc<ImageView>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TransparentDraw {
+    pub object: ObjectDraw,
+    // In world space.
+    pub center: Point3<f32>,
+    pub color: [f32; 4],
+    pub double_sided: bool,
+    pub depth_bias: DepthBias,
+}
+
+// Like `SceneObjects`, with the GPU scene set the draws' objects index.
+pub struct TransparentObjects<'a> {
+    pub set: &'a Arc<DescriptorSet>,
+    pub draws: &'a [TransparentDraw],
+}
+
+pub fn sort_back_to_front(draws: &mut [TransparentDraw], view_projection: &Matrix4<f32>) {
+    let view_depth = |draw: &TransparentDraw| (view_projection * draw.center.to_homogeneous()).w;
+    draws.sort_by(|a, b| view_depth(b).total_cmp(&view_depth(a)));
+}
+
+pub struct TransparencyPass {
+    back_face_pipeline: Arc<GraphicsPipeline>,
+    front_face_pipeline: Arc<GraphicsPipeline>,
+    accumulate_pipeline: Arc<GraphicsPipeline>,
+    composite_pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl TransparencyPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        samples: SampleCount,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let vertex_shader = transparent_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = Vertex::per_vertex().definition(&vertex_shader).unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(
+                transparent_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let layout = gpu_scene::create_pipeline_layout(device, &stages, gpu_scene_set_layout)?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let create_pipeline = |cull_mode: CullMode| {
+            GraphicsPipeline::new(
+                Arc::clone(device),
+                Some(Arc::clone(pipeline_cache)),
+                GraphicsPipelineCreateInfo {
+                    stages: stages.iter().cloned().collect(),
+                    vertex_input_state: Some(vertex_input_state.clone()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState {
+                        cull_mode,
+                        front_face: SCENE_FRONT_FACE,
+                        depth_bias: Some(DepthBiasState::default()),
+                        ..Default::default()
+                    }),
+                    depth_stencil_state: Some(DepthStencilState {
+                        depth: Some(DepthState {
+                            write_enable: false,
+                            compare_op: CompareOp::Less,
+                        }),
+                        ..Default::default()
+                    }),
+                    multisample_state: Some(MultisampleState {
+                        rasterization_samples: samples,
+                        ..Default::default()
+                    }),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        1,
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend::alpha()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport, DynamicState::DepthBias]
+                        .into_iter()
+                        .collect(),
+                    subpass: Some(subpass.clone().into()),
+                    ..GraphicsPipelineCreateInfo::layout(Arc::clone(&layout))
+                },
+            )
+        };
+
+        let accumulate_stages = [
+            stages[0].clone(),
+            PipelineShaderStageCreateInfo::new(
+                weighted_fs::load(Arc::clone(device))?
+                    .entry_point("main")
+                    .unwrap(),
+            ),
+        ];
+
+        let accumulate_layout =
+            gpu_scene::create_pipeline_layout(device, &accumulate_stages, gpu_scene_set_layout)?;
+
+        let accumulate_subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(ACCUMULATION_FORMAT), Some(REVEALAGE_FORMAT)],
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            ..Default::default()
+        };
+
+        let accumulate_pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            Some(Arc::clone(pipeline_cache)),
+            GraphicsPipelineCreateInfo {
+                stages: accumulate_stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state.clone()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState {
+                    cull_mode: CullMode::None,
+                    front_face: SCENE_FRONT_FACE,
+                    depth_bias: Some(DepthBiasState::default()),
+                    ..Default::default()
+                }),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: CompareOp::Less,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState {
+                    attachments: vec![
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend {
+                                src_color_blend_factor: BlendFactor::One,
+                                dst_color_blend_factor: BlendFactor::One,
+                                color_blend_op: BlendOp::Add,
+                                src_alpha_blend_factor: BlendFactor::One,
+                                dst_alpha_blend_factor: BlendFactor::One,
+                                alpha_blend_op: BlendOp::Add,
+                            }),
+                            ..Default::default()
+                        },
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend {
+                                src_color_blend_factor: BlendFactor::Zero,
+                                dst_color_blend_factor: BlendFactor::OneMinusSrcColor,
+                                color_blend_op: BlendOp::Add,
+                                src_alpha_blend_factor: BlendFactor::Zero,
+                                dst_alpha_blend_factor: BlendFactor::OneMinusSrcAlpha,
+                                alpha_blend_op: BlendOp::Add,
+                            }),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }),
+                dynamic_state: [DynamicState::Viewport, DynamicState::DepthBias]
+                    .into_iter()
+                    .collect(),
+                subpass: Some(accumulate_subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(accumulate_layout)
+            },
+        )?;
+
+        let composite_pipeline = compute::create_compute_pipeline(
+            device,
+            Some(Arc::clone(pipeline_cache)),
+            composite_cs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            back_face_pipeline: create_pipeline(CullMode::Front)?,
+            front_face_pipeline: create_pipeline(CullMode::Back)?,
+            accumulate_pipeline,
+            composite_pipeline,
+            sampler,
+        })
+    }
+
+    // Draws must already be sorted back to front; double-sided ones render their back faces before their front faces.
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        objects: &TransparentObjects,
+        sun: &DirectionalLight,
+    ) -> Result<()> {
+        if objects.draws.is_empty() {
+            return Ok(());
+        }
+
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.front_face_pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(
+                0,
+                vulkan_device.uniform_buffer().clone(),
+            )],
+            [],
+        )?;
+
+        let sun_direction = sun.direction.normalize();
+        let [sun_r, sun_g, sun_b] = sun.color;
+
+        builder
+            .bind_vertex_buffers(0, vulkan_device.vertex_buffer().clone())?
+            .bind_index_buffer(vulkan_device.index_buffer().clone())?;
+
+        for draw in objects.draws {
+            let pipelines = if draw.double_sided {
+                &[&self.back_face_pipeline, &self.front_face_pipeline][..]
+            } else {
+                &[&self.front_face_pipeline][..]
+            };
+
+            for pipeline in pipelines {
+                builder.bind_pipeline_graphics(Arc::clone(pipeline))?;
+                draw.depth_bias.record(builder)?;
+                builder
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        Arc::clone(pipeline.layout()),
+                        0,
+                        Arc::clone(&set),
+                    )?
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        Arc::clone(pipeline.layout()),
+                        GPU_SCENE_SET,
+                        Arc::clone(objects.set),
+                    )?
+                    .push_constants(
+                        Arc::clone(pipeline.layout()),
+                        0,
+                        transparent_fs::TransparentParameters {
+                            baseColor: draw.color,
+                            sunDirection: [sun_direction.x, sun_direction.y, sun_direction.z, 0.0],
+                            sunColor: [sun_r, sun_g, sun_b, 1.0],
+                        },
+                    )?;
+                draw.object.record(builder)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Order-independent alternative to draw: accumulates weighted color and revealage, then resolves them over the scene.
+    pub fn draw_weighted_blended(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        objects: &TransparentObjects,
+        sun: &DirectionalLight,
+        views: &WeightedBlendedViews,
+        viewport: &Viewport,
+    ) -> Result<()> {
+        if objects.draws.is_empty() {
+            return Ok(());
+        }
+
+        let WeightedBlendedViews {
+            accumulation,
+            revealage,
+            depth,
+            scene_color,
+        } = views;
+        let [width, height, _] = scene_color.image().extent();
+
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.accumulate_pipeline.layout().set_layouts()[0]),
+            [WriteDescriptorSet::buffer(
+                0,
+                vulkan_device.uniform_buffer().clone(),
+            )],
+            [],
+        )?;
+
+        let sun_direction = sun.direction.normalize();
+        let [sun_r, sun_g, sun_b] = sun.color;
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![
+                    Some(RenderingAttachmentInfo {
+                        load_op: AttachmentLoadOp::Clear,
+                        store_op: AttachmentStoreOp::Store,
+                        clear_value: Some(ClearValue::Float([0.0; 4])),
+                        ..RenderingAttachmentInfo::image_view(Arc::clone(accumulation))
+                    }),
+                    Some(RenderingAttachmentInfo {
+                        load_op: AttachmentLoadOp::Clear,
+                        store_op: AttachmentStoreOp::Store,
+                        clear_value: Some(ClearValue::Float([1.0; 4])),
+                        ..RenderingAttachmentInfo::image_view(Arc::clone(revealage))
+                    }),
+                ],
+                depth_attachment: Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(depth))
+                }),
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport.clone()].into_iter().collect())?
+            .bind_pipeline_graphics(Arc::clone(&self.accumulate_pipeline))?
+            .bind_vertex_buffers(0, vulkan_device.vertex_buffer().clone())?
+            .bind_index_buffer(vulkan_device.index_buffer().clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.accumulate_pipeline.layout()),
+                0,
+                set,
+            )?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.accumulate_pipeline.layout()),
+                GPU_SCENE_SET,
+                Arc::clone(objects.set),
+            )?;
+
+        for draw in objects.draws {
+            draw.depth_bias.record(builder)?;
+            builder.push_constants(
+                Arc::clone(self.accumulate_pipeline.layout()),
+                0,
+                weighted_fs::TransparentParameters {
+                    baseColor: draw.color,
+                    sunDirection: [sun_direction.x, sun_direction.y, sun_direction.z, 0.0],
+                    sunColor: [sun_r, sun_g, sun_b, 1.0],
+                },
+            )?;
+            draw.object.record(builder)?;
+        }
+
+        builder.end_rendering()?;
+
+        vulkan_device.bind_compute(
+            builder,
+            &self.composite_pipeline,
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    Arc::clone(accumulation),
+                    Arc::clone(&self.sampler),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    Arc::clone(revealage),
+                    Arc::clone(&self.sampler),
+                ),
+                WriteDescriptorSet::image_view(2, Arc::clone(scene_color)),
+            ],
+        )?;
+        unsafe { builder.dispatch([width.div_ceil(8), height.div_ceil(8), 1]) }?;
+
+        Ok(())
+    }
+}
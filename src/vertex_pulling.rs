@@ -0,0 +1,269 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::DescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::SampleCount;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, DepthBiasState, RasterizationState};
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineShaderStageCreateInfo,
+};
+use vulkano::shader::EntryPoint;
+use vulkano::Version;
+
+use crate::gpu_scene::{self, GPU_SCENE_SET};
+use crate::material::input_assembly_state;
+use crate::vulkan_device::{
+    specialize, vs, DebugView, VulkanDevice, DEPTH_FORMAT, HDR_FORMAT, NORMAL_FORMAT,
+    SCENE_FRONT_FACE, VELOCITY_FORMAT,
+};
+
+// The scene vertex shader with the vertex input stage replaced by reads through the vertex
+// buffer's device address. `Vertex` is a tightly packed position, so each vertex is 3 floats.
+mod pulling_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        custom_derives: [Clone, Copy],
+        src: r"
+                #version 460
+                #extension GL_EXT_buffer_reference : require
+                #extension GL_EXT_buffer_reference_uvec2 : require
+
+                layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer Vertices {
+                    float data[];
+                };
+
+                layout(location = 0) out vec3 fragColor;
+                layout(location = 1) out vec3 viewPosition;
+                layout(location = 2) out vec4 currentClipPosition;
+                layout(location = 3) out vec4 previousClipPosition;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                    mat4 previous_view_projection;
+                } uniforms;
+
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                struct GpuMaterial {
+                    vec4 baseColor;
+                    float alphaCutoff;
+                    float pointSize;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                layout(set = 3, binding = 1) readonly buffer Materials {
+                    GpuMaterial materials[];
+                } sceneMaterials;
+
+                layout(push_constant) uniform PushConstantData {
+                    float time;
+                    vec2 mousePosition;
+                    vec4 baseColor;
+                    float alphaCutoff;
+                    float pointSize;
+                    uvec2 vertexAddress;
+                } pc;
+
+                // The draw's first instance selects its object.
+                void main() {
+                    // gl_VertexIndex already includes the draw's vertex offset.
+                    Vertices vertices = Vertices(pc.vertexAddress);
+                    uint base = gl_VertexIndex * 3;
+                    vec3 position = vec3(vertices.data[base], vertices.data[base + 1], vertices.data[base + 2]);
+
+                    GpuObject object = scene.objects[gl_InstanceIndex];
+                    vec4 worldPosition = object.transform * vec4(position, 1.0);
+                    gl_Position = uniforms.view_projection * worldPosition;
+                    gl_PointSize = sceneMaterials.materials[object.material].pointSize;
+                    fragColor = worldPosition.xyz;
+                    viewPosition = (uniforms.view * worldPosition).xyz;
+                    currentClipPosition = gl_Position;
+                    previousClipPosition = uniforms.previous_view_projection * object.previousTransform * vec4(position, 1.0);
+                }
+            ",
+    }
+}
+
+// Pipelines for the scene's depth prepass and lit pass that fetch their own vertices, so they
+// carry no vertex input state and work with any vertex layout the shader knows how to read.
+pub struct VertexPullingPass {
+    pipeline: Arc<GraphicsPipeline>,
+    prepass_pipeline: Arc<GraphicsPipeline>,
+}
+
+impl VertexPullingPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: &Arc<PipelineCache>,
+        samples: SampleCount,
+        topology: PrimitiveTopology,
+        fragment_shader: EntryPoint,
+        prepass_fragment_shader: EntryPoint,
+        gpu_scene_set_layout: &Arc<DescriptorSetLayout>,
+    ) -> Result<Self> {
+        let create_pipeline = |fragment_stage: PipelineShaderStageCreateInfo,
+                               color_attachment_formats: Vec<_>,
+                               depth: DepthState| {
+            let stages = [
+                PipelineShaderStageCreateInfo::new(
+                    pulling_vs::load(Arc::clone(device))?
+                        .entry_point("main")
+                        .unwrap(),
+                ),
+                fragment_stage,
+            ];
+
+            let layout = gpu_scene::create_pipeline_layout(device, &stages, gpu_scene_set_layout)?;
+
+            let subpass = PipelineRenderingCreateInfo {
+                color_attachment_formats,
+                depth_attachment_format: Some(DEPTH_FORMAT),
+                ..Default::default()
+            };
+
+            anyhow::Ok(GraphicsPipeline::new(
+                Arc::clone(device),
+                Some(Arc::clone(pipeline_cache)),
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    input_assembly_state: Some(input_assembly_state(topology)),
+                    vertex_input_state: Some(VertexInputState::new()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState {
+                        cull_mode: CullMode::None,
+                        front_face: SCENE_FRONT_FACE,
+                        depth_bias: Some(DepthBiasState::default()),
+                        ..Default::default()
+                    }),
+                    depth_stencil_state: Some(DepthStencilState {
+                        depth: Some(depth),
+                        ..Default::default()
+                    }),
+                    multisample_state: Some(MultisampleState {
+                        rasterization_samples: samples,
+                        ..Default::default()
+                    }),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        subpass.color_attachment_formats.len() as u32,
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    dynamic_state: [
+                        DynamicState::Viewport,
+                        DynamicState::DepthBias,
+                        DynamicState::LineWidth,
+                    ]
+                    .into_iter()
+                    .chain(
+                        device
+                            .enabled_features()
+                            .pipeline_fragment_shading_rate
+                            .then_some(DynamicState::FragmentShadingRate),
+                    )
+                    .chain(
+                        (device.api_version() >= Version::V1_3
+                            || device.enabled_features().extended_dynamic_state)
+                            .then_some(DynamicState::CullMode),
+                    )
+                    .collect(),
+                    subpass: Some(subpass.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )?)
+        };
+
+        Ok(Self {
+            pipeline: create_pipeline(
+                PipelineShaderStageCreateInfo::new(specialize(
+                    &fragment_shader,
+                    [(0, (DebugView::Lit as u32).into())],
+                )?),
+                vec![Some(HDR_FORMAT)],
+                DepthState {
+                    write_enable: false,
+                    compare_op: CompareOp::LessOrEqual,
+                },
+            )?,
+            prepass_pipeline: create_pipeline(
+                PipelineShaderStageCreateInfo::new(prepass_fragment_shader),
+                vec![Some(NORMAL_FORMAT), Some(VELOCITY_FORMAT)],
+                DepthState::simple(),
+            )?,
+        })
+    }
+
+    pub fn pipeline(&self) -> &Arc<GraphicsPipeline> {
+        &self.pipeline
+    }
+
+    pub fn prepass_pipeline(&self) -> &Arc<GraphicsPipeline> {
+        &self.prepass_pipeline
+    }
+
+    // Binds one of this pass's pipelines for drawing the scene's objects, with the material push
+    // constants followed by the vertex buffer's address, which material changes leave in place.
+    // Only the index buffer is bound.
+    pub fn bind(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        pipeline: &Arc<GraphicsPipeline>,
+        descriptor_sets: Vec<Arc<DescriptorSet>>,
+        gpu_scene_set: &Arc<DescriptorSet>,
+        push_constants: vs::PushConstantData,
+    ) -> Result<()> {
+        let vertex_address = vulkan_device.vertex_buffer().device_address()?.get();
+
+        builder
+            .bind_pipeline_graphics(Arc::clone(pipeline))?
+            .bind_index_buffer(vulkan_device.index_buffer().clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(pipeline.layout()),
+                0,
+                descriptor_sets,
+            )?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(pipeline.layout()),
+                GPU_SCENE_SET,
+                Arc::clone(gpu_scene_set),
+            )?
+            .push_constants(
+                Arc::clone(pipeline.layout()),
+                0,
+                pulling_vs::PushConstantData {
+                    time: push_constants.time,
+                    mousePosition: push_constants.mousePosition,
+                    baseColor: push_constants.baseColor,
+                    alphaCutoff: push_constants.alphaCutoff,
+                    pointSize: push_constants.pointSize,
+                    vertexAddress: [vertex_address as u32, (vertex_address >> 32) as u32],
+                },
+            )?;
+
+        Ok(())
+    }
+}
@@ -1,75 +1,301 @@
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
 use gltf::camera::Projection;
-use nalgebra::{Isometry3, OMatrix, Perspective3, Point3, Vector3};
+use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, Vector3};
 use palette::angle::RealAngle;
+use tracing::warn;
 use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
-use vulkano::buffer::{BufferContents, BufferUsage, Subbuffer};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
 use vulkano::command_buffer::allocator::{
     StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo,
 };
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferInfo};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferInfo, CopyImageToBufferInfo,
+    PrimaryAutoCommandBuffer,
+};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocatorCreateInfo;
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
 use vulkano::descriptor_set::{
-    allocator::StandardDescriptorSetAllocator, DescriptorSet, PersistentDescriptorSet,
-    WriteDescriptorSet,
+    allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+};
+use vulkano::device::{
+    Device, DeviceCreateInfo, DeviceFeatures, DeviceOwned, Queue, QueueCreateInfo,
 };
-use vulkano::device::{Device, DeviceCreateInfo, Features, Queue, QueueCreateInfo};
 use vulkano::format::Format;
-use vulkano::image::SampleCount;
-use vulkano::memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator};
-use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
-use vulkano::pipeline::graphics::depth_stencil::{DepthState, DepthStencilState};
-use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageUsage, SampleCount};
+use vulkano::memory::allocator::{
+    AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator,
+};
+use vulkano::pipeline::graphics::color_blend::{
+    AttachmentBlend, ColorBlendAttachmentState, ColorBlendState,
+};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
 use vulkano::pipeline::graphics::multisample::MultisampleState;
-use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::rasterization::{
+    CullMode, DepthBiasState, FrontFace, PolygonMode, RasterizationState,
+};
 use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
 use vulkano::pipeline::graphics::vertex_input::{Vertex as VertexInputVertex, VertexDefinition};
 use vulkano::pipeline::graphics::viewport::ViewportState;
 use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
-use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::cache::{PipelineCache, PipelineCacheCreateInfo};
 use vulkano::pipeline::{
-    DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineShaderStageCreateInfo,
 };
+use vulkano::shader::{EntryPoint, SpecializationConstant};
+use vulkano::sync::future::FenceSignalFuture;
 use vulkano::sync::GpuFuture;
-use vulkano::{sync, DeviceSize};
+use vulkano::{sync, DeviceSize, Version};
 
+use crate::allocator_stats::AllocatorStatistics;
+use crate::background::BackgroundPass;
+use crate::billboard::BillboardPass;
+use crate::color_lut::ColorLut;
+use crate::compute;
+use crate::contact_shadows::ContactShadowPass;
+use crate::debug_draw::DebugDrawPass;
+use crate::download::{
+    ensure_buffer_downloadable, image_download_size, Download, DownloadedImage, ImageDownload,
+};
+use crate::embedded_assets::{import_scene, SCENE_PATH};
+use crate::foliage::FoliagePass;
+use crate::fxaa::FxaaPass;
+use crate::gpu_scene;
+use crate::grid::GridPass;
+use crate::ibl::ImageBasedLighting;
+use crate::material::{
+    check_restart_index, input_assembly_state, primitive_topology, triangulate_fan, SceneMaterial,
+};
+use crate::memory::{query_heap_budgets, HeapBudget};
+use crate::mesh_pool::{
+    MeshAllocation, MeshPool, DEFAULT_INDEX_CAPACITY, DEFAULT_VERTEX_CAPACITY,
+};
+use crate::meshlet::{MeshletMesh, MeshletPass};
+use crate::motion_blur::MotionBlurPass;
+use crate::normal_visualization::NormalVisualizationPass;
+use crate::outline::OutlinePass;
+use crate::particles::ParticlePass;
+use crate::path_tracing::PathTracingPass;
+use crate::picking::PickingPass;
+use crate::punctual_light::{load_punctual_lights, PunctualLight};
+use crate::ray_query::RayQueryPass;
+use crate::ray_tracing::{RayTracingPass, SceneAccelerationStructure};
+use crate::scene_camera::{load_scene_cameras, SceneCamera};
+use crate::scene_graph::{load_scenes, GltfScene, SceneGraph};
+use crate::shadow_map::ShadowMapPass;
+use crate::skybox::SkyboxPass;
+use crate::sprite::SpritePass;
+use crate::ssao::SsaoPass;
+use crate::stereo::StereoPass;
+use crate::tessellation::TessellationPass;
+use crate::tonemap::TonemapPass;
+use crate::transparency::TransparencyPass;
+use crate::vertex_pulling::VertexPullingPass;
 use crate::vulkan_instance::VulkanInstance;
+use crate::water::WaterPass;
+
+pub const HDR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+pub const DEPTH_FORMAT: Format = Format::D32_SFLOAT;
+pub const NORMAL_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+pub const VELOCITY_FORMAT: Format = Format::R16G16_SFLOAT;
+// The GL-style projection is not y-flipped, so counter-clockwise glTF triangles rasterize clockwise.
+pub const SCENE_FRONT_FACE: FrontFace = FrontFace::Clockwise;
+
+const PIPELINE_CACHE_PATH: &str = "pipeline_cache.bin";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+    None,
+    Msaa(SampleCount),
+    Fxaa,
+}
+
+impl FromStr for AntiAliasing {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "none" | "off" => AntiAliasing::None,
+            "fxaa" => AntiAliasing::Fxaa,
+            "msaa2" => AntiAliasing::Msaa(SampleCount::Sample2),
+            "msaa4" => AntiAliasing::Msaa(SampleCount::Sample4),
+            "msaa" | "msaa8" => AntiAliasing::Msaa(SampleCount::Sample8),
+            "msaa16" => AntiAliasing::Msaa(SampleCount::Sample16),
+            _ => bail!("Unknown anti-aliasing mode: {value}"),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    Lit,
+    Albedo,
+    Normals,
+    Material,
+    Depth,
+    Overdraw,
+    MipLevel,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 7] = [
+        DebugView::Lit,
+        DebugView::Albedo,
+        DebugView::Normals,
+        DebugView::Material,
+        DebugView::Depth,
+        DebugView::Overdraw,
+        DebugView::MipLevel,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+impl FromStr for DebugView {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "lit" | "none" | "off" => DebugView::Lit,
+            "albedo" | "unlit" => DebugView::Albedo,
+            "normals" => DebugView::Normals,
+            "material" => DebugView::Material,
+            "depth" => DebugView::Depth,
+            "overdraw" => DebugView::Overdraw,
+            "mip" | "miplevel" => DebugView::MipLevel,
+            _ => bail!("Unknown debug view: {value}"),
+        })
+    }
+}
 
 pub struct VulkanDevice {
     queue: Arc<Queue>,
     memory_allocator: Arc<StandardMemoryAllocator>,
     command_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    pipeline_cache: Arc<PipelineCache>,
+    prepass_pipeline: Arc<GraphicsPipeline>,
     graphics_pipeline: Arc<GraphicsPipeline>,
-    vertex_buffer: Subbuffer<[Vertex]>,
-    index_buffer: Subbuffer<[u16]>,
+    wireframe_pipeline: Option<Arc<GraphicsPipeline>>,
+    debug_view_pipelines: Vec<Arc<GraphicsPipeline>>,
+    gpu_scene_set_layout: Arc<DescriptorSetLayout>,
+    mesh_pool: MeshPool,
+    scene_mesh: MeshAllocation,
+    uniform_buffer: Subbuffer<Uniform>,
+    camera_position: Point3<f32>,
+    camera_target: Point3<f32>,
+    camera_projection: Perspective3<f32>,
+    view_projection: Matrix4<f32>,
+    scene_cameras: Vec<SceneCamera>,
+    punctual_lights: Vec<PunctualLight>,
+    scene_graph: SceneGraph,
+    scenes: Vec<GltfScene>,
+    default_scene: usize,
+    scene_materials: Vec<SceneMaterial>,
+    scene_center: Point3<f32>,
+    scene_topology: PrimitiveTopology,
+    anti_aliasing: AntiAliasing,
     samples: SampleCount,
-    set: Arc<PersistentDescriptorSet>,
+    set: Arc<DescriptorSet>,
+    ssao_pass: SsaoPass,
+    contact_shadow_pass: ContactShadowPass,
+    shadow_map_pass: ShadowMapPass,
+    fxaa_pass: FxaaPass,
+    motion_blur_pass: MotionBlurPass,
+    tonemap_pass: TonemapPass,
+    skybox_pass: SkyboxPass,
+    background_pass: BackgroundPass,
+    water_pass: WaterPass,
+    foliage_pass: FoliagePass,
+    particle_pass: ParticlePass,
+    tessellation_pass: Option<TessellationPass>,
+    stereo_pass: Option<StereoPass>,
+    vertex_pulling_pass: Option<VertexPullingPass>,
+    normal_visualization_pass: Option<NormalVisualizationPass>,
+    debug_draw_pass: DebugDrawPass,
+    billboard_pass: BillboardPass,
+    grid_pass: GridPass,
+    transparency_pass: TransparencyPass,
+    picking_pass: PickingPass,
+    outline_pass: OutlinePass,
+    sprite_pass: SpritePass,
+    meshlet_pass: Option<MeshletPass>,
+    scene_acceleration: Option<Mutex<SceneAccelerationStructure>>,
+    ray_tracing_pass: Option<RayTracingPass>,
+    ray_query_pass: Option<RayQueryPass>,
+    path_tracing_pass: Option<PathTracingPass>,
+    identity_color_lut: ColorLut,
+    default_lighting: ImageBasedLighting,
 }
 
 pub mod vs {
     vulkano_shaders::shader! {
         ty: "vertex",
+        custom_derives: [Clone, Copy],
         src: r"
                 #version 460
 
                 layout(location = 0) in vec3 position;
                 
                 layout(location = 0) out vec3 fragColor;
+                layout(location = 1) out vec3 viewPosition;
+                layout(location = 2) out vec4 currentClipPosition;
+                layout(location = 3) out vec4 previousClipPosition;
                 
                 layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
                     mat4 view_projection;
+                    mat4 inverse_projection;
+                    mat4 previous_view_projection;
                 } uniforms;
                 
+                struct GpuObject {
+                    mat4 transform;
+                    mat4 previousTransform;
+                    uint mesh;
+                    uint material;
+                };
+
+                struct GpuMaterial {
+                    vec4 baseColor;
+                    float alphaCutoff;
+                    float pointSize;
+                };
+
+                layout(set = 3, binding = 0) readonly buffer Objects {
+                    GpuObject objects[];
+                } scene;
+
+                layout(set = 3, binding = 1) readonly buffer Materials {
+                    GpuMaterial materials[];
+                } sceneMaterials;
+                
                 layout(push_constant) uniform PushConstantData {
                     float time;
                     vec2 mousePosition;
+                    vec4 baseColor;
+                    float alphaCutoff;
+                    float pointSize;
                 } pc;
 
+                // The draw's first instance selects its object.
                 void main() {
-                    gl_Position = uniforms.view_projection * vec4(position, 1.0);
-                    fragColor = position;
+                    GpuObject object = scene.objects[gl_InstanceIndex];
+                    vec4 worldPosition = object.transform * vec4(position, 1.0);
+                    gl_Position = uniforms.view_projection * worldPosition;
+                    gl_PointSize = sceneMaterials.materials[object.material].pointSize;
+                    fragColor = worldPosition.xyz;
+                    viewPosition = (uniforms.view * worldPosition).xyz;
+                    currentClipPosition = gl_Position;
+                    previousClipPosition = uniforms.previous_view_projection * object.previousTransform * vec4(position, 1.0);
                 }
             ",
     }
@@ -82,16 +308,312 @@ mod fs {
                     #version 460
 
                     layout(location = 0) in vec3 fragColor;
+                    layout(location = 1) in vec3 viewPosition;
 
                     layout(location = 0) out vec4 outColor;
 
+                    layout(set = 0, binding = 0) uniform Data {
+                        mat4 view;
+                        mat4 projection;
+                        mat4 view_projection;
+                        mat4 inverse_projection;
+                    } uniforms;
+
+                    layout(set = 1, binding = 0) uniform sampler2D occlusionTexture;
+                    layout(set = 1, binding = 1) uniform sampler2D visibilityTexture;
+
+                    layout(set = 2, binding = 0) uniform samplerCube irradianceMap;
+                    layout(set = 2, binding = 1) uniform samplerCube prefilteredMap;
+                    layout(set = 2, binding = 2) uniform sampler2D brdfLut;
+                    layout(set = 2, binding = 3) uniform Sun {
+                        vec4 direction;
+                        vec4 color;
+                    } sun;
+
+                    // Has to match MAX_REFLECTION_PROBES on the host.
+                    const uint MAX_REFLECTION_PROBES = 4;
+
+                    layout(set = 2, binding = 4) uniform samplerCube reflectionProbeMaps[MAX_REFLECTION_PROBES];
+                    layout(set = 2, binding = 5) uniform ReflectionProbes {
+                        vec4 boxMinimum[MAX_REFLECTION_PROBES];
+                        vec4 boxMaximum[MAX_REFLECTION_PROBES];
+                        vec4 position[MAX_REFLECTION_PROBES];
+                        uint count;
+                    } reflectionProbes;
+
+                    struct LightmapChart {
+                        vec4 u;
+                        vec4 v;
+                        vec4 plane;
+                        vec4 cell;
+                    };
+
+                    layout(set = 2, binding = 6) uniform sampler2D lightmap;
+                    layout(set = 2, binding = 7) readonly buffer LightmapCharts {
+                        LightmapChart charts[];
+                    } lightmapCharts;
+
+                    // Probes sit at the centers of the grid's cells, with nine vec4s of coefficients each.
+                    layout(set = 2, binding = 8) uniform ShProbeGrid {
+                        vec4 boundsMinimum;
+                        vec4 boundsMaximum;
+                        uvec4 resolution;
+                    } shProbeGrid;
+                    layout(set = 2, binding = 9) readonly buffer ShProbeCoefficients {
+                        vec4 coefficients[];
+                    } shProbes;
+
+                    layout(push_constant) uniform PushConstantData {
+                        float time;
+                        vec2 mousePosition;
+                        vec4 baseColor;
+                        float alphaCutoff;
+                        float pointSize;
+                    } pc;
+
+                    layout(constant_id = 0) const uint DEBUG_VIEW = 0;
+
+                    const float METALLIC = 0.0;
+                    const float ROUGHNESS = 0.5;
+                    const float DEBUG_TEXTURE_SIZE = 1024.0;
+
+                    // Points and lines have no screen-space area, so they face the camera instead.
+                    vec3 faceNormal(vec3 position) {
+                        vec3 normal = cross(dFdx(position), dFdy(position));
+                        return dot(normal, normal) > 0.0 ? normalize(normal) : normalize(-position);
+                    }
+
+                    vec3 heatmap(float value) {
+                        return clamp(vec3(value * 3.0, value * 3.0 - 1.0, value * 3.0 - 2.0), 0.0, 1.0);
+                    }
+
+                    vec3 fresnelSchlickRoughness(float cosTheta, vec3 f0, float roughness) {
+                        return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - cosTheta, 5.0);
+                    }
+
+                    // Fades in over the blend distance inside the probe's box, and samples where the reflected
+                    // ray leaves the box as seen from the probe. The alpha is the weight.
+                    vec4 reflectionProbe(uint index, samplerCube probeMap, vec3 worldPosition, vec3 worldReflection, float lod) {
+                        if (index >= reflectionProbes.count) {
+                            return vec4(0.0);
+                        }
+
+                        vec3 boxMinimum = reflectionProbes.boxMinimum[index].xyz;
+                        vec3 boxMaximum = reflectionProbes.boxMaximum[index].xyz;
+                        vec3 inside = min(worldPosition - boxMinimum, boxMaximum - worldPosition);
+                        float blendDistance = max(reflectionProbes.boxMinimum[index].w, 0.0001);
+                        float weight = clamp(min(min(inside.x, inside.y), inside.z) / blendDistance, 0.0, 1.0);
+                        if (weight == 0.0) {
+                            return vec4(0.0);
+                        }
+
+                        vec3 exitPlanes = mix(boxMinimum, boxMaximum, step(0.0, worldReflection));
+                        vec3 exitDistances = (exitPlanes - worldPosition) / worldReflection;
+                        float exitDistance = min(min(exitDistances.x, exitDistances.y), exitDistances.z);
+                        vec3 direction = worldPosition + worldReflection * exitDistance - reflectionProbes.position[index].xyz;
+                        return vec4(textureLod(probeMap, direction, lod).rgb * weight, weight);
+                    }
+
+                    // Overlapping probes share their weight, and the environment fills in what's left. The
+                    // probes are indexed with constants, which doesn't need dynamic indexing of sampler arrays.
+                    vec3 prefilteredReflection(vec3 worldPosition, vec3 worldReflection, float lod) {
+                        vec4 probes = reflectionProbe(0, reflectionProbeMaps[0], worldPosition, worldReflection, lod)
+                            + reflectionProbe(1, reflectionProbeMaps[1], worldPosition, worldReflection, lod)
+                            + reflectionProbe(2, reflectionProbeMaps[2], worldPosition, worldReflection, lod)
+                            + reflectionProbe(3, reflectionProbeMaps[3], worldPosition, worldReflection, lod);
+                        if (probes.a >= 1.0) {
+                            return probes.rgb / probes.a;
+                        }
+                        return probes.rgb + textureLod(prefilteredMap, worldReflection, lod).rgb * (1.0 - probes.a);
+                    }
+
+                    // The baked irradiance, with an alpha of 1 where the fragment lies on its primitive's chart.
+                    // Primitive IDs restart with every draw, so draws starting past the scene mesh's first index miss.
+                    vec4 lightmapIrradiance(vec3 worldPosition) {
+                        if (uint(gl_PrimitiveID) >= uint(lightmapCharts.charts.length())) {
+                            return vec4(0.0);
+                        }
+                        LightmapChart chart = lightmapCharts.charts[gl_PrimitiveID];
+                        if (chart.cell.z == 0.0) {
+                            return vec4(0.0);
+                        }
+
+                        vec2 local = vec2(dot(chart.u.xyz, worldPosition) + chart.u.w, dot(chart.v.xyz, worldPosition) + chart.v.w);
+                        float tolerance = 0.01 / length(chart.u.xyz);
+                        if (abs(dot(chart.plane.xyz, worldPosition) + chart.plane.w) > tolerance
+                            || any(lessThan(local, vec2(-0.01))) || any(greaterThan(local, vec2(1.01)))) {
+                            return vec4(0.0);
+                        }
+                        return vec4(textureLod(lightmap, chart.cell.xy + clamp(local, 0.0, 1.0) * chart.cell.zw, 0.0).rgb, 1.0);
+                    }
+
+                    vec3 shIrradiance(uint probe, vec3 n) {
+                        uint base = probe * 9;
+                        return shProbes.coefficients[base].rgb * 0.282095
+                            + shProbes.coefficients[base + 1].rgb * 0.488603 * n.y
+                            + shProbes.coefficients[base + 2].rgb * 0.488603 * n.z
+                            + shProbes.coefficients[base + 3].rgb * 0.488603 * n.x
+                            + shProbes.coefficients[base + 4].rgb * 1.092548 * n.x * n.y
+                            + shProbes.coefficients[base + 5].rgb * 1.092548 * n.y * n.z
+                            + shProbes.coefficients[base + 6].rgb * 0.315392 * (3.0 * n.z * n.z - 1.0)
+                            + shProbes.coefficients[base + 7].rgb * 1.092548 * n.x * n.z
+                            + shProbes.coefficients[base + 8].rgb * 0.546274 * (n.x * n.x - n.y * n.y);
+                    }
+
+                    // Trilinearly interpolates the eight closest probes, clamped to the grid. The alpha is 1
+                    // while a grid is bound.
+                    vec4 probeIrradiance(vec3 worldPosition, vec3 worldNormal) {
+                        uvec3 resolution = shProbeGrid.resolution.xyz;
+                        if (any(equal(resolution, uvec3(0)))) {
+                            return vec4(0.0);
+                        }
+
+                        vec3 size = max(shProbeGrid.boundsMaximum.xyz - shProbeGrid.boundsMinimum.xyz, vec3(0.0001));
+                        vec3 gridPosition = clamp((worldPosition - shProbeGrid.boundsMinimum.xyz) / size * vec3(resolution) - 0.5,
+                            vec3(0.0), vec3(resolution - 1));
+                        uvec3 first = uvec3(gridPosition);
+                        uvec3 last = min(first + 1, resolution - 1);
+                        vec3 weight = gridPosition - vec3(first);
+                        vec3 normal = normalize(worldNormal);
+
+                        vec3 irradiance = vec3(0.0);
+                        for (uint corner = 0; corner < 8; corner++) {
+                            bvec3 upper = bvec3(corner & 1, corner & 2, corner & 4);
+                            uvec3 cell = mix(first, last, upper);
+                            vec3 cornerWeight = mix(1.0 - weight, weight, upper);
+                            uint probe = cell.x + (cell.y + cell.z * resolution.y) * resolution.x;
+                            irradiance += shIrradiance(probe, normal) * cornerWeight.x * cornerWeight.y * cornerWeight.z;
+                        }
+                        return vec4(max(irradiance, 0.0), 1.0);
+                    }
+
+                    vec3 ambientLighting(vec3 albedo, vec3 normal, vec3 viewDirection, vec4 baked) {
+                        mat3 inverseView = transpose(mat3(uniforms.view));
+                        vec3 worldNormal = inverseView * normal;
+                        vec3 worldReflection = inverseView * reflect(-viewDirection, normal);
+                        float normalDotView = max(dot(normal, viewDirection), 0.0);
+
+                        vec3 f0 = mix(vec3(0.04), albedo, METALLIC);
+                        vec3 fresnel = fresnelSchlickRoughness(normalDotView, f0, ROUGHNESS);
+                        vec3 diffuseWeight = (1.0 - fresnel) * (1.0 - METALLIC);
+
+                        // Lightmapped fragments have the sky and the sun baked into their irradiance. Everything
+                        // else takes the probes' sky and bounce light where there are any.
+                        vec4 probes = probeIrradiance(fragColor, worldNormal);
+                        vec3 irradiance = baked.a > 0.0
+                            ? baked.rgb
+                            : mix(textureLod(irradianceMap, worldNormal, 0.0).rgb, probes.rgb, probes.a);
+                        float maxLod = float(textureQueryLevels(prefilteredMap) - 1);
+                        // The vertex position doubles as the albedo, so it's also the world position.
+                        vec3 prefiltered = prefilteredReflection(fragColor, worldReflection, ROUGHNESS * maxLod);
+                        vec2 brdf = textureLod(brdfLut, vec2(normalDotView, ROUGHNESS), 0.0).rg;
+
+                        return diffuseWeight * irradiance * albedo + prefiltered * (fresnel * brdf.x + brdf.y);
+                    }
+
                     void main() {
-                        outColor = vec4(fragColor, 1.0);
+                        if (pc.baseColor.a < pc.alphaCutoff) {
+                            discard;
+                        }
+
+                        vec3 normal = faceNormal(viewPosition);
+                        if (dot(normal, viewPosition) > 0.0) {
+                            normal = -normal;
+                        }
+
+                        vec3 lightDirection = normalize(mat3(uniforms.view) * sun.direction.xyz);
+                        vec2 visibility = texelFetch(visibilityTexture, ivec2(gl_FragCoord.xy), 0).rg;
+                        float occlusion = texelFetch(occlusionTexture, ivec2(gl_FragCoord.xy), 0).r * visibility.r;
+
+                        vec4 baked = lightmapIrradiance(fragColor);
+                        vec3 ambient = ambientLighting(fragColor, normal, normalize(-viewPosition), baked) * mix(occlusion, 1.0, baked.a);
+                        vec3 diffuse = max(dot(normal, lightDirection), 0.0) * fragColor * sun.color.rgb * visibility.g * (1.0 - baked.a);
+                        outColor = vec4(ambient + diffuse, 1.0);
+
+                        switch (DEBUG_VIEW) {
+                            case 1:
+                                outColor = vec4(fragColor, 1.0);
+                                break;
+                            case 2:
+                                outColor = vec4(transpose(mat3(uniforms.view)) * normal * 0.5 + 0.5, 1.0);
+                                break;
+                            case 3:
+                                outColor = vec4(ROUGHNESS, METALLIC, 0.0, 1.0);
+                                break;
+                            case 4:
+                                outColor = vec4(vec3(1.0 - exp(viewPosition.z * 0.1)), 1.0);
+                                break;
+                            case 5:
+                                outColor = vec4(0.1, 0.04, 0.01, 1.0);
+                                break;
+                            case 6: {
+                                // Surfaces are untextured, so this shows the mip a texture of DEBUG_TEXTURE_SIZE texels per unit would pick.
+                                vec3 footprint = max(abs(dFdx(fragColor)), abs(dFdy(fragColor))) * DEBUG_TEXTURE_SIZE;
+                                float level = log2(max(max(footprint.x, footprint.y), max(footprint.z, 1.0)));
+                                outColor = vec4(heatmap(level / log2(DEBUG_TEXTURE_SIZE)), 1.0);
+                                break;
+                            }
+                        }
+                    }
+            ",
+    }
+}
+
+mod prepass_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                    #version 460
+
+                    layout(location = 1) in vec3 viewPosition;
+                    layout(location = 2) in vec4 currentClipPosition;
+                    layout(location = 3) in vec4 previousClipPosition;
+
+                    layout(location = 0) out vec4 outNormal;
+                    layout(location = 1) out vec2 outVelocity;
+
+                    layout(push_constant) uniform PushConstantData {
+                        float time;
+                        vec2 mousePosition;
+                        vec4 baseColor;
+                        float alphaCutoff;
+                        float pointSize;
+                    } pc;
+
+                    vec3 faceNormal(vec3 position) {
+                        vec3 normal = cross(dFdx(position), dFdy(position));
+                        return dot(normal, normal) > 0.0 ? normalize(normal) : normalize(-position);
+                    }
+
+                    void main() {
+                        if (pc.baseColor.a < pc.alphaCutoff) {
+                            discard;
+                        }
+
+                        vec3 normal = faceNormal(viewPosition);
+                        if (dot(normal, viewPosition) > 0.0) {
+                            normal = -normal;
+                        }
+                        outNormal = vec4(normal, 0.0);
+
+                        vec2 current = currentClipPosition.xy / currentClipPosition.w;
+                        vec2 previous = previousClipPosition.xy / previousClipPosition.w;
+                        outVelocity = (current - previous) * 0.5;
                     }
             ",
     }
 }
 
+#[derive(BufferContents)]
+#[repr(C)]
+pub struct Uniform {
+    view: Matrix4<f32>,
+    projection: Matrix4<f32>,
+    view_projection: Matrix4<f32>,
+    inverse_projection: Matrix4<f32>,
+    previous_view_projection: Matrix4<f32>,
+}
+
 #[derive(VertexInputVertex, bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
 #[repr(C)]
 pub struct Vertex {
@@ -99,16 +621,87 @@ pub struct Vertex {
     position: [f32; 3],
 }
 
+impl From<[f32; 3]> for Vertex {
+    fn from(position: [f32; 3]) -> Self {
+        Self { position }
+    }
+}
+
+impl Vertex {
+    pub fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+// The merged scene mesh: the first buffer view holds the vertices and the second the indices.
+pub fn scene_mesh_data<'a>(
+    document: &gltf::Document,
+    buffer: &'a [u8],
+) -> (&'a [Vertex], &'a [u16]) {
+    let mut views = document.views();
+    let vertex_buffer_view = views.next().unwrap();
+    let index_buffer_view = views.next().unwrap();
+    let vertices =
+        bytemuck::cast_slice(&buffer[vertex_buffer_view.offset()..vertex_buffer_view.length()]);
+    let index_offset = index_buffer_view.offset();
+    let indices =
+        bytemuck::cast_slice(&buffer[index_offset..index_offset + index_buffer_view.length()]);
+    (vertices, indices)
+}
+
 fn align_usize(number: usize, alignment: usize) -> usize {
     ((number as f64 / alignment as f64).ceil()) as usize * alignment
 }
 
+// Alpha-masked geometry gets smooth edges from alpha-to-coverage whenever MSAA is active.
+pub fn alpha_masked_multisample_state(samples: SampleCount) -> MultisampleState {
+    MultisampleState {
+        rasterization_samples: samples,
+        alpha_to_coverage_enable: samples != SampleCount::Sample1,
+        ..Default::default()
+    }
+}
+
+// Entry points come from unspecialized modules, so this specializes the module they belong to and
+// looks the same entry point up again.
+pub fn specialize(
+    entry_point: &EntryPoint,
+    constants: impl IntoIterator<Item = (u32, SpecializationConstant)>,
+) -> Result<EntryPoint> {
+    let module = entry_point
+        .module()
+        .base_module()
+        .specialize(constants.into_iter().collect())?;
+    Ok(module.entry_point(&entry_point.info().name).unwrap())
+}
+
 impl VulkanDevice {
-    pub(crate) fn new(instance: Arc<VulkanInstance>, samples: SampleCount) -> Result<Self> {
+    pub(crate) fn new(instance: Arc<VulkanInstance>, anti_aliasing: AntiAliasing) -> Result<Self> {
         let physical_device = instance.physical_device();
         let queue_family_index = instance.queue_family_index();
         let device_extensions = instance.device_extensions();
 
+        let anti_aliasing = match anti_aliasing {
+            AntiAliasing::Msaa(samples) => {
+                let properties = physical_device.properties();
+                let supported_samples = properties
+                    .framebuffer_color_sample_counts
+                    .intersection(properties.framebuffer_depth_sample_counts);
+                if supported_samples.contains_enum(samples) {
+                    anti_aliasing
+                } else {
+                    warn!("{samples:?} MSAA is not supported, falling back to FXAA");
+                    AntiAliasing::Fxaa
+                }
+            }
+            _ => anti_aliasing,
+        };
+
+        let samples = match anti_aliasing {
+            AntiAliasing::Msaa(samples) => samples,
+            _ => SampleCount::Sample1,
+        };
+
         let (device, mut queues) = Device::new(
             Arc::clone(physical_device),
             DeviceCreateInfo {
@@ -117,9 +710,44 @@ impl VulkanDevice {
                     ..Default::default()
                 }],
                 enabled_extensions: *device_extensions,
-                enabled_features: Features {
+                enabled_features: DeviceFeatures {
                     dynamic_rendering: true,
-                    ..Features::empty()
+                    fill_mode_non_solid: physical_device.supported_features().fill_mode_non_solid,
+                    tessellation_shader: physical_device.supported_features().tessellation_shader,
+                    geometry_shader: physical_device.supported_features().geometry_shader,
+                    task_shader: device_extensions.ext_mesh_shader
+                        && physical_device.supported_features().task_shader,
+                    mesh_shader: device_extensions.ext_mesh_shader
+                        && physical_device.supported_features().mesh_shader,
+                    pipeline_fragment_shading_rate: device_extensions.khr_fragment_shading_rate
+                        && physical_device.supported_features().pipeline_fragment_shading_rate,
+                    acceleration_structure: device_extensions.khr_acceleration_structure
+                        && physical_device.supported_features().acceleration_structure,
+                    ray_query: device_extensions.khr_ray_query
+                        && physical_device.supported_features().ray_query,
+                    buffer_device_address: (device_extensions.khr_acceleration_structure
+                        || physical_device.api_version() >= Version::V1_2)
+                        && physical_device.supported_features().buffer_device_address,
+                    extended_dynamic_state: device_extensions.ext_extended_dynamic_state
+                        && physical_device.supported_features().extended_dynamic_state,
+                    wide_lines: physical_device.supported_features().wide_lines,
+                    multiview: physical_device.supported_features().multiview,
+                    present_id: device_extensions.khr_present_id
+                        && physical_device.supported_features().present_id,
+                    present_wait: device_extensions.khr_present_wait
+                        && physical_device.supported_features().present_wait,
+                    swapchain_maintenance1: device_extensions.ext_swapchain_maintenance1
+                        && physical_device.supported_features().swapchain_maintenance1,
+                    // On portability devices anything outside the subset has to be opted into.
+                    triangle_fans: device_extensions.khr_portability_subset
+                        && physical_device.supported_features().triangle_fans,
+                    point_polygons: device_extensions.khr_portability_subset
+                        && physical_device.supported_features().point_polygons,
+                    image_view_format_swizzle: device_extensions.khr_portability_subset
+                        && physical_device.supported_features().image_view_format_swizzle,
+                    mutable_comparison_samplers: device_extensions.khr_portability_subset
+                        && physical_device.supported_features().mutable_comparison_samplers,
+                    ..DeviceFeatures::empty()
                 },
                 ..Default::default()
             },
@@ -139,27 +767,77 @@ impl VulkanDevice {
             StandardDescriptorSetAllocatorCreateInfo::default(),
         ));
 
-        let (document, buffers, images) = gltf::import("assets/cube.gltf")?;
+        // The driver validates the header of the initial data and ignores it when stale.
+        let pipeline_cache = unsafe {
+            PipelineCache::new(
+                Arc::clone(&device),
+                PipelineCacheCreateInfo {
+                    initial_data: std::fs::read(PIPELINE_CACHE_PATH).unwrap_or_default(),
+                    ..Default::default()
+                },
+            )?
+        };
+
+        let (document, buffers, _) = import_scene(SCENE_PATH)?;
 
         let buffer = buffers.into_iter().next().unwrap().0;
-        let mut views = document.views();
-        let vertex_buffer_view = views.next().unwrap();
-        let index_buffer_view = views.next().unwrap();
-        let vertices =
-            bytemuck::cast_slice(&buffer[vertex_buffer_view.offset()..vertex_buffer_view.length()]);
-        let indices = bytemuck::cast_slice(
-            &buffer[index_buffer_view.offset()
-                ..index_buffer_view.offset() + index_buffer_view.length()],
-        );
+        let (vertices, indices) = scene_mesh_data(&document, &buffer);
 
         let max_initial_data_size = align_usize(
-            std::mem::size_of_val(&vertices) + std::mem::size_of_val(&indices),
+            std::mem::size_of_val(vertices) + std::mem::size_of_val(indices),
             256,
         );
 
-        let cameraNode = document.nodes().next().unwrap();
+        let scene_primitive = document
+            .meshes()
+            .next()
+            .and_then(|mesh| mesh.primitives().next());
+        // Primitives without a material of their own use the default, which comes last.
+        let scene_materials = document
+            .materials()
+            .map(|material| SceneMaterial::from_gltf(&material))
+            .chain(std::iter::once(SceneMaterial::default()))
+            .collect::<Vec<_>>();
+        let scene_topology = scene_primitive
+            .as_ref()
+            .map(|primitive| primitive_topology(primitive.mode()))
+            .unwrap_or_default();
+        // The scene pipelines are built for a single topology, and every primitive indexes its own
+        // vertices.
+        for primitive in document.meshes().flat_map(|mesh| mesh.primitives()) {
+            ensure!(
+                primitive_topology(primitive.mode()) == scene_topology,
+                "Scene mixes {:?} and {:?} primitives, which isn't supported",
+                scene_topology,
+                primitive_topology(primitive.mode())
+            );
+            let vertex_count = primitive
+                .get(&gltf::Semantic::Positions)
+                .map_or(0, |positions| positions.count());
+            check_restart_index(scene_topology, vertex_count)?;
+        }
+        let triangulates_fans = scene_topology == PrimitiveTopology::TriangleFan
+            && device_extensions.khr_portability_subset
+            && !device.enabled_features().triangle_fans;
+        let loaded_indices = indices;
+        let fan_indices;
+        let (scene_topology, indices) = if triangulates_fans {
+            warn!("Triangle fans are not supported, drawing as a triangle list");
+            fan_indices = triangulate_fan(indices);
+            (PrimitiveTopology::TriangleList, fan_indices.as_slice())
+        } else {
+            (scene_topology, indices)
+        };
+        let scene_center = scene_primitive
+            .map(|primitive| {
+                let bounds = primitive.bounding_box();
+                Point3::from((Vector3::from(bounds.min) + Vector3::from(bounds.max)) * 0.5)
+            })
+            .unwrap_or_else(Point3::origin);
 
-        let camera_projection = match cameraNode.camera().unwrap().projection() {
+        let camera_node = document.nodes().next().unwrap();
+
+        let camera_projection = match camera_node.camera().unwrap().projection() {
             Projection::Perspective(perspective) => Perspective3::new(
                 800.0 / 600.0,
                 f32::degrees_to_radians(70.0),
@@ -168,7 +846,7 @@ impl VulkanDevice {
             ),
             _ => unimplemented!(),
         };
-        // let camera_isometry = match cameraNode.transform() {
+        // let camera_isometry = match camera_node.transform() {
         //     gltf::scene::Transform::Decomposed {
         //         translation,
         //         rotation,
@@ -185,19 +863,23 @@ impl VulkanDevice {
         //     _ => unimplemented!(),
         // };
 
+        let scene_cameras = load_scene_cameras(&document);
+        let punctual_lights = load_punctual_lights(&document);
+        let mut scenes = load_scenes(&document);
+        let default_scene = document.default_scene().map_or(0, |scene| scene.index());
+
         let eye = Point3::new(2.0, -2.0, 2.0);
         let target = Point3::new(0.0, 0.0, 0.0);
         let camera_view = Isometry3::look_at_rh(&eye, &target, &Vector3::y());
-        let view_projection = camera_projection.into_inner() * camera_view.to_homogeneous();
+        let view = camera_view.to_homogeneous();
+        let projection = camera_projection.into_inner();
+        let view_projection = projection * view;
 
         let device_buffer_allocator = SubbufferAllocator::new(
             memory_allocator.clone(),
             SubbufferAllocatorCreateInfo {
                 arena_size: max_initial_data_size as DeviceSize,
-                buffer_usage: BufferUsage::TRANSFER_DST
-                    | BufferUsage::VERTEX_BUFFER
-                    | BufferUsage::INDEX_BUFFER
-                    | BufferUsage::UNIFORM_BUFFER,
+                buffer_usage: BufferUsage::TRANSFER_DST | BufferUsage::UNIFORM_BUFFER,
                 memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
                 ..Default::default()
             },
@@ -214,96 +896,216 @@ impl VulkanDevice {
             },
         );
 
-        use nalgebra as na;
-
-        #[derive(BufferContents)]
-        #[repr(C)]
-        struct Uniform {
-            view_projection: OMatrix<f32, na::U4, na::U4>,
-        }
-
-        let uniform = Uniform { view_projection };
+        let uniform = Uniform {
+            view,
+            projection,
+            view_projection,
+            inverse_projection: camera_projection.inverse(),
+            previous_view_projection: view_projection,
+        };
 
-        let vertex_buffer = device_buffer_allocator.allocate_slice(vertices.len() as DeviceSize)?;
-        let index_buffer = device_buffer_allocator.allocate_slice(indices.len() as DeviceSize)?;
+        let mesh_pool = MeshPool::new(
+            memory_allocator.clone(),
+            DEFAULT_VERTEX_CAPACITY.max(vertices.len() as DeviceSize),
+            DEFAULT_INDEX_CAPACITY.max(indices.len() as DeviceSize),
+            device.enabled_features().buffer_device_address,
+        )?;
         let uniform_buffer = device_buffer_allocator.allocate_sized::<Uniform>()?;
 
-        let vertex_staging_buffer =
-            host_buffer_allocator.allocate_slice::<Vertex>(vertices.len() as DeviceSize)?;
-        let index_staging_buffer =
-            host_buffer_allocator.allocate_slice::<u16>(indices.len() as DeviceSize)?;
         let uniform_staging_buffer = host_buffer_allocator.allocate_sized::<Uniform>()?;
-
-        {
-            let mut vertex_writer = vertex_staging_buffer.write()?;
-            vertex_writer.copy_from_slice(&vertices);
-            let mut indices_writer = index_staging_buffer.write()?;
-            indices_writer.copy_from_slice(&indices);
-            let mut uniform_writer = uniform_staging_buffer.write()?;
-            *uniform_writer = uniform;
-        }
+        *uniform_staging_buffer.write()? = uniform;
 
         let mut command_builder = AutoCommandBufferBuilder::primary(
-            &command_allocator,
+            command_allocator.clone(),
             queue_family_index,
             CommandBufferUsage::OneTimeSubmit,
         )?;
 
-        command_builder.copy_buffer(CopyBufferInfo::buffers(
-            vertex_staging_buffer,
-            vertex_buffer.clone(),
-        ))?;
-        command_builder.copy_buffer(CopyBufferInfo::buffers(
-            index_staging_buffer,
-            index_buffer.clone(),
-        ))?;
+        let scene_mesh =
+            mesh_pool.upload(&mut command_builder, &host_buffer_allocator, vertices, indices)?;
+        // Nodes' primitives move into the pool along with the mesh, and fans rewritten as lists
+        // take up as many indices as their triangles do.
+        for scene in &mut scenes {
+            scene.graph.remap_primitives(|primitive| {
+                let first = primitive.first_index as usize;
+                let last = first + primitive.index_count as usize;
+                let (first_index, index_count) = if triangulates_fans {
+                    (
+                        triangulate_fan(&loaded_indices[..first]).len() as u32,
+                        triangulate_fan(&loaded_indices[first..last]).len() as u32,
+                    )
+                } else {
+                    (primitive.first_index, primitive.index_count)
+                };
+                MeshAllocation {
+                    first_index: scene_mesh.first_index + first_index,
+                    index_count,
+                    vertex_offset: scene_mesh.vertex_offset + primitive.vertex_offset,
+                }
+            });
+        }
+        let scene_graph = scenes
+            .get(default_scene)
+            .map(|scene| scene.graph.clone())
+            .unwrap_or_default();
         command_builder.copy_buffer(CopyBufferInfo::buffers(
             uniform_staging_buffer,
             uniform_buffer.clone(),
         ))?;
 
+        let identity_color_lut = ColorLut::identity(memory_allocator.clone(), &mut command_builder)?;
+        let default_lighting = ImageBasedLighting::uniform(
+            memory_allocator.clone(),
+            &descriptor_set_allocator,
+            &pipeline_cache,
+            &mut command_builder,
+            [0.3, 0.3, 0.3],
+        )?;
+
         let command_buffer = command_builder.build()?;
 
         let buffers_upload_future = sync::now(Arc::clone(&device))
             .then_execute(Arc::clone(&queue), command_buffer)?
             .then_signal_fence_and_flush()?;
 
-        let graphics_pipeline = {
+        let dynamic_cull_mode = device.api_version() >= Version::V1_3
+            || device.enabled_features().extended_dynamic_state;
+
+        let gpu_scene_set_layout = gpu_scene::create_set_layout(&device)?;
+        let (graphics_pipeline, wireframe_pipeline, debug_view_pipelines) = {
             let vertex_shader = vs::load(Arc::clone(&device))?.entry_point("main").unwrap();
             let fragment_shader = fs::load(Arc::clone(&device))?.entry_point("main").unwrap();
 
-            let vertex_input_state = Vertex::per_vertex()
-                .definition(&vertex_shader.info().input_interface)
+            let vertex_input_state = Vertex::per_vertex().definition(&vertex_shader).unwrap();
+
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vertex_shader),
+                PipelineShaderStageCreateInfo::new(fragment_shader),
+            ];
+
+            // Renderers allocate their scene sets from the shared layout, which a layout derived
+            // from the shaders wouldn't be compatible with.
+            let layout =
+                gpu_scene::create_pipeline_layout(&device, &stages, &gpu_scene_set_layout)?;
+
+            let subpass = PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(HDR_FORMAT)],
+                depth_attachment_format: Some(DEPTH_FORMAT),
+                ..Default::default()
+            };
+
+            let create_pipeline = |polygon_mode, debug_view: DebugView| -> Result<_> {
+                let overdraw = debug_view == DebugView::Overdraw;
+                let [vertex_stage, fragment_stage] = stages.clone();
+
+                Ok(GraphicsPipeline::new(
+                    Arc::clone(&device),
+                    Some(Arc::clone(&pipeline_cache)),
+                    GraphicsPipelineCreateInfo {
+                        stages: [
+                            vertex_stage,
+                            PipelineShaderStageCreateInfo::new(specialize(
+                                &fragment_stage.entry_point,
+                                [(0, (debug_view as u32).into())],
+                            )?),
+                        ]
+                        .into_iter()
+                        .collect(),
+                        input_assembly_state: Some(input_assembly_state(scene_topology)),
+                        vertex_input_state: Some(vertex_input_state.clone()),
+                        viewport_state: Some(ViewportState::default()),
+                        rasterization_state: Some(RasterizationState {
+                            polygon_mode,
+                            cull_mode: CullMode::None,
+                            front_face: SCENE_FRONT_FACE,
+                            depth_bias: Some(DepthBiasState::default()),
+                            ..Default::default()
+                        }),
+                        depth_stencil_state: Some(DepthStencilState {
+                            depth: (!overdraw).then_some(DepthState {
+                                write_enable: false,
+                                compare_op: CompareOp::LessOrEqual,
+                            }),
+                            ..Default::default()
+                        }),
+                        multisample_state: Some(MultisampleState {
+                            rasterization_samples: samples,
+                            ..Default::default()
+                        }),
+                        color_blend_state: Some(ColorBlendState::with_attachment_states(
+                            subpass.color_attachment_formats.len() as u32,
+                            ColorBlendAttachmentState {
+                                blend: overdraw.then(AttachmentBlend::additive),
+                                ..Default::default()
+                            },
+                        )),
+                        dynamic_state: [
+                            DynamicState::Viewport,
+                            DynamicState::DepthBias,
+                            DynamicState::LineWidth,
+                        ]
+                            .into_iter()
+                            .chain(
+                                device
+                                    .enabled_features()
+                                    .pipeline_fragment_shading_rate
+                                    .then_some(DynamicState::FragmentShadingRate),
+                            )
+                            .chain(dynamic_cull_mode.then_some(DynamicState::CullMode))
+                            .collect(),
+                        subpass: Some(subpass.clone().into()),
+                        ..GraphicsPipelineCreateInfo::layout(Arc::clone(&layout))
+                    },
+                )?)
+            };
+
+            (
+                create_pipeline(PolygonMode::Fill, DebugView::Lit)?,
+                device
+                    .enabled_features()
+                    .fill_mode_non_solid
+                    .then(|| create_pipeline(PolygonMode::Line, DebugView::Lit))
+                    .transpose()?,
+                DebugView::ALL[1..]
+                    .iter()
+                    .map(|&debug_view| create_pipeline(PolygonMode::Fill, debug_view))
+                    .try_collect::<Vec<_>>()?,
+            )
+        };
+
+        let prepass_pipeline = {
+            let vertex_shader = vs::load(Arc::clone(&device))?.entry_point("main").unwrap();
+            let fragment_shader = prepass_fs::load(Arc::clone(&device))?
+                .entry_point("main")
                 .unwrap();
 
+            let vertex_input_state = Vertex::per_vertex().definition(&vertex_shader).unwrap();
+
             let stages = [
                 PipelineShaderStageCreateInfo::new(vertex_shader),
                 PipelineShaderStageCreateInfo::new(fragment_shader),
             ];
 
-            let layout = PipelineLayout::new(
-                Arc::clone(&device),
-                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
-                    .into_pipeline_layout_create_info(device.clone())
-                    .unwrap(),
-            )?;
+            let layout = Arc::clone(graphics_pipeline.layout());
 
             let subpass = PipelineRenderingCreateInfo {
-                color_attachment_formats: vec![Some(Format::B8G8R8A8_SRGB)],
-                depth_attachment_format: Some(Format::D16_UNORM),
+                color_attachment_formats: vec![Some(NORMAL_FORMAT), Some(VELOCITY_FORMAT)],
+                depth_attachment_format: Some(DEPTH_FORMAT),
                 ..Default::default()
             };
 
             GraphicsPipeline::new(
-                device,
-                None,
+                Arc::clone(&device),
+                Some(Arc::clone(&pipeline_cache)),
                 GraphicsPipelineCreateInfo {
                     stages: stages.into_iter().collect(),
-                    input_assembly_state: Some(InputAssemblyState::default()),
+                    input_assembly_state: Some(input_assembly_state(scene_topology)),
                     vertex_input_state: Some(vertex_input_state),
                     viewport_state: Some(ViewportState::default()),
                     rasterization_state: Some(RasterizationState {
                         cull_mode: CullMode::None,
+                        front_face: SCENE_FRONT_FACE,
+                        depth_bias: Some(DepthBiasState::default()),
                         ..Default::default()
                     }),
                     depth_stencil_state: Some(DepthStencilState {
@@ -318,38 +1120,377 @@ impl VulkanDevice {
                         subpass.color_attachment_formats.len() as u32,
                         ColorBlendAttachmentState::default(),
                     )),
-                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    dynamic_state: [
+                        DynamicState::Viewport,
+                        DynamicState::DepthBias,
+                        DynamicState::LineWidth,
+                    ]
+                        .into_iter()
+                        .chain(dynamic_cull_mode.then_some(DynamicState::CullMode))
+                        .collect(),
                     subpass: Some(subpass.into()),
                     ..GraphicsPipelineCreateInfo::layout(layout)
                 },
             )
         }?;
 
-        let set = PersistentDescriptorSet::new(
-            &descriptor_set_allocator,
-            Arc::clone(graphics_pipeline.layout().set_layouts().get(0).unwrap()),
-            [WriteDescriptorSet::buffer(0, uniform_buffer)],
+        let ssao_pass = SsaoPass::new(&device)?;
+        let contact_shadow_pass = ContactShadowPass::new(&device, &pipeline_cache)?;
+        let shadow_map_pass = ShadowMapPass::new(
+            &device,
+            &pipeline_cache,
+            scene_topology,
+            &gpu_scene_set_layout,
+        )?;
+        let fxaa_pass = FxaaPass::new(&device)?;
+        let motion_blur_pass = MotionBlurPass::new(&device, HDR_FORMAT)?;
+        let tonemap_pass = TonemapPass::new(&device)?;
+        let skybox_pass = SkyboxPass::new(&device, samples)?;
+        let background_pass = BackgroundPass::new(&device, samples)?;
+        let water_pass = WaterPass::new(&device)?;
+        let foliage_pass = FoliagePass::new(&device, &pipeline_cache, samples)?;
+        let particle_pass = ParticlePass::new(&device, &pipeline_cache)?;
+        let tessellation_pass = device
+            .enabled_features()
+            .tessellation_shader
+            .then(|| TessellationPass::new(&device, &pipeline_cache, samples))
+            .transpose()?;
+        let stereo_pass = device
+            .enabled_features()
+            .multiview
+            .then(|| {
+                StereoPass::new(
+                    &device,
+                    &pipeline_cache,
+                    scene_topology,
+                    &gpu_scene_set_layout,
+                )
+            })
+            .transpose()?;
+        let vertex_pulling_pass = if device.enabled_features().buffer_device_address {
+            Some(VertexPullingPass::new(
+                &device,
+                &pipeline_cache,
+                samples,
+                scene_topology,
+                fs::load(Arc::clone(&device))?.entry_point("main").unwrap(),
+                prepass_fs::load(Arc::clone(&device))?
+                    .entry_point("main")
+                    .unwrap(),
+                &gpu_scene_set_layout,
+            )?)
+        } else {
+            None
+        };
+        let normal_visualization_pass = device
+            .enabled_features()
+            .geometry_shader
+            .then(|| {
+                NormalVisualizationPass::new(
+                    &device,
+                    &pipeline_cache,
+                    samples,
+                    &gpu_scene_set_layout,
+                )
+            })
+            .transpose()?;
+        let debug_draw_pass = DebugDrawPass::new(
+            &device,
+            memory_allocator.clone(),
+            &pipeline_cache,
+            samples,
+        )?;
+        let billboard_pass = BillboardPass::new(
+            &device,
+            memory_allocator.clone(),
+            &pipeline_cache,
+            samples,
+        )?;
+        let grid_pass = GridPass::new(&device, &pipeline_cache, samples)?;
+        let transparency_pass =
+            TransparencyPass::new(&device, &pipeline_cache, samples, &gpu_scene_set_layout)?;
+        let picking_pass = PickingPass::new(
+            &device,
+            &pipeline_cache,
+            scene_topology,
+            &gpu_scene_set_layout,
+        )?;
+        let outline_pass = OutlinePass::new(&device, &pipeline_cache, &gpu_scene_set_layout)?;
+        let sprite_pass = SpritePass::new(&device, memory_allocator.clone(), &pipeline_cache)?;
+        let meshlet_pass =
+            if device.enabled_features().mesh_shader && device.enabled_features().task_shader {
+                Some(MeshletPass::new(
+                    &device,
+                    &pipeline_cache,
+                    &descriptor_set_allocator,
+                    samples,
+                    fs::load(Arc::clone(&device))?.entry_point("main").unwrap(),
+                    &gpu_scene_set_layout,
+                    MeshletMesh::build(
+                        memory_allocator.clone(),
+                        vertices,
+                        indices,
+                        &scene_mesh,
+                        scenes.iter().flat_map(|scene| scene.graph.meshes()),
+                    )?,
+                )?)
+            } else {
+                None
+            };
+        let ray_tracing_pass = device
+            .enabled_features()
+            .ray_query
+            .then(|| RayTracingPass::new(&device, &pipeline_cache, &gpu_scene_set_layout))
+            .transpose()?;
+        let ray_query_pass = device
+            .enabled_features()
+            .ray_query
+            .then(|| RayQueryPass::new(&device, &pipeline_cache))
+            .transpose()?;
+        let path_tracing_pass = device
+            .enabled_features()
+            .ray_query
+            .then(|| PathTracingPass::new(&device, &pipeline_cache, &gpu_scene_set_layout))
+            .transpose()?;
+
+        let set = DescriptorSet::new(
+            descriptor_set_allocator.clone(),
+            Arc::clone(graphics_pipeline.layout().set_layouts().first().unwrap()),
+            [WriteDescriptorSet::buffer(0, uniform_buffer.clone())],
             [],
         )?;
 
         buffers_upload_future.wait(None)?;
 
-        Ok(Self {
+        let mut vulkan_device = Self {
             queue,
             memory_allocator,
             command_allocator,
+            descriptor_set_allocator,
+            pipeline_cache,
+            prepass_pipeline,
             graphics_pipeline,
-            vertex_buffer,
-            index_buffer,
+            wireframe_pipeline,
+            debug_view_pipelines,
+            gpu_scene_set_layout,
+            mesh_pool,
+            scene_mesh,
+            uniform_buffer,
+            camera_position: eye,
+            camera_target: target,
+            camera_projection,
+            view_projection,
+            scene_cameras,
+            punctual_lights,
+            scene_graph,
+            scenes,
+            default_scene,
+            scene_materials,
+            scene_center,
+            scene_topology,
+            anti_aliasing,
             samples,
             set,
-        })
+            ssao_pass,
+            contact_shadow_pass,
+            shadow_map_pass,
+            fxaa_pass,
+            motion_blur_pass,
+            tonemap_pass,
+            skybox_pass,
+            background_pass,
+            water_pass,
+            foliage_pass,
+            particle_pass,
+            tessellation_pass,
+            stereo_pass,
+            vertex_pulling_pass,
+            normal_visualization_pass,
+            debug_draw_pass,
+            billboard_pass,
+            grid_pass,
+            transparency_pass,
+            picking_pass,
+            outline_pass,
+            sprite_pass,
+            meshlet_pass,
+            scene_acceleration: None,
+            ray_tracing_pass,
+            ray_query_pass,
+            path_tracing_pass,
+            identity_color_lut,
+            default_lighting,
+        };
+
+        if vulkan_device.ray_tracing_pass.is_some() {
+            vulkan_device.scene_acceleration = Some(Mutex::new(SceneAccelerationStructure::build(
+                &vulkan_device,
+                vertices,
+                indices,
+                &vulkan_device.scene_mesh,
+                vulkan_device
+                    .scenes
+                    .iter()
+                    .flat_map(|scene| scene.graph.meshes()),
+            )?));
+        }
+
+        Ok(vulkan_device)
+    }
+
+    pub fn pipeline_cache(&self) -> &Arc<PipelineCache> {
+        &self.pipeline_cache
+    }
+
+    pub fn save_pipeline_cache(&self) -> Result<()> {
+        std::fs::write(PIPELINE_CACHE_PATH, self.pipeline_cache.get_data()?)?;
+        Ok(())
+    }
+
+    pub fn create_compute_pipeline(&self, shader: EntryPoint) -> Result<Arc<ComputePipeline>> {
+        compute::create_compute_pipeline(
+            self.queue.device(),
+            Some(Arc::clone(&self.pipeline_cache)),
+            shader,
+        )
+    }
+
+    pub fn bind_compute<'a>(
+        &self,
+        builder: &'a mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: &Arc<ComputePipeline>,
+        writes: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) -> Result<&'a mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>> {
+        let set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            Arc::clone(&pipeline.layout().set_layouts()[0]),
+            writes,
+            [],
+        )?;
+
+        compute::bind_compute(builder, pipeline, set)
+    }
+
+    pub fn submit_and_wait(
+        &self,
+        record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> Result<()>,
+    ) -> Result<()> {
+        self.submit(record)?.wait(None)?;
+
+        Ok(())
+    }
+
+    // Like `submit_and_wait`, but leaves waiting on the fence to the caller.
+    pub fn submit(
+        &self,
+        record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> Result<()>,
+    ) -> Result<FenceSignalFuture<Box<dyn GpuFuture>>> {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        record(&mut builder)?;
+
+        Ok(sync::now(Arc::clone(self.queue.device()))
+            .then_execute(Arc::clone(&self.queue), builder.build()?)?
+            .boxed()
+            .then_signal_fence_and_flush()?)
+    }
+
+    // Blocks until the whole buffer is back on the CPU, so it's meant for tools rather than frames.
+    pub fn download_buffer<T: BufferContents + Copy>(
+        &self,
+        buffer: &Subbuffer<[T]>,
+    ) -> Result<Vec<T>> {
+        self.download_buffer_async(buffer)?.wait()
+    }
+
+    pub fn download_buffer_async<T: BufferContents + Copy>(
+        &self,
+        buffer: &Subbuffer<[T]>,
+    ) -> Result<Download<T>> {
+        ensure_buffer_downloadable(buffer)?;
+        let staging_buffer = self.download_staging_buffer(buffer.len())?;
+        let future = self.submit(|builder| {
+            builder.copy_buffer(CopyBufferInfo::buffers(
+                buffer.clone(),
+                staging_buffer.clone(),
+            ))?;
+            Ok(())
+        })?;
+        Ok(Download::new(future, staging_buffer))
+    }
+
+    // The first mip level of every layer, tightly packed in the image's format whatever its layout
+    // and tiling on the GPU. `DownloadedImage` converts it to floats or 8-bit RGBA.
+    pub fn download_image(&self, image: &Arc<Image>) -> Result<DownloadedImage> {
+        self.download_image_async(image)?.wait()
+    }
+
+    pub fn download_image_async(&self, image: &Arc<Image>) -> Result<ImageDownload> {
+        let staging_buffer = self.download_staging_buffer(image_download_size(image)?)?;
+        let future = self.submit(|builder| {
+            builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                Arc::clone(image),
+                staging_buffer.clone(),
+            ))?;
+            Ok(())
+        })?;
+        Ok(ImageDownload::new(
+            Download::new(future, staging_buffer),
+            image,
+        ))
+    }
+
+    fn download_staging_buffer<T: BufferContents>(
+        &self,
+        len: DeviceSize,
+    ) -> Result<Subbuffer<[T]>> {
+        Ok(Buffer::new_slice(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            len,
+        )?)
+    }
+
+    pub fn create_attachment(
+        &self,
+        format: Format,
+        extent: [u32; 2],
+        usage: ImageUsage,
+        samples: SampleCount,
+    ) -> Result<Arc<ImageView>> {
+        Ok(ImageView::new_default(Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                format,
+                extent: [extent[0], extent[1], 1],
+                usage,
+                samples,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?)?)
     }
 
     pub fn queue(&self) -> &Arc<Queue> {
         &self.queue
     }
 
+    pub fn device_name(&self) -> &str {
+        &self.queue.device().physical_device().properties().device_name
+    }
+
     pub fn memory_allocator(&self) -> &Arc<StandardMemoryAllocator> {
         &self.memory_allocator
     }
@@ -358,23 +1499,378 @@ impl VulkanDevice {
         &self.command_allocator
     }
 
+    pub fn descriptor_set_allocator(&self) -> &Arc<StandardDescriptorSetAllocator> {
+        &self.descriptor_set_allocator
+    }
+
+    pub fn prepass_pipeline(&self) -> &Arc<GraphicsPipeline> {
+        &self.prepass_pipeline
+    }
+
     pub fn graphics_pipeline(&self) -> &Arc<GraphicsPipeline> {
         &self.graphics_pipeline
     }
 
+    pub fn gpu_scene_set_layout(&self) -> &Arc<DescriptorSetLayout> {
+        &self.gpu_scene_set_layout
+    }
+
+    pub fn debug_view_pipeline(&self, debug_view: DebugView) -> &Arc<GraphicsPipeline> {
+        match debug_view {
+            DebugView::Lit => &self.graphics_pipeline,
+            _ => &self.debug_view_pipelines[debug_view as usize - 1],
+        }
+    }
+
+    pub fn wireframe_pipeline(&self) -> Option<&Arc<GraphicsPipeline>> {
+        self.wireframe_pipeline.as_ref()
+    }
+
     pub fn vertex_buffer(&self) -> &Subbuffer<[Vertex]> {
-        &self.vertex_buffer
+        self.mesh_pool.vertex_buffer()
     }
 
     pub fn index_buffer(&self) -> &Subbuffer<[u16]> {
-        &self.index_buffer
+        self.mesh_pool.index_buffer()
+    }
+
+    pub fn scene_mesh(&self) -> &MeshAllocation {
+        &self.scene_mesh
+    }
+
+    // Adds a mesh to the pool after startup, waiting for its upload to finish.
+    pub fn upload_mesh(&self, vertices: &[Vertex], indices: &[u16]) -> Result<MeshAllocation> {
+        let staging_allocator = SubbufferAllocator::new(
+            self.memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::TRANSFER_SRC,
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        let mut mesh = None;
+        self.submit_and_wait(|builder| {
+            mesh = Some(
+                self.mesh_pool
+                    .upload(builder, &staging_allocator, vertices, indices)?,
+            );
+            Ok(())
+        })?;
+        Ok(mesh.unwrap())
+    }
+
+    // Frees a mesh added with `upload_mesh`, once the GPU is done with every frame drawing it.
+    pub fn free_mesh(&self, mesh: MeshAllocation) -> Result<()> {
+        self.mesh_pool.free(mesh)
+    }
+
+    pub fn uniform_buffer(&self) -> &Subbuffer<Uniform> {
+        &self.uniform_buffer
+    }
+
+    pub fn camera_position(&self) -> &Point3<f32> {
+        &self.camera_position
+    }
+
+    pub fn camera_target(&self) -> &Point3<f32> {
+        &self.camera_target
+    }
+
+    pub fn camera_projection(&self) -> &Perspective3<f32> {
+        &self.camera_projection
+    }
+
+    pub fn scene_cameras(&self) -> &[SceneCamera] {
+        &self.scene_cameras
+    }
+
+    pub fn punctual_lights(&self) -> &[PunctualLight] {
+        &self.punctual_lights
+    }
+
+    // The graph of the document's default scene, or its first.
+    pub fn scene_graph(&self) -> &SceneGraph {
+        &self.scene_graph
+    }
+
+    pub fn scenes(&self) -> &[GltfScene] {
+        &self.scenes
+    }
+
+    pub fn default_scene(&self) -> usize {
+        self.default_scene
+    }
+
+    pub fn view_projection(&self) -> &Matrix4<f32> {
+        &self.view_projection
+    }
+
+    // The loaded camera fitted to a target of another shape, such as a resized window.
+    pub fn camera_projection_with_aspect(&self, aspect_ratio: f32) -> Perspective3<f32> {
+        let mut projection = self.camera_projection;
+        projection.set_aspect(aspect_ratio);
+        projection
+    }
+
+    // Only the GPU copy of the camera moves; CPU-side users such as transparency sorting keep the load-time view.
+    // `pre_rotation` is folded into the projection, so it must be a pure clip-space rotation.
+    pub fn record_camera(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        eye: &Point3<f32>,
+        target: &Point3<f32>,
+        projection: &Perspective3<f32>,
+        previous_view_projection: &Matrix4<f32>,
+        pre_rotation: &Matrix4<f32>,
+    ) -> Result<Matrix4<f32>> {
+        let view = Isometry3::look_at_rh(eye, target, &Vector3::y()).to_homogeneous();
+        self.write_camera(
+            builder,
+            view,
+            pre_rotation * projection.into_inner(),
+            projection.inverse() * pre_rotation.transpose(),
+            previous_view_projection,
+        )
+    }
+
+    // For cameras with a projection of their own, such as the minimap's orthographic one.
+    pub fn record_view(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        view: Matrix4<f32>,
+        projection: Matrix4<f32>,
+        previous_view_projection: &Matrix4<f32>,
+    ) -> Result<Matrix4<f32>> {
+        let inverse_projection = projection
+            .try_inverse()
+            .context("Camera projection is not invertible")?;
+        self.write_camera(
+            builder,
+            view,
+            projection,
+            inverse_projection,
+            previous_view_projection,
+        )
+    }
+
+    fn write_camera(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        view: Matrix4<f32>,
+        projection: Matrix4<f32>,
+        inverse_projection: Matrix4<f32>,
+        previous_view_projection: &Matrix4<f32>,
+    ) -> Result<Matrix4<f32>> {
+        let view_projection = projection * view;
+
+        builder.update_buffer(
+            self.uniform_buffer.clone(),
+            Box::new(Uniform {
+                view,
+                projection,
+                view_projection,
+                inverse_projection,
+                previous_view_projection: *previous_view_projection,
+            }),
+        )?;
+
+        Ok(view_projection)
+    }
+
+    pub fn scene_materials(&self) -> &[SceneMaterial] {
+        &self.scene_materials
+    }
+
+    pub fn scene_center(&self) -> &Point3<f32> {
+        &self.scene_center
+    }
+
+    pub fn scene_topology(&self) -> PrimitiveTopology {
+        self.scene_topology
+    }
+
+    // Scene geometry lives in the mesh pool's fixed buffers rather than an arena, so only the
+    // per-frame arenas owned by passes are listed.
+    pub fn allocator_statistics(&self) -> AllocatorStatistics {
+        AllocatorStatistics::collect(
+            &self.memory_allocator,
+            vec![
+                self.debug_draw_pass.arena_statistics(),
+                self.billboard_pass.arena_statistics(),
+                self.sprite_pass.arena_statistics(),
+            ],
+        )
+    }
+
+    pub fn supports_memory_budget(&self) -> bool {
+        self.queue.device().enabled_extensions().ext_memory_budget
+    }
+
+    pub fn heap_budgets(&self) -> Vec<HeapBudget> {
+        query_heap_budgets(
+            self.queue.device().physical_device(),
+            self.supports_memory_budget(),
+        )
+    }
+
+    pub fn mesh_memory_size(&self) -> DeviceSize {
+        self.mesh_pool.memory_size()
+    }
+
+    pub fn supports_shading_rate(&self) -> bool {
+        self.queue.device().enabled_features().pipeline_fragment_shading_rate
+    }
+
+    pub fn supports_present_wait(&self) -> bool {
+        let features = self.queue.device().enabled_features();
+        features.present_id && features.present_wait
+    }
+
+    pub fn supports_swapchain_maintenance(&self) -> bool {
+        self.queue
+            .device()
+            .enabled_features()
+            .swapchain_maintenance1
+    }
+
+    pub fn supports_wide_lines(&self) -> bool {
+        self.queue.device().enabled_features().wide_lines
+    }
+
+    pub fn supports_dynamic_cull_mode(&self) -> bool {
+        let device = self.queue.device();
+        device.api_version() >= Version::V1_3 || device.enabled_features().extended_dynamic_state
+    }
+
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        self.anti_aliasing
     }
 
     pub fn samples(&self) -> SampleCount {
         self.samples
     }
 
-    pub fn set(&self) -> &Arc<PersistentDescriptorSet> {
+    pub fn set(&self) -> &Arc<DescriptorSet> {
         &self.set
     }
+
+    pub fn ssao_pass(&self) -> &SsaoPass {
+        &self.ssao_pass
+    }
+
+    pub fn contact_shadow_pass(&self) -> &ContactShadowPass {
+        &self.contact_shadow_pass
+    }
+
+    pub fn shadow_map_pass(&self) -> &ShadowMapPass {
+        &self.shadow_map_pass
+    }
+
+    pub fn fxaa_pass(&self) -> &FxaaPass {
+        &self.fxaa_pass
+    }
+
+    pub fn motion_blur_pass(&self) -> &MotionBlurPass {
+        &self.motion_blur_pass
+    }
+
+    pub fn tonemap_pass(&self) -> &TonemapPass {
+        &self.tonemap_pass
+    }
+
+    pub fn skybox_pass(&self) -> &SkyboxPass {
+        &self.skybox_pass
+    }
+
+    pub fn background_pass(&self) -> &BackgroundPass {
+        &self.background_pass
+    }
+
+    pub fn water_pass(&self) -> &WaterPass {
+        &self.water_pass
+    }
+
+    pub fn foliage_pass(&self) -> &FoliagePass {
+        &self.foliage_pass
+    }
+
+    pub fn particle_pass(&self) -> &ParticlePass {
+        &self.particle_pass
+    }
+
+    pub fn tessellation_pass(&self) -> Option<&TessellationPass> {
+        self.tessellation_pass.as_ref()
+    }
+
+    pub fn stereo_pass(&self) -> Option<&StereoPass> {
+        self.stereo_pass.as_ref()
+    }
+
+    pub fn vertex_pulling_pass(&self) -> Option<&VertexPullingPass> {
+        self.vertex_pulling_pass.as_ref()
+    }
+
+    pub fn normal_visualization_pass(&self) -> Option<&NormalVisualizationPass> {
+        self.normal_visualization_pass.as_ref()
+    }
+
+    pub fn debug_draw_pass(&self) -> &DebugDrawPass {
+        &self.debug_draw_pass
+    }
+
+    pub fn billboard_pass(&self) -> &BillboardPass {
+        &self.billboard_pass
+    }
+
+    pub fn grid_pass(&self) -> &GridPass {
+        &self.grid_pass
+    }
+
+    pub fn transparency_pass(&self) -> &TransparencyPass {
+        &self.transparency_pass
+    }
+
+    pub fn picking_pass(&self) -> &PickingPass {
+        &self.picking_pass
+    }
+
+    pub fn outline_pass(&self) -> &OutlinePass {
+        &self.outline_pass
+    }
+
+    pub fn sprite_pass(&self) -> &SpritePass {
+        &self.sprite_pass
+    }
+
+    pub fn meshlet_pass(&self) -> Option<&MeshletPass> {
+        self.meshlet_pass.as_ref()
+    }
+
+    pub fn scene_acceleration(&self) -> Option<MutexGuard<'_, SceneAccelerationStructure>> {
+        self.scene_acceleration
+            .as_ref()
+            .map(|scene_acceleration| scene_acceleration.lock().unwrap())
+    }
+
+    pub fn ray_tracing_pass(&self) -> Option<&RayTracingPass> {
+        self.ray_tracing_pass.as_ref()
+    }
+
+    pub fn ray_query_pass(&self) -> Option<&RayQueryPass> {
+        self.ray_query_pass.as_ref()
+    }
+
+    pub fn path_tracing_pass(&self) -> Option<&PathTracingPass> {
+        self.path_tracing_pass.as_ref()
+    }
+
+    pub fn identity_color_lut(&self) -> &ColorLut {
+        &self.identity_color_lut
+    }
+
+    pub fn default_lighting(&self) -> &ImageBasedLighting {
+        &self.default_lighting
+    }
 }
@@ -1,13 +1,121 @@
-use anyhow::{Context as AnyhowContext, Result};
+use anyhow::{anyhow, bail, Context as AnyhowContext, Result};
+use std::str::FromStr;
 use std::sync::Arc;
 use tracing::info;
 use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
 use vulkano::device::{DeviceExtensions, QueueFlags};
-use vulkano::instance::{Instance, InstanceCreateInfo};
+use vulkano::instance::{Instance, InstanceCreateFlags, InstanceCreateInfo};
 use vulkano::swapchain::Surface;
 use vulkano::{Version, VulkanLibrary};
 use winit::window::Window;
 
+use crate::app::env_flag;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceSelector {
+    #[default]
+    Auto,
+    // Position in the driver's enumeration order, as printed in the device list at startup.
+    Index(usize),
+    // Case-insensitive substring of the device name.
+    Name(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.parse() {
+            Ok(index) => DeviceSelector::Index(index),
+            Err(_) if value.eq_ignore_ascii_case("auto") => DeviceSelector::Auto,
+            Err(_) => DeviceSelector::Name(value.to_lowercase()),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DevicePreference {
+    #[default]
+    HighPerformance,
+    LowPower,
+}
+
+impl FromStr for DevicePreference {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "high-performance" | "discrete" => DevicePreference::HighPerformance,
+            "low-power" | "integrated" => DevicePreference::LowPower,
+            _ => bail!("Unknown device preference: {value}"),
+        })
+    }
+}
+
+impl DevicePreference {
+    fn rank(self, device_type: PhysicalDeviceType) -> u32 {
+        match (self, device_type) {
+            (DevicePreference::HighPerformance, PhysicalDeviceType::DiscreteGpu) => 0,
+            (DevicePreference::HighPerformance, PhysicalDeviceType::IntegratedGpu) => 1,
+            (DevicePreference::LowPower, PhysicalDeviceType::IntegratedGpu) => 0,
+            (DevicePreference::LowPower, PhysicalDeviceType::DiscreteGpu) => 1,
+            (_, PhysicalDeviceType::VirtualGpu) => 2,
+            (_, PhysicalDeviceType::Cpu) => 3,
+            _ => 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DeviceSelection {
+    pub selector: DeviceSelector,
+    pub preference: DevicePreference,
+    // Also opens every other supported device so windows can be spread across them.
+    pub multi_gpu: bool,
+}
+
+impl DeviceSelection {
+    // `VULKANOX_DEVICE`, `VULKANOX_DEVICE_PREFERENCE` and `VULKANOX_MULTI_GPU` are overridden by
+    // `--device <index|name>`, `--device-preference <high-performance|low-power>` and `--multi-gpu`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut selection = Self {
+            selector: std::env::var("VULKANOX_DEVICE")
+                .ok()
+                .map(|value| value.parse())
+                .transpose()?
+                .unwrap_or_default(),
+            preference: std::env::var("VULKANOX_DEVICE_PREFERENCE")
+                .ok()
+                .map(|value| value.parse())
+                .transpose()?
+                .unwrap_or_default(),
+            multi_gpu: env_flag("VULKANOX_MULTI_GPU")?,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--device" => {
+                    selection.selector = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--device needs an index or name"))?
+                        .parse()?;
+                }
+                "--device-preference" => {
+                    selection.preference = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--device-preference needs a value"))?
+                        .parse()?;
+                }
+                "--multi-gpu" => selection.multi_gpu = true,
+                _ => {}
+            }
+        }
+
+        Ok(selection)
+    }
+}
+
 pub struct VulkanInstance {
     physical_device: Arc<PhysicalDevice>,
     queue_family_index: u32,
@@ -15,20 +123,42 @@ pub struct VulkanInstance {
 }
 
 impl VulkanInstance {
-    pub fn new(compatible_window: &Window) -> Result<VulkanInstance> {
+    // The selected device always comes first; the others follow in preference order when
+    // multi-GPU is enabled.
+    pub fn select(
+        compatible_window: &Window,
+        selection: &DeviceSelection,
+    ) -> Result<Vec<VulkanInstance>> {
         let library = VulkanLibrary::new()?;
 
-        let mut instance_extensions = Surface::required_extensions(&compatible_window);
+        let mut instance_extensions = Surface::required_extensions(&compatible_window)?;
 
         if cfg!(debug_assertions) {
             instance_extensions.ext_debug_utils = library.supported_extensions().ext_debug_utils;
         }
 
+        // Decided at runtime rather than by target, so the same path runs wherever a portability
+        // driver (MoltenVK, or a layered implementation in CI) is installed.
+        let portability_enumeration = library.supported_extensions().khr_portability_enumeration;
+        instance_extensions.khr_portability_enumeration = portability_enumeration;
+
+        // Surface maintenance is what devices need for swapchain maintenance.
+        let surface_maintenance = library.supported_extensions().ext_surface_maintenance1
+            && library.supported_extensions().khr_get_surface_capabilities2;
+        instance_extensions.ext_surface_maintenance1 = surface_maintenance;
+        instance_extensions.khr_get_surface_capabilities2 = surface_maintenance;
+        // Lets surfaces offer wide-gamut color spaces besides sRGB.
+        instance_extensions.ext_swapchain_colorspace =
+            library.supported_extensions().ext_swapchain_colorspace;
+
         let instance = Instance::new(
             library,
             InstanceCreateInfo {
-                #[cfg(target_os = "macos")]
-                flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+                flags: if portability_enumeration {
+                    InstanceCreateFlags::ENUMERATE_PORTABILITY
+                } else {
+                    InstanceCreateFlags::empty()
+                },
                 enabled_extensions: instance_extensions,
                 ..InstanceCreateInfo::application_from_cargo_toml()
             },
@@ -37,18 +167,20 @@ impl VulkanInstance {
         let dummy_surface =
             unsafe { Surface::from_window_ref(Arc::clone(&instance), &compatible_window) }?;
 
-        let mut device_extensions = DeviceExtensions {
+        let device_extensions = DeviceExtensions {
             khr_swapchain: true,
             ..DeviceExtensions::empty()
         };
 
-        let (physical_device, queue_family_index) = instance
-            .enumerate_physical_devices()?
-            .filter(|p| {
+        let physical_devices = instance.enumerate_physical_devices()?.collect::<Vec<_>>();
+        let mut candidates = physical_devices
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| {
                 p.api_version() >= Version::V1_3 || p.supported_extensions().khr_dynamic_rendering
             })
-            .filter(|p| p.supported_extensions().contains(&device_extensions))
-            .filter_map(|p| {
+            .filter(|(_, p)| p.supported_extensions().contains(&device_extensions))
+            .filter_map(|(index, p)| {
                 p.queue_family_properties()
                     .iter()
                     .enumerate()
@@ -56,18 +188,61 @@ impl VulkanInstance {
                         q.queue_flags.intersects(QueueFlags::GRAPHICS)
                             && p.surface_support(i as u32, &dummy_surface).unwrap_or(false)
                     })
-                    .map(|i| (p, i as u32))
+                    .map(|i| (index, Arc::clone(p), i as u32))
             })
-            .min_by_key(|(p, _)| match p.properties().device_type {
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                PhysicalDeviceType::Other => 4,
-                _ => unreachable!(),
+            .collect::<Vec<_>>();
+
+        for (index, p) in physical_devices.iter().enumerate() {
+            info!(
+                "Physical device {index}: {} (type: {:?}, api: {}){}",
+                p.properties().device_name,
+                p.properties().device_type,
+                p.api_version(),
+                if candidates.iter().any(|(i, ..)| *i == index) {
+                    ""
+                } else {
+                    " [unsupported]"
+                }
+            );
+        }
+
+        let selected = match &selection.selector {
+            DeviceSelector::Auto => candidates
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (_, p, _))| selection.preference.rank(p.properties().device_type))
+                .map(|(position, _)| position)
+                .context("No suitable physical devices found")?,
+            DeviceSelector::Index(index) => candidates
+                .iter()
+                .position(|(i, ..)| i == index)
+                .with_context(|| format!("Physical device {index} is missing or unsupported"))?,
+            DeviceSelector::Name(name) => candidates
+                .iter()
+                .position(|(_, p, _)| p.properties().device_name.to_lowercase().contains(name))
+                .with_context(|| format!("No supported physical device matches \"{name}\""))?,
+        };
+
+        let mut selected_devices = vec![candidates.remove(selected)];
+        if selection.multi_gpu {
+            candidates
+                .sort_by_key(|(_, p, _)| selection.preference.rank(p.properties().device_type));
+            selected_devices.extend(candidates);
+        }
+
+        Ok(selected_devices
+            .into_iter()
+            .map(|(_, physical_device, queue_family_index)| {
+                Self::for_physical_device(physical_device, queue_family_index, device_extensions)
             })
-            .context("No suitable physical devices found")?;
+            .collect())
+    }
 
+    fn for_physical_device(
+        physical_device: Arc<PhysicalDevice>,
+        queue_family_index: u32,
+        mut device_extensions: DeviceExtensions,
+    ) -> VulkanInstance {
         info!(
             "Using physical device {} (type: {:?})",
             physical_device.properties().device_name,
@@ -76,11 +251,41 @@ impl VulkanInstance {
 
         device_extensions.khr_dynamic_rendering = physical_device.api_version() < Version::V1_3;
 
-        Ok(VulkanInstance {
+        let supported_extensions = physical_device.supported_extensions();
+        // Required whenever it is supported; the device only implements a subset of Vulkan.
+        device_extensions.khr_portability_subset = supported_extensions.khr_portability_subset;
+        device_extensions.ext_mesh_shader = supported_extensions.ext_mesh_shader;
+        device_extensions.khr_spirv_1_4 =
+            supported_extensions.ext_mesh_shader && physical_device.api_version() < Version::V1_2;
+        device_extensions.ext_extended_dynamic_state = supported_extensions
+            .ext_extended_dynamic_state
+            && physical_device.api_version() < Version::V1_3;
+        device_extensions.ext_memory_budget = supported_extensions.ext_memory_budget;
+        device_extensions.khr_present_id = supported_extensions.khr_present_id;
+        device_extensions.khr_present_wait =
+            supported_extensions.khr_present_id && supported_extensions.khr_present_wait;
+        device_extensions.google_display_timing = supported_extensions.google_display_timing;
+        device_extensions.ext_swapchain_maintenance1 = supported_extensions
+            .ext_swapchain_maintenance1
+            && physical_device
+                .instance()
+                .enabled_extensions()
+                .ext_surface_maintenance1;
+        device_extensions.khr_fragment_shading_rate = supported_extensions.khr_fragment_shading_rate;
+        device_extensions.khr_create_renderpass2 = supported_extensions.khr_fragment_shading_rate
+            && physical_device.api_version() < Version::V1_2;
+        let ray_query = supported_extensions.khr_acceleration_structure
+            && supported_extensions.khr_ray_query
+            && physical_device.api_version() >= Version::V1_2;
+        device_extensions.khr_acceleration_structure = ray_query;
+        device_extensions.khr_deferred_host_operations = ray_query;
+        device_extensions.khr_ray_query = ray_query;
+
+        VulkanInstance {
             physical_device,
             queue_family_index,
             device_extensions,
-        })
+        }
     }
 
     pub fn physical_device(&self) -> &Arc<PhysicalDevice> {
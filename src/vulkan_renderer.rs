@@ -1,45 +1,471 @@
+use std::num::NonZeroU64;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, Vector3};
 use palette::Srgba;
+use tracing::warn;
 use vulkano::command_buffer::{
-    AutoCommandBufferBuilder, CommandBufferUsage, RenderingAttachmentInfo,
-    RenderingAttachmentResolveInfo, RenderingInfo,
+    AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferInheritanceRenderingInfo,
+    CommandBufferUsage, PrimaryAutoCommandBuffer, RenderingAttachmentInfo,
+    RenderingAttachmentResolveInfo, RenderingInfo, SubpassContents,
 };
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::device::DeviceOwned;
-use vulkano::format::Format::B8G8R8A8_SRGB;
-use vulkano::format::{ClearValue, Format};
+use vulkano::format::{ClearColorValue, ClearValue, Format};
 use vulkano::image::view::ImageView;
-use vulkano::image::{Image, ImageCreateInfo, ImageUsage};
-use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::image::{Image, ImageUsage, SampleCount};
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
 use vulkano::pipeline::graphics::viewport::Viewport;
-use vulkano::pipeline::{Pipeline, PipelineBindPoint};
-use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp, ResolveMode};
 use vulkano::swapchain::{
-    acquire_next_image, PresentMode, Surface, SurfaceInfo, Swapchain, SwapchainCreateInfo,
-    SwapchainPresentInfo,
+    acquire_next_image, CompositeAlpha, PresentGravity, PresentMode, PresentScaling, Surface,
+    SurfaceCapabilities, SurfaceInfo, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo,
 };
 use vulkano::sync::GpuFuture;
-use vulkano::{sync, Validated, VulkanError};
+use vulkano::{sync, DeviceSize, Validated, VulkanError};
 use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton};
 use winit::window::Window;
 
-use crate::vulkan_device::{vs, VulkanDevice};
+use crate::background::Background;
+use crate::benchmark::Benchmark;
+use crate::billboard::Billboards;
+use crate::capture::FrameCapture;
+use crate::color_management::{authored_color, OutputColorSpace};
+use crate::contact_shadows::ContactShadowSettings;
+use crate::debug_draw::{DebugDraw, FrustumVisualizationSettings};
+use crate::dynamic_mesh::DynamicMesh;
+use crate::environment::EnvironmentMap;
+use crate::foliage::{Foliage, FoliageSettings};
+use crate::frame_pacing::{monitor_refresh_interval, refresh_interval};
+use crate::fxaa::FxaaEffect;
+use crate::gif_recorder::{GifRecorder, GifSettings};
+use crate::gizmo::{Gizmo, Ray};
+use crate::gpu_scene::{
+    object_index, record_object_draws, GpuScene, ObjectDraw, SceneObjects, GPU_SCENE_SET,
+};
+use crate::grid::GridSettings;
+use crate::ibl::ImageBasedLighting;
+use crate::light::DirectionalLight;
+use crate::lightmap::Lightmap;
+use crate::material::SceneMaterial;
+use crate::memory::{image_size, MemoryCategory, MemoryReport};
+use crate::meshlet::MeshletObjects;
+use crate::metrics::{triangle_count, FrameMetrics};
+use crate::minimap::Minimap;
+use crate::motion_blur::MotionBlurEffect;
+use crate::normal_visualization::NormalVisualizationSettings;
+use crate::outline::{OutlineSettings, OutlineViews, MASK_FORMAT};
+use crate::parallel_recording::{ParallelRecordingSettings, RecordedScene, SceneDraw};
+use crate::particles::{CollisionViews, EmitterSettings, ParticleSystem};
+use crate::path_tracing::{PathAccumulation, PathTracedScene, PathTracingSettings};
+use crate::picking::{ObjectId, PickResult, Picker};
+use crate::picture_in_picture::PictureInPicture;
+use crate::pixel_inspector::{InspectedTargets, PixelInspector};
+use crate::post_process::{PostProcessContext, PostProcessStack};
+use crate::pre_rotation::{pre_rotate_position, pre_rotated_extent, pre_rotation_matrix};
+use crate::punctual_light::{draw_light_ranges, LightIcons, LightVisualizationSettings};
+use crate::ray_query::{RayQuerySettings, RayQueryViews, VISIBILITY_FORMAT};
+use crate::ray_tracing::RayTracedScene;
+use crate::reflection_probe::{ReflectionProbe, ReflectionProbeSettings};
+use crate::render_pass_plugin::{
+    PluginStage, RenderPassContext, RenderPassPlugin, RenderPassPlugins,
+};
+use crate::render_texture::RenderTexture;
+use crate::scene_file::{CameraState, SceneFile, CAMERA_BOOKMARK_SLOTS};
+use crate::scene_graph::{SceneGraph, TraversalSettings};
+use crate::sh_probes::ShProbeBuffers;
+use crate::shading_rate::{ShadingRate, ShadingRateSettings};
+use crate::shadow_map::{ShadowCasters, ShadowMap, ShadowMapSettings, ShadowViews};
+use crate::sprite::SpriteBatch;
+use crate::split_screen::SplitViewport;
+use crate::ssao::{SsaoSettings, SsaoTargets};
+use crate::stereo::{StereoScene, StereoSettings, StereoTargets};
+use crate::swapchain_format::SwapchainFormat;
+use crate::tessellation::TessellationSettings;
+use crate::tonemap::TonemapEffect;
+use crate::transient_pool::TransientImagePool;
+use crate::transparency::{
+    sort_back_to_front, TransparencyMode, TransparentDraw, TransparentObjects,
+    WeightedBlendedViews, ACCUMULATION_FORMAT, REVEALAGE_FORMAT,
+};
+use crate::vertex_pulling::VertexPullingPass;
+use crate::vulkan_device::{
+    vs, AntiAliasing, DebugView, VulkanDevice, DEPTH_FORMAT, HDR_FORMAT, NORMAL_FORMAT, VELOCITY_FORMAT,
+};
+use crate::water::{WaterSettings, WaterViews};
+
+const MEMORY_CHECK_INTERVAL: u64 = 300;
+// Bounds the stall when a present never completes, e.g. while the window is occluded.
+const PRESENT_WAIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+fn subpass_contents(secondary: bool) -> SubpassContents {
+    if secondary {
+        SubpassContents::SecondaryCommandBuffers
+    } else {
+        SubpassContents::Inline
+    }
+}
+
+// Takes the metrics alone so it can be called while render attachments borrow the renderer.
+fn count_object_draws(
+    frame_metrics: &mut FrameMetrics,
+    topology: PrimitiveTopology,
+    objects: &[ObjectDraw],
+) {
+    for object in objects {
+        frame_metrics.draw(triangle_count(topology, object.mesh.index_count));
+    }
+}
+
+fn select_present_mode(surface_present_modes: &[PresentMode], vsync: bool) -> PresentMode {
+    let preferred: &[PresentMode] = if vsync {
+        &[PresentMode::Mailbox]
+    } else {
+        &[PresentMode::Immediate, PresentMode::FifoRelaxed]
+    };
+    preferred
+        .iter()
+        .copied()
+        .find(|present_mode| surface_present_modes.contains(present_mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+// Shows a frame that doesn't match the window's size, as happens mid-resize, at its own size in the
+// top-left corner rather than stretched over the window.
+fn present_scaling(
+    surface_capabilities: &SurfaceCapabilities,
+) -> (Option<PresentScaling>, Option<[PresentGravity; 2]>) {
+    if surface_capabilities
+        .supported_present_scaling
+        .contains_enum(PresentScaling::OneToOne)
+        && surface_capabilities
+            .supported_present_gravity
+            .iter()
+            .all(|gravity| gravity.contains_enum(PresentGravity::Min))
+    {
+        (
+            Some(PresentScaling::OneToOne),
+            Some([PresentGravity::Min; 2]),
+        )
+    } else {
+        (None, None)
+    }
+}
+
+pub struct RenderTargets {
+    intermediary_image: Option<Arc<ImageView>>,
+    depth_view: Arc<ImageView>,
+    normal_view: Arc<ImageView>,
+    velocity_view: Arc<ImageView>,
+    resolved_depth_view: Option<Arc<ImageView>>,
+    resolved_normal_view: Option<Arc<ImageView>>,
+    resolved_velocity_view: Option<Arc<ImageView>>,
+    scene_color_view: Arc<ImageView>,
+    water_refraction_view: Arc<ImageView>,
+    visibility_view: Arc<ImageView>,
+    selection_mask_view: Arc<ImageView>,
+    accumulation_view: Arc<ImageView>,
+    revealage_view: Arc<ImageView>,
+    ssao: SsaoTargets,
+    occlusion_set: Arc<DescriptorSet>,
+    recorded_prepass: RecordedScene,
+    recorded_scene: RecordedScene,
+}
+
+impl RenderTargets {
+    pub fn new(vulkan_device: &VulkanDevice, extent: [u32; 2]) -> Result<Self> {
+        let samples = vulkan_device.samples();
+
+        let intermediary_image = if samples != SampleCount::Sample1 {
+            Some(vulkan_device.create_attachment(
+                HDR_FORMAT,
+                extent,
+                ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+                samples,
+            )?)
+        } else {
+            None
+        };
+
+        let (depth_view, resolved_depth_view) = Self::create_sampled_attachment(
+            vulkan_device,
+            DEPTH_FORMAT,
+            extent,
+            // Copied from by the pixel inspector.
+            ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+        )?;
+        let (normal_view, resolved_normal_view) = Self::create_sampled_attachment(
+            vulkan_device,
+            NORMAL_FORMAT,
+            extent,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+        )?;
+        let (velocity_view, resolved_velocity_view) = Self::create_sampled_attachment(
+            vulkan_device,
+            VELOCITY_FORMAT,
+            extent,
+            ImageUsage::COLOR_ATTACHMENT,
+        )?;
+
+        let scene_color_view = vulkan_device.create_attachment(
+            HDR_FORMAT,
+            extent,
+            ImageUsage::COLOR_ATTACHMENT
+                | ImageUsage::SAMPLED
+                | ImageUsage::TRANSFER_SRC
+                | ImageUsage::TRANSFER_DST
+                | ImageUsage::STORAGE,
+            SampleCount::Sample1,
+        )?;
+        let water_refraction_view = vulkan_device.create_attachment(
+            HDR_FORMAT,
+            extent,
+            ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+            SampleCount::Sample1,
+        )?;
+        let visibility_view = vulkan_device.create_attachment(
+            VISIBILITY_FORMAT,
+            extent,
+            ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED | ImageUsage::STORAGE,
+            SampleCount::Sample1,
+        )?;
+
+        let selection_mask_view = vulkan_device.create_attachment(
+            MASK_FORMAT,
+            extent,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            SampleCount::Sample1,
+        )?;
+
+        let accumulation_view = vulkan_device.create_attachment(
+            ACCUMULATION_FORMAT,
+            extent,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            SampleCount::Sample1,
+        )?;
+        let revealage_view = vulkan_device.create_attachment(
+            REVEALAGE_FORMAT,
+            extent,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            SampleCount::Sample1,
+        )?;
+
+        let ssao = SsaoTargets::new(
+            vulkan_device,
+            extent,
+            resolved_depth_view.as_ref().unwrap_or(&depth_view),
+            resolved_normal_view.as_ref().unwrap_or(&normal_view),
+        )?;
+
+        let occlusion_set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&vulkan_device.graphics_pipeline().layout().set_layouts()[1]),
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    Arc::clone(ssao.occlusion_view()),
+                    Arc::clone(vulkan_device.ssao_pass().sampler()),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    Arc::clone(&visibility_view),
+                    Arc::clone(vulkan_device.ssao_pass().sampler()),
+                ),
+            ],
+            [],
+        )?;
+
+        Ok(Self {
+            intermediary_image,
+            depth_view,
+            normal_view,
+            velocity_view,
+            resolved_depth_view,
+            resolved_normal_view,
+            resolved_velocity_view,
+            scene_color_view,
+            water_refraction_view,
+            visibility_view,
+            selection_mask_view,
+            accumulation_view,
+            revealage_view,
+            ssao,
+            occlusion_set,
+            recorded_prepass: RecordedScene::default(),
+            recorded_scene: RecordedScene::default(),
+        })
+    }
+
+    fn create_sampled_attachment(
+        vulkan_device: &VulkanDevice,
+        format: Format,
+        extent: [u32; 2],
+        usage: ImageUsage,
+    ) -> Result<(Arc<ImageView>, Option<Arc<ImageView>>)> {
+        let samples = vulkan_device.samples();
+
+        if samples == SampleCount::Sample1 {
+            return Ok((
+                vulkan_device.create_attachment(format, extent, usage | ImageUsage::SAMPLED, samples)?,
+                None,
+            ));
+        }
+
+        Ok((
+            vulkan_device.create_attachment(format, extent, usage, samples)?,
+            Some(vulkan_device.create_attachment(
+                format,
+                extent,
+                usage | ImageUsage::SAMPLED,
+                SampleCount::Sample1,
+            )?),
+        ))
+    }
+
+    pub fn memory_size(&self) -> DeviceSize {
+        [
+            self.intermediary_image.as_ref(),
+            Some(&self.depth_view),
+            Some(&self.normal_view),
+            Some(&self.velocity_view),
+            self.resolved_depth_view.as_ref(),
+            self.resolved_normal_view.as_ref(),
+            self.resolved_velocity_view.as_ref(),
+            Some(&self.scene_color_view),
+            Some(&self.water_refraction_view),
+            Some(&self.visibility_view),
+            Some(&self.selection_mask_view),
+            Some(&self.accumulation_view),
+            Some(&self.revealage_view),
+            Some(self.ssao.occlusion_view()),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|view| image_size(view.image()))
+        .sum()
+    }
+
+    pub fn scene_color_view(&self) -> &Arc<ImageView> {
+        &self.scene_color_view
+    }
+
+    fn depth_view(&self) -> &Arc<ImageView> {
+        self.resolved_depth_view.as_ref().unwrap_or(&self.depth_view)
+    }
+
+    fn normal_view(&self) -> &Arc<ImageView> {
+        self.resolved_normal_view
+            .as_ref()
+            .unwrap_or(&self.normal_view)
+    }
+
+    fn velocity_view(&self) -> &Arc<ImageView> {
+        self.resolved_velocity_view
+            .as_ref()
+            .unwrap_or(&self.velocity_view)
+    }
+}
 
 pub struct VulkanRenderer {
     vulkan_device: Arc<VulkanDevice>,
-    window: Arc<Window>,
     swapchain: Arc<Swapchain>,
     swapchain_images: Vec<Arc<Image>>,
     swapchain_image_views: Vec<Arc<ImageView>>,
-    intermediary_image: Arc<ImageView>,
-    depth_view: Arc<ImageView>,
-    clear_color: [f32; 4],
-    previous_frame_end: Option<Box<dyn GpuFuture>>,
+    targets: RenderTargets,
+    ssao_settings: SsaoSettings,
+    contact_shadow_settings: ContactShadowSettings,
+    shadow_map_settings: ShadowMapSettings,
+    shadow_map: Option<ShadowMap>,
+    water_settings: WaterSettings,
+    tessellation_settings: TessellationSettings,
+    normal_visualization_settings: NormalVisualizationSettings,
+    debug_draw: DebugDraw,
+    overlay_draw: DebugDraw,
+    frustum_visualization_settings: FrustumVisualizationSettings,
+    light_visualization_settings: LightVisualizationSettings,
+    light_icons: LightIcons,
+    sprite_batch: SpriteBatch,
+    color_audit: bool,
+    billboards: Billboards,
+    transparency_mode: TransparencyMode,
+    scene_materials: Vec<SceneMaterial>,
+    gizmo: Option<Gizmo>,
+    // The scene node the gizmo moves.
+    gizmo_node: Option<usize>,
+    picker: Picker,
+    pixel_inspector: PixelInspector,
+    selection: Option<ObjectId>,
+    outline_settings: OutlineSettings,
+    grid_settings: GridSettings,
+    mesh_shading: bool,
+    vertex_pulling: bool,
+    ray_tracing: bool,
+    wireframe: bool,
+    debug_view: DebugView,
+    split_viewports: Vec<SplitViewport>,
+    render_textures: Vec<RenderTexture>,
+    reflection_probes: Vec<ReflectionProbe>,
+    lightmap: Arc<Lightmap>,
+    sh_probes: Arc<ShProbeBuffers>,
+    minimap: Option<Minimap>,
+    picture_in_picture: Option<PictureInPicture>,
+    ray_query_settings: RayQuerySettings,
+    path_tracing_settings: PathTracingSettings,
+    path_accumulation: Option<PathAccumulation>,
+    parallel_recording_settings: ParallelRecordingSettings,
+    scene_graph: SceneGraph,
+    selected_scene: usize,
+    traversal_settings: TraversalSettings,
+    gpu_scene: GpuScene,
+    dynamic_meshes: Vec<DynamicMesh>,
+    stereo_settings: StereoSettings,
+    stereo_targets: Option<StereoTargets>,
+    shading_rate_settings: ShadingRateSettings,
+    post_process_stack: PostProcessStack,
+    render_pass_plugins: RenderPassPlugins,
+    transient_pool: TransientImagePool,
+    environment: Option<Arc<EnvironmentMap>>,
+    environment_intensity: f32,
+    background: Background,
+    sun: Option<DirectionalLight>,
+    camera: Option<CameraState>,
+    camera_bookmarks: [Option<CameraState>; CAMERA_BOOKMARK_SLOTS],
+    // The main view's view-projection as uploaded last frame, for motion vectors.
+    previous_view_projection: Option<Matrix4<f32>>,
+    lighting_set: Arc<DescriptorSet>,
+    foliage: Option<Arc<Foliage>>,
+    foliage_settings: FoliageSettings,
+    particle_systems: Vec<ParticleSystem>,
+    frame_capture: Option<FrameCapture>,
+    gif_recorder: GifRecorder,
+    benchmark: Option<Benchmark>,
+    frame_metrics: FrameMetrics,
+    background_alpha: f32,
+    previous_frame_end: Option<Box<dyn GpuFuture + Send>>,
+    surface_present_modes: Vec<PresentMode>,
+    vsync: bool,
+    // Can differ from the swapchain's own mode when it was created with several.
+    present_mode: PresentMode,
+    present_id: u64,
+    pending_present: Option<(NonZeroU64, Instant)>,
+    present_latency_ms: Option<f32>,
+    refresh_interval: Option<Duration>,
+    // The window's state as the event loop last reported it. The render thread can't query the
+    // window itself, since some platforms answer on the event loop's thread, which may be waiting
+    // on the render thread.
+    window_size: [u32; 2],
+    monitor_refresh_interval: Option<Duration>,
+    // The window's size when the swapchain was last built, to notice when it no longer fits.
+    swapchain_window_size: [u32; 2],
+    // Set once the surface is lost; nothing is drawn until the event loop hands over a new one.
+    surface_lost: bool,
     start_time: Instant,
-    window_index: usize,
-    window_count: usize,
+    previous_frame_time: Instant,
     mouse_position: [f32; 2],
 }
 
@@ -47,11 +473,11 @@ impl VulkanRenderer {
     pub fn new(
         vulkan_device: Arc<VulkanDevice>,
         window: Arc<Window>,
-        clear_color: [f32; 4],
         is_vsync: bool,
+        swapchain_format: SwapchainFormat,
+        output_color_space: OutputColorSpace,
+        background_alpha: Option<f32>,
         image_usage: ImageUsage,
-        window_index: usize,
-        window_count: usize,
     ) -> Result<Self> {
         let device = vulkan_device.queue().device();
         let physical_device = device.physical_device();
@@ -64,39 +490,84 @@ impl VulkanRenderer {
 
         let surface_capabilities =
             physical_device.surface_capabilities(&surface, surface_info.clone())?;
-        let surface_present_modes = physical_device
-            .surface_present_modes(&surface, surface_info)?
-            .collect::<Vec<_>>();
+        let surface_formats = physical_device.surface_formats(&surface, surface_info.clone())?;
+        let surface_present_modes =
+            physical_device.surface_present_modes(&surface, surface_info)?;
 
-        let present_mode = if is_vsync {
-            if surface_present_modes.contains(&PresentMode::Mailbox) {
-                PresentMode::Mailbox
-            } else {
-                PresentMode::Fifo
-            }
-        } else {
-            if surface_present_modes.contains(&PresentMode::Immediate) {
-                PresentMode::Immediate
-            } else if surface_present_modes.contains(&PresentMode::FifoRelaxed) {
-                PresentMode::FifoRelaxed
+        let present_mode = select_present_mode(&surface_present_modes, is_vsync);
+        // With swapchain maintenance, toggling vsync switches modes at the next present instead of
+        // rebuilding the swapchain, when the surface allows switching between them.
+        let (present_modes, scaling_behavior, present_gravity) =
+            if vulkan_device.supports_swapchain_maintenance() {
+                let surface_capabilities = physical_device.surface_capabilities(
+                    &surface,
+                    SurfaceInfo {
+                        present_mode: Some(present_mode),
+                        ..Default::default()
+                    },
+                )?;
+                let other_present_mode = select_present_mode(&surface_present_modes, !is_vsync);
+                let mut present_modes = vec![present_mode];
+                if other_present_mode != present_mode
+                    && surface_capabilities
+                        .compatible_present_modes
+                        .contains(&other_present_mode)
+                {
+                    present_modes.push(other_present_mode);
+                }
+                let (scaling_behavior, present_gravity) = present_scaling(&surface_capabilities);
+                (present_modes, scaling_behavior, present_gravity)
             } else {
-                PresentMode::Fifo
-            }
+                (Vec::new(), None, None)
+            };
+
+        // Premultiplied is preferred, since blending in the scene already produces premultiplied color.
+        let composite_alpha = match background_alpha {
+            Some(_) => [
+                CompositeAlpha::PreMultiplied,
+                CompositeAlpha::PostMultiplied,
+                CompositeAlpha::Inherit,
+            ]
+            .into_iter()
+            .find(|&composite_alpha| {
+                surface_capabilities
+                    .supported_composite_alpha
+                    .contains_enum(composite_alpha)
+            })
+            .unwrap_or_else(|| {
+                warn!("The surface doesn't support transparent compositing");
+                CompositeAlpha::Opaque
+            }),
+            None => CompositeAlpha::Opaque,
+        };
+        let background_alpha = match composite_alpha {
+            CompositeAlpha::Opaque => 1.0,
+            _ => background_alpha.unwrap_or(1.0).clamp(0.0, 1.0),
         };
 
+        let (image_format, output_color_space) =
+            output_color_space.select(swapchain_format, &surface_formats)?;
         let (swapchain, swapchain_images) = Swapchain::new(
             Arc::clone(device),
             surface,
             SwapchainCreateInfo {
-                image_extent: surface_capabilities
-                    .current_extent
-                    .unwrap_or(window_inner_size.into()),
-                image_format: B8G8R8A8_SRGB,
+                image_extent: pre_rotated_extent(
+                    surface_capabilities
+                        .current_extent
+                        .unwrap_or(window_inner_size.into()),
+                    surface_capabilities.current_transform,
+                ),
+                image_format,
+                image_color_space: output_color_space.color_space(),
                 min_image_count: (surface_capabilities.min_image_count + 1)
                     .min(surface_capabilities.max_image_count.unwrap_or(u32::MAX)),
                 pre_transform: surface_capabilities.current_transform,
                 present_mode,
+                present_modes: present_modes.into_iter().collect(),
+                scaling_behavior,
+                present_gravity,
                 image_usage,
+                composite_alpha,
                 ..Default::default()
             },
         )?;
@@ -106,234 +577,2342 @@ impl VulkanRenderer {
             .map(|image| ImageView::new_default(Arc::clone(image)))
             .try_collect::<Vec<_>>()?;
 
-        let intermediary_image = ImageView::new_default(
-            Image::new(
-                vulkan_device.memory_allocator().clone(),
-                ImageCreateInfo {
-                    format: swapchain.image_format(),
-                    extent: [swapchain.image_extent()[0], swapchain.image_extent()[1], 1],
-                    usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
-                    samples: vulkan_device.samples(),
-                    ..Default::default()
-                },
-                AllocationCreateInfo::default(),
-            )
-            .unwrap(),
-        )
-        .unwrap();
+        let targets = RenderTargets::new(&vulkan_device, swapchain.image_extent())?;
+        let picker = Picker::new(&vulkan_device)?;
+        let pixel_inspector = PixelInspector::new(&vulkan_device)?;
+        let monitor_refresh_interval = monitor_refresh_interval(&window);
+        let refresh_interval = refresh_interval(&swapchain, monitor_refresh_interval);
 
-        let depth_view = ImageView::new_default(Image::new(
-            vulkan_device.memory_allocator().clone(),
-            ImageCreateInfo {
-                format: Format::D16_UNORM,
-                extent: [swapchain.image_extent()[0], swapchain.image_extent()[1], 1],
-                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
-                samples: vulkan_device.samples(),
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )?)?;
+        let mut post_process_stack = PostProcessStack::new();
+        post_process_stack.push(MotionBlurEffect::default(), false);
+        let mut tonemap_effect = TonemapEffect::new(swapchain.image_format());
+        tonemap_effect.settings.preserve_alpha = composite_alpha != CompositeAlpha::Opaque;
+        tonemap_effect.settings.output_color_space = output_color_space;
+        post_process_stack.push(tonemap_effect, true);
+        post_process_stack.push(
+            FxaaEffect::new(swapchain.image_format()),
+            vulkan_device.anti_aliasing() == AntiAliasing::Fxaa,
+        );
 
-        let previous_frame_end = Some(sync::now(device.clone()).boxed());
+        let lightmap = Arc::new(Lightmap::empty(&vulkan_device)?);
+        let sh_probes = Arc::new(ShProbeBuffers::empty(&vulkan_device)?);
+        let lighting_set = vulkan_device.default_lighting().create_set(
+            &vulkan_device,
+            &DirectionalLight::default(),
+            &[],
+            &lightmap,
+            &sh_probes,
+        )?;
+        let scene_materials = vulkan_device.scene_materials().to_vec();
+        let scene_graph = vulkan_device.scene_graph().clone();
+        let selected_scene = vulkan_device.default_scene();
+        let gpu_scene = GpuScene::new(&vulkan_device)?;
+
+        let previous_frame_end = Some(sync::now(device.clone()).boxed_send());
 
         Ok(Self {
             vulkan_device,
-            window,
             swapchain,
             swapchain_images,
             swapchain_image_views,
-            intermediary_image,
-            depth_view,
-            clear_color,
+            targets,
+            ssao_settings: SsaoSettings::default(),
+            contact_shadow_settings: ContactShadowSettings::default(),
+            shadow_map_settings: ShadowMapSettings::default(),
+            shadow_map: None,
+            water_settings: WaterSettings::default(),
+            tessellation_settings: TessellationSettings::default(),
+            normal_visualization_settings: NormalVisualizationSettings::default(),
+            debug_draw: DebugDraw::new(),
+            overlay_draw: DebugDraw::new(),
+            frustum_visualization_settings: FrustumVisualizationSettings::default(),
+            light_visualization_settings: LightVisualizationSettings::default(),
+            light_icons: LightIcons::new(),
+            sprite_batch: SpriteBatch::new(),
+            color_audit: false,
+            billboards: Billboards::new(),
+            transparency_mode: TransparencyMode::default(),
+            scene_materials,
+            gizmo: None,
+            gizmo_node: None,
+            picker,
+            pixel_inspector,
+            selection: None,
+            outline_settings: OutlineSettings::default(),
+            grid_settings: GridSettings::default(),
+            mesh_shading: false,
+            vertex_pulling: false,
+            ray_tracing: false,
+            wireframe: false,
+            debug_view: DebugView::Lit,
+            split_viewports: Vec::new(),
+            render_textures: Vec::new(),
+            reflection_probes: Vec::new(),
+            lightmap,
+            sh_probes,
+            minimap: None,
+            picture_in_picture: None,
+            ray_query_settings: RayQuerySettings::default(),
+            path_tracing_settings: PathTracingSettings::default(),
+            path_accumulation: None,
+            parallel_recording_settings: ParallelRecordingSettings::default(),
+            scene_graph,
+            selected_scene,
+            traversal_settings: TraversalSettings::default(),
+            gpu_scene,
+            dynamic_meshes: Vec::new(),
+            stereo_settings: StereoSettings::default(),
+            stereo_targets: None,
+            shading_rate_settings: ShadingRateSettings::default(),
+            post_process_stack,
+            render_pass_plugins: RenderPassPlugins::new(),
+            transient_pool: TransientImagePool::new(),
+            environment: None,
+            environment_intensity: 1.0,
+            background: Background::default(),
+            sun: None,
+            camera: None,
+            camera_bookmarks: Default::default(),
+            previous_view_projection: None,
+            lighting_set,
+            foliage: None,
+            foliage_settings: FoliageSettings::default(),
+            particle_systems: Vec::new(),
+            frame_capture: None,
+            gif_recorder: GifRecorder::new(GifSettings::default()),
+            benchmark: None,
+            frame_metrics: FrameMetrics::default(),
+            background_alpha,
             previous_frame_end,
+            surface_present_modes,
+            vsync: is_vsync,
+            present_mode,
+            present_id: 0,
+            pending_present: None,
+            present_latency_ms: None,
+            refresh_interval,
+            window_size: window_inner_size.into(),
+            monitor_refresh_interval,
+            swapchain_window_size: window_inner_size.into(),
+            surface_lost: false,
             start_time: Instant::now(),
-            window_index,
-            window_count,
+            previous_frame_time: Instant::now(),
             mouse_position: [0.0, 0.0],
         })
     }
 
     pub fn on_mouse_moved(&mut self, position: PhysicalPosition<f64>) {
-        let size = self.window.inner_size();
+        let [width, height] = self.window_size;
         self.mouse_position = [
-            position.x as f32 / size.width as f32,
-            position.y as f32 / size.height as f32,
+            position.x as f32 / width as f32,
+            position.y as f32 / height as f32,
         ];
+
+        let ray = self.mouse_ray();
+        let eye = self.camera().eye;
+        if let (Some(gizmo), Some(ray)) = (&mut self.gizmo, ray) {
+            gizmo.update(&ray, &eye);
+            if let Some(node) = self.gizmo_node.filter(|_| gizmo.is_dragging()) {
+                self.scene_graph
+                    .set_world_transform(node, *gizmo.transform());
+            }
+        }
     }
 
-    pub fn recreate(&mut self) -> Result<()> {
-        let surface_info = SurfaceInfo::default();
-        let surface_capabilities = self
-            .swapchain
-            .device()
-            .physical_device()
-            .surface_capabilities(&self.swapchain.surface(), surface_info.clone())?;
+    pub fn on_mouse_input(&mut self, state: ElementState, button: MouseButton) {
+        if button != MouseButton::Left {
+            return;
+        }
 
-        self.swapchain_images.clear();
-        self.swapchain_image_views.clear();
+        let ray = self.mouse_ray();
+        match state {
+            ElementState::Pressed => {
+                let dragging = match (&mut self.gizmo, self.gizmo_node, ray) {
+                    (Some(gizmo), Some(_), Some(ray)) => gizmo.begin_drag(&ray),
+                    _ => false,
+                };
+                if !dragging {
+                    self.select_under_cursor();
+                }
+            }
+            ElementState::Released => {
+                if let Some(gizmo) = &mut self.gizmo {
+                    gizmo.end_drag();
+                }
+            }
+        }
+    }
 
-        let (new_swapchain, new_swapchain_images) =
-            self.swapchain.recreate(SwapchainCreateInfo {
-                image_extent: surface_capabilities
-                    .current_extent
-                    .unwrap_or(self.window.inner_size().into()),
-                ..self.swapchain.create_info()
-            })?;
+    pub fn select_under_cursor(&mut self) {
+        self.set_selection(self.picker.result().map(|result| result.object));
+    }
 
-        self.swapchain = new_swapchain;
-        self.swapchain_image_views = new_swapchain_images
-            .iter()
-            .map(|image| ImageView::new_default(Arc::clone(image)))
-            .try_collect::<Vec<_>>()?;
-        self.swapchain_images = new_swapchain_images;
-        self.intermediary_image = ImageView::new_default(Image::new(
-            self.vulkan_device.memory_allocator().clone(),
-            ImageCreateInfo {
-                format: self.swapchain.image_format(),
-                extent: [
-                    self.swapchain.image_extent()[0],
-                    self.swapchain.image_extent()[1],
-                    1,
-                ],
-                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
-                samples: self.vulkan_device.samples(),
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )?)?;
-
-        self.depth_view = ImageView::new_default(Image::new(
-            self.vulkan_device.memory_allocator().clone(),
-            ImageCreateInfo {
-                format: Format::D16_UNORM,
-                extent: [
-                    self.swapchain.image_extent()[0],
-                    self.swapchain.image_extent()[1],
-                    1,
-                ],
-                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
-                samples: self.vulkan_device.samples(),
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )?)?;
+    pub fn selection(&self) -> Option<ObjectId> {
+        self.selection
+    }
 
-        Ok(())
+    // The gizmo follows the selection, and is hidden without one.
+    pub fn set_selection(&mut self, selection: Option<ObjectId>) {
+        self.selection = selection;
+        self.set_gizmo_node(selection.map(|selection| selection.node_index()));
     }
 
-    pub fn render(&mut self) -> Result<()> {
-        let image_extent: [u32; 2] = self.window.inner_size().into();
-        if image_extent.contains(&0) {
-            return Ok(());
+    pub fn outline_settings_mut(&mut self) -> &mut OutlineSettings {
+        &mut self.outline_settings
+    }
+
+    fn mouse_ray(&self) -> Option<Ray> {
+        Ray::from_screen(&self.view_projection(), self.mouse_position)
+    }
+
+    pub fn ssao_settings_mut(&mut self) -> &mut SsaoSettings {
+        &mut self.ssao_settings
+    }
+
+    pub fn contact_shadow_settings_mut(&mut self) -> &mut ContactShadowSettings {
+        &mut self.contact_shadow_settings
+    }
+
+    pub fn shadow_map_settings_mut(&mut self) -> &mut ShadowMapSettings {
+        &mut self.shadow_map_settings
+    }
+
+    pub fn water_settings_mut(&mut self) -> &mut WaterSettings {
+        &mut self.water_settings
+    }
+
+    pub fn tessellation_settings_mut(&mut self) -> &mut TessellationSettings {
+        &mut self.tessellation_settings
+    }
+
+    pub fn normal_visualization_settings_mut(&mut self) -> &mut NormalVisualizationSettings {
+        &mut self.normal_visualization_settings
+    }
+
+    pub fn debug_draw_mut(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    pub fn overlay_draw_mut(&mut self) -> &mut DebugDraw {
+        &mut self.overlay_draw
+    }
+
+    pub fn frustum_visualization_settings_mut(&mut self) -> &mut FrustumVisualizationSettings {
+        &mut self.frustum_visualization_settings
+    }
+
+    pub fn light_visualization_settings_mut(&mut self) -> &mut LightVisualizationSettings {
+        &mut self.light_visualization_settings
+    }
+
+    pub fn sprite_batch_mut(&mut self) -> &mut SpriteBatch {
+        &mut self.sprite_batch
+    }
+
+    pub fn billboards_mut(&mut self) -> &mut Billboards {
+        &mut self.billboards
+    }
+
+    pub fn transparency_mode(&self) -> TransparencyMode {
+        self.transparency_mode
+    }
+
+    pub fn set_transparency_mode(&mut self, transparency_mode: TransparencyMode) {
+        self.transparency_mode = transparency_mode;
+    }
+
+    pub fn scene_materials_mut(&mut self) -> &mut [SceneMaterial] {
+        &mut self.scene_materials
+    }
+
+    // The first scene material, whose state every scene draw still shares.
+    fn scene_material(&self) -> &SceneMaterial {
+        &self.scene_materials[0]
+    }
+
+    pub fn set_gizmo(&mut self, gizmo: Option<Gizmo>) {
+        self.gizmo = gizmo;
+        self.sync_gizmo();
+    }
+
+    pub fn gizmo_node(&self) -> Option<usize> {
+        self.gizmo_node
+    }
+
+    pub fn set_gizmo_node(&mut self, node: Option<usize>) {
+        self.gizmo_node = node.filter(|&node| node < self.scene_graph.node_count());
+        self.sync_gizmo();
+    }
+
+    // The node the gizmo is moving, which physics should leave where the gizmo puts it.
+    pub fn dragged_node(&self) -> Option<usize> {
+        self.gizmo_node
+            .filter(|_| self.gizmo.as_ref().is_some_and(Gizmo::is_dragging))
+    }
+
+    // Moves the gizmo to its node, which physics or edits may have moved since. Left alone mid-drag,
+    // when the gizmo is what moves the node.
+    fn sync_gizmo(&mut self) {
+        let (Some(gizmo), Some(node)) = (&mut self.gizmo, self.gizmo_node) else {
+            return;
+        };
+        if !gizmo.is_dragging() {
+            gizmo.set_transform(self.scene_graph.world_transforms()[node]);
         }
+    }
 
-        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+    pub fn object_under_cursor(&self) -> Option<PickResult> {
+        self.picker.result()
+    }
 
-        let (image_index, suboptimal, acquire_future) =
-            match acquire_next_image(self.swapchain.clone(), None).map_err(Validated::unwrap) {
-                Ok(r) => r,
-                Err(VulkanError::OutOfDate) => {
-                    todo!()
-                }
-                Err(e) => panic!("failed to acquire next image: {e}"),
-            };
+    pub fn pixel_inspector(&self) -> &PixelInspector {
+        &self.pixel_inspector
+    }
+
+    pub fn pixel_inspector_mut(&mut self) -> &mut PixelInspector {
+        &mut self.pixel_inspector
+    }
+
+    pub fn set_color_audit(&mut self, enabled: bool) {
+        self.color_audit = enabled;
+    }
+
+    pub fn gizmo(&self) -> Option<&Gizmo> {
+        self.gizmo.as_ref()
+    }
+
+    pub fn gizmo_mut(&mut self) -> Option<&mut Gizmo> {
+        self.gizmo.as_mut()
+    }
+
+    pub fn grid_settings_mut(&mut self) -> &mut GridSettings {
+        &mut self.grid_settings
+    }
+
+    pub fn shading_rate_settings_mut(&mut self) -> &mut ShadingRateSettings {
+        &mut self.shading_rate_settings
+    }
 
-        if suboptimal {
-            todo!()
+    // Only the inline scene draws pull their vertices; parallel recording keeps vertex input.
+    pub fn set_vertex_pulling(&mut self, enabled: bool) {
+        if enabled && self.vulkan_device.vertex_pulling_pass().is_none() {
+            warn!("Buffer device addresses are not supported, keeping vertex input");
         }
+        self.vertex_pulling = enabled;
+    }
 
-        let mut builder = AutoCommandBufferBuilder::primary(
-            self.vulkan_device.command_allocator(),
-            self.vulkan_device.queue().queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
+    fn vertex_pulling_pass(&self) -> Option<&VertexPullingPass> {
+        self.vulkan_device
+            .vertex_pulling_pass()
+            .filter(|_| self.vertex_pulling)
+    }
 
-        let extent = self.swapchain.image_extent();
+    pub fn set_mesh_shading(&mut self, enabled: bool) {
+        if enabled && self.vulkan_device.meshlet_pass().is_none() {
+            warn!("Mesh shading is not supported, keeping the vertex pipeline");
+        }
+        self.mesh_shading = enabled;
+    }
 
-        let clear_color = Srgba::new(0.1, 0.1, 0.1, 1.0);
+    pub fn set_wireframe(&mut self, enabled: bool) {
+        if enabled && self.vulkan_device.wireframe_pipeline().is_none() {
+            warn!("Wireframe rendering is not supported, keeping filled polygons");
+        }
+        self.wireframe = enabled;
+    }
 
-        let push_constants = vs::PushConstantData {
-            time: (Instant::now() - self.start_time).as_secs_f32().into(),
-            mousePosition: self.mouse_position,
-        };
+    pub fn background(&self) -> Background {
+        self.background
+    }
 
-        builder
-            .begin_rendering(RenderingInfo {
-                color_attachments: vec![Some(RenderingAttachmentInfo {
-                    load_op: AttachmentLoadOp::Clear,
-                    store_op: AttachmentStoreOp::Store,
-                    clear_value: Some(ClearValue::Float(clear_color.into_linear().into())),
-                    resolve_info: Some(RenderingAttachmentResolveInfo::image_view(Arc::clone(
-                        &self.swapchain_image_views[image_index as usize],
-                    ))),
-                    ..RenderingAttachmentInfo::image_view(Arc::clone(&self.intermediary_image))
-                })],
-                depth_attachment: Some(RenderingAttachmentInfo {
-                    load_op: AttachmentLoadOp::Clear,
-                    clear_value: Some(1.0f32.into()),
-                    ..RenderingAttachmentInfo::image_view(Arc::clone(&self.depth_view))
-                }),
-                ..Default::default()
-            })?
-            .set_viewport(
-                0,
-                [Viewport {
-                    offset: [0.0, 0.0],
-                    extent: [extent[0] as f32, extent[1] as f32],
-                    depth_range: 0.0..=1.0,
-                }]
-                .into_iter()
-                .collect(),
-            )?
-            .bind_pipeline_graphics(Arc::clone(self.vulkan_device.graphics_pipeline()))?
-            .bind_vertex_buffers(0, self.vulkan_device.vertex_buffer().clone())?
-            .bind_index_buffer(self.vulkan_device.index_buffer().clone())?
-            .bind_descriptor_sets(
-                PipelineBindPoint::Graphics,
-                Arc::clone(self.vulkan_device.graphics_pipeline().layout()),
-                0,
-                Arc::clone(self.vulkan_device.set()),
-            )?
-            .push_constants(
-                self.vulkan_device.graphics_pipeline().layout().clone(),
-                0,
-                push_constants,
-            )?
-            .draw_indexed(self.vulkan_device.index_buffer().len() as u32, 1, 0, 0, 0)?
-            .end_rendering()?;
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
+    }
 
-        let command_buffer = builder.build()?;
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
 
-        let future = self
-            .previous_frame_end
-            .take()
-            .unwrap()
-            .join(acquire_future)
-            .then_execute(Arc::clone(self.vulkan_device.queue()), command_buffer)?
-            .then_swapchain_present(
-                Arc::clone(self.vulkan_device.queue()),
-                SwapchainPresentInfo::swapchain_image_index(
-                    Arc::clone(&self.swapchain),
-                    image_index,
-                ),
-            )
-            .then_signal_fence_and_flush();
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+    }
 
-        match future.map_err(Validated::unwrap) {
-            Ok(future) => {
-                self.previous_frame_end = Some(future.boxed());
-            }
-            Err(VulkanError::OutOfDate) => {
-                self.recreate()?;
-                self.previous_frame_end =
-                    Some(sync::now(Arc::clone(self.vulkan_device.queue().device())).boxed());
-            }
-            Err(e) => {
-                todo!()
+    // An empty list renders the whole window from the shared camera.
+    pub fn set_split_viewports(&mut self, split_viewports: Vec<SplitViewport>) {
+        self.split_viewports = split_viewports;
+    }
+
+    pub fn ray_query_settings_mut(&mut self) -> &mut RayQuerySettings {
+        &mut self.ray_query_settings
+    }
+
+    pub fn path_tracing_settings_mut(&mut self) -> &mut PathTracingSettings {
+        self.reset_path_accumulation();
+        &mut self.path_tracing_settings
+    }
+
+    pub fn set_path_tracing(&mut self, enabled: bool) -> Result<()> {
+        self.path_accumulation = None;
+        if enabled {
+            if self.vulkan_device.path_tracing_pass().is_none() {
+                warn!("Path tracing is not supported, keeping the current pipeline");
+                return Ok(());
             }
+            self.path_accumulation = Some(PathAccumulation::new(
+                &self.vulkan_device,
+                self.swapchain.image_extent(),
+            )?);
         }
+        Ok(())
+    }
+
+    pub fn parallel_recording_settings_mut(&mut self) -> &mut ParallelRecordingSettings {
+        &mut self.parallel_recording_settings
+    }
+
+    pub fn scene_graph(&self) -> &SceneGraph {
+        &self.scene_graph
+    }
 
+    pub fn scene_graph_mut(&mut self) -> &mut SceneGraph {
+        &mut self.scene_graph
+    }
+
+    // Replaces the scene graph, discarding any edits made to the previous scene's transforms.
+    pub fn select_scene(&mut self, index: usize) -> Result<()> {
+        let Some(scene) = self.vulkan_device.scenes().get(index) else {
+            bail!("Scene {index} is out of range");
+        };
+        self.scene_graph = scene.graph.clone();
+        self.selected_scene = index;
+        self.set_selection(None);
         Ok(())
     }
+
+    pub fn selected_scene(&self) -> usize {
+        self.selected_scene
+    }
+
+    pub fn traversal_settings_mut(&mut self) -> &mut TraversalSettings {
+        &mut self.traversal_settings
+    }
+
+    pub fn gpu_scene(&self) -> &GpuScene {
+        &self.gpu_scene
+    }
+
+    // Drawn after the scene with its pipeline and material.
+    pub fn dynamic_meshes_mut(&mut self) -> &mut Vec<DynamicMesh> {
+        &mut self.dynamic_meshes
+    }
+
+    pub fn stereo_settings_mut(&mut self) -> &mut StereoSettings {
+        &mut self.stereo_settings
+    }
+
+    // Each eye gets half the window's width.
+    pub fn set_stereo(&mut self, enabled: bool) -> Result<()> {
+        self.stereo_targets = None;
+        if enabled {
+            if self.vulkan_device.stereo_pass().is_none() {
+                warn!("Multiview is not supported, keeping the current pipeline");
+                return Ok(());
+            }
+            let [width, height] = self.swapchain.image_extent();
+            self.stereo_targets = Some(StereoTargets::new(
+                &self.vulkan_device,
+                [(width / 2).max(1), height],
+            )?);
+        }
+        Ok(())
+    }
+
+    pub fn reset_path_accumulation(&mut self) {
+        if let Some(path_accumulation) = &mut self.path_accumulation {
+            path_accumulation.reset();
+        }
+    }
+
+    pub fn set_ray_tracing(&mut self, enabled: bool) {
+        if enabled && self.vulkan_device.ray_tracing_pass().is_none() {
+            warn!("Ray queries are not supported, keeping the rasterized pipeline");
+        }
+        self.ray_tracing = enabled;
+    }
+
+    pub fn post_process_stack_mut(&mut self) -> &mut PostProcessStack {
+        &mut self.post_process_stack
+    }
+
+    pub fn add_render_pass_plugin(&mut self, plugin: impl RenderPassPlugin) -> Result<()> {
+        self.render_pass_plugins
+            .add(&self.vulkan_device, self.swapchain.image_extent(), plugin)
+    }
+
+    pub fn remove_render_pass_plugin(&mut self, name: &str) -> Option<Box<dyn RenderPassPlugin>> {
+        self.render_pass_plugins.remove(name)
+    }
+
+    pub fn render_pass_plugin_mut<T: RenderPassPlugin>(&mut self) -> Option<&mut T> {
+        self.render_pass_plugins.plugin_mut::<T>()
+    }
+
+    pub fn set_environment(&mut self, environment: Option<Arc<EnvironmentMap>>) -> Result<()> {
+        self.environment = environment;
+        self.update_lighting_set()?;
+        self.reset_path_accumulation();
+        Ok(())
+    }
+
+    pub fn set_sun(&mut self, sun: Option<DirectionalLight>) -> Result<()> {
+        self.sun = sun;
+        self.update_lighting_set()?;
+        self.reset_path_accumulation();
+        Ok(())
+    }
+
+    fn update_lighting_set(&mut self) -> Result<()> {
+        self.lighting_set = self.lighting().create_set(
+            &self.vulkan_device,
+            &self.sun(),
+            &self.reflection_probes,
+            &self.lightmap,
+            &self.sh_probes,
+        )?;
+        Ok(())
+    }
+
+    fn record_scene_material_state(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<()> {
+        self.scene_material().record_state(
+            builder,
+            self.vulkan_device.supports_dynamic_cull_mode(),
+            self.vulkan_device.supports_wide_lines(),
+        )
+    }
+
+    fn scene_pipeline(&self) -> &Arc<GraphicsPipeline> {
+        match self.debug_view {
+            DebugView::Lit => self
+                .vulkan_device
+                .wireframe_pipeline()
+                .filter(|_| self.wireframe)
+                .unwrap_or(self.vulkan_device.graphics_pipeline()),
+            debug_view => self.vulkan_device.debug_view_pipeline(debug_view),
+        }
+    }
+
+    fn scene_shading_rate(&self) -> Option<ShadingRate> {
+        self.vulkan_device.supports_shading_rate().then(|| {
+            self.shading_rate_settings
+                .rate_at(self.vulkan_device.camera_position().coords.norm())
+        })
+    }
+
+    // A copy of the scene draw with its material state, for recording on worker threads.
+    fn scene_draw(
+        &self,
+        pipeline: &Arc<GraphicsPipeline>,
+        descriptor_sets: Vec<Arc<DescriptorSet>>,
+        push_constants: vs::PushConstantData,
+        objects: Vec<ObjectDraw>,
+        viewport: &Viewport,
+        shading_rate: Option<ShadingRate>,
+    ) -> SceneDraw {
+        SceneDraw {
+            pipeline: Arc::clone(pipeline),
+            descriptor_sets,
+            scene_set: Arc::clone(self.gpu_scene.set()),
+            push_constants,
+            vertex_buffer: self.vulkan_device.vertex_buffer().clone(),
+            index_buffer: self.vulkan_device.index_buffer().clone(),
+            objects,
+            topology: self.vulkan_device.scene_topology(),
+            viewport: viewport.clone(),
+            dynamic_cull_mode: self.vulkan_device.supports_dynamic_cull_mode(),
+            wide_lines: self.vulkan_device.supports_wide_lines(),
+            materials: self.scene_materials.clone(),
+            shading_rate,
+        }
+    }
+
+    // The primitives of each of these nodes, drawn with the node's world transform.
+    fn object_draws(&self, nodes: &[usize]) -> Vec<ObjectDraw> {
+        nodes
+            .iter()
+            .flat_map(|&node| {
+                self.scene_graph.primitives(node).iter().enumerate().map(
+                    move |(index, primitive)| ObjectDraw {
+                        object: object_index(self.scene_graph.primitive_index(node, index)),
+                        mesh: primitive.mesh,
+                        material: primitive.material,
+                    })
+            })
+            .collect()
+    }
+
+    // The primitives of these nodes whose material blends, each sorted by its node's center.
+    fn transparent_draws(&self, nodes: &[usize]) -> Vec<TransparentDraw> {
+        nodes
+            .iter()
+            .flat_map(|&node| {
+                let center = self
+                    .scene_graph
+                    .node_bounds(node)
+                    .map_or_else(Point3::origin, |bounds| bounds.center());
+                self.scene_graph
+                    .primitives(node)
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(index, primitive)| {
+                        let material = &self.scene_materials[primitive.material as usize];
+                        material.is_transparent().then(|| TransparentDraw {
+                            object: ObjectDraw {
+                                object: object_index(self.scene_graph.primitive_index(node, index)),
+                                mesh: primitive.mesh,
+                                material: primitive.material,
+                            },
+                            center,
+                            color: material.base_color,
+                            double_sided: material.double_sided,
+                            depth_bias: material.depth_bias,
+                        })
+                    })
+            })
+            .collect()
+    }
+
+    // Overrides the device's camera for this renderer's main view.
+    pub fn set_camera(&mut self, camera: Option<CameraState>) {
+        self.camera = camera;
+        self.reset_path_accumulation();
+    }
+
+    pub fn camera(&self) -> CameraState {
+        self.camera.unwrap_or_else(|| CameraState {
+            eye: *self.vulkan_device.camera_position(),
+            target: *self.vulkan_device.camera_target(),
+        })
+    }
+
+    pub fn save_camera_bookmark(&mut self, slot: usize) {
+        self.camera_bookmarks[slot] = Some(self.camera());
+    }
+
+    // Returns whether the slot had a bookmark to recall.
+    pub fn recall_camera_bookmark(&mut self, slot: usize) -> bool {
+        let Some(camera) = self.camera_bookmarks[slot] else {
+            return false;
+        };
+        self.set_camera(Some(camera));
+        true
+    }
+
+    fn view_projection(&self) -> Matrix4<f32> {
+        let camera = self.camera();
+        self.camera_projection().as_matrix()
+            * Isometry3::look_at_rh(&camera.eye, &camera.target, &Vector3::y()).to_homogeneous()
+    }
+
+    // Fitted to the window's current shape, so resizing it doesn't stretch the scene.
+    fn camera_projection(&self) -> Perspective3<f32> {
+        self.camera_projection_for(self.swapchain.image_extent())
+    }
+
+    // Swapchain extents are pre-rotated, while the projection has to match what the viewer sees.
+    fn camera_projection_for(&self, extent: [u32; 2]) -> Perspective3<f32> {
+        let [width, height] = pre_rotated_extent(extent, self.swapchain.pre_transform());
+        self.vulkan_device
+            .camera_projection_with_aspect(width as f32 / height.max(1) as f32)
+    }
+
+    // Every camera but the one the main view is looking through. The picture-in-picture inset is
+    // covered by its scene camera, or by the main camera while the two are swapped.
+    fn draw_camera_frusta(&mut self) {
+        let settings = self.frustum_visualization_settings;
+        let default_projection = self.vulkan_device.camera_projection().into_inner();
+        let swapped_camera = self
+            .picture_in_picture
+            .as_ref()
+            .filter(|picture_in_picture| picture_in_picture.is_swapped())
+            .map(PictureInPicture::camera);
+
+        for camera in self.vulkan_device.scene_cameras() {
+            if swapped_camera.is_some_and(|swapped_camera| swapped_camera.name == camera.name) {
+                continue;
+            }
+            self.debug_draw.camera_frustum(
+                &camera.view(),
+                &camera.projection,
+                settings.max_distance,
+                settings.scene_camera_color,
+            );
+        }
+
+        for render_texture in self
+            .render_textures
+            .iter()
+            .chain(self.minimap.as_ref().map(Minimap::render_texture))
+        {
+            self.debug_draw.camera_frustum(
+                &render_texture.view(),
+                &render_texture.projection.unwrap_or(default_projection),
+                settings.max_distance,
+                settings.render_texture_color,
+            );
+        }
+
+        if swapped_camera.is_some() {
+            let camera = self.camera();
+            self.debug_draw.camera_frustum(
+                &Isometry3::look_at_rh(&camera.eye, &camera.target, &Vector3::y()).to_homogeneous(),
+                &self.camera_projection().into_inner(),
+                settings.max_distance,
+                settings.main_camera_color,
+            );
+        }
+    }
+
+    pub fn scene_file(&self) -> SceneFile {
+        SceneFile {
+            scene: self.selected_scene,
+            local_transforms: self.scene_graph.local_transforms().to_vec(),
+            sun: self.sun,
+            camera: self.camera,
+            camera_bookmarks: self.camera_bookmarks,
+            materials: self.scene_materials.clone(),
+        }
+    }
+
+    pub fn apply_scene_file(&mut self, scene_file: &SceneFile) -> Result<()> {
+        self.select_scene(scene_file.scene)?;
+        let local_transforms = self.scene_graph.local_transforms_mut();
+        if local_transforms.len() != scene_file.local_transforms.len() {
+            bail!(
+                "The scene file has {} node transforms, but scene {} has {} nodes",
+                scene_file.local_transforms.len(),
+                scene_file.scene,
+                local_transforms.len()
+            );
+        }
+        local_transforms.copy_from_slice(&scene_file.local_transforms);
+        if !scene_file.materials.is_empty() {
+            if self.scene_materials.len() != scene_file.materials.len() {
+                bail!(
+                    "The scene file has {} materials, but the scene has {}",
+                    scene_file.materials.len(),
+                    self.scene_materials.len()
+                );
+            }
+            self.scene_materials.copy_from_slice(&scene_file.materials);
+        }
+        self.camera_bookmarks = scene_file.camera_bookmarks;
+        self.set_camera(scene_file.camera);
+        self.set_sun(scene_file.sun)
+    }
+
+    fn sun(&self) -> DirectionalLight {
+        self.sun
+            .or_else(|| {
+                self.environment
+                    .as_ref()
+                    .and_then(|environment| environment.sun().copied())
+            })
+            .unwrap_or_default()
+    }
+
+    fn lighting(&self) -> &ImageBasedLighting {
+        match &self.environment {
+            Some(environment) => environment.lighting(),
+            None => self.vulkan_device.default_lighting(),
+        }
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment_intensity = intensity;
+        self.reset_path_accumulation();
+    }
+
+    pub fn set_foliage(&mut self, foliage: Option<Arc<Foliage>>) {
+        self.foliage = foliage;
+    }
+
+    pub fn foliage_settings_mut(&mut self) -> &mut FoliageSettings {
+        &mut self.foliage_settings
+    }
+
+    pub fn add_particle_system(&mut self, capacity: u32, settings: EmitterSettings) -> Result<()> {
+        self.particle_systems
+            .push(ParticleSystem::new(&self.vulkan_device, capacity, settings)?);
+        Ok(())
+    }
+
+    pub fn particle_systems_mut(&mut self) -> &mut [ParticleSystem] {
+        &mut self.particle_systems
+    }
+
+    pub fn add_render_texture(&mut self, render_texture: RenderTexture) {
+        self.render_textures.push(render_texture);
+    }
+
+    pub fn render_textures_mut(&mut self) -> &mut [RenderTexture] {
+        &mut self.render_textures
+    }
+
+    // Captured at the start of the next frame.
+    pub fn add_reflection_probe(&mut self, settings: ReflectionProbeSettings) -> Result<()> {
+        self.reflection_probes
+            .push(ReflectionProbe::new(&self.vulkan_device, settings)?);
+        Ok(())
+    }
+
+    pub fn reflection_probes_mut(&mut self) -> &mut [ReflectionProbe] {
+        &mut self.reflection_probes
+    }
+
+    pub fn capture_reflection_probes(&mut self) {
+        self.reflection_probes
+            .iter_mut()
+            .for_each(ReflectionProbe::request_capture);
+    }
+
+    // Fragments of the static scene that line up with the lightmap's charts use it in place of the
+    // sun and the sky.
+    pub fn set_lightmap(&mut self, lightmap: Option<Arc<Lightmap>>) -> Result<()> {
+        self.lightmap = match lightmap {
+            Some(lightmap) => lightmap,
+            None => Arc::new(Lightmap::empty(&self.vulkan_device)?),
+        };
+        self.update_lighting_set()
+    }
+
+    // Everything the lightmap doesn't cover takes its ambient light from the probe grid in place of
+    // the irradiance map.
+    pub fn set_sh_probes(&mut self, sh_probes: Option<Arc<ShProbeBuffers>>) -> Result<()> {
+        self.sh_probes = match sh_probes {
+            Some(sh_probes) => sh_probes,
+            None => Arc::new(ShProbeBuffers::empty(&self.vulkan_device)?),
+        };
+        self.update_lighting_set()
+    }
+
+    pub fn set_minimap(&mut self, minimap: Option<Minimap>) {
+        self.minimap = minimap;
+    }
+
+    pub fn set_picture_in_picture(&mut self, picture_in_picture: Option<PictureInPicture>) {
+        self.picture_in_picture = picture_in_picture;
+    }
+
+    pub fn picture_in_picture_mut(&mut self) -> Option<&mut PictureInPicture> {
+        self.picture_in_picture.as_mut()
+    }
+
+    pub fn swap_picture_in_picture(&mut self) {
+        if let Some(picture_in_picture) = &mut self.picture_in_picture {
+            picture_in_picture.swap(&self.vulkan_device);
+        }
+    }
+
+    fn attachment_memory_size(&self) -> DeviceSize {
+        self.targets.memory_size()
+            + self
+                .render_textures
+                .iter()
+                .chain(self.minimap.as_ref().map(Minimap::render_texture))
+                .chain(
+                    self.picture_in_picture
+                        .as_ref()
+                        .map(PictureInPicture::render_texture),
+                )
+                .map(RenderTexture::memory_size)
+                .sum::<DeviceSize>()
+    }
+
+    pub fn set_frame_capture(&mut self, frame_capture: Option<FrameCapture>) {
+        self.frame_capture = frame_capture;
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        let lighting = self.lighting();
+        let textures = self
+            .environment
+            .iter()
+            .map(|environment| image_size(environment.cubemap()))
+            .chain(
+                [
+                    lighting.irradiance_view(),
+                    lighting.prefiltered_view(),
+                    lighting.brdf_lut_view(),
+                ]
+                .map(|view| image_size(view.image())),
+            )
+            .chain(
+                self.reflection_probes
+                    .iter()
+                    .map(ReflectionProbe::memory_size),
+            )
+            .chain([self.lightmap.memory_size(), self.sh_probes.memory_size()])
+            .chain(self.shadow_map.as_ref().map(ShadowMap::memory_size))
+            .sum();
+
+        MemoryReport {
+            heaps: self.vulkan_device.heap_budgets(),
+            categories: vec![
+                (
+                    MemoryCategory::Meshes,
+                    self.vulkan_device.mesh_memory_size()
+                        + self.gpu_scene.memory_size()
+                        + self
+                            .dynamic_meshes
+                            .iter()
+                            .map(DynamicMesh::memory_size)
+                            .sum::<DeviceSize>(),
+                ),
+                (MemoryCategory::Textures, textures),
+                (MemoryCategory::Attachments, self.attachment_memory_size()),
+            ],
+        }
+    }
+
+    pub fn frame_metrics(&self) -> &FrameMetrics {
+        &self.frame_metrics
+    }
+
+    pub fn set_benchmark(&mut self, benchmark: Option<Benchmark>) {
+        self.benchmark = benchmark;
+    }
+
+    pub fn benchmark(&self) -> Option<&Benchmark> {
+        self.benchmark.as_ref()
+    }
+
+    pub fn gif_settings_mut(&mut self) -> &mut GifSettings {
+        self.gif_recorder.settings_mut()
+    }
+
+    pub fn save_gif(&self) -> Result<PathBuf> {
+        self.gif_recorder.save("captures")
+    }
+
+    // Captured frames advance on a fixed timestep so the recording plays back smoothly
+    // regardless of how long each frame took to render and encode.
+    fn time(&self) -> f32 {
+        match &self.frame_capture {
+            Some(frame_capture) => frame_capture.time(),
+            None => (Instant::now() - self.start_time).as_secs_f32(),
+        }
+    }
+
+    // Mailbox and Immediate don't throttle the CPU to the display, so without this the input
+    // sampled for a frame can be several frames older than what finally reaches the screen.
+    fn is_low_latency(&self) -> bool {
+        self.vulkan_device.supports_present_wait()
+            && matches!(
+                self.present_mode,
+                PresentMode::Mailbox | PresentMode::Immediate
+            )
+    }
+
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval
+    }
+
+    // The swapchain is rebuilt to the new size before the next frame.
+    pub fn set_window_size(&mut self, window_size: [u32; 2]) {
+        self.window_size = window_size;
+    }
+
+    pub fn set_monitor_refresh_interval(&mut self, monitor_refresh_interval: Option<Duration>) {
+        self.monitor_refresh_interval = monitor_refresh_interval;
+        self.refresh_interval = refresh_interval(&self.swapchain, monitor_refresh_interval);
+    }
+
+    pub fn vsync(&self) -> bool {
+        self.vsync
+    }
+
+    pub fn set_vsync(&mut self, vsync: bool) -> Result<()> {
+        self.vsync = vsync;
+        self.present_mode = select_present_mode(&self.surface_present_modes, vsync);
+        if self.present_mode == self.swapchain.present_mode()
+            || self.swapchain.present_modes().contains(&self.present_mode)
+        {
+            return Ok(());
+        }
+
+        // The surface can't switch to it on the fly, so it's only usable as the single mode.
+        self.recreate_with(SwapchainCreateInfo {
+            present_mode: self.present_mode,
+            present_modes: Default::default(),
+            ..self.swapchain.create_info()
+        })
+    }
+
+    // Called before simulating, so input is sampled as close as possible to when the next frame
+    // can actually be shown.
+    pub fn wait_for_previous_present(&mut self) -> Result<()> {
+        let Some((present_id, queued)) = self.pending_present.take() else {
+            return Ok(());
+        };
+
+        match self
+            .swapchain
+            .wait_for_present(present_id, Some(PRESENT_WAIT_TIMEOUT))
+            .map_err(Validated::unwrap)
+        {
+            Ok(_) => self.present_latency_ms = Some(queued.elapsed().as_secs_f32() * 1000.0),
+            // Recreation is left to the next acquire.
+            Err(VulkanError::Timeout | VulkanError::OutOfDate | VulkanError::SurfaceLost) => {}
+            Err(e) => bail!("failed to wait for present: {e}"),
+        }
+
+        Ok(())
+    }
+
+    // Blocks until the last submitted frame has finished, so the swapchain and surface can be
+    // destroyed safely.
+    pub fn wait_for_frames(&mut self) -> Result<()> {
+        if let Some(previous_frame_end) = self.previous_frame_end.take() {
+            previous_frame_end
+                .then_signal_fence_and_flush()?
+                .wait(None)?;
+        }
+        self.previous_frame_end =
+            Some(sync::now(Arc::clone(self.vulkan_device.queue().device())).boxed_send());
+        Ok(())
+    }
+
+    pub fn recreate(&mut self) -> Result<()> {
+        self.recreate_with(self.swapchain.create_info())
+    }
+
+    // The old swapchain is handed over to the new one and released along with the future of its
+    // last present; vulkano doesn't expose VK_EXT_swapchain_maintenance1's present fences.
+    fn recreate_with(&mut self, create_info: SwapchainCreateInfo) -> Result<()> {
+        let surface_capabilities = self
+            .swapchain
+            .device()
+            .physical_device()
+            .surface_capabilities(self.swapchain.surface(), SurfaceInfo::default())?;
+
+        self.swapchain_images.clear();
+        self.swapchain_image_views.clear();
+
+        let (new_swapchain, new_swapchain_images) =
+            self.swapchain.recreate(SwapchainCreateInfo {
+                image_extent: pre_rotated_extent(
+                    surface_capabilities
+                        .current_extent
+                        .unwrap_or(self.window_size),
+                    surface_capabilities.current_transform,
+                ),
+                pre_transform: surface_capabilities.current_transform,
+                ..create_info
+            })?;
+
+        self.replace_swapchain(new_swapchain, new_swapchain_images)
+    }
+
+    pub fn surface_lost(&self) -> bool {
+        self.surface_lost
+    }
+
+    // A lost surface can't be recovered through `Swapchain::recreate`, since the old swapchain is
+    // tied to it; both are built again on a surface the event loop created for the window.
+    pub fn replace_surface(&mut self, surface: Arc<Surface>) -> Result<()> {
+        let device = self.vulkan_device.queue().device();
+        let surface_capabilities = device
+            .physical_device()
+            .surface_capabilities(&surface, SurfaceInfo::default())?;
+
+        self.swapchain_images.clear();
+        self.swapchain_image_views.clear();
+
+        let (new_swapchain, new_swapchain_images) = Swapchain::new(
+            Arc::clone(device),
+            surface,
+            SwapchainCreateInfo {
+                image_extent: pre_rotated_extent(
+                    surface_capabilities
+                        .current_extent
+                        .unwrap_or(self.window_size),
+                    surface_capabilities.current_transform,
+                ),
+                pre_transform: surface_capabilities.current_transform,
+                ..self.swapchain.create_info()
+            },
+        )?;
+
+        self.surface_lost = false;
+        self.replace_swapchain(new_swapchain, new_swapchain_images)
+    }
+
+    fn replace_swapchain(
+        &mut self,
+        swapchain: Arc<Swapchain>,
+        swapchain_images: Vec<Arc<Image>>,
+    ) -> Result<()> {
+        self.swapchain = swapchain;
+        self.pending_present = None;
+        // A resize can also mean the window moved to a display with a different refresh rate.
+        self.refresh_interval = refresh_interval(&self.swapchain, self.monitor_refresh_interval);
+        self.swapchain_window_size = self.window_size;
+        self.swapchain_image_views = swapchain_images
+            .iter()
+            .map(|image| ImageView::new_default(Arc::clone(image)))
+            .try_collect::<Vec<_>>()?;
+        self.swapchain_images = swapchain_images;
+        self.targets = RenderTargets::new(&self.vulkan_device, self.swapchain.image_extent())?;
+        self.transient_pool.clear();
+        self.render_pass_plugins
+            .resize(&self.vulkan_device, self.swapchain.image_extent())?;
+        if self.path_accumulation.is_some() {
+            self.set_path_tracing(true)?;
+        }
+        if self.stereo_targets.is_some() {
+            self.set_stereo(true)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn render(&mut self) -> Result<()> {
+        let image_extent = self.window_size;
+        if image_extent.contains(&0) || self.surface_lost {
+            return Ok(());
+        }
+
+        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+        self.picker.poll();
+        self.pixel_inspector.poll(self.picker.result())?;
+        self.gif_recorder.poll();
+
+        // Not every platform reports the swapchain out of date when the window is resized, so the
+        // last reported size is checked before every frame.
+        if image_extent != self.swapchain_window_size {
+            self.recreate()?;
+        }
+
+        let mut acquired =
+            acquire_next_image(self.swapchain.clone(), None).map_err(Validated::unwrap);
+        // Skipping the frame would leave the last one stretched over the window while it's being
+        // dragged, so it's drawn right away on a swapchain of the new size instead.
+        if matches!(acquired, Err(VulkanError::OutOfDate)) {
+            self.recreate()?;
+            acquired = acquire_next_image(self.swapchain.clone(), None).map_err(Validated::unwrap);
+        }
+        let (image_index, suboptimal, acquire_future) = match acquired {
+            Ok(r) => r,
+            // Still changing size; the next frame tries again.
+            Err(VulkanError::OutOfDate) => return Ok(()),
+            Err(VulkanError::SurfaceLost) => {
+                warn!("Surface lost, waiting for a new one");
+                self.surface_lost = true;
+                return Ok(());
+            }
+            Err(e) => bail!("failed to acquire next image: {e}"),
+        };
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.vulkan_device.command_allocator().clone(),
+            self.vulkan_device.queue().queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        // Every camera this frame, secondary ones included, draws the scene as uploaded here.
+        self.scene_graph
+            .update_world_transforms(&self.traversal_settings);
+        self.sync_gizmo();
+        let sun = self.sun();
+        self.gpu_scene.update(
+            &mut builder,
+            &self.vulkan_device,
+            &self.scene_graph,
+            &self.scene_materials,
+            &[sun],
+        )?;
+        if let Some(mut scene_acceleration) = self.vulkan_device.scene_acceleration() {
+            scene_acceleration.update(&self.vulkan_device, &self.scene_graph)?;
+        }
+
+        let scene_material = self.scene_material();
+        let push_constants = vs::PushConstantData {
+            time: self.time().into(),
+            mousePosition: self.mouse_position,
+            baseColor: scene_material.base_color,
+            alphaCutoff: scene_material.alpha_cutoff(),
+            pointSize: scene_material.point_size,
+        };
+
+        let pre_transform = self.swapchain.pre_transform();
+        let pre_rotation = pre_rotation_matrix(pre_transform);
+        let camera = self.camera();
+        let camera_projection = self.camera_projection();
+        if self.frustum_visualization_settings.enabled {
+            self.draw_camera_frusta();
+        }
+        if self.light_visualization_settings.enabled {
+            draw_light_ranges(
+                &mut self.debug_draw,
+                self.vulkan_device.punctual_lights(),
+                &self.light_visualization_settings,
+            );
+        }
+        self.light_icons.update(
+            &self.vulkan_device,
+            &mut self.billboards,
+            self.vulkan_device.punctual_lights(),
+            &self.light_visualization_settings,
+        )?;
+        if self
+            .reflection_probes
+            .iter()
+            .any(ReflectionProbe::needs_capture)
+        {
+            self.record_reflection_probes(&mut builder, &pre_rotation, push_constants)?;
+        }
+        if !self.render_textures.is_empty()
+            || self.minimap.is_some()
+            || self.picture_in_picture.is_some()
+        {
+            self.record_render_textures(&mut builder, &pre_rotation, push_constants)?;
+        }
+        let mut view_projection = match &mut self.benchmark {
+            Some(benchmark) => {
+                let view_projection = benchmark.begin_frame(
+                    &mut builder,
+                    &self.vulkan_device,
+                    &camera_projection,
+                    &pre_rotation,
+                )?;
+                // The benchmark's camera path keeps a history of its own.
+                self.previous_view_projection = None;
+                view_projection
+            }
+            // The device's uniform holds the camera as loaded, for a window of another shape.
+            None => {
+                let previous_view_projection = self.previous_view_projection();
+                let view_projection = self.vulkan_device.record_camera(
+                    &mut builder,
+                    &camera.eye,
+                    &camera.target,
+                    &camera_projection,
+                    &previous_view_projection,
+                    &pre_rotation,
+                )?;
+                self.previous_view_projection = Some(view_projection);
+                view_projection
+            }
+        };
+        if let Some(picture_in_picture) = &mut self.picture_in_picture {
+            if let Some(swapped_view_projection) = picture_in_picture.record_main_camera(
+                &mut builder,
+                &self.vulkan_device,
+                &pre_rotation,
+            )? {
+                view_projection = swapped_view_projection;
+            }
+        }
+
+        let extent = self.swapchain.image_extent();
+
+        let viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: [extent[0] as f32, extent[1] as f32],
+            depth_range: 0.0..=1.0,
+        };
+
+        let now = Instant::now();
+        self.frame_metrics = FrameMetrics {
+            frame: self.frame_metrics.frame + 1,
+            frame_time_ms: (now - self.previous_frame_time).as_secs_f32() * 1000.0,
+            render_target_bytes: self.attachment_memory_size(),
+            present_latency_ms: self.present_latency_ms,
+            ..Default::default()
+        };
+        if self
+            .frame_metrics
+            .frame
+            .is_multiple_of(MEMORY_CHECK_INTERVAL)
+        {
+            self.memory_report().warn_if_near_budget();
+        }
+
+        let delta_time = match &self.frame_capture {
+            Some(frame_capture) => frame_capture.delta_time(),
+            None => (now - self.previous_frame_time).as_secs_f32().min(0.1),
+        };
+        self.previous_frame_time = now;
+
+        for dynamic_mesh in &mut self.dynamic_meshes {
+            dynamic_mesh.record_upload(&mut builder)?;
+        }
+
+        if let (Some(path_tracing_pass), Some(path_accumulation)) = (
+            self.vulkan_device.path_tracing_pass(),
+            &mut self.path_accumulation,
+        ) {
+            path_tracing_pass.trace(
+                &mut builder,
+                &self.vulkan_device,
+                path_accumulation,
+                &PathTracedScene {
+                    objects: self.gpu_scene.set(),
+                    environment: match &self.environment {
+                        Some(environment) => environment.lighting(),
+                        None => self.vulkan_device.default_lighting(),
+                    },
+                    sun: &sun,
+                    environment_intensity: self.environment_intensity,
+                },
+                &self.targets.scene_color_view,
+                &self.path_tracing_settings,
+            )?;
+        } else if let Some(ray_tracing_pass) = self
+            .vulkan_device
+            .ray_tracing_pass()
+            .filter(|_| self.ray_tracing)
+        {
+            ray_tracing_pass.trace(
+                &mut builder,
+                &self.vulkan_device,
+                &RayTracedScene {
+                    objects: self.gpu_scene.set(),
+                    environment: self.lighting(),
+                    sun: &sun,
+                    environment_intensity: self.environment_intensity,
+                },
+                &self.targets.scene_color_view,
+            )?;
+        } else if let (Some(stereo_pass), Some(stereo_targets)) =
+            (self.vulkan_device.stereo_pass(), &self.stereo_targets)
+        {
+            // Either eye sees the whole scene, so none of it is culled.
+            let objects = self.object_draws(&self.scene_graph.mesh_nodes().collect::<Vec<_>>());
+            self.frame_metrics.pass();
+            for object in &objects {
+                self.frame_metrics.draw(
+                    triangle_count(self.vulkan_device.scene_topology(), object.mesh.index_count)
+                        * 2,
+                );
+            }
+            stereo_pass.record(
+                &mut builder,
+                &self.vulkan_device,
+                stereo_targets,
+                &self.targets.scene_color_view,
+                &StereoScene {
+                    objects: SceneObjects {
+                        set: self.gpu_scene.set(),
+                        draws: &objects,
+                    },
+                    sun: &sun,
+                },
+                &self.stereo_settings,
+            )?;
+        } else if !self.split_viewports.is_empty() {
+            self.record_split_screen(&mut builder, &pre_rotation, push_constants, delta_time)?;
+        } else {
+            self.record_rasterized(
+                &mut builder,
+                viewport,
+                &view_projection,
+                push_constants,
+                delta_time,
+            )?;
+        }
+
+        let swapchain_image_view = &self.swapchain_image_views[image_index as usize];
+
+        // Selections outlive scene reloads, so one may name a node that's gone.
+        if let Some(selection) = self
+            .selection
+            .filter(|selection| selection.node_index() < self.scene_graph.node_count())
+        {
+            let selected_objects = self.object_draws(&[selection.node_index()]);
+            self.frame_metrics.pass();
+            count_object_draws(
+                &mut self.frame_metrics,
+                self.vulkan_device.scene_topology(),
+                &selected_objects,
+            );
+            self.vulkan_device.outline_pass().record(
+                &mut builder,
+                &self.vulkan_device,
+                &SceneObjects {
+                    set: self.gpu_scene.set(),
+                    draws: &selected_objects,
+                },
+                &OutlineViews {
+                    mask: &self.targets.selection_mask_view,
+                    scene_color: &self.targets.scene_color_view,
+                },
+                &self.outline_settings,
+            )?;
+        }
+
+        let cursor_position = pre_rotate_position(self.mouse_position, pre_transform);
+        let cursor = [0, 1].map(|axis| {
+            (cursor_position[axis] * extent[axis] as f32).clamp(0.0, (extent[axis] - 1) as f32)
+                as u32
+        });
+        let pickable_objects = self.object_draws(
+            &self
+                .scene_graph
+                .cull(&view_projection, &self.traversal_settings),
+        );
+        self.frame_metrics.pass();
+        count_object_draws(
+            &mut self.frame_metrics,
+            self.vulkan_device.scene_topology(),
+            &pickable_objects,
+        );
+        self.vulkan_device.picking_pass().record(
+            &mut builder,
+            &self.vulkan_device,
+            &mut self.picker,
+            &SceneObjects {
+                set: self.gpu_scene.set(),
+                draws: &pickable_objects,
+            },
+            cursor,
+            extent,
+        )?;
+        self.pixel_inspector.record(
+            &mut builder,
+            &InspectedTargets {
+                color: &self.targets.scene_color_view,
+                depth: self.targets.depth_view(),
+                normal: self.targets.normal_view(),
+            },
+            cursor,
+        )?;
+
+        let plugin_context = RenderPassContext {
+            vulkan_device: &self.vulkan_device,
+            gpu_scene: &self.gpu_scene,
+            scene_color_view: &self.targets.scene_color_view,
+            depth_view: self.targets.depth_view(),
+            normal_view: self.targets.normal_view(),
+            velocity_view: self.targets.velocity_view(),
+            swapchain_image_view,
+        };
+        self.render_pass_plugins.record(
+            &mut builder,
+            &plugin_context,
+            PluginStage::BeforePostProcess,
+        )?;
+
+        self.transient_pool.begin_frame();
+        self.frame_metrics.gpu_passes += self.post_process_stack.enabled_count() as u32;
+        self.post_process_stack.record(
+            &mut builder,
+            &PostProcessContext {
+                vulkan_device: &self.vulkan_device,
+                depth_view: self.targets.depth_view(),
+                normal_view: self.targets.normal_view(),
+                velocity_view: self.targets.velocity_view(),
+            },
+            &mut self.transient_pool,
+            &self.targets.scene_color_view,
+            swapchain_image_view,
+        )?;
+        if let Some(minimap) = &self.minimap {
+            self.sprite_batch.draw(minimap.sprite(extent));
+        }
+        if let Some(picture_in_picture) = &self.picture_in_picture {
+            self.sprite_batch.draw(picture_in_picture.sprite(extent));
+        }
+        if !self.sprite_batch.is_empty() {
+            self.frame_metrics.pass();
+        }
+        self.vulkan_device.sprite_pass().draw(
+            &mut builder,
+            &self.vulkan_device,
+            &self.sprite_batch,
+            swapchain_image_view,
+            self.color_audit,
+        )?;
+        self.render_pass_plugins.record(
+            &mut builder,
+            &plugin_context,
+            PluginStage::AfterPostProcess,
+        )?;
+        if let Some(frame_capture) = &mut self.frame_capture {
+            frame_capture.record(
+                &mut builder,
+                &self.vulkan_device,
+                &self.swapchain_images[image_index as usize],
+            )?;
+        }
+        self.gif_recorder.record(
+            &mut builder,
+            &self.vulkan_device,
+            &self.swapchain_images[image_index as usize],
+        )?;
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.end_frame(&mut builder)?;
+        }
+
+        let command_buffer = builder.build()?;
+        self.debug_draw.clear();
+        self.overlay_draw.clear();
+        self.sprite_batch.clear();
+
+        let present_id = self
+            .is_low_latency()
+            .then(|| {
+                self.present_id += 1;
+                NonZeroU64::new(self.present_id)
+            })
+            .flatten();
+
+        let future = self
+            .previous_frame_end
+            .take()
+            .unwrap()
+            .join(acquire_future)
+            .then_execute(Arc::clone(self.vulkan_device.queue()), command_buffer)?
+            .then_swapchain_present(
+                Arc::clone(self.vulkan_device.queue()),
+                SwapchainPresentInfo {
+                    present_id,
+                    // Only a swapchain created with several modes takes one per present.
+                    present_mode: (!self.swapchain.present_modes().is_empty())
+                        .then_some(self.present_mode),
+                    ..SwapchainPresentInfo::swapchain_image_index(
+                        Arc::clone(&self.swapchain),
+                        image_index,
+                    )
+                },
+            )
+            .then_signal_fence_and_flush()
+            .map_err(Validated::unwrap);
+
+        match future {
+            Ok(future) => {
+                if let Some(frame_capture) = &mut self.frame_capture {
+                    future.wait(None)?;
+                    frame_capture.write_frame(self.swapchain.image_format())?;
+                }
+                self.previous_frame_end = Some(future.boxed_send());
+                self.pending_present = present_id.map(|present_id| (present_id, Instant::now()));
+                // Usually a rotated display whose transform no longer matches the swapchain.
+                if suboptimal {
+                    self.recreate()?;
+                }
+            }
+            Err(VulkanError::OutOfDate) => {
+                self.recreate()?;
+                self.previous_frame_end =
+                    Some(sync::now(Arc::clone(self.vulkan_device.queue().device())).boxed_send());
+            }
+            Err(VulkanError::SurfaceLost) => {
+                warn!("Surface lost, waiting for a new one");
+                self.surface_lost = true;
+                self.previous_frame_end =
+                    Some(sync::now(Arc::clone(self.vulkan_device.queue().device())).boxed_send());
+            }
+            Err(e) => {
+                self.previous_frame_end =
+                    Some(sync::now(Arc::clone(self.vulkan_device.queue().device())).boxed_send());
+                bail!("failed to present: {e}");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_rasterized(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        viewport: Viewport,
+        view_projection: &Matrix4<f32>,
+        push_constants: vs::PushConstantData,
+        delta_time: f32,
+    ) -> Result<()> {
+        let mut clear_color = authored_color(Srgba::new(0.1, 0.1, 0.1, self.background_alpha));
+        if self.swapchain.composite_alpha() == CompositeAlpha::PreMultiplied {
+            let alpha = clear_color[3];
+            clear_color[..3]
+                .iter_mut()
+                .for_each(|channel| *channel *= alpha);
+        }
+        // Clears stay inside the viewport, so split-screen viewports don't erase each other.
+        let render_area_offset = viewport.offset.map(|offset| offset as u32);
+        let render_area_extent = viewport.extent.map(|extent| extent as u32);
+
+        let visible_nodes = self
+            .scene_graph
+            .cull(view_projection, &self.traversal_settings);
+        self.frame_metrics.visible_nodes += visible_nodes.len() as u32;
+        // Blended primitives are drawn after everything else, without writing depth.
+        let objects: Vec<_> = self
+            .object_draws(&visible_nodes)
+            .into_iter()
+            .filter(|object| !self.scene_materials[object.material as usize].is_transparent())
+            .collect();
+        let secondary_prepass = self.parallel_recording_settings.uses_secondaries();
+
+        self.frame_metrics.pass();
+        builder.begin_rendering(RenderingInfo {
+            color_attachments: vec![
+                Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::Store,
+                    clear_value: Some(ClearValue::Float([0.0, 0.0, 1.0, 0.0])),
+                    resolve_info: self
+                        .targets
+                        .resolved_normal_view
+                        .as_ref()
+                        .map(|view| RenderingAttachmentResolveInfo::image_view(Arc::clone(view))),
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(&self.targets.normal_view))
+                }),
+                Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::Store,
+                    clear_value: Some(ClearValue::Float([0.0, 0.0, 0.0, 0.0])),
+                    resolve_info: self
+                        .targets
+                        .resolved_velocity_view
+                        .as_ref()
+                        .map(|view| RenderingAttachmentResolveInfo::image_view(Arc::clone(view))),
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(&self.targets.velocity_view))
+                }),
+            ],
+            depth_attachment: Some(RenderingAttachmentInfo {
+                load_op: AttachmentLoadOp::Clear,
+                store_op: AttachmentStoreOp::Store,
+                clear_value: Some(1.0f32.into()),
+                resolve_info: self.targets.resolved_depth_view.as_ref().map(|view| {
+                    RenderingAttachmentResolveInfo {
+                        mode: ResolveMode::SampleZero,
+                        ..RenderingAttachmentResolveInfo::image_view(Arc::clone(view))
+                    }
+                }),
+                ..RenderingAttachmentInfo::image_view(Arc::clone(&self.targets.depth_view))
+            }),
+            render_area_offset,
+            render_area_extent,
+            contents: subpass_contents(secondary_prepass),
+            ..Default::default()
+        })?;
+
+        if secondary_prepass {
+            let scene_draw = self.scene_draw(
+                self.vulkan_device.prepass_pipeline(),
+                vec![Arc::clone(self.vulkan_device.set())],
+                push_constants,
+                objects.clone(),
+                &viewport,
+                None,
+            );
+            self.targets.recorded_prepass.record(
+                builder,
+                self.vulkan_device.command_allocator(),
+                self.vulkan_device.queue().queue_family_index(),
+                &CommandBufferInheritanceRenderingInfo {
+                    color_attachment_formats: vec![Some(NORMAL_FORMAT), Some(VELOCITY_FORMAT)],
+                    depth_attachment_format: Some(DEPTH_FORMAT),
+                    rasterization_samples: self.vulkan_device.samples(),
+                    ..Default::default()
+                },
+                scene_draw,
+                &self.parallel_recording_settings,
+            )?;
+            count_object_draws(
+                &mut self.frame_metrics,
+                self.vulkan_device.scene_topology(),
+                &objects,
+            );
+        } else {
+            builder.set_viewport(0, [viewport.clone()].into_iter().collect())?;
+            if let Some(vertex_pulling_pass) = self.vertex_pulling_pass() {
+                vertex_pulling_pass.bind(
+                    builder,
+                    &self.vulkan_device,
+                    vertex_pulling_pass.prepass_pipeline(),
+                    vec![Arc::clone(self.vulkan_device.set())],
+                    self.gpu_scene.set(),
+                    push_constants,
+                )?;
+                record_object_draws(
+                    builder,
+                    vertex_pulling_pass.prepass_pipeline().layout(),
+                    push_constants,
+                    &self.scene_materials,
+                    self.vulkan_device.supports_dynamic_cull_mode(),
+                    self.vulkan_device.supports_wide_lines(),
+                    &objects,
+                )?;
+                count_object_draws(
+                    &mut self.frame_metrics,
+                    self.vulkan_device.scene_topology(),
+                    &objects,
+                );
+            } else {
+                builder
+                    .bind_pipeline_graphics(Arc::clone(self.vulkan_device.prepass_pipeline()))?
+                    .bind_vertex_buffers(0, self.vulkan_device.vertex_buffer().clone())?
+                    .bind_index_buffer(self.vulkan_device.index_buffer().clone())?
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        Arc::clone(self.vulkan_device.prepass_pipeline().layout()),
+                        0,
+                        Arc::clone(self.vulkan_device.set()),
+                    )?
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        Arc::clone(self.vulkan_device.prepass_pipeline().layout()),
+                        GPU_SCENE_SET,
+                        Arc::clone(self.gpu_scene.set()),
+                    )?;
+                record_object_draws(
+                    builder,
+                    self.vulkan_device.prepass_pipeline().layout(),
+                    push_constants,
+                    &self.scene_materials,
+                    self.vulkan_device.supports_dynamic_cull_mode(),
+                    self.vulkan_device.supports_wide_lines(),
+                    &objects,
+                )?;
+                count_object_draws(
+                    &mut self.frame_metrics,
+                    self.vulkan_device.scene_topology(),
+                    &objects,
+                );
+            }
+        }
+        builder.end_rendering()?;
+
+        self.frame_metrics.pass();
+        self.vulkan_device
+            .ssao_pass()
+            .record(builder, &self.targets.ssao, &self.ssao_settings)?;
+
+        match self
+            .vulkan_device
+            .ray_query_pass()
+            .filter(|_| self.ray_query_settings.enabled())
+        {
+            Some(ray_query_pass) => ray_query_pass.record(
+                builder,
+                &self.vulkan_device,
+                &RayQueryViews {
+                    visibility: &self.targets.visibility_view,
+                    depth: self.targets.depth_view(),
+                    normal: self.targets.normal_view(),
+                },
+                &self.ray_query_settings,
+                &self.sun(),
+            )?,
+            None => {
+                builder.clear_color_image(ClearColorImageInfo {
+                    clear_value: ClearColorValue::Float([1.0; 4]),
+                    ..ClearColorImageInfo::image(Arc::clone(self.targets.visibility_view.image()))
+                })?;
+            }
+        }
+
+        // Ray-traced shadows are exact, so the shadow map only fills in without them.
+        let ray_traced_shadows = self.vulkan_device.ray_query_pass().is_some()
+            && self.ray_query_settings.enabled()
+            && self.ray_query_settings.shadows;
+        if self.shadow_map_settings.enabled && !ray_traced_shadows {
+            let resolution = self.shadow_map_settings.resolution;
+            if self.shadow_map.as_ref().map(ShadowMap::resolution) != Some(resolution) {
+                self.shadow_map = Some(ShadowMap::new(&self.vulkan_device, resolution)?);
+            }
+            if let Some(shadow_map) = &self.shadow_map {
+                let light_view_projection = self
+                    .shadow_map_settings
+                    .light_view_projection(self.vulkan_device.scene_center(), &self.sun());
+                let casters = self.object_draws(
+                    &self
+                        .scene_graph
+                        .cull(&light_view_projection, &self.traversal_settings),
+                );
+                self.frame_metrics.pass();
+                self.vulkan_device.shadow_map_pass().record(
+                    builder,
+                    &self.vulkan_device,
+                    shadow_map,
+                    &ShadowCasters {
+                        light_view_projection,
+                        objects: SceneObjects {
+                            set: self.gpu_scene.set(),
+                            draws: &casters,
+                        },
+                    },
+                    &ShadowViews {
+                        visibility: &self.targets.visibility_view,
+                        depth: self.targets.depth_view(),
+                    },
+                    &self.shadow_map_settings,
+                )?;
+            }
+        }
+
+        self.vulkan_device.contact_shadow_pass().record(
+            builder,
+            &self.vulkan_device,
+            &self.targets.visibility_view,
+            self.targets.depth_view(),
+            &self.contact_shadow_settings,
+            &self.sun(),
+        )?;
+
+        if let Some(foliage) = &self.foliage {
+            self.frame_metrics.pass();
+            self.vulkan_device.foliage_pass().cull(
+                builder,
+                &self.vulkan_device,
+                foliage,
+                &self.foliage_settings,
+            )?;
+        }
+
+        for system in &mut self.particle_systems {
+            self.frame_metrics.pass();
+            self.vulkan_device.particle_pass().simulate(
+                builder,
+                &self.vulkan_device,
+                system,
+                &CollisionViews {
+                    depth: self.targets.depth_view(),
+                    normal: self.targets.normal_view(),
+                },
+                &viewport,
+                delta_time,
+            )?;
+        }
+
+        let scene_output_view = &self.targets.scene_color_view;
+        let (color_view, resolve_view) = match &self.targets.intermediary_image {
+            Some(intermediary_image) => (intermediary_image, Some(scene_output_view)),
+            None => (scene_output_view, None),
+        };
+
+        let secondary_scene = self.parallel_recording_settings.uses_secondaries()
+            && !(self.mesh_shading && self.vulkan_device.meshlet_pass().is_some());
+        let lit_rendering = |load_op: AttachmentLoadOp, contents: SubpassContents| RenderingInfo {
+            color_attachments: vec![Some(RenderingAttachmentInfo {
+                load_op,
+                store_op: AttachmentStoreOp::Store,
+                clear_value: (load_op == AttachmentLoadOp::Clear)
+                    .then_some(ClearValue::Float(clear_color)),
+                resolve_info: resolve_view
+                    .map(|view| RenderingAttachmentResolveInfo::image_view(Arc::clone(view))),
+                ..RenderingAttachmentInfo::image_view(Arc::clone(color_view))
+            })],
+            depth_attachment: Some(RenderingAttachmentInfo {
+                load_op: AttachmentLoadOp::Load,
+                store_op: AttachmentStoreOp::DontCare,
+                ..RenderingAttachmentInfo::image_view(Arc::clone(&self.targets.depth_view))
+            }),
+            render_area_offset,
+            render_area_extent,
+            contents,
+            ..Default::default()
+        };
+
+        // Like the sky, which covers it anyway, a background would paint over a transparent one.
+        let draws_background = self.background != Background::Clear
+            && self.environment.is_none()
+            && self.background_alpha == 1.0;
+        if draws_background {
+            self.frame_metrics.pass();
+            self.frame_metrics.draw(1);
+            self.vulkan_device.background_pass().draw(
+                builder,
+                self.background,
+                color_view,
+                &viewport,
+                *push_constants.time,
+                push_constants.mousePosition,
+            )?;
+        }
+
+        self.frame_metrics.pass();
+        builder.begin_rendering(lit_rendering(
+            if draws_background {
+                AttachmentLoadOp::Load
+            } else {
+                AttachmentLoadOp::Clear
+            },
+            subpass_contents(secondary_scene),
+        ))?;
+        if secondary_scene {
+            let scene_draw = self.scene_draw(
+                self.scene_pipeline(),
+                vec![
+                    Arc::clone(self.vulkan_device.set()),
+                    Arc::clone(&self.targets.occlusion_set),
+                    Arc::clone(&self.lighting_set),
+                ],
+                push_constants,
+                objects.clone(),
+                &viewport,
+                self.scene_shading_rate(),
+            );
+            self.targets.recorded_scene.record(
+                builder,
+                self.vulkan_device.command_allocator(),
+                self.vulkan_device.queue().queue_family_index(),
+                &CommandBufferInheritanceRenderingInfo {
+                    color_attachment_formats: vec![Some(HDR_FORMAT)],
+                    depth_attachment_format: Some(DEPTH_FORMAT),
+                    rasterization_samples: self.vulkan_device.samples(),
+                    ..Default::default()
+                },
+                scene_draw,
+                &self.parallel_recording_settings,
+            )?;
+            count_object_draws(
+                &mut self.frame_metrics,
+                self.vulkan_device.scene_topology(),
+                &objects,
+            );
+
+            // Everything else records inline, which a rendering of secondary buffers doesn't allow.
+            builder.end_rendering()?;
+            self.frame_metrics.pass();
+            builder.begin_rendering(lit_rendering(
+                AttachmentLoadOp::Load,
+                SubpassContents::Inline,
+            ))?;
+        }
+        builder.set_viewport(0, [viewport.clone()].into_iter().collect())?;
+
+        match self
+            .vulkan_device
+            .meshlet_pass()
+            .filter(|_| self.mesh_shading)
+        {
+            _ if secondary_scene => {}
+            Some(meshlet_pass) => {
+                meshlet_pass.draw(
+                    builder,
+                    &self.vulkan_device,
+                    &self.targets.occlusion_set,
+                    &self.lighting_set,
+                    &MeshletObjects {
+                        objects: SceneObjects {
+                            set: self.gpu_scene.set(),
+                            draws: &objects,
+                        },
+                        materials: &self.scene_materials,
+                        push_constants,
+                    },
+                )?;
+                count_object_draws(
+                    &mut self.frame_metrics,
+                    self.vulkan_device.scene_topology(),
+                    &objects,
+                );
+            }
+            None => {
+                if let Some(shading_rate) = self.scene_shading_rate() {
+                    shading_rate.record(builder)?;
+                }
+
+                let descriptor_sets = vec![
+                    Arc::clone(self.vulkan_device.set()),
+                    Arc::clone(&self.targets.occlusion_set),
+                    Arc::clone(&self.lighting_set),
+                ];
+                // Wireframe and debug views keep their vertex input pipelines.
+                match self
+                    .vertex_pulling_pass()
+                    .filter(|_| self.debug_view == DebugView::Lit && !self.wireframe)
+                {
+                    Some(vertex_pulling_pass) => {
+                        vertex_pulling_pass.bind(
+                            builder,
+                            &self.vulkan_device,
+                            vertex_pulling_pass.pipeline(),
+                            descriptor_sets,
+                            self.gpu_scene.set(),
+                            push_constants,
+                        )?;
+                        record_object_draws(
+                            builder,
+                            vertex_pulling_pass.pipeline().layout(),
+                            push_constants,
+                            &self.scene_materials,
+                            self.vulkan_device.supports_dynamic_cull_mode(),
+                            self.vulkan_device.supports_wide_lines(),
+                            &objects,
+                        )?;
+                        count_object_draws(
+                            &mut self.frame_metrics,
+                            self.vulkan_device.scene_topology(),
+                            &objects,
+                        );
+                    }
+                    None => {
+                        let pipeline = self.scene_pipeline();
+
+                        builder
+                            .bind_pipeline_graphics(Arc::clone(pipeline))?
+                            .bind_vertex_buffers(0, self.vulkan_device.vertex_buffer().clone())?
+                            .bind_index_buffer(self.vulkan_device.index_buffer().clone())?
+                            .bind_descriptor_sets(
+                                PipelineBindPoint::Graphics,
+                                Arc::clone(pipeline.layout()),
+                                0,
+                                descriptor_sets,
+                            )?
+                            .bind_descriptor_sets(
+                                PipelineBindPoint::Graphics,
+                                Arc::clone(pipeline.layout()),
+                                GPU_SCENE_SET,
+                                Arc::clone(self.gpu_scene.set()),
+                            )?;
+                        record_object_draws(
+                            builder,
+                            pipeline.layout(),
+                            push_constants,
+                            &self.scene_materials,
+                            self.vulkan_device.supports_dynamic_cull_mode(),
+                            self.vulkan_device.supports_wide_lines(),
+                            &objects,
+                        )?;
+                        count_object_draws(
+                            &mut self.frame_metrics,
+                            self.vulkan_device.scene_topology(),
+                            &objects,
+                        );
+                    }
+                }
+            }
+        }
+
+        if !self.dynamic_meshes.is_empty() {
+            self.record_scene_material_state(builder)?;
+            for dynamic_mesh in &self.dynamic_meshes {
+                dynamic_mesh.draw(
+                    builder,
+                    self.scene_pipeline(),
+                    vec![
+                        Arc::clone(self.vulkan_device.set()),
+                        Arc::clone(&self.targets.occlusion_set),
+                        Arc::clone(&self.lighting_set),
+                        Arc::clone(self.gpu_scene.set()),
+                    ],
+                    push_constants,
+                )?;
+                self.frame_metrics.draw(triangle_count(
+                    self.vulkan_device.scene_topology(),
+                    dynamic_mesh.index_count(),
+                ));
+            }
+        }
+
+        if let Some(foliage) = &self.foliage {
+            self.frame_metrics.draw(0);
+            self.vulkan_device.foliage_pass().draw(
+                builder,
+                &self.vulkan_device,
+                foliage,
+                &self.foliage_settings,
+                &self.sun(),
+                *push_constants.time,
+            )?;
+        }
+
+        if let Some(tessellation_pass) = self
+            .vulkan_device
+            .tessellation_pass()
+            .filter(|_| self.tessellation_settings.enabled)
+        {
+            self.frame_metrics.draw(0);
+            tessellation_pass.draw(
+                builder,
+                &self.vulkan_device,
+                &self.tessellation_settings,
+                &self.sun(),
+            )?;
+        }
+
+        if let Some(normal_visualization_pass) = self
+            .vulkan_device
+            .normal_visualization_pass()
+            .filter(|_| self.normal_visualization_settings.enabled)
+        {
+            normal_visualization_pass.draw(
+                builder,
+                &self.vulkan_device,
+                &SceneObjects {
+                    set: self.gpu_scene.set(),
+                    draws: &objects,
+                },
+                &self.normal_visualization_settings,
+            )?;
+        }
+
+        self.vulkan_device
+            .debug_draw_pass()
+            .draw(builder, &self.vulkan_device, &self.debug_draw)?;
+
+        // A transparent background would be painted over by the sky.
+        if let Some(environment) = self
+            .environment
+            .as_ref()
+            .filter(|_| self.background_alpha == 1.0)
+        {
+            self.frame_metrics.draw(1);
+            self.vulkan_device.skybox_pass().draw(
+                builder,
+                &self.vulkan_device,
+                environment,
+                self.environment_intensity,
+            )?;
+        }
+
+        if self.grid_settings.enabled {
+            self.frame_metrics.draw(1);
+            self.vulkan_device
+                .grid_pass()
+                .draw(builder, &self.vulkan_device, &self.grid_settings)?;
+        }
+
+        let mut transparent_draws = self.transparent_draws(&visible_nodes);
+        // Double-sided draws are issued once per face.
+        for draw in &transparent_draws {
+            let triangles = triangle_count(
+                self.vulkan_device.scene_topology(),
+                draw.object.mesh.index_count,
+            );
+            for _ in 0..if draw.double_sided { 2 } else { 1 } {
+                self.frame_metrics.draw(triangles);
+            }
+        }
+        if self.transparency_mode == TransparencyMode::Sorted {
+            sort_back_to_front(&mut transparent_draws, view_projection);
+            self.vulkan_device.transparency_pass().draw(
+                builder,
+                &self.vulkan_device,
+                &TransparentObjects {
+                    set: self.gpu_scene.set(),
+                    draws: &transparent_draws,
+                },
+                &self.sun(),
+            )?;
+        }
+
+        self.vulkan_device
+            .billboard_pass()
+            .draw(builder, &self.vulkan_device, &self.billboards)?;
+
+        if let Some(gizmo) = self.gizmo.as_ref().filter(|_| self.gizmo_node.is_some()) {
+            gizmo.draw(&mut self.overlay_draw);
+        }
+        self.vulkan_device
+            .debug_draw_pass()
+            .draw_overlay(builder, &self.vulkan_device, &self.overlay_draw)?;
+
+        builder.end_rendering()?;
+
+        if self.transparency_mode == TransparencyMode::WeightedBlended {
+            self.frame_metrics.gpu_passes += 2;
+            self.vulkan_device
+                .transparency_pass()
+                .draw_weighted_blended(
+                    builder,
+                    &self.vulkan_device,
+                    &TransparentObjects {
+                        set: self.gpu_scene.set(),
+                        draws: &transparent_draws,
+                    },
+                    &self.sun(),
+                    &WeightedBlendedViews {
+                        accumulation: &self.targets.accumulation_view,
+                        revealage: &self.targets.revealage_view,
+                        depth: self.targets.depth_view(),
+                        scene_color: &self.targets.scene_color_view,
+                    },
+                    &viewport,
+                )?;
+        }
+
+        if self.water_settings.enabled {
+            self.frame_metrics.pass();
+            let resolution = self.water_settings.resolution as u64;
+            self.frame_metrics.draw(resolution * resolution * 2);
+            self.vulkan_device.water_pass().record(
+                builder,
+                &self.vulkan_device,
+                &WaterViews {
+                    scene_color: &self.targets.scene_color_view,
+                    refraction: &self.targets.water_refraction_view,
+                    depth: self.targets.depth_view(),
+                    reflection: self.lighting().prefiltered_view(),
+                },
+                &self.water_settings,
+                self.time(),
+                &viewport,
+            )?;
+        }
+
+        for system in &self.particle_systems {
+            self.frame_metrics.pass();
+            self.frame_metrics.draw(0);
+            self.vulkan_device.particle_pass().draw(
+                builder,
+                &self.vulkan_device,
+                system,
+                &self.targets.scene_color_view,
+                self.targets.depth_view(),
+                &viewport,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn record_split_screen(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pre_rotation: &Matrix4<f32>,
+        push_constants: vs::PushConstantData,
+        delta_time: f32,
+    ) -> Result<()> {
+        let extent = self.swapchain.image_extent();
+        let debug_view = self.debug_view;
+        let mut split_viewports = std::mem::take(&mut self.split_viewports);
+        for (index, split_viewport) in split_viewports.iter_mut().enumerate() {
+            let projection = self.camera_projection_for(
+                split_viewport
+                    .viewport(extent)
+                    .extent
+                    .map(|length| length as u32),
+            );
+            let view_projection = split_viewport.record_camera(
+                builder,
+                &self.vulkan_device,
+                &projection,
+                pre_rotation,
+            )?;
+            self.debug_view = split_viewport.debug_view.unwrap_or(debug_view);
+            // Simulation advances once per frame, however many cameras see it.
+            self.record_rasterized(
+                builder,
+                split_viewport.viewport(extent),
+                &view_projection,
+                push_constants,
+                if index == 0 { delta_time } else { 0.0 },
+            )?;
+        }
+        self.debug_view = debug_view;
+        self.split_viewports = split_viewports;
+
+        self.record_shared_camera(builder, pre_rotation)
+    }
+
+    // Secondary cameras draw before the main pass, so materials there sample this frame's view.
+    fn record_render_textures(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pre_rotation: &Matrix4<f32>,
+        push_constants: vs::PushConstantData,
+    ) -> Result<()> {
+        // Billboards may show the very texture being drawn, which can't be sampled at the same time.
+        let billboards = std::mem::take(&mut self.billboards);
+        let mut render_textures = std::mem::take(&mut self.render_textures);
+        let mut minimap = self.minimap.take();
+        let mut picture_in_picture = self.picture_in_picture.take();
+        for render_texture in render_textures
+            .iter_mut()
+            .chain(minimap.as_mut().map(Minimap::render_texture_mut))
+            .chain(
+                picture_in_picture
+                    .as_mut()
+                    .map(PictureInPicture::render_texture_mut),
+            )
+        {
+            let view_projection = render_texture.record_camera(builder, &self.vulkan_device)?;
+            std::mem::swap(&mut self.targets, render_texture.targets_mut());
+            let result = self.record_rasterized(
+                builder,
+                render_texture.viewport(),
+                &view_projection,
+                push_constants,
+                0.0,
+            );
+            std::mem::swap(&mut self.targets, render_texture.targets_mut());
+            result?;
+        }
+        self.render_textures = render_textures;
+        self.minimap = minimap;
+        self.picture_in_picture = picture_in_picture;
+        self.billboards = billboards;
+
+        self.record_shared_camera(builder, pre_rotation)
+    }
+
+    // Picking and other windows on this device expect the shared camera.
+    // The other probes' reflections come from their previous capture, if any.
+    fn record_reflection_probes(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pre_rotation: &Matrix4<f32>,
+        push_constants: vs::PushConstantData,
+    ) -> Result<()> {
+        let vulkan_device = Arc::clone(&self.vulkan_device);
+        let mut reflection_probes = std::mem::take(&mut self.reflection_probes);
+        for reflection_probe in reflection_probes
+            .iter_mut()
+            .filter(|reflection_probe| reflection_probe.needs_capture())
+        {
+            reflection_probe.record_capture(
+                builder,
+                &vulkan_device,
+                |builder, capture, view_projection| {
+                    std::mem::swap(&mut self.targets, capture.targets_mut());
+                    let result = self.record_rasterized(
+                        builder,
+                        capture.viewport(),
+                        view_projection,
+                        push_constants,
+                        0.0,
+                    );
+                    std::mem::swap(&mut self.targets, capture.targets_mut());
+                    result
+                },
+            )?;
+        }
+        self.reflection_probes = reflection_probes;
+
+        self.update_lighting_set()?;
+        self.record_shared_camera(builder, pre_rotation)
+    }
+
+    fn record_shared_camera(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pre_rotation: &Matrix4<f32>,
+    ) -> Result<()> {
+        let camera = self.camera();
+        self.vulkan_device.record_camera(
+            builder,
+            &camera.eye,
+            &camera.target,
+            &self.camera_projection(),
+            &self.previous_view_projection(),
+            pre_rotation,
+        )?;
+        Ok(())
+    }
+
+    // Without history, as on the first frame, the scene reads as not having moved.
+    fn previous_view_projection(&self) -> Matrix4<f32> {
+        self.previous_view_projection.unwrap_or_else(|| {
+            pre_rotation_matrix(self.swapchain.pre_transform()) * self.view_projection()
+        })
+    }
 }
@@ -0,0 +1,445 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CopyImageInfo, PrimaryAutoCommandBuffer, RenderingAttachmentInfo,
+    RenderingInfo,
+};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{
+    DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineShaderStageCreateInfo,
+};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+
+use crate::vulkan_device::{VulkanDevice, HDR_FORMAT};
+
+mod water_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) out vec3 worldNormal;
+                layout(location = 1) out vec3 viewPosition;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(push_constant) uniform WaterParameters {
+                    vec4 waves[4];
+                    vec4 shallowColor;
+                    vec4 absorption;
+                    float time;
+                    float height;
+                    float extent;
+                    uint resolution;
+                } parameters;
+
+                const float PI = 3.14159265359;
+                const float GRAVITY = 9.81;
+                const uvec2 CORNERS[6] = uvec2[](
+                    uvec2(0, 0), uvec2(1, 0), uvec2(0, 1),
+                    uvec2(0, 1), uvec2(1, 0), uvec2(1, 1)
+                );
+
+                vec3 gerstner(vec4 wave, vec2 position, inout vec3 tangent, inout vec3 binormal) {
+                    float steepness = wave.z;
+                    float k = 2.0 * PI / wave.w;
+                    float speed = sqrt(GRAVITY / k);
+                    vec2 direction = normalize(wave.xy);
+                    float phase = k * (dot(direction, position) - speed * parameters.time);
+                    float amplitude = steepness / k;
+
+                    tangent += vec3(
+                        -direction.x * direction.x * steepness * sin(phase),
+                        direction.x * steepness * cos(phase),
+                        -direction.x * direction.y * steepness * sin(phase)
+                    );
+                    binormal += vec3(
+                        -direction.x * direction.y * steepness * sin(phase),
+                        direction.y * steepness * cos(phase),
+                        -direction.y * direction.y * steepness * sin(phase)
+                    );
+
+                    return vec3(
+                        direction.x * amplitude * cos(phase),
+                        amplitude * sin(phase),
+                        direction.y * amplitude * cos(phase)
+                    );
+                }
+
+                void main() {
+                    uint quad = uint(gl_VertexIndex) / 6u;
+                    uvec2 cell = uvec2(quad % parameters.resolution, quad / parameters.resolution)
+                        + CORNERS[uint(gl_VertexIndex) % 6u];
+                    vec2 grid = (vec2(cell) / float(parameters.resolution) - 0.5) * parameters.extent;
+
+                    vec3 tangent = vec3(1.0, 0.0, 0.0);
+                    vec3 binormal = vec3(0.0, 0.0, 1.0);
+                    vec3 position = vec3(grid.x, parameters.height, grid.y);
+                    for (uint i = 0u; i < 4u; i++) {
+                        position += gerstner(parameters.waves[i], grid, tangent, binormal);
+                    }
+
+                    worldNormal = normalize(cross(binormal, tangent));
+                    viewPosition = (uniforms.view * vec4(position, 1.0)).xyz;
+                    gl_Position = uniforms.view_projection * vec4(position, 1.0);
+                }
+            ",
+    }
+}
+
+mod water_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 worldNormal;
+                layout(location = 1) in vec3 viewPosition;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform Data {
+                    mat4 view;
+                    mat4 projection;
+                    mat4 view_projection;
+                    mat4 inverse_projection;
+                } uniforms;
+
+                layout(set = 0, binding = 1) uniform sampler2D depthTexture;
+                layout(set = 0, binding = 2) uniform sampler2D sceneTexture;
+                layout(set = 0, binding = 3) uniform samplerCube reflectionMap;
+
+                layout(push_constant) uniform WaterParameters {
+                    vec4 waves[4];
+                    vec4 shallowColor;
+                    vec4 absorption;
+                    float time;
+                    float height;
+                    float extent;
+                    uint resolution;
+                } parameters;
+
+                const uint REFLECTION_STEP_COUNT = 48u;
+
+                vec3 viewPositionAt(vec2 uv, float depth) {
+                    vec4 position = uniforms.inverse_projection * vec4(uv * 2.0 - 1.0, depth, 1.0);
+                    return position.xyz / position.w;
+                }
+
+                vec3 screenSpaceReflection(vec3 origin, vec3 direction, vec3 fallback) {
+                    vec3 position = origin;
+                    float stepLength = 0.1;
+                    for (uint i = 0u; i < REFLECTION_STEP_COUNT; i++) {
+                        position += direction * stepLength;
+                        stepLength *= 1.1;
+
+                        vec4 clip = uniforms.projection * vec4(position, 1.0);
+                        vec2 uv = clip.xy / clip.w * 0.5 + 0.5;
+                        if (clip.w <= 0.0 || any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+                            break;
+                        }
+
+                        vec3 hit = viewPositionAt(uv, textureLod(depthTexture, uv, 0.0).r);
+                        float difference = hit.z - position.z;
+                        if (difference > 0.0 && difference < stepLength * 2.0) {
+                            vec2 edge = smoothstep(0.0, 0.1, uv) * smoothstep(1.0, 0.9, uv);
+                            return mix(fallback, textureLod(sceneTexture, uv, 0.0).rgb, edge.x * edge.y);
+                        }
+                    }
+                    return fallback;
+                }
+
+                void main() {
+                    vec2 uv = gl_FragCoord.xy / vec2(textureSize(sceneTexture, 0));
+                    float sceneDepth = texelFetch(depthTexture, ivec2(gl_FragCoord.xy), 0).r;
+                    if (gl_FragCoord.z > sceneDepth) {
+                        discard;
+                    }
+
+                    vec3 viewDirection = normalize(-viewPosition);
+                    vec3 normal = normalize(mat3(uniforms.view) * worldNormal);
+                    if (dot(normal, viewDirection) < 0.0) {
+                        normal = -normal;
+                    }
+
+                    float thickness = max(length(viewPositionAt(uv, sceneDepth)) - length(viewPosition), 0.0);
+
+                    vec2 refractedUv = uv + normal.xy * parameters.absorption.a * min(thickness, 1.0);
+                    if (textureLod(depthTexture, refractedUv, 0.0).r < gl_FragCoord.z) {
+                        refractedUv = uv;
+                    }
+                    vec3 refraction = mix(
+                        parameters.shallowColor.rgb,
+                        textureLod(sceneTexture, refractedUv, 0.0).rgb,
+                        exp(-parameters.absorption.rgb * thickness)
+                    );
+
+                    vec3 reflectedDirection = reflect(-viewDirection, normal);
+                    vec3 environment = textureLod(
+                        reflectionMap,
+                        transpose(mat3(uniforms.view)) * reflectedDirection,
+                        0.0
+                    ).rgb;
+                    vec3 reflection = screenSpaceReflection(viewPosition, reflectedDirection, environment);
+
+                    float fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, viewDirection), 0.0), 5.0);
+                    outColor = vec4(mix(refraction, reflection, fresnel), 1.0);
+                }
+            ",
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GerstnerWave {
+    pub direction: [f32; 2],
+    pub steepness: f32,
+    pub wavelength: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WaterSettings {
+    pub enabled: bool,
+    pub height: f32,
+    pub extent: f32,
+    pub resolution: u32,
+    pub waves: [GerstnerWave; 4],
+    pub shallow_color: [f32; 3],
+    pub absorption: [f32; 3],
+    pub refraction_strength: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            height: 0.0,
+            extent: 40.0,
+            resolution: 256,
+            waves: [
+                GerstnerWave {
+                    direction: [1.0, 0.0],
+                    steepness: 0.25,
+                    wavelength: 6.0,
+                },
+                GerstnerWave {
+                    direction: [0.0, 1.0],
+                    steepness: 0.2,
+                    wavelength: 3.1,
+                },
+                GerstnerWave {
+                    direction: [1.0, 1.0],
+                    steepness: 0.15,
+                    wavelength: 1.8,
+                },
+                GerstnerWave {
+                    direction: [-0.6, 1.0],
+                    steepness: 0.1,
+                    wavelength: 0.9,
+                },
+            ],
+            shallow_color: [0.02, 0.12, 0.14],
+            absorption: [0.45, 0.09, 0.06],
+            refraction_strength: 0.03,
+        }
+    }
+}
+
+pub struct WaterViews<'a> {
+    pub scene_color: &'a Arc<ImageView>,
+    pub refraction: &'a Arc<ImageView>,
+    pub depth: &'a Arc<ImageView>,
+    pub reflection: &'a Arc<ImageView>,
+}
+
+pub struct WaterPass {
+    pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    depth_sampler: Arc<Sampler>,
+}
+
+impl WaterPass {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let vertex_shader = water_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+        let fragment_shader = water_fs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(fragment_shader),
+        ];
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(Arc::clone(device))
+                .unwrap(),
+        )?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        let depth_sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            pipeline,
+            sampler,
+            depth_sampler,
+        })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vulkan_device: &VulkanDevice,
+        views: &WaterViews,
+        settings: &WaterSettings,
+        time: f32,
+        viewport: &Viewport,
+    ) -> Result<()> {
+        let WaterViews {
+            scene_color,
+            refraction,
+            depth,
+            reflection,
+        } = views;
+
+        builder.copy_image(CopyImageInfo::images(
+            Arc::clone(scene_color.image()),
+            Arc::clone(refraction.image()),
+        ))?;
+
+        let set = DescriptorSet::new(
+            vulkan_device.descriptor_set_allocator().clone(),
+            Arc::clone(&self.pipeline.layout().set_layouts()[0]),
+            [
+                WriteDescriptorSet::buffer(0, vulkan_device.uniform_buffer().clone()),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    Arc::clone(depth),
+                    Arc::clone(&self.depth_sampler),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    2,
+                    Arc::clone(refraction),
+                    Arc::clone(&self.sampler),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    3,
+                    Arc::clone(reflection),
+                    Arc::clone(&self.sampler),
+                ),
+            ],
+            [],
+        )?;
+
+        let [shallow_r, shallow_g, shallow_b] = settings.shallow_color;
+        let [absorption_r, absorption_g, absorption_b] = settings.absorption;
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(Arc::clone(scene_color))
+                })],
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport.clone()].into_iter().collect())?
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                set,
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                water_vs::WaterParameters {
+                    waves: settings.waves.map(|wave| {
+                        [
+                            wave.direction[0],
+                            wave.direction[1],
+                            wave.steepness,
+                            wave.wavelength,
+                        ]
+                    }),
+                    shallowColor: [shallow_r, shallow_g, shallow_b, 1.0],
+                    absorption: [
+                        absorption_r,
+                        absorption_g,
+                        absorption_b,
+                        settings.refraction_strength,
+                    ],
+                    time,
+                    height: settings.height,
+                    extent: settings.extent,
+                    resolution: settings.resolution,
+                },
+            )?;
+        unsafe { builder.draw(settings.resolution * settings.resolution * 6, 1, 0, 0) }?;
+        builder.end_rendering()?;
+
+        Ok(())
+    }
+}
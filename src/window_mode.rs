@@ -0,0 +1,103 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use tracing::warn;
+use winit::monitor::MonitorHandle;
+use winit::window::{Fullscreen, Window, WindowBuilder, WindowLevel};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    // No title bar or borders, for kiosks and overlays that position themselves.
+    Undecorated,
+    // Covers the current monitor without an exclusive mode switch.
+    BorderlessFullscreen,
+}
+
+impl FromStr for WindowMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "windowed" => WindowMode::Windowed,
+            "undecorated" | "borderless" => WindowMode::Undecorated,
+            "fullscreen" | "borderless-fullscreen" => WindowMode::BorderlessFullscreen,
+            _ => bail!("Unknown window mode: {value}"),
+        })
+    }
+}
+
+impl WindowMode {
+    pub fn of(window: &Window) -> Self {
+        if window.fullscreen().is_some() {
+            WindowMode::BorderlessFullscreen
+        } else if window.is_decorated() {
+            WindowMode::Windowed
+        } else {
+            WindowMode::Undecorated
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            WindowMode::Windowed => WindowMode::Undecorated,
+            WindowMode::Undecorated => WindowMode::BorderlessFullscreen,
+            WindowMode::BorderlessFullscreen => WindowMode::Windowed,
+        }
+    }
+
+    pub fn window_builder(self) -> WindowBuilder {
+        WindowBuilder::new()
+            .with_decorations(self == WindowMode::Windowed)
+            .with_fullscreen(self.fullscreen(None))
+    }
+
+    // The swapchain follows through the resize this causes.
+    pub fn apply(self, window: &Window) {
+        window.set_decorations(self == WindowMode::Windowed);
+        window.set_fullscreen(self.fullscreen(window.current_monitor()));
+    }
+
+    fn fullscreen(self, monitor: Option<MonitorHandle>) -> Option<Fullscreen> {
+        (self == WindowMode::BorderlessFullscreen).then(|| Fullscreen::Borderless(monitor))
+    }
+}
+
+// Secondary windows can float above other applications as heads-up displays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverlayMode {
+    Interactive,
+    // Input passes through to whatever is underneath.
+    ClickThrough,
+}
+
+impl FromStr for OverlayMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "interactive" => OverlayMode::Interactive,
+            "click-through" => OverlayMode::ClickThrough,
+            _ => bail!("Unknown overlay mode: {value}"),
+        })
+    }
+}
+
+impl OverlayMode {
+    pub fn configure(self, builder: WindowBuilder) -> WindowBuilder {
+        builder
+            .with_decorations(false)
+            .with_transparent(true)
+            .with_window_level(WindowLevel::AlwaysOnTop)
+    }
+
+    // Hit testing can only be changed once the window exists.
+    pub fn apply(self, window: &Window) {
+        if self == OverlayMode::ClickThrough {
+            if let Err(error) = window.set_cursor_hittest(false) {
+                warn!("Overlay can't be made click-through: {error}");
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use winit::window::{Icon, Window};
+
+use crate::metrics::FrameMetrics;
+use crate::pixel_inspector::PixelSample;
+
+pub const DEFAULT_TITLE_TEMPLATE: &str = "{app} - {gpu} - {fps} FPS ({frame_time} ms)";
+pub const ICON_PATH: &str = "assets/icon.png";
+
+// Setting the title is a round trip to the window system, so it is only refreshed a few times a
+// second from the frames averaged in between.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct WindowTitle {
+    template: String,
+    window_index: usize,
+    previous_refresh: Instant,
+    frame_count: u32,
+    frame_time_ms: f32,
+}
+
+impl WindowTitle {
+    pub fn new(template: &str, window_index: usize) -> Self {
+        Self {
+            template: template.to_owned(),
+            window_index,
+            previous_refresh: Instant::now(),
+            frame_count: 0,
+            frame_time_ms: 0.0,
+        }
+    }
+
+    // The pixel inspector's sample follows the template while it's on, since there's no text overlay.
+    pub fn update(
+        &mut self,
+        window: &Window,
+        gpu_name: &str,
+        frame_metrics: &FrameMetrics,
+        pixel_sample: Option<&PixelSample>,
+    ) {
+        self.frame_count += 1;
+        self.frame_time_ms += frame_metrics.frame_time_ms;
+
+        let elapsed = self.previous_refresh.elapsed();
+        if elapsed < REFRESH_INTERVAL {
+            return;
+        }
+
+        let fps = self.frame_count as f32 / elapsed.as_secs_f32();
+        let frame_time_ms = self.frame_time_ms / self.frame_count as f32;
+        let mut title = self
+            .template
+            .replace("{app}", env!("CARGO_PKG_NAME"))
+            .replace("{window}", &self.window_index.to_string())
+            .replace("{gpu}", gpu_name)
+            .replace("{fps}", &format!("{fps:.0}"))
+            .replace("{frame_time}", &format!("{frame_time_ms:.2}"));
+        if let Some(pixel_sample) = pixel_sample {
+            title.push_str(&format!(" - {pixel_sample}"));
+        }
+        window.set_title(&title);
+
+        self.previous_refresh = Instant::now();
+        self.frame_count = 0;
+        self.frame_time_ms = 0.0;
+    }
+}
+
+// A missing icon keeps the platform default.
+pub fn load_icon(path: impl AsRef<Path>) -> Result<Option<Icon>> {
+    if !path.as_ref().exists() {
+        return Ok(None);
+    }
+    let image = image::open(path)?.to_rgba8();
+    let (width, height) = image.dimensions();
+    Ok(Some(Icon::from_rgba(image.into_raw(), width, height)?))
+}